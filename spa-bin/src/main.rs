@@ -1,12 +1,16 @@
 
 mod debug;
+mod mic;
 mod run;
 
 use clap::{clap_app, crate_version};
 
 use spa::{gba, ds};
 
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::Arc
+};
 
 fn main() {
     //env_logger::init();
@@ -15,32 +19,122 @@ fn main() {
         (version: crate_version!())
         (author: "Simon Cooper")
         (about: "Gameboy Advance and DS emulator.")
-        (@arg ROM: "The path to the game ROM to use.")
+        (@arg ROM: "The path to the game ROM to use. If omitted, the NDS boots into the firmware menu.")
         (@arg debug: -d +takes_value "Enter debug mode.")
         (@arg mute: -m "Mute all audio.")
         (@arg save: -s +takes_value "Save file path.")
         (@arg biosrom: -r +takes_value "BIOS ROM path. Needed for certain games.")
         (@arg dsbios: -b +takes_value "BIOS folder for NDS. Inside should be [bios7.bin, bios9.bin, firmware.bin]. Needed for certain games.")
+        (@arg firmwaresave: -w +takes_value "User firmware file for NDS. Settings changed in-game are saved here. Defaults to user_firmware.bin in the BIOS folder.")
+        (@arg language: -l +takes_value "NDS firmware language: jp, en, fr, de, it, es.")
+        (@arg key1: -k +takes_value "KEY1 table file for NDS. Only needed if bios7.bin is missing, and the ROM secure area is encrypted.")
+        (@arg gbarom: -g +takes_value "GBA ROM to insert into the NDS GBA slot.")
+        (@arg gbasave: -v +takes_value "Save file for the GBA ROM in the NDS GBA slot.")
+        (@arg slot2: -x +takes_value "Accessory to insert into the NDS GBA slot: rumble, expansion, guitar. Ignored if a GBA ROM is used.")
+        (@arg time: -t +takes_value "Start the NDS clock at this time (YYYY-MM-DDTHH:MM:SS). Defaults to the local time.")
+        (@arg freezetime: -z "Freeze the NDS clock at the start time.")
+        (@arg mic: -i +takes_value "WAV file to use as NDS microphone input, while M is held. Defaults to noise.")
+        (@arg wifiport: -p +takes_value "Localhost UDP port for NDS local wireless.")
+        (@arg wifipeers: -c +takes_value "Comma-separated localhost UDP ports of other NDS instances to connect to over local wireless.")
+        (@arg wificapture: -u +takes_value "Record NDS wireless traffic to this pcap file.")
+        (@arg swaprom: -n +takes_value "Another NDS ROM. Press E to eject the card, and E again to insert the next one.")
+        (@arg upscale: -a +takes_value "Render NDS 3D graphics at this multiple of the native resolution, from 1 to 4.")
+        (@arg dumptextures: -o +takes_value "Dump NDS 3D textures as PNG, to a folder for the game inside this folder.")
+        (@arg texturepack: -q +takes_value "Replace NDS 3D textures with PNGs from a folder for the game inside this folder.")
         (@arg fastboot: -f "Skip the firmware screen and boot directly.")
+        (@arg jit: -j "Use the JIT compiler. For GBA, and NDS ARM7.")
     );
 
     let cmd_args = app.get_matches();
 
-    let rom_path = match cmd_args.value_of("ROM") {
-        Some(c) => PathBuf::from(c),
-        None => panic!("Usage: spa [ROM name]. Run with --help for more options."),
-    };
+    let rom_path = cmd_args.value_of("ROM").map(|c| PathBuf::from(c));
 
     let save_path = cmd_args.value_of("save").map(|s| PathBuf::from(s));
     let bios_path = cmd_args.value_of("biosrom").map(|s| PathBuf::from(s));
     let ds_bios_path = cmd_args.value_of("dsbios").map(|s| PathBuf::from(s));
+    let key1_path = cmd_args.value_of("key1").map(|s| PathBuf::from(s));
+    let slot_2 = if let Some(gba_rom) = cmd_args.value_of("gbarom") {
+        ds::Slot2Device::GBACart {
+            rom_path:   PathBuf::from(gba_rom),
+            save_path:  cmd_args.value_of("gbasave").map(|s| PathBuf::from(s)),
+        }
+    } else {
+        match cmd_args.value_of("slot2") {
+            None => ds::Slot2Device::Empty,
+            Some("rumble") => ds::Slot2Device::RumblePak,
+            Some("expansion") => ds::Slot2Device::MemoryExpansionPak,
+            Some("guitar") => ds::Slot2Device::GuitarGrip,
+            Some(other) => panic!("unknown slot-2 device '{}'. use rumble, expansion or guitar", other),
+        }
+    };
+    let firmware_save_path = cmd_args.value_of("firmwaresave").map(|s| PathBuf::from(s)).or_else(|| {
+        ds_bios_path.clone().map(|mut p| {
+            p.push("user_firmware.bin");
+            p
+        })
+    });
+
+    let user_profile = cmd_args.value_of("language").map(|l| ds::UserProfile {
+        language: match l {
+            "jp" => ds::Language::Japanese,
+            "en" => ds::Language::English,
+            "fr" => ds::Language::French,
+            "de" => ds::Language::German,
+            "it" => ds::Language::Italian,
+            "es" => ds::Language::Spanish,
+            other => panic!("unknown language '{}'. use jp, en, fr, de, it or es", other),
+        },
+        ..Default::default()
+    });
+
+    let rtc_time = match (cmd_args.value_of("time"), cmd_args.is_present("freezetime")) {
+        (None, false) => ds::RTCTime::Host,
+        (None, true) => ds::RTCTime::Frozen(chrono::Local::now().naive_local()),
+        (Some(t), freeze) => {
+            let time = t.parse::<chrono::NaiveDateTime>().expect("time should be in the format YYYY-MM-DDTHH:MM:SS");
+            if freeze {
+                ds::RTCTime::Frozen(time)
+            } else {
+                ds::RTCTime::Fixed(time)
+            }
+        },
+    };
+
+    let (wifi_medium, mac_address) = if let Some(port) = cmd_args.value_of("wifiport") {
+        let port = port.parse::<u16>().expect("wifi port should be a number");
+        let peer_ports = cmd_args.value_of("wifipeers").map(|peers| peers.split(',')
+            .map(|p| p.trim().parse::<u16>().expect("wifi peers should be comma-separated port numbers"))
+            .collect::<Vec<_>>()
+        ).unwrap_or_default();
+        let medium = ds::UdpWifi::new(port, &peer_ports).unwrap_or_else(|e| panic!("couldn't open wifi port {}: {}", port, e));
+        // Each instance needs its own MAC address.
+        let mut mac_address = ds::DEFAULT_MAC_ADDRESS;
+        mac_address[4..].copy_from_slice(&port.to_be_bytes());
+        (Some(Arc::new(medium) as Arc<dyn ds::WifiMedium>), Some(mac_address))
+    } else {
+        (None, None)
+    };
+
+    let wifi_capture_path = cmd_args.value_of("wificapture").map(|s| PathBuf::from(s));
+
+    let render_scale = cmd_args.value_of("upscale").map(|s| s.parse::<usize>().expect("upscale should be a number")).unwrap_or(1);
+
+    // Textures for each game are kept in a folder named after the ROM.
+    let game_name = rom_path.as_ref()
+        .and_then(|p| p.file_stem())
+        .map(|s| s.to_os_string())
+        .unwrap_or_else(|| "firmware".into());
+    let texture_dump_path = cmd_args.value_of("dumptextures").map(|s| PathBuf::from(s).join(&game_name));
+    let texture_pack_path = cmd_args.value_of("texturepack").map(|s| PathBuf::from(s).join(&game_name));
 
     let fast_boot = cmd_args.is_present("fastboot");
+    let jit = cmd_args.is_present("jit");
 
     if let Some(value) = cmd_args.value_of("debug") {
         if value == "gba" {
+            let rom_path = rom_path.expect("Usage: spa [ROM name] -d gba. Run with --help for more options.");
             let debug_interface = gba::GBA::new_debug(gba::MemoryConfig{
-                rom_path, save_path, bios_path
+                rom_path, save_path, bios_path, jit, jit_ranges: None
             });
             debug::debug_mode(debug_interface);
         } else {
@@ -57,14 +151,18 @@ fn main() {
                 p
             });
             let config = ds::MemoryConfig{
-                rom_path, save_path, ds7_bios_path, ds9_bios_path, firmware_path, fast_boot
+                rom_path, save_path, ds7_bios_path, ds9_bios_path, firmware_path, firmware_save_path, mac_address, user_profile, key1_path, slot_2, rtc_time, wifi_medium, wifi_capture_path, render_scale, texture_dump_path, texture_pack_path, fast_boot, jit, jit_ranges: None
             };
             if value == "ds7" {
-                let debug_interface = ds::NDS::new_debug_7(config);
-                debug::debug_mode(debug_interface);
+                match ds::NDS::new_debug_7(config) {
+                    Ok(debug_interface) => debug::debug_mode(debug_interface),
+                    Err(e) => println!("couldn't start NDS: {}", e),
+                }
             } else if value == "ds9" {
-                let debug_interface = ds::NDS::new_debug_9(config);
-                debug::debug_mode(debug_interface);
+                match ds::NDS::new_debug_9(config) {
+                    Ok(debug_interface) => debug::debug_mode(debug_interface),
+                    Err(e) => println!("couldn't start NDS: {}", e),
+                }
             } else {
                 println!("unknown debug mode {}. use gba or ds[7|9]", value);
             }
@@ -72,30 +170,36 @@ fn main() {
         return;
     }
 
-    if let Some(ext) = rom_path.extension() {
-        match ext.to_str().unwrap() {
-            "gba" => run::run_gba(gba::MemoryConfig{
-                rom_path, save_path, bios_path
-            }, cmd_args.is_present("mute")),
-            "nds" => {
-                let ds7_bios_path = ds_bios_path.clone().map(|mut p| {
-                    p.push("bios7.bin");
-                    p
-                });
-                let ds9_bios_path = ds_bios_path.clone().map(|mut p| {
-                    p.push("bios9.bin");
-                    p
-                });
-                let firmware_path = ds_bios_path.clone().map(|mut p| {
-                    p.push("firmware.bin");
-                    p
-                });
-                let config = ds::MemoryConfig{
-                    rom_path, save_path, ds7_bios_path, ds9_bios_path, firmware_path, fast_boot
-                };
-                run::run_nds(config, cmd_args.is_present("mute"))
-            },
-            other => panic!("unknown ext '{}'", other)
-        }
+    let ext = rom_path.as_ref().map(|p| p.extension().and_then(|e| e.to_str()).unwrap_or_default().to_string());
+    match ext.as_deref() {
+        Some("gba") | Some("mb") => run::run_gba(gba::MemoryConfig{
+            rom_path: rom_path.unwrap(), save_path, bios_path, jit, jit_ranges: None
+        }, cmd_args.is_present("mute")),
+        Some("nds") | None => {
+            let ds7_bios_path = ds_bios_path.clone().map(|mut p| {
+                p.push("bios7.bin");
+                p
+            });
+            let ds9_bios_path = ds_bios_path.clone().map(|mut p| {
+                p.push("bios9.bin");
+                p
+            });
+            let firmware_path = ds_bios_path.clone().map(|mut p| {
+                p.push("firmware.bin");
+                p
+            });
+            let mut carts = rom_path.iter().map(|p| (p.clone(), save_path.clone())).collect::<Vec<_>>();
+            if let Some(swap_rom) = cmd_args.value_of("swaprom") {
+                carts.push((PathBuf::from(swap_rom), None));
+            }
+            let config = ds::MemoryConfig{
+                rom_path, save_path, ds7_bios_path, ds9_bios_path, firmware_path, firmware_save_path, mac_address, user_profile, key1_path, slot_2, rtc_time, wifi_medium, wifi_capture_path, render_scale, texture_dump_path, texture_pack_path, fast_boot, jit, jit_ranges: None
+            };
+            let mic_source = cmd_args.value_of("mic").map(|p| {
+                mic::MicSource::from_wav(&PathBuf::from(p)).unwrap_or_else(|e| panic!("couldn't load mic input {}: {}", p, e))
+            }).unwrap_or_else(mic::MicSource::noise);
+            run::run_nds(config, cmd_args.is_present("mute"), mic_source, carts)
+        },
+        Some(other) => panic!("unknown ext '{}'", other)
     }
 }
@@ -0,0 +1,118 @@
+/// Microphone input sources.
+
+use std::path::Path;
+
+/// Frames per second that samples are generated for.
+const FRAME_RATE: usize = 60;
+/// Sample rate of generated noise.
+const NOISE_SAMPLE_RATE: usize = 16_000;
+
+pub enum MicSource {
+    /// No input.
+    Silence,
+    /// White noise, like blowing into the microphone.
+    Noise {
+        state: u32,
+    },
+    /// Mono PCM loaded from a WAV file. Loops.
+    Wav {
+        samples:        Vec<i16>,
+        sample_rate:    usize,
+        position:       usize,
+        /// Fractional samples carried between frames.
+        remainder:      usize,
+    },
+}
+
+impl MicSource {
+    pub fn noise() -> Self {
+        MicSource::Noise { state: 0x1234_5678 }
+    }
+
+    /// Load a 8 or 16-bit PCM WAV file. Multiple channels are mixed down.
+    pub fn from_wav(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err("not a WAV file".to_string());
+        }
+
+        let mut format = None;
+        let mut pcm = None;
+        let mut offset = 12;
+        while offset + 8 <= data.len() {
+            let id = &data[offset..(offset + 4)];
+            let size = u32::from_le_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]) as usize;
+            let start = offset + 8;
+            let end = (start + size).min(data.len());
+            match id {
+                b"fmt " if size >= 16 => {
+                    let chunk = &data[start..end];
+                    let audio_format = u16::from_le_bytes([chunk[0], chunk[1]]);
+                    let channels = u16::from_le_bytes([chunk[2], chunk[3]]) as usize;
+                    let sample_rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+                    let bits = u16::from_le_bytes([chunk[14], chunk[15]]);
+                    if audio_format != 1 || channels == 0 || (bits != 8 && bits != 16) {
+                        return Err("only 8 or 16-bit PCM is supported".to_string());
+                    }
+                    format = Some((channels, sample_rate, bits));
+                },
+                b"data" => pcm = Some(&data[start..end]),
+                _ => {},
+            }
+            // Chunks are padded to an even size.
+            offset = start + size + (size & 1);
+        }
+
+        let (channels, sample_rate, bits) = format.ok_or_else(|| "missing fmt chunk".to_string())?;
+        let pcm = pcm.ok_or_else(|| "missing data chunk".to_string())?;
+        let channel_samples = if bits == 16 {
+            pcm.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]) as i32).collect::<Vec<_>>()
+        } else {
+            pcm.iter().map(|b| ((*b as i32) - 0x80) << 8).collect::<Vec<_>>()
+        };
+        let samples = channel_samples.chunks_exact(channels)
+            .map(|frame| (frame.iter().sum::<i32>() / (channels as i32)) as i16)
+            .collect::<Vec<_>>();
+        if samples.is_empty() {
+            return Err("no samples in file".to_string());
+        }
+
+        Ok(MicSource::Wav {
+            samples, sample_rate, position: 0, remainder: 0
+        })
+    }
+
+    /// Get samples for a single frame.
+    pub fn frame_samples(&mut self, out: &mut Vec<i16>) {
+        out.clear();
+        match self {
+            MicSource::Silence => out.resize(NOISE_SAMPLE_RATE / FRAME_RATE, 0),
+            MicSource::Noise { state } => {
+                for _ in 0..(NOISE_SAMPLE_RATE / FRAME_RATE) {
+                    // Xorshift
+                    *state ^= *state << 13;
+                    *state ^= *state >> 17;
+                    *state ^= *state << 5;
+                    out.push(*state as i16);
+                }
+            },
+            MicSource::Wav { samples, sample_rate, position, remainder } => {
+                *remainder += *sample_rate;
+                let count = *remainder / FRAME_RATE;
+                *remainder %= FRAME_RATE;
+                for _ in 0..count {
+                    out.push(samples[*position]);
+                    *position = (*position + 1) % samples.len();
+                }
+            },
+        }
+    }
+
+    /// Start from the beginning.
+    pub fn reset(&mut self) {
+        if let MicSource::Wav { position, remainder, .. } = self {
+            *position = 0;
+            *remainder = 0;
+        }
+    }
+}
@@ -1,4 +1,7 @@
 use spa::{ds, gba, Coords, Device};
+use crate::mic::MicSource;
+
+use std::path::PathBuf;
 
 use winit::{
     application::ApplicationHandler, dpi::{
@@ -50,11 +53,33 @@ struct App {
     clicked: bool,
     coords:  Option<spa::Coords<f64>>,
 
+    /// Used while the mic key is held.
+    mic_source: MicSource,
+    mic_active: bool,
+    mic_buffer: Vec<i16>,
+
+    lid_closed: bool,
+
+    /// Cart sensors.
+    light_level: f64,
+    /// Anticlockwise, clockwise.
+    rotation_keys: [bool; 2],
+    /// Up, down, left, right.
+    tilt_keys: [bool; 4],
+
+    /// Render size multiplier. The window is sized for native resolution.
+    render_scale: usize,
+
+    /// Cards that can be swapped in, with their save files.
+    carts: Vec<(PathBuf, Option<PathBuf>)>,
+    cart_index: usize,
+    cart_inserted: bool,
+
     audio_stream: cpal::Stream
 }
 
 impl App {
-    fn new(console: Box<dyn spa::Device>, audio_stream: cpal::Stream) -> Self {
+    fn new(console: Box<dyn spa::Device>, audio_stream: cpal::Stream, mic_source: MicSource) -> Self {
         // Setup wgpu
         let instance = wgpu::Instance::new(&Default::default());
 
@@ -199,16 +224,78 @@ impl App {
             clicked: false,
             coords: None,
 
+            mic_source: mic_source,
+            mic_active: false,
+            mic_buffer: Vec::new(),
+
+            lid_closed: false,
+
+            light_level: 0.0,
+            rotation_keys: [false; 2],
+            tilt_keys: [false; 4],
+
+            render_scale: 1,
+
+            carts: Vec::new(),
+            cart_index: 0,
+            cart_inserted: false,
+
             audio_stream: audio_stream
         }
     }
+
+    /// Eject the card, or insert the next one if the slot is empty.
+    fn swap_cart(&mut self) {
+        if self.cart_inserted {
+            self.console.eject_cart();
+            self.cart_inserted = false;
+            self.cart_index = (self.cart_index + 1) % self.carts.len();
+        } else if let Some((rom_path, save_path)) = self.carts.get(self.cart_index) {
+            match self.console.insert_cart(rom_path, save_path.clone()) {
+                Ok(_) => self.cart_inserted = true,
+                Err(e) => println!("couldn't insert card {}: {}", rom_path.display(), e),
+            }
+        }
+    }
+}
+
+// Cart sensors
+impl App {
+    fn change_light_level(&mut self, change: f64) {
+        self.light_level = (self.light_level + change).clamp(0.0, 1.0);
+        self.console.set_light_level(self.light_level);
+    }
+
+    fn set_rotation_key(&mut self, index: usize, pressed: bool) {
+        self.rotation_keys[index] = pressed;
+        let rotation = key_axis(self.rotation_keys[1], self.rotation_keys[0]);
+        self.console.set_rotation(rotation);
+    }
+
+    fn set_tilt_key(&mut self, index: usize, pressed: bool) {
+        self.tilt_keys[index] = pressed;
+        let [up, down, left, right] = self.tilt_keys;
+        self.console.set_tilt(Coords {
+            x: key_axis(right, left),
+            y: key_axis(down, up),
+        });
+    }
+}
+
+/// 1.0 if only `positive` is held, -1.0 if only `negative` is held.
+fn key_axis(positive: bool, negative: bool) -> f64 {
+    match (positive, negative) {
+        (true, false) => 1.0,
+        (false, true) => -1.0,
+        _ => 0.0,
+    }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         let [upper_render_size, lower_render_size] = self.console.render_size();
-        let width = upper_render_size.x;
-        let height = upper_render_size.y + lower_render_size.y;
+        let width = upper_render_size.x / self.render_scale;
+        let height = (upper_render_size.y + lower_render_size.y) / self.render_scale;
         let window_attrs = Window::default_attributes()
             .with_inner_size(Size::Logical(LogicalSize{width: (width * 2) as f64, height: (height * 2) as f64}))
             .with_title("SPA");
@@ -250,8 +337,19 @@ impl ApplicationHandler for App {
                 let now = chrono::Utc::now();
                 if now.signed_duration_since(self.last_frame_time) >= FRAME_TIME {
                     self.last_frame_time = now;
+
+                    if self.mic_active {
+                        self.mic_source.frame_samples(&mut self.mic_buffer);
+                    } else {
+                        MicSource::Silence.frame_samples(&mut self.mic_buffer);
+                    }
+                    self.console.microphone_samples(&self.mic_buffer);
     
                     self.console.frame(&mut self.upper_screen_buffer, &mut self.lower_screen_buffer);
+
+                    if self.console.take_events().contains(&spa::Event::PowerOff) {
+                        event_loop.exit();
+                    }
     
                     self.frame_buffer.clear();
                     self.frame_buffer.extend_from_slice(&self.upper_screen_buffer);
@@ -315,6 +413,35 @@ impl ApplicationHandler for App {
                     PhysicalKey::Code(KeyCode::ArrowDown)   => self.console.set_button(spa::Button::Down, pressed),
                     PhysicalKey::Code(KeyCode::ArrowLeft)   => self.console.set_button(spa::Button::Left, pressed),
                     PhysicalKey::Code(KeyCode::ArrowRight)  => self.console.set_button(spa::Button::Right, pressed),
+                    PhysicalKey::Code(KeyCode::KeyM)        => {
+                        if pressed && !event.repeat {
+                            self.mic_source.reset();
+                        }
+                        self.mic_active = pressed;
+                    },
+                    PhysicalKey::Code(KeyCode::Digit1)      => self.console.set_guitar_fret(spa::GuitarFret::Green, pressed),
+                    PhysicalKey::Code(KeyCode::Digit2)      => self.console.set_guitar_fret(spa::GuitarFret::Red, pressed),
+                    PhysicalKey::Code(KeyCode::Digit3)      => self.console.set_guitar_fret(spa::GuitarFret::Yellow, pressed),
+                    PhysicalKey::Code(KeyCode::Digit4)      => self.console.set_guitar_fret(spa::GuitarFret::Blue, pressed),
+                    PhysicalKey::Code(KeyCode::KeyL)        => if pressed && !event.repeat {
+                        self.lid_closed = !self.lid_closed;
+                        self.console.set_lid_closed(self.lid_closed);
+                    },
+                    PhysicalKey::Code(KeyCode::KeyE)        => if pressed && !event.repeat {
+                        self.swap_cart();
+                    },
+                    PhysicalKey::Code(KeyCode::Equal)       => if pressed {
+                        self.change_light_level(0.1);
+                    },
+                    PhysicalKey::Code(KeyCode::Minus)       => if pressed {
+                        self.change_light_level(-0.1);
+                    },
+                    PhysicalKey::Code(KeyCode::KeyR)        => self.set_rotation_key(0, pressed),
+                    PhysicalKey::Code(KeyCode::KeyY)        => self.set_rotation_key(1, pressed),
+                    PhysicalKey::Code(KeyCode::KeyT)        => self.set_tilt_key(0, pressed),
+                    PhysicalKey::Code(KeyCode::KeyG)        => self.set_tilt_key(1, pressed),
+                    PhysicalKey::Code(KeyCode::KeyF)        => self.set_tilt_key(2, pressed),
+                    PhysicalKey::Code(KeyCode::KeyH)        => self.set_tilt_key(3, pressed),
                     PhysicalKey::Code(KeyCode::KeyQ)        => self.console.trigger_debug(),
                     _ => {},
                 }
@@ -364,14 +491,26 @@ impl ApplicationHandler for App {
     }
 }
 
-pub fn run_nds(config: ds::MemoryConfig, mute: bool) {
-    let mut nds: Box<dyn Device> = Box::new(ds::NDS::new(config));
+pub fn run_nds(config: ds::MemoryConfig, mute: bool, mic_source: MicSource, carts: Vec<(PathBuf, Option<PathBuf>)>) {
+    let cart_inserted = config.rom_path.is_some();
+    let render_scale = config.render_scale();
+    let nds = match ds::NDS::new(config) {
+        Ok(nds) => nds,
+        Err(e) => {
+            println!("couldn't start NDS: {}", e);
+            return;
+        }
+    };
+    let mut nds: Box<dyn Device> = Box::new(nds);
 
     let audio_stream = make_audio_stream(&mut nds, mute);
 
     let event_loop = EventLoop::new().expect("Failed to create event loop");
 
-    let mut app = App::new(nds, audio_stream);
+    let mut app = App::new(nds, audio_stream, mic_source);
+    app.carts = carts;
+    app.cart_inserted = cart_inserted;
+    app.render_scale = render_scale;
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
     event_loop.run_app(&mut app).unwrap();
 }
@@ -383,7 +522,7 @@ pub fn run_gba(config: gba::MemoryConfig, mute: bool) {
 
     let event_loop = EventLoop::new().expect("Failed to create event loop");
 
-    let mut app = App::new(gba, audio_stream);
+    let mut app = App::new(gba, audio_stream, MicSource::Silence);
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
     event_loop.run_app(&mut app).unwrap();
 }
@@ -0,0 +1,229 @@
+/// JIT write tracking.
+
+use std::ops::Range;
+
+/// Memory which repeats every `size` bytes from `start` to `end`.
+pub struct MirroredRAM {
+    pub start:  u32,
+    pub end:    u32,
+    pub size:   u32,
+}
+
+/// Size of the blocks that writes are tracked in.
+const PAGE_SIZE: u32 = 0x400;
+
+/// Dirty pages for a single JIT range.
+struct DirtyPages {
+    /// Set if the page has been written to.
+    pages:  Vec<bool>,
+    /// Pages that have been written to. May contain pages that have since been taken.
+    list:   Vec<usize>,
+}
+
+impl DirtyPages {
+    fn new(range: &Range<u32>) -> Self {
+        let page_count = (range.end - range.start + PAGE_SIZE - 1) / PAGE_SIZE;
+        Self {
+            pages:  vec![false; page_count as usize],
+            list:   Vec::new(),
+        }
+    }
+
+    fn mark(&mut self, page: usize) {
+        if !self.pages[page] {
+            self.pages[page] = true;
+            self.list.push(page);
+        }
+    }
+
+    /// Take the run of dirty pages containing a dirty page.
+    fn take(&mut self) -> Option<Range<usize>> {
+        while let Some(page) = self.list.pop() {
+            if !self.pages[page] {
+                continue;
+            }
+            let mut start = page;
+            while start > 0 && self.pages[start - 1] {
+                start -= 1;
+            }
+            let mut end = page + 1;
+            while end < self.pages.len() && self.pages[end] {
+                end += 1;
+            }
+            self.pages[start..end].fill(false);
+            return Some(start..end);
+        }
+        None
+    }
+}
+
+/// Tracks writes into address ranges that the JIT compiles code from.
+///
+/// The memory bus reports writes here, and the CPU thread takes the dirty
+/// ranges and invalidates any compiled code inside them.
+/// Writes are tracked per page, so that writes to data far from code
+/// don't invalidate it.
+pub struct JITTracker {
+    ranges: Vec<Range<u32>>,
+    dirty:  Vec<DirtyPages>,
+}
+
+impl JITTracker {
+    /// Create a new tracker for the ranges provided.
+    ///
+    /// If the JIT is disabled, this should be empty.
+    pub fn new(ranges: &[Range<u32>]) -> Self {
+        Self {
+            ranges: ranges.to_vec(),
+            dirty:  ranges.iter().map(DirtyPages::new).collect(),
+        }
+    }
+
+    /// Call when `size` bytes are written at `addr` in mirrored memory.
+    ///
+    /// Every mirror of the written address is marked,
+    /// since code may have been compiled from any of them.
+    #[inline]
+    pub fn write(&mut self, addr: u32, size: u32, mem: &MirroredRAM) {
+        let offset = (addr - mem.start) % mem.size;
+        for (range, dirty) in self.ranges.iter().zip(&mut self.dirty) {
+            let start = std::cmp::max(range.start, mem.start);
+            let end = std::cmp::min(range.end, mem.end);
+            if start >= end {
+                continue;
+            }
+            // Find the first mirror of the write inside the range.
+            let start_offset = (start - mem.start) % mem.size;
+            let mut mirror_addr = start + (offset + mem.size - start_offset) % mem.size;
+            while mirror_addr < end {
+                let first_page = (mirror_addr - range.start) / PAGE_SIZE;
+                let last_page = (std::cmp::min(mirror_addr + size, end) - 1 - range.start) / PAGE_SIZE;
+                for page in first_page..=last_page {
+                    dirty.mark(page as usize);
+                }
+                mirror_addr += mem.size;
+            }
+        }
+    }
+
+    /// Get a range that has been written to since it was last taken.
+    ///
+    /// Call repeatedly until None is returned to get all dirty ranges.
+    pub fn take_dirty(&mut self) -> Option<Range<u32>> {
+        self.ranges.iter().zip(&mut self.dirty).find_map(|(range, dirty)| {
+            dirty.take().map(|pages| {
+                let start = range.start + (pages.start as u32) * PAGE_SIZE;
+                let end = std::cmp::min(range.start + (pages.end as u32) * PAGE_SIZE, range.end);
+                start..end
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::mem::ram::RAM;
+    use arm::{Mem32, MemCycleType, ARM7TDMI, ARMDriver, ARMCore};
+
+    const TEST_RAM: MirroredRAM = MirroredRAM { start: 0x0300_0000, end: 0x0400_0000, size: 0x8000 };
+
+    struct TestMem {
+        ram:    RAM,
+        jit:    JITTracker,
+    }
+
+    impl Mem32 for TestMem {
+        type Addr = u32;
+
+        fn load_byte(&mut self, _cycle: MemCycleType, addr: Self::Addr) -> (u8, usize) {
+            (self.ram.read_byte(addr & 0x7FFF), 1)
+        }
+        fn store_byte(&mut self, _cycle: MemCycleType, addr: Self::Addr, data: u8) -> usize {
+            self.ram.write_byte(addr & 0x7FFF, data);
+            self.jit.write(addr, 1, &TEST_RAM);
+            1
+        }
+
+        fn load_halfword(&mut self, _cycle: MemCycleType, addr: Self::Addr) -> (u16, usize) {
+            (self.ram.read_halfword(addr & 0x7FFF), 1)
+        }
+        fn store_halfword(&mut self, _cycle: MemCycleType, addr: Self::Addr, data: u16) -> usize {
+            self.ram.write_halfword(addr & 0x7FFF, data);
+            self.jit.write(addr, 2, &TEST_RAM);
+            1
+        }
+
+        fn load_word(&mut self, _cycle: MemCycleType, addr: Self::Addr) -> (u32, usize) {
+            (self.ram.read_word(addr & 0x7FFF), 1)
+        }
+        fn store_word(&mut self, _cycle: MemCycleType, addr: Self::Addr, data: u32) -> usize {
+            self.ram.write_word(addr & 0x7FFF, data);
+            self.jit.write(addr, 4, &TEST_RAM);
+            1
+        }
+
+        fn clock(&mut self, _cycles: usize) -> Option<arm::ExternalException> {
+            None
+        }
+    }
+
+    #[test]
+    fn mirrored_writes() {
+        let mut jit = JITTracker::new(&[0x0300_0000..0x0300_8000, 0x0300_8000..0x0301_0000]);
+        jit.write(0x03FF_0010, 4, &TEST_RAM);
+        assert_eq!(jit.take_dirty(), Some(0x0300_0000..0x0300_0400));
+        assert_eq!(jit.take_dirty(), Some(0x0300_8000..0x0300_8400));
+        assert_eq!(jit.take_dirty(), None);
+    }
+
+    #[test]
+    fn dirty_pages() {
+        let mut jit = JITTracker::new(&[0x0300_0000..0x0300_8000]);
+        // Far apart writes stay separate.
+        jit.write(0x0300_0010, 4, &TEST_RAM);
+        jit.write(0x0300_7FFC, 4, &TEST_RAM);
+        let mut dirty = vec![jit.take_dirty().unwrap(), jit.take_dirty().unwrap()];
+        dirty.sort_by_key(|r| r.start);
+        assert_eq!(dirty, vec![0x0300_0000..0x0300_0400, 0x0300_7C00..0x0300_8000]);
+        assert_eq!(jit.take_dirty(), None);
+
+        // Adjacent pages are merged.
+        jit.write(0x0300_0800, 4, &TEST_RAM);
+        jit.write(0x0300_0400, 4, &TEST_RAM);
+        jit.write(0x0300_0C00, 4, &TEST_RAM);
+        assert_eq!(jit.take_dirty(), Some(0x0300_0400..0x0300_1000));
+        assert_eq!(jit.take_dirty(), None);
+    }
+
+    #[test]
+    fn recompile_rewritten_code() {
+        const MOV_R0_1: u32 = 0xE3A0_0001;
+        const MOV_R0_2: u32 = 0xE3A0_0002;
+        const LOOP: u32 = 0xEAFF_FFFD;  // B 0x0300_0000
+
+        let mem = Box::new(TestMem {
+            ram:    RAM::new(0x8000),
+            jit:    JITTracker::new(&[0x0300_0000..0x0300_8000]),
+        });
+        let mut cpu = ARM7TDMI::new(mem).enable_jit_in_ranges(vec![0x0300_0000..0x0300_8000]).build();
+        cpu.mut_mem().store_word(MemCycleType::N, 0x0300_0000, MOV_R0_1);
+        cpu.mut_mem().store_word(MemCycleType::N, 0x0300_0004, LOOP);
+        while cpu.mut_mem().jit.take_dirty().is_some() {}
+        cpu.do_branch(0x0300_0000);
+        for _ in 0..8 {
+            cpu.step();
+        }
+        assert_eq!(cpu.read_reg(0), 1);
+
+        // Rewrite the loop through a mirror.
+        cpu.mut_mem().store_word(MemCycleType::N, 0x0300_8000, MOV_R0_2);
+        while let Some(range) = cpu.mut_mem().jit.take_dirty() {
+            cpu.invalidate_jit_range(range);
+        }
+        for _ in 0..8 {
+            cpu.step();
+        }
+        assert_eq!(cpu.read_reg(0), 2);
+    }
+}
@@ -2,4 +2,5 @@
 #[macro_use]
 pub mod membusio;
 pub mod ram;
-pub mod bios;
\ No newline at end of file
+pub mod bios;
+pub mod jit;
\ No newline at end of file
@@ -5,6 +5,7 @@ pub mod mem;
 pub mod peripheral;
 pub mod video;
 pub mod resampler;
+pub mod swi;
 
 #[cfg(feature = "debug")]
 pub mod debug;
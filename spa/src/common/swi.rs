@@ -0,0 +1,528 @@
+/// Software interrupt routines shared by the emulated GBA and DS BIOSes.
+
+use arm::{
+    Mem32,
+    MemCycleType
+};
+use crate::utils::{
+    bytes::{u16, u32},
+    bits
+};
+
+/*** MATHS ***/
+pub fn divide(op1: u32, op2: u32) -> [u32; 3] {
+    let op1_signed = op1 as i32;
+    let op2_signed = op2 as i32;
+
+    let div_res = op1_signed / op2_signed;
+    let div_mod = op1_signed % op2_signed;
+    let abs_res = div_res.abs();
+    [div_res as u32, div_mod as u32, abs_res as u32]
+}
+
+pub fn sqrt(op: u32) -> u32 {
+    let sqrt = (op as f64).sqrt();
+    sqrt.floor() as u32
+}
+
+/*** MEMSET ***/
+pub fn cpu_set(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32, len_mode: u32) {
+    use crate::utils::bits::u32;
+    let mut count = len_mode & 0x1F_FFFF;
+    let fixed_src = u32::test_bit(len_mode, 24);
+    let use_word = u32::test_bit(len_mode, 26);
+
+    mem.clock(96);
+
+    if use_word {
+        if fixed_src {
+            let (data, read_cycles) = mem.load_word(arm::MemCycleType::N, src_addr);
+            mem.clock(read_cycles);
+            while count != 0 {
+                let write_cycles = mem.store_word(arm::MemCycleType::N, dst_addr, data);
+                dst_addr += 4;
+                count -= 1;
+                mem.clock(write_cycles + 7);
+            }
+        } else {
+            while count != 0 {
+                let (data, read_cycles) = mem.load_word(arm::MemCycleType::N, src_addr);
+                let write_cycles = mem.store_word(arm::MemCycleType::N, dst_addr, data);
+                src_addr += 4;
+                dst_addr += 4;
+                count -= 1;
+                mem.clock(read_cycles + write_cycles + 9);
+            }
+        }
+    } else {
+        mem.clock(2);
+        if fixed_src {
+            let (data, read_cycles) = mem.load_halfword(arm::MemCycleType::N, src_addr);
+            mem.clock(read_cycles);
+            while count != 0 {
+                let write_cycles = mem.store_halfword(arm::MemCycleType::N, dst_addr, data);
+                dst_addr += 2;
+                count -= 1;
+                mem.clock(write_cycles + 7);
+            }
+        } else {
+            while count != 0 {
+                let (data, read_cycles) = mem.load_halfword(arm::MemCycleType::N, src_addr);
+                let write_cycles = mem.store_halfword(arm::MemCycleType::N, dst_addr, data);
+                src_addr += 2;
+                dst_addr += 2;
+                count -= 1;
+                mem.clock(read_cycles + write_cycles + 9);
+            }
+        }
+    }
+}
+
+pub fn cpu_fast_set(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32, len_mode: u32) {
+    use crate::utils::bits::u32;
+    let mut count = len_mode & 0x1F_FFF8;
+    let fixed_src = u32::test_bit(len_mode, 24);
+
+    mem.clock(95);
+
+    if fixed_src {
+        let (data, read_cycles) = mem.load_word(arm::MemCycleType::N, src_addr);
+        mem.clock(read_cycles + 9);
+        while count != 0 {
+            // 8 words transferred at a time.
+            let mut cycle_type = arm::MemCycleType::N;
+            for _ in 0..8 {
+                let write_cycles = mem.store_word(cycle_type, dst_addr, data);
+                dst_addr += 4;
+                count -= 1;
+                mem.clock(write_cycles);
+                cycle_type = arm::MemCycleType::S;
+            }
+            mem.clock(6);
+        }
+    } else {
+        while count != 0 {
+            // 8 words transferred at a time.
+            let mut cycle_type = arm::MemCycleType::N;
+            for _ in 0..8 {
+                let (data, read_cycles) = mem.load_word(cycle_type, src_addr);
+                let write_cycles = mem.store_word(cycle_type, dst_addr, data);
+                src_addr += 4;
+                dst_addr += 4;
+                count -= 1;
+                mem.clock(read_cycles + write_cycles + 1);
+                cycle_type = arm::MemCycleType::S;
+            }
+        }
+    }
+}
+
+/*** DECOMPRESS ***/
+pub fn bit_unpack(mem: &mut impl Mem32<Addr = u32>, src_addr: u32, mut dst_addr: u32, info_ptr: u32) {
+    let (info_lo, cycles_lo) = mem.load_word(MemCycleType::N, info_ptr);
+    let (info_hi, cycles_hi) = mem.load_word(MemCycleType::S, info_ptr + 4);
+    mem.clock(82 + cycles_lo + cycles_hi);
+
+    // Unpack info
+    let len = u32::lo(info_lo) as u32;
+    let info_mid = u32::hi(info_lo);
+    let src_width = u16::lo(info_mid);
+    let dst_width = u16::hi(info_mid);
+    let data_offset = info_hi & 0x7FFF_FFFF;
+    let zero_data = bits::u32::test_bit(info_hi, 31);
+
+    let src_mask = (1 << src_width) - 1;
+
+    // Do unpack
+    let mut out = 0_u32;
+    let mut out_bit_idx = 0;
+    for i in 0..len {
+        let (data, cycles) = mem.load_byte(MemCycleType::N, src_addr + i);
+        mem.clock(196 + cycles);
+
+        for offset in (0..8).step_by(src_width.into()) {
+            let src_data = ((data >> offset) & src_mask) as u32;
+            let dst_data = if src_data != 0 || zero_data {
+                src_data + data_offset
+            } else {
+                src_data
+            };
+            
+            out = out | (dst_data << out_bit_idx);
+            out_bit_idx += dst_width;
+            if out_bit_idx >= 32 {
+                let cycles = mem.store_word(MemCycleType::N, dst_addr, out);
+                mem.clock(cycles);
+                dst_addr += 4;
+                out = 0;
+                out_bit_idx = 0;
+            }
+        }
+    }
+
+    if out_bit_idx > 0 {
+        let cycles = mem.store_word(MemCycleType::N, dst_addr, out);
+        mem.clock(cycles);
+    }
+}
+
+pub fn lz77_uncomp_byte(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let len = header >> 8;
+    let end = dst_addr + len;
+    'outer: loop {
+        // Process block.
+        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
+        mem.clock(cycles);
+        src_addr += 1;
+
+        for i in (0..8).rev() {
+            if bits::u8::test_bit(flags, i) {
+                // Compressed
+                let (disp_lo, load_cycles_lo) = mem.load_byte(MemCycleType::N, src_addr);
+                let (disp_hi, load_cycles_hi) = mem.load_byte(MemCycleType::S, src_addr + 1);
+                mem.clock(load_cycles_lo + load_cycles_hi);
+                src_addr += 2;
+
+                let displacement = u16::make(disp_lo & 0xF, disp_hi) + 1;
+                let mut copy_src_addr = dst_addr - (displacement as u32);
+                let copy_len = 3 + ((disp_lo >> 4) & 0xF);
+
+                for _ in 0..copy_len {
+                    let (data, load_cycles) = mem.load_byte(MemCycleType::N, copy_src_addr);
+                    copy_src_addr += 1;
+                    let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
+                    dst_addr += 1;
+                    mem.clock(load_cycles + store_cycles);
+                }
+            } else {
+                // Raw data
+                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
+                src_addr += 1;
+                let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
+                dst_addr += 1;
+                mem.clock(load_cycles + store_cycles);
+            }
+
+            if dst_addr >= end {
+                break 'outer;
+            }
+        }
+    }
+}
+
+pub fn lz77_uncomp_halfword(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let len = header >> 8;
+    let end = dst_addr + len;
+    let mut to_write = None;
+    'outer: loop {
+        // Process block.
+        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
+        mem.clock(cycles);
+        src_addr += 1;
+
+        for i in (0..8).rev() {
+            if bits::u8::test_bit(flags, i) {
+                // Compressed
+                let (disp_lo, load_cycles_lo) = mem.load_byte(MemCycleType::N, src_addr);
+                let (disp_hi, load_cycles_hi) = mem.load_byte(MemCycleType::S, src_addr + 1);
+                mem.clock(load_cycles_lo + load_cycles_hi);
+                src_addr += 2;
+
+                let displacement = u16::make(disp_lo & 0xF, disp_hi) + 1;
+                // Account for the byte waiting to be written.
+                let out_addr = if to_write.is_some() {dst_addr + 1} else {dst_addr};
+                let mut copy_src_addr = out_addr - (displacement as u32);
+                let copy_len = 3 + ((disp_lo >> 4) & 0xF);
+
+                for _ in 0..copy_len {
+                    // The source might be the byte that hasn't been written yet.
+                    let (data, load_cycles) = match to_write {
+                        Some(lo_byte) if copy_src_addr == dst_addr => (lo_byte, 0),
+                        _ => mem.load_byte(MemCycleType::N, copy_src_addr)
+                    };
+                    copy_src_addr += 1;
+
+                    let store_cycles = if let Some(lo_byte) = to_write.take() {
+                        let halfword = u16::make(data, lo_byte);
+                        let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
+                        dst_addr += 2;
+                        store_cycles
+                    } else {
+                        to_write = Some(data);
+                        0
+                    };
+
+                    mem.clock(load_cycles + store_cycles);
+                }
+            } else {
+                // Raw data
+                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
+                src_addr += 1;
+
+                let store_cycles = if let Some(lo_byte) = to_write.take() {
+                    let halfword = u16::make(data, lo_byte);
+                    let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
+                    dst_addr += 2;
+                    store_cycles
+                } else {
+                    to_write = Some(data);
+                    0
+                };
+                
+                mem.clock(load_cycles + store_cycles);
+            }
+
+            if dst_addr >= end {
+                break 'outer;
+            }
+        }
+    }
+}
+
+pub fn rl_uncomp_byte(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let len = header >> 8;
+    let end = dst_addr + len;
+    'outer: loop {
+        // Process block.
+        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
+        mem.clock(cycles);
+        src_addr += 1;
+
+        if bits::u8::test_bit(flags, 7) {
+            // Compressed
+            let data_len = (flags & 0x7F) + 3;
+            let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
+            src_addr += 1;
+            mem.clock(load_cycles);
+
+            for _ in 0..data_len {
+                let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
+                dst_addr += 1;
+                mem.clock(store_cycles);
+            }
+        } else {
+            // Uncompressed
+            let data_len = (flags & 0x7F) + 1;
+
+            for _ in 0..data_len {
+                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
+                src_addr += 1;
+                let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
+                dst_addr += 1;
+                mem.clock(load_cycles + store_cycles);
+            }
+        }
+
+        if dst_addr >= end {
+            break 'outer;
+        }
+    }
+}
+
+pub fn rl_uncomp_halfword(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let len = header >> 8;
+    let end = dst_addr + len;
+    let mut to_write = None;
+    'outer: loop {
+        // Process block.
+        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
+        mem.clock(cycles);
+        src_addr += 1;
+
+        if bits::u8::test_bit(flags, 7) {
+            // Compressed
+            let data_len = (flags & 0x7F) + 3;
+            let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
+            src_addr += 1;
+            mem.clock(load_cycles);
+
+            for _ in 0..data_len {
+                if let Some(lo_byte) = to_write.take() {
+                    let halfword = u16::make(data, lo_byte);
+                    let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
+                    dst_addr += 2;
+                    mem.clock(store_cycles);
+                } else {
+                    to_write = Some(data);
+                }
+            }
+        } else {
+            // Uncompressed
+            let data_len = (flags & 0x7F) + 1;
+
+            for _ in 0..data_len {
+                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
+                src_addr += 1;
+
+                let store_cycles = if let Some(lo_byte) = to_write.take() {
+                    let halfword = u16::make(data, lo_byte);
+                    let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
+                    dst_addr += 2;
+                    store_cycles
+                } else {
+                    to_write = Some(data);
+                    0
+                };
+
+                mem.clock(load_cycles + store_cycles);
+            }
+        }
+
+        if dst_addr >= end {
+            break 'outer;
+        }
+    }
+}
+
+pub fn huff_uncomp(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let data_size = header & 0xF;
+    let data_mask = (1 << data_size) - 1;
+    let len = header >> 8;
+    let end = dst_addr + len;
+
+    // Tree
+    let (tree_size, cycles) = mem.load_byte(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    let root_addr = src_addr + 1;
+    src_addr += ((tree_size as u32) + 1) * 2;
+
+    let mut out = 0_u32;
+    let mut out_bit_idx = 0;
+    let mut node_addr = root_addr;
+    let (mut node, cycles) = mem.load_byte(MemCycleType::N, node_addr);
+    mem.clock(cycles);
+    'outer: loop {
+        // Bitstream is read a word at a time, MSB first.
+        let (bitstream, cycles) = mem.load_word(MemCycleType::N, src_addr);
+        mem.clock(cycles);
+        src_addr += 4;
+
+        for i in (0..32).rev() {
+            let child_addr = (node_addr & 0xFFFF_FFFE) + ((node & 0x3F) as u32) * 2 + 2;
+            let (next_node_addr, is_data) = if bits::u32::test_bit(bitstream, i) {
+                (child_addr + 1, bits::u8::test_bit(node, 6))
+            } else {
+                (child_addr, bits::u8::test_bit(node, 7))
+            };
+            let (next_node, cycles) = mem.load_byte(MemCycleType::N, next_node_addr);
+            mem.clock(cycles);
+
+            if is_data {
+                out |= ((next_node as u32) & data_mask) << out_bit_idx;
+                out_bit_idx += data_size;
+                if out_bit_idx >= 32 {
+                    let cycles = mem.store_word(MemCycleType::N, dst_addr, out);
+                    mem.clock(cycles);
+                    dst_addr += 4;
+                    out = 0;
+                    out_bit_idx = 0;
+
+                    if dst_addr >= end {
+                        break 'outer;
+                    }
+                }
+                node_addr = root_addr;
+                let (root, cycles) = mem.load_byte(MemCycleType::N, node_addr);
+                mem.clock(cycles);
+                node = root;
+            } else {
+                node_addr = next_node_addr;
+                node = next_node;
+            }
+        }
+    }
+}
+
+pub fn diff_8_unfilter_byte(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let len = header >> 8;
+    let end = dst_addr + len;
+    let mut acc = 0_u8;
+    while dst_addr < end {
+        let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
+        src_addr += 1;
+        acc = acc.wrapping_add(data);
+        let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, acc);
+        dst_addr += 1;
+        mem.clock(load_cycles + store_cycles);
+    }
+}
+
+pub fn diff_8_unfilter_halfword(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let len = header >> 8;
+    let end = dst_addr + len;
+    let mut acc = 0_u8;
+    while dst_addr < end {
+        let (data_lo, load_cycles_lo) = mem.load_byte(MemCycleType::N, src_addr);
+        let (data_hi, load_cycles_hi) = mem.load_byte(MemCycleType::S, src_addr + 1);
+        src_addr += 2;
+        let lo = acc.wrapping_add(data_lo);
+        acc = lo.wrapping_add(data_hi);
+        let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, u16::make(acc, lo));
+        dst_addr += 2;
+        mem.clock(load_cycles_lo + load_cycles_hi + store_cycles);
+    }
+}
+
+pub fn diff_16_unfilter(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
+    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+    mem.clock(cycles);
+    src_addr += 4;
+
+    let len = header >> 8;
+    let end = dst_addr + len;
+    let mut acc = 0_u16;
+    while dst_addr < end {
+        let (data, load_cycles) = mem.load_halfword(MemCycleType::N, src_addr);
+        src_addr += 2;
+        acc = acc.wrapping_add(data);
+        let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, acc);
+        dst_addr += 2;
+        mem.clock(load_cycles + store_cycles);
+    }
+}
+
+/*** CRC ***/
+
+/// CRC-16 (polynomial 0xA001) used by the DS BIOS and firmware.
+pub fn crc16(initial: u16, data: &[u8]) -> u16 {
+    let mut crc = initial;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
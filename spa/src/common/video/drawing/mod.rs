@@ -60,7 +60,9 @@ pub struct SoftwareRenderer {
     mode:           RendererMode,
     h_res:          usize,
     palette_cache:  PaletteCache,
+    /// 3D layer, at `scale_3d` times the native resolution.
     pub frame_3d:   Vec<ColourAlpha>,
+    scale_3d:       usize,
 }
 
 impl SoftwareRenderer {
@@ -78,9 +80,23 @@ impl SoftwareRenderer {
             h_res:          h_res,
             palette_cache:  PaletteCache::new(),
             frame_3d,
+            scale_3d:       1,
         }
     }
 
+    /// Set the resolution multiplier of the 3D layer (NDS A only).
+    pub fn set_3d_scale(&mut self, scale: usize) {
+        self.scale_3d = scale;
+        self.frame_3d = vec![ColourAlpha::default(); 256 * 192 * scale * scale];
+    }
+
+    /// Get the 3D layer pixel at native coordinates.
+    /// 
+    /// If the 3D layer is upscaled, the top-left sub-pixel is used.
+    pub fn native_3d_pixel(&self, x: usize, y: usize) -> ColourAlpha {
+        self.frame_3d[(x + y * 256 * self.scale_3d) * self.scale_3d]
+    }
+
     /// Create caches from dirty memory.
     pub fn setup_caches<V: VRAM2D>(&mut self, mem: &mut VideoMemory<V>) {
         // Refresh palette cache
@@ -127,7 +143,28 @@ impl SoftwareRenderer {
             self.draw_obj_line(mem, &mut obj_line, line);
         }
         for x in 0..self.h_res {
-            target[x] = self.eval_pixel(mem, &obj_line[x], &bg_data, x as u8, line);
+            target[x] = self.eval_pixel(mem, &obj_line[x], &bg_data, x as u8, line, (0, 0));
+        }
+    }
+
+    /// Draw a line, sampling the 3D layer at its full resolution.
+    /// 
+    /// The target is `scale_3d` lines of `scale_3d` times the native width.
+    /// 2D layers are stretched.
+    pub fn draw_upscaled<V: VRAM2D>(&self, mem: &VideoMemory<V>, target: &mut [Colour], line: u8) {
+        let bg_data = mem.registers.nds_bg_data_for_mode();
+
+        let mut obj_line = vec![ObjectPixelWindow::new(); self.h_res];
+        if mem.registers.is_obj_enabled() {
+            self.draw_obj_line(mem, &mut obj_line, line);
+        }
+        let scale = self.scale_3d;
+        for (sub_y, target_line) in target.chunks_exact_mut(self.h_res * scale).enumerate() {
+            for x in 0..self.h_res {
+                for sub_x in 0..scale {
+                    target_line[x * scale + sub_x] = self.eval_pixel(mem, &obj_line[x], &bg_data, x as u8, line, (sub_x, sub_y));
+                }
+            }
         }
     }
 
@@ -608,7 +645,8 @@ impl SoftwareRenderer {
 // Internal: draw modes
 impl SoftwareRenderer {
 
-    fn eval_pixel<V: VRAM2D>(&self, mem: &VideoMemory<V>, obj: &ObjectPixelWindow, bg_data: &[BackgroundData], x: u8, y: u8) -> Colour {
+    /// `sub_pixel` selects the 3D layer sample inside the native pixel.
+    fn eval_pixel<V: VRAM2D>(&self, mem: &VideoMemory<V>, obj: &ObjectPixelWindow, bg_data: &[BackgroundData], x: u8, y: u8, sub_pixel: (usize, usize)) -> Colour {
         let colour_window_mask = mem.registers.colour_window_mask();
         let inside_colour_window = Self::window_pixel(&mem.registers, colour_window_mask, obj.window, x, y);
         let alpha_blend = mem.registers.colour_effect() == ColourEffect::AlphaBlend;
@@ -635,7 +673,7 @@ impl SoftwareRenderer {
             }
             for bg in bg_data {
                 if bg.priority == priority {
-                    match self.bg_pixel(mem, bg, obj.window, x, y) {
+                    match self.bg_pixel(mem, bg, obj.window, x, y, sub_pixel) {
                         BGPixel::_2D(colour) => if inside_colour_window || target_1.is_some() {
                             // Target 1 is only set outside the colour window if BG3D is of higher priority and is blending.
                             match Self::colour_effect(&mem.registers, bg.blend_mask, colour, target_1, BlendType::None) {
@@ -671,7 +709,7 @@ impl SoftwareRenderer {
     }
 
     /// Find a pixel value for a particular background.
-    fn bg_pixel<V: VRAM2D>(&self, mem: &VideoMemory<V>, bg: &BackgroundData, obj_window: bool, x: u8, y: u8) -> BGPixel {
+    fn bg_pixel<V: VRAM2D>(&self, mem: &VideoMemory<V>, bg: &BackgroundData, obj_window: bool, x: u8, y: u8, sub_pixel: (usize, usize)) -> BGPixel {
         use BGPixel::*;
         if !Self::window_pixel(&mem.registers, bg.window_mask, obj_window, x, y) {
             return None;
@@ -687,7 +725,8 @@ impl SoftwareRenderer {
                 let scroll_x = u16::sign_extend(d.scroll_x & 0x1FF, 9);
                 let scrolled_x = (x as i16).wrapping_add(scroll_x) as u16;
                 if scrolled_x < 0x100 {
-                    let idx = (scrolled_x as usize) + (y as usize) * 256;
+                    let scale = self.scale_3d;
+                    let idx = ((scrolled_x as usize) * scale + sub_pixel.0) + ((y as usize) * scale + sub_pixel.1) * 256 * scale;
                     if self.frame_3d[idx].alpha == 0 {
                         None
                     } else {
@@ -87,6 +87,11 @@ impl DSAudio {
         self.sample_sender = Some(sample_sender);
     }
 
+    /// Cycles until the next output sample is generated.
+    pub fn cycles_to_next_sample(&self) -> usize {
+        CYCLES_PER_SAMPLE - self.cycle_count
+    }
+
     /// Advance the channels and generate audio samples.
     /// 
     /// Returns a bit array of the channels that requested DMA,
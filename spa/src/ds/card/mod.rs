@@ -64,19 +64,51 @@ bitflags!{
 /// We read 16kB at a time from disk.
 const ROM_BUFFER_SIZE: u32 = 16 * 1024;
 
+/// Size of the KEY1 seed table, in words.
+pub const KEY1_TABLE_SIZE: usize = 0x412;
+
+const SECURE_AREA_START: u32 = 0x4000;
+/// Size of the KEY1 encrypted part of the secure area.
+const SECURE_AREA_ENCRYPTED_SIZE: u32 = 0x800;
+
+const ENCRY_OBJ: u64 = 0x6A624F7972636E65;
+const DESTROYED: u64 = 0xE7FFDEFFE7FFDEFF;
+
+/// Load the KEY1 seed table from a standalone key file.
+/// 
+/// This is the same data as found in the ARM7 BIOS at 0x30.
+pub fn load_key1_table(key_path: &Path) -> Result<Vec<u32>> {
+    let data = std::fs::read(key_path)?;
+    if data.len() < KEY1_TABLE_SIZE * 4 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "KEY1 table too small"));
+    }
+    Ok(data.chunks_exact(4)
+        .take(KEY1_TABLE_SIZE)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
 /// DS Card attached to IO ports.
 pub struct DSCardIO {
-    card:       Arc<Mutex<DSCard>>
+    card:       Arc<Mutex<DSCard>>,
+    /// 0 for ARM9, 1 for ARM7.
+    cpu:        usize,
 }
 
 impl DSCardIO {
-    pub fn new(rom_path: &Path, save_path: Option<PathBuf>, key1: Vec<u32>) -> Result<(Self, Self)> {
+    /// Without a KEY1 table, the card can only be used with fast boot,
+    /// and the secure area is read as-is.
+    /// 
+    /// If `rom_path` is None, the slot starts empty.
+    pub fn new(rom_path: Option<&Path>, save_path: Option<PathBuf>, key1: Option<Vec<u32>>) -> Result<(Self, Self)> {
         let card = DSCard::new(rom_path, save_path, key1)?;
         let card_arc = Arc::new(Mutex::new(card));
         Ok((DSCardIO{
-            card: card_arc.clone()
+            card: card_arc.clone(),
+            cpu:  0,
         }, DSCardIO{
-            card: card_arc
+            card: card_arc,
+            cpu:  1,
         }))
     }
 
@@ -85,6 +117,16 @@ impl DSCardIO {
     pub fn clock(&mut self, cycles: usize) -> (Interrupts, bool) {
         self.card.lock().clock(cycles)
     }
+
+    /// To be called by both processors, whether or not they have access rights.
+    /// Returns an interrupt if the card was inserted or removed.
+    pub fn take_detect_interrupt(&mut self) -> Interrupts {
+        if std::mem::replace(&mut self.card.lock().detect_interrupt[self.cpu], false) {
+            Interrupts::CARD_IRQ
+        } else {
+            Interrupts::empty()
+        }
+    }
     
     /// To be called by only the ARM7 processor.
     pub fn flush_save(&mut self) {
@@ -109,6 +151,30 @@ impl DSCardIO {
     pub fn fast_boot(&mut self, rom_ctrl_init: u32) {
         self.card.lock().fast_boot(rom_ctrl_init);
     }
+
+    pub fn card_slot(&self) -> CardSlot {
+        CardSlot {
+            card: self.card.clone()
+        }
+    }
+}
+
+/// Used to insert and remove cards while running.
+#[derive(Clone)]
+pub struct CardSlot {
+    card:       Arc<Mutex<DSCard>>
+}
+
+impl CardSlot {
+    /// Insert a new card, replacing any current card.
+    pub fn insert(&self, rom_path: &Path, save_path: Option<PathBuf>) -> Result<()> {
+        self.card.lock().insert(rom_path, save_path)
+    }
+
+    /// Remove the current card.
+    pub fn eject(&self) {
+        self.card.lock().eject();
+    }
 }
 
 impl MemInterface32 for DSCardIO {
@@ -148,8 +214,11 @@ struct DSCard {
     rom_control_lo: RomControlLo,
     rom_control_hi: RomControlHi,
 
-    key1_instr: Vec<u32>, // 0x1048 byte key
-    key1_secure: Vec<u32>, // 0x1048 byte key
+    key1_table: Option<Vec<u32>>,
+    key1_instr: Option<Vec<u32>>, // 0x1048 byte key
+    key1_secure: Option<Vec<u32>>, // 0x1048 byte key
+    /// Set once a KEY1 command has been ignored for lack of a table.
+    key1_warned: bool,
     rom_id: [u8; 4],
 
     command: [u8; 8],
@@ -169,41 +238,15 @@ struct DSCard {
 
     dma_ready: bool,
     interrupt: bool,
+    /// Card was inserted or removed. Set for each CPU.
+    detect_interrupt: [bool; 2],
 }
 
 impl DSCard {
-    fn new(rom_path: &Path, save_path: Option<PathBuf>, key1: Vec<u32>) -> Result<Self> {
-        let mut buffer = vec![0xFF; ROM_BUFFER_SIZE as usize];
-
-        let rom_file: Option<File> = {
-            let mut rom_file = File::open(rom_path)?;
-            rom_file.seek(SeekFrom::Start(0))?;
-            rom_file.read_exact(&mut buffer)?;
-            Some(rom_file)
-        };
-
-        // Game ID code.
-        let game_id = u32::from_le_bytes([buffer[0xC], buffer[0xD], buffer[0xE], buffer[0xF]]);
-        let key1_instr = dscrypto::key1::init(game_id, &key1, 2, 2);
-        let key1_secure = dscrypto::key1::init(game_id, &key1, 2, 3);
-
-        // ROM ID
-        let rom_id = if let Some(rom_file) = rom_file.as_ref() {
-            let unit_code = buffer[0x12];
-            let dsi = (unit_code & 2) == 2;
-            let file_size_mb = rom_file.metadata().unwrap().len() / (1024 * 1024);
-            let id_size = (file_size_mb as u8) - 1;
-            let id_hi_flags =
-                if id_size >= 0x7F {0x80} else {0x00} |
-                if dsi {0xC0} else {0x00};
-            [0xC2, id_size, 0x00, id_hi_flags]
-        } else {
-            [0xFF, 0xFF, 0xFF, 0xFF]
-        };
-
-        Ok(Self {
-            rom_file:       rom_file,
-            rom_buffer:     buffer,
+    fn new(rom_path: Option<&Path>, save_path: Option<PathBuf>, key1: Option<Vec<u32>>) -> Result<Self> {
+        let mut card = Self {
+            rom_file:       None,
+            rom_buffer:     vec![0xFF; ROM_BUFFER_SIZE as usize],
             buffer_tag:     0,
             read_addr:      0,
             secure_block:   0,
@@ -213,9 +256,11 @@ impl DSCard {
             rom_control_lo: RomControlLo::default(),
             rom_control_hi: RomControlHi::default(),
 
-            key1_instr,
-            key1_secure,
-            rom_id,
+            key1_table:     key1,
+            key1_instr:     None,
+            key1_secure:    None,
+            key1_warned:    false,
+            rom_id:         [0xFF, 0xFF, 0xFF, 0xFF],
 
             command: [0; 8],
             seed_0: [0xE8, 0xE0, 0x6D, 0xC5, 0x58, 0, 0, 0],
@@ -231,7 +276,98 @@ impl DSCard {
 
             dma_ready: false,
             interrupt: false,
-        })
+            detect_interrupt: [false; 2],
+        };
+        if let Some(rom_path) = rom_path {
+            card.load_rom(rom_path)?;
+        }
+        Ok(card)
+    }
+
+    /// Open the ROM file and read its header.
+    fn load_rom(&mut self, rom_path: &Path) -> Result<()> {
+        let mut buffer = vec![0xFF; ROM_BUFFER_SIZE as usize];
+        let mut rom_file = File::open(rom_path)?;
+        rom_file.seek(SeekFrom::Start(0))?;
+        rom_file.read_exact(&mut buffer)?;
+
+        // Game ID code.
+        let game_id = u32::from_le_bytes([buffer[0xC], buffer[0xD], buffer[0xE], buffer[0xF]]);
+        self.key1_instr = self.key1_table.as_ref().map(|key1| dscrypto::key1::init(game_id, key1, 2, 2));
+        self.key1_secure = self.key1_table.as_ref().map(|key1| dscrypto::key1::init(game_id, key1, 2, 3));
+        if self.key1_table.is_none() && Self::has_encrypted_secure_area(&buffer, &mut rom_file)? {
+            println!("Secure area is encrypted, and there is no KEY1 table to decrypt it: the game might not boot. Provide the ARM7 BIOS or a key file.");
+        }
+
+        // ROM ID
+        let unit_code = buffer[0x12];
+        let dsi = (unit_code & 2) == 2;
+        // Chip size in MB, rounded up to a power of two.
+        let file_size_mb = rom_file.metadata()?.len().div_ceil(1024 * 1024)
+            .max(1)
+            .checked_next_power_of_two()
+            .unwrap_or(u64::MAX);
+        let id_size = (std::cmp::min(file_size_mb, 0x100) - 1) as u8;
+        let id_hi_flags =
+            if id_size >= 0x7F {0x80} else {0x00} |
+            if dsi {0xC0} else {0x00};
+        self.rom_id = [0xC2, id_size, 0x00, id_hi_flags];
+
+        self.rom_file = Some(rom_file);
+        self.rom_buffer = buffer;
+        self.buffer_tag = 0;
+        Ok(())
+    }
+
+    /// Check if the ARM9 code starts in the secure area, and it is still encrypted.
+    /// Decrypted dumps start it with the destroyed ID.
+    fn has_encrypted_secure_area(header: &[u8], rom_file: &mut File) -> Result<bool> {
+        let arm9_rom_offset = u32::from_le_bytes(header[0x20..0x24].try_into().unwrap());
+        if arm9_rom_offset < SECURE_AREA_START || arm9_rom_offset >= 2 * SECURE_AREA_START {
+            return Ok(false);
+        }
+        let mut id = [0; 8];
+        rom_file.seek(SeekFrom::Start(SECURE_AREA_START as u64))?;
+        rom_file.read_exact(&mut id)?;
+        let id = u64::from_le_bytes(id);
+        Ok(id != DESTROYED && id != ENCRY_OBJ)
+    }
+
+    /// Swap in a new card. The old save is flushed first.
+    fn insert(&mut self, rom_path: &Path, save_path: Option<PathBuf>) -> Result<()> {
+        self.spi.flush();
+        self.load_rom(rom_path)?;
+        self.spi = SPI::new(save_path);
+        self.reset_state();
+        Ok(())
+    }
+
+    /// Leave the slot empty. The save is flushed first.
+    fn eject(&mut self) {
+        self.spi.flush();
+        self.rom_file = None;
+        self.rom_buffer.fill(0xFF);
+        self.buffer_tag = 0;
+        self.key1_instr = None;
+        self.key1_secure = None;
+        self.rom_id = [0xFF, 0xFF, 0xFF, 0xFF];
+        self.spi = SPI::new(None);
+        self.reset_state();
+    }
+
+    /// A newly inserted or removed card starts from power-on.
+    fn reset_state(&mut self) {
+        self.read_addr = 0;
+        self.secure_block = 0;
+        self.key2_0 = 0;
+        self.key2_1 = 0;
+        self.transfer_count = 0;
+        self.transfer_cycles = 0;
+        self.cmd_encrypt_mode = CommandEncryptMode::None;
+        self.data_state = DSCardDataState::Dummy;
+        self.rom_control_hi.remove(RomControlHi::START_STAT | RomControlHi::DATA_STATUS);
+        self.dma_ready = false;
+        self.detect_interrupt = [true; 2];
     }
 
     fn clock(&mut self, cycles: usize) -> (Interrupts, bool) {
@@ -259,8 +395,48 @@ impl DSCard {
             rom_file.seek(SeekFrom::Start(from_addr as u64)).unwrap();
             rom_file.read(into_buffer).unwrap();
         } else {
-            // TODO: handle this more gracefully...
-            panic!("no cart loaded");
+            // Empty slot.
+            into_buffer.fill(0xFF);
+        }
+        self.decrypt_secure_area(from_addr, into_buffer);
+    }
+
+    /// When fast booting, the BIOS isn't there to decrypt the secure area.
+    /// 
+    /// If the secure area in `buffer` is encrypted, and the KEY1 table is available,
+    /// decrypt it. ROM dumps with a decrypted secure area are left as-is.
+    fn decrypt_secure_area(&self, from_addr: u32, buffer: &mut [u8]) {
+        let end_addr = from_addr + buffer.len() as u32;
+        if from_addr > SECURE_AREA_START || end_addr < SECURE_AREA_START + SECURE_AREA_ENCRYPTED_SIZE {
+            return;
+        }
+        let (key1_instr, key1_secure) = match (&self.key1_instr, &self.key1_secure) {
+            (Some(key1_instr), Some(key1_secure)) => (key1_instr, key1_secure),
+            _ => return
+        };
+        let start = (SECURE_AREA_START - from_addr) as usize;
+        let secure_area = &mut buffer[start..(start + SECURE_AREA_ENCRYPTED_SIZE as usize)];
+
+        let id = u64::from_le_bytes(secure_area[0..8].try_into().unwrap());
+        if id == DESTROYED || id == ENCRY_OBJ {
+            // Already decrypted.
+            return;
+        }
+        let block = dscrypto::key1::decrypt(id, key1_instr);
+        if dscrypto::key1::decrypt(block, key1_secure) != ENCRY_OBJ {
+            println!("Could not decrypt secure area: wrong KEY1 table?");
+            return;
+        }
+
+        for (i, chunk) in secure_area.chunks_exact_mut(8).enumerate() {
+            let encrypted_block = u64::from_le_bytes((&*chunk).try_into().unwrap());
+            let decrypted_block = if i == 0 {
+                // The BIOS destroys the ID after checking it.
+                DESTROYED
+            } else {
+                dscrypto::key1::decrypt(encrypted_block, key1_secure)
+            };
+            chunk.copy_from_slice(&decrypted_block.to_le_bytes());
         }
     }
 
@@ -584,7 +760,16 @@ impl DSCard {
 
     fn key1_command(&mut self) -> DSCardDataState {
         use DSCardDataState::*;
-        let command = dscrypto::key1::decrypt(u64::from_le_bytes(self.command), &self.key1_instr);
+        let Some(key1_instr) = self.key1_instr.as_ref() else {
+            // Booting through the BIOS always provides the table,
+            // so this is only reached after fast booting, when the secure area has already been read.
+            if !self.key1_warned {
+                println!("Can't decrypt KEY1 card commands: no KEY1 table. Provide the ARM7 BIOS or a key file.");
+                self.key1_warned = true;
+            }
+            return if self.cmd_encrypt_mode == CommandEncryptMode::Key2 {Key2Dummy} else {Dummy};
+        };
+        let command = dscrypto::key1::decrypt(u64::from_le_bytes(self.command), key1_instr);
         //println!("got K1 command {:X} => {:X}", u64::from_le_bytes(self.command), command);
         let key2_dummy_count = (self.rom_control_lo & RomControlLo::KEY1_GAP1_LEN).bits();
         // TODO: check bit size.
@@ -717,8 +902,10 @@ impl DSCard {
     /// 
     /// Many ROM dumps decrypt this area.
     fn encrypt_secure_area(&mut self) {
-        const ENCRY_OBJ: u64 = 0x6A624F7972636E65;
-        const DESTROYED: u64 = 0xE7FFDEFFE7FFDEFF;
+        let (key1_instr, key1_secure) = match (&self.key1_instr, &self.key1_secure) {
+            (Some(key1_instr), Some(key1_secure)) => (key1_instr, key1_secure),
+            _ => return
+        };
 
         // Re-encrypt the first 2kB.
         let id = u64::from_le_bytes([
@@ -754,10 +941,10 @@ impl DSCard {
                     self.rom_buffer[addr_offset + 7]
                 ]);
                 let encrypted_block = if i == 0 {
-                    let block = dscrypto::key1::encrypt(decrypted_block, &self.key1_secure);
-                    dscrypto::key1::encrypt(block, &self.key1_instr)
+                    let block = dscrypto::key1::encrypt(decrypted_block, key1_secure);
+                    dscrypto::key1::encrypt(block, key1_instr)
                 } else {
-                    dscrypto::key1::encrypt(decrypted_block, &self.key1_secure)
+                    dscrypto::key1::encrypt(decrypted_block, key1_secure)
                 };
                 for (n, b) in encrypted_block.to_le_bytes().iter().enumerate() {
                     self.rom_buffer[addr_offset + n] = *b;
@@ -810,3 +997,66 @@ impl DSCard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send an unencrypted command and start a transfer of `block_size` (ROMCTRL bits 24-26).
+    fn start_command(card: &mut DSCardIO, command: u8, block_size: u32) {
+        card.write_word(0x0400_01A8, command as u32);
+        card.write_word(0x0400_01AC, 0);
+        card.write_word(0x0400_01A4, 0x8000_0000 | (block_size << 24));
+    }
+
+    fn temp_rom(name: &str, size: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("spa_card_{}_{}.nds", name, std::process::id()));
+        std::fs::write(&path, vec![0; size]).unwrap();
+        path
+    }
+
+    #[test]
+    fn empty_slot_reads() {
+        let (mut card, _) = DSCardIO::new(None, None, None).unwrap();
+        assert_eq!(card.get_rom_id(), 0xFFFF_FFFF);
+
+        start_command(&mut card, 0x90, 7);
+        assert_eq!(card.read_word(0x0410_0010), 0xFFFF_FFFF);
+
+        start_command(&mut card, 0x00, 1);
+        for _ in 0..0x80 {
+            assert_eq!(card.read_word(0x0410_0010), 0xFFFF_FFFF);
+        }
+
+        let mut data = vec![0; 0x200];
+        card.load_data(0x8000, &mut data);
+        assert!(data.iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn small_rom_id() {
+        let small = temp_rom("small", 0x1_0000);
+        let medium = temp_rom("medium", 0x18_0000);
+        let (small_card, _) = DSCardIO::new(Some(&small), None, None).unwrap();
+        let (medium_card, _) = DSCardIO::new(Some(&medium), None, None).unwrap();
+        std::fs::remove_file(&small).unwrap();
+        std::fs::remove_file(&medium).unwrap();
+
+        // At least 1MB, rounded up to a power of two.
+        assert_eq!(small_card.get_rom_id().to_le_bytes(), [0xC2, 0x00, 0x00, 0x00]);
+        assert_eq!(medium_card.get_rom_id().to_le_bytes(), [0xC2, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn detect_interrupt() {
+        let (mut card_9, mut card_7) = DSCardIO::new(None, None, None).unwrap();
+        assert_eq!(card_9.take_detect_interrupt(), Interrupts::empty());
+
+        card_9.card_slot().eject();
+        // Both CPUs see the interrupt, whichever has access to the card.
+        assert_eq!(card_9.take_detect_interrupt(), Interrupts::CARD_IRQ);
+        assert_eq!(card_9.take_detect_interrupt(), Interrupts::empty());
+        assert_eq!(card_7.take_detect_interrupt(), Interrupts::CARD_IRQ);
+        assert_eq!(card_9.clock(1).0, Interrupts::empty());
+    }
+}
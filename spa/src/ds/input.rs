@@ -8,7 +8,13 @@ pub struct UserInput {
     pub buttons:    Buttons,
     pub ds_buttons: DSButtons,
 
-    pub touchscreen:    Option<(f64, f64)>
+    pub touchscreen:    Option<(f64, f64)>,
+    /// New time for the real-time clock. Only sent once.
+    pub rtc_time:       Option<chrono::NaiveDateTime>,
+    /// Microphone samples since the last frame.
+    pub microphone:     Vec<i16>,
+    /// Guitar Grip frets that are held down.
+    pub guitar_frets:   u8,
 }
 
 impl Default for UserInput {
@@ -18,6 +24,9 @@ impl Default for UserInput {
             ds_buttons: DSButtons::from_bits_truncate(0x4B),
 
             touchscreen:    None,
+            rtc_time:       None,
+            microphone:     Vec::new(),
+            guitar_frets:   0,
         }
     }
 }
@@ -41,6 +50,25 @@ impl UserInput {
         }
     }
 
+    pub fn set_guitar_fret(&mut self, fret: crate::GuitarFret, pressed: bool) {
+        use crate::GuitarFret::*;
+        let bit = match fret {
+            Green   => 0x40,
+            Red     => 0x20,
+            Yellow  => 0x10,
+            Blue    => 0x08,
+        };
+        if pressed {
+            self.guitar_frets |= bit;
+        } else {
+            self.guitar_frets &= !bit;
+        }
+    }
+
+    pub fn set_lid_closed(&mut self, closed: bool) {
+        self.ds_buttons.set(DSButtons::HINGE_DOWN, closed);
+    }
+
     pub fn set_touchscreen(&mut self, coords: Option<(f64, f64)>) {
         self.ds_buttons.set(DSButtons::PEN_DOWN, !coords.is_some());
         self.touchscreen = coords;
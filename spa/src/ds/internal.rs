@@ -16,7 +16,7 @@ use crate::{
     common::mem::ram::RAM,
 };
 use super::{
-    memory::DS9MemoryBus,
+    memory::{DS9MemoryBus, DSBIOSMem, ARM9_INTR_WAIT},
     video::Renderer,
     cache::*
 };
@@ -58,6 +58,9 @@ pub struct DS9InternalMem<R: Renderer> {
     instr_tcm_region:   MemRegion,
 
     data_tcm_base: u32,
+
+    /// BIOS routine requested by an emulated SWI.
+    bios_call: Option<u32>,
 }
 
 impl<R: Renderer> DS9InternalMem<R> {
@@ -91,6 +94,8 @@ impl<R: Renderer> DS9InternalMem<R> {
             instr_tcm_region: MemRegion::default(),
 
             data_tcm_base: 0,
+
+            bios_call: None,
         }
     }
 
@@ -99,6 +104,12 @@ impl<R: Renderer> DS9InternalMem<R> {
         self.write_tcm_settings(0x0080_000A, 0);
         self.write_control_reg(0x0001_2078);
     }
+
+    /// Get the BIOS routine that an emulated SWI requested, if any.
+    /// The CPU should call it with `call_bios_routine`.
+    pub fn take_bios_call(&mut self) -> Option<u32> {
+        self.bios_call.take()
+    }
 }
 
 impl<R: Renderer> Mem32 for DS9InternalMem<R> {
@@ -601,6 +612,20 @@ impl MemRegion {
     }
 }
 
+impl<R: Renderer> DSBIOSMem for DS9InternalMem<R> {
+    fn halt(&mut self) {
+        self.wait_for_interrupt();
+    }
+
+    fn irq_check_addr(&self) -> u32 {
+        self.data_tcm_base + DATA_TCM_SIZE - 8
+    }
+
+    fn request_intr_wait(&mut self) {
+        self.bios_call = Some(ARM9_INTR_WAIT);
+    }
+}
+
 impl<R: Renderer> ARM9Mem for DS9InternalMem<R> {
     fn mut_cp15<'a>(&'a mut self) -> &'a mut dyn CoprocV5 {
         self
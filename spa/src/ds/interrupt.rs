@@ -33,6 +33,9 @@ bitflags!{
         const V_COUNTER = u32::bit(2);
         const H_BLANK   = u32::bit(1);
         const V_BLANK   = u32::bit(0);
+
+        /// Interrupts which can wake the system from sleep.
+        const SLEEP_WAKE = Self::SCREEN_UNFOLD.bits | Self::GAME_PAK.bits | Self::KEYPAD.bits | Self::RTC.bits;
     }
 }
 
@@ -40,6 +43,8 @@ pub struct InterruptControl {
     interrupt_enable:   Interrupts,
     interrupt_req:      Interrupts,
     interrupt_master:   bool,
+    /// Requests made since sleep mode was entered.
+    wake_req:           Interrupts,
     name: String
 }
 
@@ -49,6 +54,7 @@ impl InterruptControl {
             interrupt_enable:   Interrupts::default(),
             interrupt_req:      Interrupts::default(),
             interrupt_master:   false,
+            wake_req:           Interrupts::default(),
             name: name.to_string()
         }
     }
@@ -56,6 +62,19 @@ impl InterruptControl {
     /// Set from other devices when an interrupt should happen.
     pub fn interrupt_request(&mut self, interrupts: Interrupts) {
         self.interrupt_req.insert(interrupts);
+        self.wake_req.insert(interrupts);
+    }
+
+    /// Start tracking requests which can wake the system.
+    /// Requests which are already pending are ignored.
+    pub fn start_sleep(&mut self) {
+        self.wake_req = Interrupts::empty();
+    }
+
+    /// Check if an enabled wake source has been requested since sleep started,
+    /// ignoring the master enable.
+    pub fn wake_pending(&self) -> bool {
+        self.interrupt_enable.intersects(self.wake_req & Interrupts::SLEEP_WAKE)
     }
 
     /// Check if an IRQ should be sent to the CPU.
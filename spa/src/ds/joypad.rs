@@ -33,6 +33,10 @@ impl DSJoypad {
     pub fn set_all_buttons(&mut self, buttons: DSButtons) {
         self.buttons_pressed = buttons;
     }
+
+    pub fn lid_closed(&self) -> bool {
+        self.buttons_pressed.contains(DSButtons::HINGE_DOWN)
+    }
 }
 
 impl MemInterface16 for DSJoypad {
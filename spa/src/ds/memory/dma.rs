@@ -42,6 +42,8 @@ impl Control {
 }
 
 const WORD_COUNT_MASK: u32 = 0x1F_FFFF;
+/// Number of words transferred each time the geometry FIFO requests data.
+const GEOM_FIFO_BURST_LEN: u32 = 112;
 
 /// The DMA Channels.
 pub struct DMA {
@@ -231,6 +233,7 @@ pub struct DMAChannel {
     current_src_addr:   u32,
     current_dst_addr:   u32,
     current_count:      u32,
+    burst_count:        u32,
 
     // Channel-specific data (will remain const)
     interrupt:          Interrupts,
@@ -248,6 +251,7 @@ impl DMAChannel {
             current_src_addr:   0,
             current_dst_addr:   0,
             current_count:      0,
+            burst_count:        0,
 
             interrupt:          interrupt,
         }
@@ -288,6 +292,21 @@ impl DMAChannel {
         (self.control & Control::SHOULD_START) == Control::START_DS_CART
     }
 
+    /// Call after each transfer that isn't the final one.
+    /// 
+    /// Returns true if the channel should pause until the geometry FIFO
+    /// requests more data.
+    pub fn end_of_burst(&mut self) -> bool {
+        if self.should_start_geom_fifo() {
+            self.burst_count += 1;
+            if self.burst_count == GEOM_FIFO_BURST_LEN {
+                self.burst_count = 0;
+                return true;
+            }
+        }
+        false
+    }
+
     /// Check to see if a 32-bit word should be transferred.
     pub fn transfer_32bit_word(&self) -> bool {
         self.control.contains(Control::WORD_TYPE)
@@ -404,6 +423,7 @@ impl DMAChannel {
 
     /// Call on completion of DMA transfer.
     fn reset(&mut self) -> Interrupts {
+        self.burst_count = 0;
         if self.control.contains(Control::REPEAT) {
             self.current_count = self.control.word_count();
 
@@ -424,4 +444,49 @@ impl DMAChannel {
             Interrupts::empty()
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geom_fifo_burst() {
+        let mut channel = DMAChannel::new(Interrupts::DMA_0);
+        channel.write_word(0x0, 0x0200_0000);
+        channel.write_word(0x4, 0x0400_0400);
+        // Fixed destination, 32-bit words, 300 words, start on geometry FIFO request.
+        channel.write_word(0x8, (Control::START_G_FIFO | Control::WORD_TYPE | Control::END_IRQ).bits() | (0b10 << 21) | 300);
+        assert!(channel.should_start_geom_fifo());
+
+        let mut pauses = Vec::new();
+        for n in 1..=300 {
+            match channel.next_addrs() {
+                DMAAddress::Addr{source, dest} => {
+                    assert_eq!(source, 0x0200_0000 + (n - 1) * 4);
+                    assert_eq!(dest, 0x0400_0400);
+                    if channel.end_of_burst() {
+                        pauses.push(n);
+                    }
+                },
+                DMAAddress::Done{irq, ..} => {
+                    assert_eq!(n, 300);
+                    assert_eq!(irq, Interrupts::DMA_0.bits() as u16);
+                }
+            }
+        }
+        assert_eq!(pauses, vec![112, 224]);
+        assert!(!channel.control.contains(Control::ENABLE));
+    }
+
+    #[test]
+    fn immediate_no_burst() {
+        let mut channel = DMAChannel::new(Interrupts::DMA_1);
+        channel.write_word(0x8, (Control::ENABLE | Control::WORD_TYPE).bits() | 200);
+        for _ in 0..199 {
+            assert!(matches!(channel.next_addrs(), DMAAddress::Addr{..}));
+            assert!(!channel.end_of_burst());
+        }
+        assert!(matches!(channel.next_addrs(), DMAAddress::Done{irq: 0, ..}));
+    }
+}
@@ -1,5 +1,6 @@
 // External memory control
 
+use arm::MemCycleType;
 use bitflags::bitflags;
 use crate::utils::{
     bits::u16,
@@ -20,6 +21,26 @@ bitflags! {
     }
 }
 
+impl GBAAccess {
+    /// Cycles for a 16-bit access to GBA slot ROM.
+    pub fn rom_cycles(&self, cycle: MemCycleType) -> usize {
+        const WAIT_N: [usize; 4] = [10, 8, 6, 18];
+        if cycle.is_non_seq() {
+            WAIT_N[((*self & GBAAccess::WAIT_N).bits() >> 2) as usize]
+        } else if self.contains(GBAAccess::WAIT_S) {
+            4
+        } else {
+            6
+        }
+    }
+
+    /// Cycles for an 8-bit access to GBA slot RAM.
+    pub fn ram_cycles(&self) -> usize {
+        const WAIT: [usize; 4] = [10, 8, 6, 18];
+        WAIT[(*self & GBAAccess::SRAM_WAIT).bits() as usize]
+    }
+}
+
 bitflags! {
     #[derive(Default)]
     pub struct AccessRights: u16 {
@@ -56,6 +77,10 @@ impl ExMemControl {
         !access.contains(AccessRights::GBA_CART)
     }
 
+    pub fn gba_access(&self) -> GBAAccess {
+        self.gba_access
+    }
+
     pub fn has_nds_access(&self) -> bool {
         let access = AccessRights::from_bits_truncate(self.access_rights.load(Ordering::Acquire));
         !access.contains(AccessRights::NDS_CARD)
@@ -94,6 +119,10 @@ impl ExMemStatus {
         access.contains(AccessRights::GBA_CART)
     }
 
+    pub fn gba_access(&self) -> GBAAccess {
+        self.gba_access
+    }
+
     pub fn has_nds_access(&self) -> bool {
         let access = AccessRights::from_bits_truncate(self.access_rights.load(Ordering::Acquire));
         access.contains(AccessRights::NDS_CARD)
@@ -0,0 +1,260 @@
+/// GBA slot (slot-2).
+
+use crossbeam_channel::Sender;
+use parking_lot::Mutex;
+use std::{
+    path::PathBuf,
+    sync::Arc
+};
+use crate::{
+    common::mem::ram::RAM,
+    gba::GamePak,
+    utils::meminterface::MemInterface16,
+    Event
+};
+
+/// Where GBA cart RAM is found on the GBA.
+const GBA_RAM_BASE: u32 = 0x0E00_0000;
+/// Size of the RAM in the Memory Expansion Pak.
+const EXPANSION_RAM_SIZE: usize = 8 * 1024 * 1024;
+/// Header values that identify the Memory Expansion Pak, from 0x0800_00B0.
+const EXPANSION_ID: [u16; 8] = [0xFFFF, 0x0000, 0x2400, 0x2424, 0xFFFF, 0xFFFF, 0xFFFF, 0x7FFF];
+/// More ID values, from 0x0801_FFFC.
+const EXPANSION_ID_2: [u16; 2] = [0xFFFF, 0x007F];
+/// Value read from all ROM addresses of the Guitar Grip.
+const GUITAR_GRIP_ID: u16 = 0xF9FF;
+
+/// Device to insert into the GBA slot.
+#[derive(Clone, Debug, Default)]
+pub enum Slot2Device {
+    #[default]
+    Empty,
+    /// GBA cartridge, with an optional save file.
+    GBACart {
+        rom_path:   PathBuf,
+        save_path:  Option<PathBuf>,
+    },
+    /// Rumble writes are sent as `Event::Rumble`.
+    RumblePak,
+    /// 8MB of extra RAM.
+    MemoryExpansionPak,
+    /// Guitar Hero: On Tour controller. Frets are set by `Device::set_guitar_fret`.
+    GuitarGrip,
+}
+
+enum SlotContents {
+    Empty,
+    GBACart(GamePak),
+    RumblePak {
+        events:     Sender<Event>,
+        position:   bool,
+    },
+    MemoryExpansionPak {
+        ram:        RAM,
+        unlocked:   bool,
+    },
+    GuitarGrip {
+        /// Active high.
+        frets:      u8,
+    },
+}
+
+/// The GBA slot, which may contain a GBA cartridge or an accessory.
+/// 
+/// The slot is shared between both processors.
+/// EXMEMCNT decides which one can access it.
+#[derive(Clone)]
+pub struct GBASlot {
+    contents: Arc<Mutex<SlotContents>>,
+}
+
+impl GBASlot {
+    pub fn new(device: &Slot2Device, events: Sender<Event>) -> std::io::Result<Self> {
+        let contents = match device {
+            Slot2Device::Empty => SlotContents::Empty,
+            Slot2Device::GBACart { rom_path, save_path } => SlotContents::GBACart(
+                GamePak::new(rom_path, save_path.as_ref().map(|p| p.as_path()), events)?
+            ),
+            Slot2Device::RumblePak => SlotContents::RumblePak {
+                events:     events,
+                position:   false,
+            },
+            Slot2Device::MemoryExpansionPak => SlotContents::MemoryExpansionPak {
+                ram:        RAM::new(EXPANSION_RAM_SIZE),
+                unlocked:   false,
+            },
+            Slot2Device::GuitarGrip => SlotContents::GuitarGrip {
+                frets:      0,
+            },
+        };
+        Ok(Self {
+            contents: Arc::new(Mutex::new(contents))
+        })
+    }
+
+    /// Write the GBA cart save to disk.
+    pub fn flush_save(&self) {
+        if let SlotContents::GBACart(cart) = &mut *self.contents.lock() {
+            cart.flush_save();
+        }
+    }
+
+    /// Set the Guitar Grip frets that are held down.
+    pub fn set_guitar_frets(&self, held: u8) {
+        if let SlotContents::GuitarGrip { frets } = &mut *self.contents.lock() {
+            *frets = held;
+        }
+    }
+
+    pub fn read_byte(&self, addr: u32) -> u8 {
+        match addr {
+            0x0800_0000..=0x09FF_FFFF => match &mut *self.contents.lock() {
+                SlotContents::GBACart(cart) => cart.read_byte(addr),
+                contents => {
+                    let data = contents.read_rom_halfword(addr & 0xFFFF_FFFE);
+                    (data >> ((addr & 1) * 8)) as u8
+                },
+            },
+            _ => self.contents.lock().read_ram(addr),
+        }
+    }
+
+    pub fn read_halfword(&self, addr: u32) -> u16 {
+        match addr {
+            0x0800_0000..=0x09FF_FFFF => self.contents.lock().read_rom_halfword(addr),
+            // RAM has an 8-bit bus.
+            _ => (self.contents.lock().read_ram(addr) as u16) * 0x0101,
+        }
+    }
+
+    pub fn read_word(&self, addr: u32) -> u32 {
+        match addr {
+            0x0800_0000..=0x09FF_FFFF => match &mut *self.contents.lock() {
+                SlotContents::GBACart(cart) => cart.read_word(addr),
+                contents => {
+                    let lo = contents.read_rom_halfword(addr) as u32;
+                    let hi = contents.read_rom_halfword(addr + 2) as u32;
+                    lo | (hi << 16)
+                },
+            },
+            _ => (self.contents.lock().read_ram(addr) as u32) * 0x0101_0101,
+        }
+    }
+
+    pub fn write_byte(&self, addr: u32, data: u8) {
+        match addr {
+            0x0800_0000..=0x09FF_FFFF => match &mut *self.contents.lock() {
+                SlotContents::MemoryExpansionPak { ram, unlocked: true } => if let 0x0900_0000..=0x097F_FFFF = addr {
+                    ram.write_byte(addr & 0x7F_FFFF, data);
+                },
+                // The ROM bus is 16-bit.
+                contents => contents.write_rom_halfword(addr & 0xFFFF_FFFE, (data as u16) * 0x0101),
+            },
+            _ => self.contents.lock().write_ram(addr, data),
+        }
+    }
+
+    pub fn write_halfword(&self, addr: u32, data: u16) {
+        match addr {
+            0x0800_0000..=0x09FF_FFFF => self.contents.lock().write_rom_halfword(addr, data),
+            _ => self.contents.lock().write_ram(addr, data as u8),
+        }
+    }
+
+    pub fn write_word(&self, addr: u32, data: u32) {
+        match addr {
+            0x0800_0000..=0x09FF_FFFF => {
+                let mut contents = self.contents.lock();
+                contents.write_rom_halfword(addr, data as u16);
+                contents.write_rom_halfword(addr + 2, (data >> 16) as u16);
+            },
+            _ => self.contents.lock().write_ram(addr, data as u8),
+        }
+    }
+}
+
+impl SlotContents {
+    fn read_rom_halfword(&mut self, addr: u32) -> u16 {
+        match self {
+            SlotContents::Empty => 0xFFFF,
+            SlotContents::GBACart(cart) => cart.read_halfword(addr),
+            // Open bus, with D1 pulled low.
+            SlotContents::RumblePak { .. } => ((addr >> 1) as u16) & 0xFFFD,
+            SlotContents::MemoryExpansionPak { ram, unlocked } => match addr {
+                0x0800_00B0..=0x0800_00BF => EXPANSION_ID[((addr - 0x0800_00B0) >> 1) as usize],
+                0x0801_FFFC..=0x0801_FFFF => EXPANSION_ID_2[((addr - 0x0801_FFFC) >> 1) as usize],
+                0x0824_0000 => *unlocked as u16,
+                0x0824_0002 => 0,
+                0x0900_0000..=0x097F_FFFF if *unlocked => ram.read_halfword(addr & 0x7F_FFFF),
+                _ => 0xFFFF,
+            },
+            SlotContents::GuitarGrip { .. } => GUITAR_GRIP_ID,
+        }
+    }
+
+    fn write_rom_halfword(&mut self, addr: u32, data: u16) {
+        match self {
+            SlotContents::RumblePak { events, position } => {
+                let new_position = (data & 2) != 0;
+                if new_position != *position {
+                    *position = new_position;
+                    let _ = events.send(Event::Rumble(new_position));
+                }
+            },
+            SlotContents::MemoryExpansionPak { ram, unlocked } => match addr {
+                0x0824_0000 => *unlocked = (data & 1) != 0,
+                0x0900_0000..=0x097F_FFFF if *unlocked => ram.write_halfword(addr & 0x7F_FFFF, data),
+                _ => {},
+            },
+            // Writes to ROM are ignored.
+            _ => {},
+        }
+    }
+
+    fn read_ram(&mut self, addr: u32) -> u8 {
+        match self {
+            SlotContents::GBACart(cart) => cart.read_byte(GBA_RAM_BASE | (addr & 0xFFFF)),
+            // Active low.
+            SlotContents::GuitarGrip { frets } => !*frets,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u32, data: u8) {
+        if let SlotContents::GBACart(cart) = self {
+            cart.write_byte(GBA_RAM_BASE | (addr & 0xFFFF), data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_expansion_pak() {
+        let (events, _) = crossbeam_channel::unbounded();
+        let slot = GBASlot::new(&Slot2Device::MemoryExpansionPak, events).unwrap();
+
+        // Detection
+        let id = (0x0800_00B0..0x0800_00C0).step_by(2).map(|addr| slot.read_halfword(addr)).collect::<Vec<_>>();
+        assert_eq!(id, EXPANSION_ID);
+        assert_eq!(slot.read_halfword(0x0801_FFFC), 0xFFFF);
+        assert_eq!(slot.read_halfword(0x0801_FFFE), 0x007F);
+
+        // RAM is locked until enabled.
+        assert_eq!(slot.read_halfword(0x0824_0000), 0);
+        slot.write_halfword(0x0900_0000, 0x1234);
+        assert_eq!(slot.read_halfword(0x0900_0000), 0xFFFF);
+
+        slot.write_halfword(0x0824_0000, 1);
+        assert_eq!(slot.read_halfword(0x0824_0000), 1);
+        slot.write_halfword(0x0900_0000, 0x1234);
+        slot.write_byte(0x097F_FFFF, 0x56);
+        assert_eq!(slot.read_halfword(0x0900_0000), 0x1234);
+        assert_eq!(slot.read_word(0x097F_FFFC) >> 24, 0x56);
+
+        slot.write_halfword(0x0824_0000, 0);
+        assert_eq!(slot.read_halfword(0x0900_0000), 0xFFFF);
+    }
+}
@@ -4,12 +4,15 @@ mod shared;
 mod power;
 mod exmem;
 mod wifi;
+mod swi;
+mod gbaslot;
 
 use arm::{Mem32, MemCycleType};
 use crossbeam_channel::{Sender, Receiver, bounded, unbounded};
 
 use std::{
     path::PathBuf,
+    ops::Range,
     sync::{Arc, Barrier}
 };
 
@@ -18,6 +21,7 @@ use crate::{
         mem::{
             bios::BIOS,
             ram::RAM,
+            jit::{JITTracker, MirroredRAM},
         },
         peripheral::{
             dma::{
@@ -39,12 +43,13 @@ use crate::{
         joypad::DSJoypad,
         interrupt::{Interrupts, InterruptControl},
         card::*,
-        rtc::RealTimeClock,
-        spi::SPI,
+        rtc::{RealTimeClock, RTCTime},
+        spi::{SPI, Backlights, UserProfile},
         video::*,
         audio::DSAudio,
         input::UserInput
-    }
+    },
+    Event
 };
 use dma::DMA;
 use main::MainRAM;
@@ -52,6 +57,11 @@ use shared::*;
 use power::*;
 use exmem::*;
 use wifi::*;
+pub use wifi::{WifiMedium, LocalWifiHub, UdpWifi};
+use gbaslot::GBASlot;
+pub use gbaslot::Slot2Device;
+
+pub use swi::{DSBIOSMem, emulated_swi_arm9, emulated_swi_arm7, call_bios_routine};
 
 /// How many cycles the ARM7 should run for before syncing.
 const ARM7_THREAD_SYNC_CYCLES: usize = 2000;
@@ -60,13 +70,103 @@ const ARM9_THREAD_SYNC_CYCLES: usize = ARM7_THREAD_SYNC_CYCLES * 2;
 
 /// Locations for external files that are used by NDS.
 pub struct MemoryConfig {
-    pub rom_path:       PathBuf,
+    /// If None, slot-1 is empty and the firmware menu boots.
+    pub rom_path:       Option<PathBuf>,
     pub save_path:      Option<PathBuf>,
     pub ds9_bios_path:  Option<PathBuf>,
     pub ds7_bios_path:  Option<PathBuf>,
     pub firmware_path:  Option<PathBuf>,
+    /// User-specific firmware. Settings changed in-game are written here.
+    /// If it exists, it is loaded in place of `firmware_path`.
+    pub firmware_save_path: Option<PathBuf>,
+    /// Replaces the MAC address in firmware.
+    /// If None, the address from firmware is used, or `DEFAULT_MAC_ADDRESS` for generated firmware.
+    pub mac_address:    Option<[u8; 6]>,
+    /// Replaces the user settings in firmware.
+    /// If None, the settings from firmware are used.
+    pub user_profile:   Option<UserProfile>,
+    /// Standalone KEY1 table (0x1048 bytes).
+    /// Only used if the ARM7 BIOS is not provided.
+    pub key1_path:      Option<PathBuf>,
+    /// Cartridge or accessory to insert into the GBA slot.
+    pub slot_2:         Slot2Device,
+    /// Time that the real-time clock starts at.
+    pub rtc_time:       RTCTime,
+    /// Medium to send and receive wifi frames over.
+    /// If None, transmitted frames are dropped.
+    pub wifi_medium:    Option<Arc<dyn WifiMedium>>,
+    /// Record all wifi frames sent and received to this pcap file.
+    pub wifi_capture_path: Option<PathBuf>,
+    /// Internal resolution multiplier for 3D graphics, from 1 to `MAX_RENDER_SCALE`.
+    /// 2D graphics are stretched to match.
+    pub render_scale:   usize,
+    /// Dump 3D textures to this folder as PNG.
+    pub texture_dump_path:  Option<PathBuf>,
+    /// Load replacements for 3D textures from this folder.
+    /// Replacements are named by the hash of the texture they replace.
+    pub texture_pack_path:  Option<PathBuf>,
+
+    pub fast_boot:      bool,
+
+    /// Use the JIT compiler for the ARM7.
+    pub jit:            bool,
+    /// Address ranges for the ARM7 JIT to compile from.
+    /// If None, `DEFAULT_ARM7_JIT_RANGES` are used.
+    /// 
+    /// Writes from the ARM9 are passed to the ARM7 when the threads sync,
+    /// and when the ARM9 writes to the IPC registers.
+    pub jit_ranges:     Option<Vec<Range<u32>>>,
+}
+
+/// Largest supported 3D resolution multiplier.
+pub const MAX_RENDER_SCALE: usize = 4;
+
+/// Address ranges that the ARM7 JIT compiles from by default:
+/// BIOS, main RAM, and the top mirror of shared WRAM followed by ARM7 WRAM.
+pub const DEFAULT_ARM7_JIT_RANGES: &[Range<u32>] = &[0..0x4000, 0x0200_0000..0x0240_0000, 0x037F_8000..0x0381_0000];
+
+/// Main RAM as seen by the ARM7, for JIT invalidation.
+const ARM7_MAIN_RAM: MirroredRAM = MirroredRAM { start: 0x0200_0000, end: 0x0300_0000, size: 0x40_0000 };
+/// Shared WRAM as seen by the ARM7. Each bank can be mapped at any multiple of its size.
+const ARM7_SHARED_WRAM: MirroredRAM = MirroredRAM { start: 0x0300_0000, end: 0x0380_0000, size: 0x4000 };
+/// ARM7 WRAM.
+const ARM7_WRAM: MirroredRAM = MirroredRAM { start: 0x0380_0000, end: 0x0400_0000, size: 0x1_0000 };
 
-    pub fast_boot:      bool
+impl MemoryConfig {
+    /// If either BIOS is missing, it is emulated and the boot process is skipped.
+    pub fn no_bios(&self) -> bool {
+        self.ds9_bios_path.is_none() || self.ds7_bios_path.is_none()
+    }
+
+    /// Skip the boot process. Booting needs the BIOS and firmware boot code.
+    pub fn skip_boot(&self) -> bool {
+        self.fast_boot || self.no_bios() || self.firmware_path.is_none()
+    }
+
+    /// Get the 3D resolution multiplier, limited to the supported range.
+    pub fn render_scale(&self) -> usize {
+        self.render_scale.clamp(1, MAX_RENDER_SCALE)
+    }
+
+    /// Get the folders for 3D texture dumping and replacement.
+    pub fn texture_config(&self) -> TextureConfig {
+        TextureConfig {
+            dump_path:  self.texture_dump_path.clone(),
+            pack_path:  self.texture_pack_path.clone(),
+        }
+    }
+
+    /// Get the ranges that the ARM7 JIT should compile from.
+    /// Empty if JIT is disabled.
+    pub fn jit_ranges(&self) -> Vec<Range<u32>> {
+        if !self.jit {
+            Vec::new()
+        } else if let Some(ranges) = &self.jit_ranges {
+            ranges.clone()
+        } else {
+            DEFAULT_ARM7_JIT_RANGES.to_vec()
+        }
+    }
 }
 
 /// Memory bus for DS ARM9 processor.
@@ -90,6 +190,7 @@ pub struct DS9MemoryBus<R: Renderer> {
     interrupt_control:  InterruptControl,
     ex_mem_control:     ExMemControl,
     card:               DSCardIO,
+    gba_slot:           GBASlot,
 
     // sync
     inner_counter:      usize,
@@ -97,36 +198,73 @@ pub struct DS9MemoryBus<R: Renderer> {
     timer_counter:      usize,
     barrier:            Arc<Barrier>,
     frame_sender:       FrameSender<UserInput>,
-    input_send:         Sender<UserInput>
+    input_send:         Sender<UserInput>,
+
+    /// Writes into memory that the ARM7 JIT compiles from.
+    arm7_jit:           JITTracker,
+    arm7_jit_send:      Sender<Range<u32>>,
 }
 
 impl<R: Renderer> DS9MemoryBus<R> {
-    pub fn new(config: &MemoryConfig, frame_sender: FrameSender<UserInput>) -> (Self, Box<DS7MemoryBus>) {
+    /// Fails if the card can't be loaded,
+    /// or if there is no card and no BIOS and firmware to boot from.
+    pub fn new(config: &MemoryConfig, frame_sender: FrameSender<UserInput>, events: Sender<Event>) -> std::io::Result<(Self, Box<DS7MemoryBus>)> {
+        let fast_boot = config.skip_boot();
+        if fast_boot && config.rom_path.is_none() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "booting without a card needs the BIOS and firmware"));
+        }
+
         let (arm9_wram, arm7_wram) = ARM9SharedRAM::new();
         let (ds9_ipc, ds7_ipc) = IPC::new();
         let main_ram = MainRAM::new();
 
-        let (arm9_video, arm7_video, arm7_vram) = DSVideo::new(frame_sender.get_frame_buffer(0), frame_sender.get_frame_buffer(1));
+        let (arm9_video, arm7_video, arm7_vram) = DSVideo::new(frame_sender.get_frame_buffer(0), frame_sender.get_frame_buffer(1), config.render_scale(), config.texture_config());
 
-        let arm9_bios = BIOS::new_from_file(config.ds9_bios_path.as_ref().map(|p| p.as_path()).unwrap()).unwrap();
-        let arm7_bios = BIOS::new_from_file(config.ds7_bios_path.as_ref().map(|p| p.as_path()).unwrap()).unwrap();
-        let spi = SPI::new(config.firmware_path.as_ref().map(|p| p.as_path()));
+        let arm9_bios = if let Some(path) = &config.ds9_bios_path {
+            BIOS::new_from_file(path).unwrap()
+        } else {
+            construct_arm9_bios()
+        };
+        let arm7_bios = if let Some(path) = &config.ds7_bios_path {
+            BIOS::new_from_file(path).unwrap()
+        } else {
+            construct_arm7_bios()
+        };
+        let gba_slot = GBASlot::new(&config.slot_2, events.clone()).unwrap();
+        let spi = SPI::new(
+            config.firmware_path.as_ref().map(|p| p.as_path()),
+            config.firmware_save_path.clone(),
+            config.mac_address,
+            config.user_profile.as_ref(),
+            events
+        );
 
         let (ex_mem_control, ex_mem_status) = ExMemControl::new();
-        let key1 = (0..0x412).map(|n| arm7_bios.read_word(0x30 + (n*4))).collect::<Vec<_>>();
-        let (card_9, card_7) = DSCardIO::new(&config.rom_path, config.save_path.clone(), key1).unwrap();
+        let key1 = if config.ds7_bios_path.is_some() {
+            Some((0..KEY1_TABLE_SIZE as u32).map(|n| arm7_bios.read_word(0x30 + (n*4))).collect::<Vec<_>>())
+        } else if let Some(path) = &config.key1_path {
+            Some(load_key1_table(path).unwrap())
+        } else {
+            None
+        };
+        let (card_9, card_7) = DSCardIO::new(config.rom_path.as_deref(), config.save_path.clone(), key1)?;
 
-        let mut arm7_wifi = Wifi::new();
-        if config.fast_boot {
+        let mut arm7_wifi = Wifi::new(
+            config.wifi_medium.clone(),
+            config.wifi_capture_path.as_ref().map(|p| PcapWriter::new(p)
+                .unwrap_or_else(|e| panic!("couldn't create wifi capture {}: {}", p.display(), e)))
+        );
+        if fast_boot {
             arm7_wifi.fast_boot();
         }
 
         let barrier = Arc::new(Barrier::new(2));
         let (input_send, input_recv) = bounded(1);
+        let (arm7_jit_send, arm7_jit_recv) = unbounded();
 
-        (Self{
+        Ok((Self{
             bios:               arm9_bios,
-            power_control:      DS9PowerControl::new(config.fast_boot),
+            power_control:      DS9PowerControl::new(fast_boot),
             halt:               false,
 
             main_ram:           main_ram.clone(),
@@ -142,16 +280,20 @@ impl<R: Renderer> DS9MemoryBus<R> {
             interrupt_control:  InterruptControl::new("ARM9"),
             ex_mem_control:     ex_mem_control,
             card:               card_9,
+            gba_slot:           gba_slot.clone(),
             
             inner_counter:      0,
             counter:            0,
             timer_counter:      0,
             barrier:            barrier.clone(),
             frame_sender:       frame_sender,
-            input_send:         input_send
+            input_send:         input_send,
+
+            arm7_jit:           JITTracker::new(&config.jit_ranges()),
+            arm7_jit_send:      arm7_jit_send,
         }, Box::new(DS7MemoryBus{
             bios:               arm7_bios,
-            power_control:      DS7PowerControl::new(config.fast_boot),
+            power_control:      DS7PowerControl::new(fast_boot),
 
             main_ram:           main_ram,
             wram:               RAM::new(64 * 1024),
@@ -167,20 +309,26 @@ impl<R: Renderer> DS9MemoryBus<R> {
             timers:             Timers::new(),
             joypad:             Joypad::new(),
             ds_joypad:          DSJoypad::new(),
-            rtc:                RealTimeClock::new(),
+            rtc:                RealTimeClock::new(config.rtc_time),
             spi:                spi,
 
             dma:                ds7DMA::new(),
             interrupt_control:  InterruptControl::new("ARM7"),
             ex_mem_status:      ex_mem_status,
             card:               card_7,
+            gba_slot:           gba_slot,
 
             inner_counter:      0,
             counter:            0,
             v_counter:          0,
             barrier:            barrier,
-            input_recv:         input_recv
-        }))
+            input_recv:         input_recv,
+
+            jit:                JITTracker::new(&config.jit_ranges()),
+            arm9_jit_recv:      arm7_jit_recv,
+
+            bios_call:          None,
+        })))
     }
 
     /// Get the game cart header.
@@ -188,6 +336,11 @@ impl<R: Renderer> DS9MemoryBus<R> {
         self.card.get_header()
     }
 
+    /// Get a handle to insert and remove cards.
+    pub fn card_slot(&self) -> CardSlot {
+        self.card.card_slot()
+    }
+
     /// Setup ARM9 boot area, for fast booting without BIOS.
     /// 
     /// Also copies header into RAM.
@@ -222,22 +375,6 @@ impl<R: Renderer> DS9MemoryBus<R> {
         self.main_ram.write_word(0x3F_FC04, self.card.get_rom_id());
         self.main_ram.write_halfword(0x3F_FC10, 0x5835); // BIOS CRC
         self.main_ram.write_word(0x3F_FC40, 1); // Boot flag
-
-        // Write user settings into RAM.
-        // Birthday:
-        self.main_ram.write_byte(0x3F_FC83, 0x1);
-        self.main_ram.write_byte(0x3F_FC84, 0x1);
-        // Language:
-        self.main_ram.write_halfword(0x3F_FCE4, 0xEC_41);
-        // Touchscreen calibration:
-        self.main_ram.write_halfword(0x3F_FCD8, 0);   // ADC.X1
-        self.main_ram.write_halfword(0x3F_FCDA, 0);   // ADC.Y1
-        self.main_ram.write_halfword(0x3F_FCDE, 0xFF0);   // ADC.X2
-        self.main_ram.write_halfword(0x3F_FCE0, 0xBF0);   // ADC.Y2
-        self.main_ram.write_byte(0x3F_FCDC, 0);   // SCR.X1
-        self.main_ram.write_byte(0x3F_FCDD, 0);   // SCR.Y1
-        self.main_ram.write_byte(0x3F_FCE2, 255);   // SCR.X2
-        self.main_ram.write_byte(0x3F_FCE3, 191);   // SCR.Y2
     }
 }
 
@@ -347,6 +484,10 @@ impl <R: Renderer> DS9MemoryBus<R> {
                             }
                             cycles = 0;
                         }
+
+                        if self.dma.channels[c].end_of_burst() {
+                            self.dma.set_inactive(c);
+                        }
                     },
                     DMAAddress::Done {
                         source, dest, irq
@@ -387,6 +528,7 @@ impl <R: Renderer> DS9MemoryBus<R> {
         self.counter += cycles;
         if self.counter >= ARM9_THREAD_SYNC_CYCLES {
             self.counter -= ARM9_THREAD_SYNC_CYCLES;
+            self.send_arm7_jit_dirty();
             self.barrier.wait();
         }
 
@@ -425,7 +567,7 @@ impl <R: Renderer> DS9MemoryBus<R> {
             card_interrupt
         } else {
             Interrupts::empty()
-        };
+        } | self.card.take_detect_interrupt();
 
         self.interrupt_control.interrupt_request(
             joypad_irq |
@@ -442,6 +584,22 @@ impl <R: Renderer> DS9MemoryBus<R> {
         self.interrupt_control.irq()
     }
 
+    /// Pass writes into memory that the ARM7 JIT compiles from to the ARM7.
+    fn send_arm7_jit_dirty(&mut self) {
+        while let Some(range) = self.arm7_jit.take_dirty() {
+            let _ = self.arm7_jit_send.send(range);
+        }
+    }
+
+    /// Stall until there is room in the geometry command FIFO.
+    fn wait_for_geom_fifo(&mut self) {
+        while self.video.geom_fifo_full() {
+            if self.do_clock(8) {
+                self.frame_end();
+            }
+        }
+    }
+
     /// Called when vblank occurs. Halts emulation until the next frame.
     fn frame_end(&mut self) {
         if let Some(input) = self.frame_sender.sync_frame() {
@@ -526,9 +684,9 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
             0x0600_0000..=0x06FF_FFFF => (self.video.mem.read_byte_vram(addr), if cycle.is_non_seq() {8} else {2}),
             0x0700_0000..=0x07FF_FFFF => (self.video.mem.read_byte_oam(addr & 0x7FF), if cycle.is_non_seq() {8} else {2}),
 
-            // TODO: GBA slot
-            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_control.has_gba_access() {0xFF} else {0x00}, 18),
-            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_control.has_gba_access() {0xFF} else {0x00}, 18),
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_control.has_gba_access() {self.gba_slot.read_byte(addr)} else {0}, self.ex_mem_control.gba_access().rom_cycles(cycle)),
+            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_control.has_gba_access() {self.gba_slot.read_byte(addr)} else {0}, self.ex_mem_control.gba_access().ram_cycles()),
 
             0xFFFF_0000..=0xFFFF_FFFF => (self.bios.read_byte(addr & 0xFFF), if cycle.is_non_seq() {8} else {2}),
 
@@ -539,10 +697,12 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
         match addr {
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.main_ram.write_byte(addr & 0x3F_FFFF, data);
+                self.arm7_jit.write(addr, 1, &ARM7_MAIN_RAM);
                 if cycle.is_non_seq() {DS9_MAIN_RAM_N} else {DS9_MAIN_RAM_S}  // TODO: S=N for instr
             },
             0x0300_0000..=0x03FF_FFFF => {  // Shared RAM
                 self.shared_wram.write_byte(addr, data);
+                self.arm7_jit.write(addr, 1, &ARM7_SHARED_WRAM);
                 if cycle.is_non_seq() {8} else {2}
             },
             // I/O
@@ -554,6 +714,12 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
                 self.video.mem.mut_engine_b().registers.write_byte(addr & 0xFF, data);
                 if cycle.is_non_seq() {8} else {2}
             },
+            0x0400_0180..=0x0400_018F => {  // IPC
+                // The ARM7 might be told to run code that was just written.
+                self.send_arm7_jit_dirty();
+                self.io_write_byte(addr, data);
+                if cycle.is_non_seq() {8} else {2}
+            },
             0x0400_0000..=0x04FF_FFFF => {  // I/O
                 self.io_write_byte(addr, data);
                 if cycle.is_non_seq() {8} else {2}
@@ -573,7 +739,19 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
                 if cycle.is_non_seq() {8} else {2}
             },
 
-            // TODO: GBA slot
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => {
+                if self.ex_mem_control.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data);
+                }
+                self.ex_mem_control.gba_access().rom_cycles(cycle)
+            },
+            0x0A00_0000..=0x0AFF_FFFF => {
+                if self.ex_mem_control.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data);
+                }
+                self.ex_mem_control.gba_access().ram_cycles()
+            },
 
             _ => 1 // Unused
         }
@@ -597,9 +775,9 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
             0x0600_0000..=0x06FF_FFFF => (self.video.mem.read_halfword_vram(addr), if cycle.is_non_seq() {8} else {2}),
             0x0700_0000..=0x07FF_FFFF => (self.video.mem.read_halfword_oam(addr & 0x7FF), if cycle.is_non_seq() {8} else {2}),
 
-            // TODO: GBA slot
-            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_control.has_gba_access() {0xFFFF} else {0}, 18),
-            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_control.has_gba_access() {0xFFFF} else {0}, 18),
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_control.has_gba_access() {self.gba_slot.read_halfword(addr)} else {0}, self.ex_mem_control.gba_access().rom_cycles(cycle)),
+            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_control.has_gba_access() {self.gba_slot.read_halfword(addr)} else {0}, self.ex_mem_control.gba_access().ram_cycles()),
 
             0xFFFF_0000..=0xFFFF_FFFF => (self.bios.read_halfword(addr & 0xFFF), if cycle.is_non_seq() {8} else {2}),
 
@@ -610,10 +788,12 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
         match addr {
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.main_ram.write_halfword(addr & 0x3F_FFFF, data);
+                self.arm7_jit.write(addr, 2, &ARM7_MAIN_RAM);
                 if cycle.is_non_seq() {DS9_MAIN_RAM_N} else {DS9_MAIN_RAM_S}  // TODO: S=N for instr
             },
             0x0300_0000..=0x03FF_FFFF => {  // Shared WRAM
                 self.shared_wram.write_halfword(addr, data);
+                self.arm7_jit.write(addr, 2, &ARM7_SHARED_WRAM);
                 if cycle.is_non_seq() {8} else {2}
             },
 
@@ -626,6 +806,17 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
                 self.video.mem.mut_engine_b().registers.write_halfword(addr & 0xFF, data);
                 if cycle.is_non_seq() {8} else {2}
             },
+            0x0400_0180..=0x0400_018F => {  // IPC
+                // The ARM7 might be told to run code that was just written.
+                self.send_arm7_jit_dirty();
+                self.io_write_halfword(addr, data);
+                if cycle.is_non_seq() {8} else {2}
+            },
+            0x0400_0400..=0x0400_05FF => {  // Geometry commands
+                self.wait_for_geom_fifo();
+                self.io_write_halfword(addr, data);
+                if cycle.is_non_seq() {8} else {2}
+            },
             0x0400_0000..=0x04FF_FFFF => {
                 self.io_write_halfword(addr, data);
                 if cycle.is_non_seq() {8} else {2}
@@ -645,7 +836,19 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
                 if cycle.is_non_seq() {8} else {2}
             },
 
-            // TODO: GBA slot
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => {
+                if self.ex_mem_control.has_gba_access() {
+                    self.gba_slot.write_halfword(addr, data);
+                }
+                self.ex_mem_control.gba_access().rom_cycles(cycle)
+            },
+            0x0A00_0000..=0x0AFF_FFFF => {
+                if self.ex_mem_control.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data as u8);
+                }
+                self.ex_mem_control.gba_access().ram_cycles()
+            },
 
             _ => 1 // Unused
         }
@@ -670,9 +873,9 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
             0x0600_0000..=0x06FF_FFFF => (self.video.mem.read_word_vram(addr), if cycle.is_non_seq() {10} else {4}),
             0x0700_0000..=0x07FF_FFFF => (self.video.mem.read_word_oam(addr & 0x7FF), if cycle.is_non_seq() {8} else {2}),
 
-            // TODO: GBA slot
-            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_control.has_gba_access() {0xFFFF_FFFF} else {0}, 18),
-            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_control.has_gba_access() {0xFFFF_FFFF} else {0}, 18),
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_control.has_gba_access() {self.gba_slot.read_word(addr)} else {0}, self.ex_mem_control.gba_access().rom_cycles(cycle) + self.ex_mem_control.gba_access().rom_cycles(MemCycleType::S)),
+            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_control.has_gba_access() {self.gba_slot.read_word(addr)} else {0}, self.ex_mem_control.gba_access().ram_cycles()),
 
             0xFFFF_0000..=0xFFFF_FFFF => (self.bios.read_word(addr & 0xFFF), if cycle.is_non_seq() {8} else {2}),
 
@@ -683,10 +886,12 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
         match addr {
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.main_ram.write_word(addr & 0x3F_FFFF, data);
+                self.arm7_jit.write(addr, 4, &ARM7_MAIN_RAM);
                 if cycle.is_non_seq() {DS9_MAIN_RAM_WORD_N} else {DS9_MAIN_RAM_WORD_S}  // TODO: S=N for instr
             },
             0x0300_0000..=0x03FF_FFFF => {  // Shared WRAM
                 self.shared_wram.write_word(addr, data);
+                self.arm7_jit.write(addr, 4, &ARM7_SHARED_WRAM);
                 if cycle.is_non_seq() {8} else {2}
             },
 
@@ -699,6 +904,17 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
                 self.video.mem.mut_engine_b().registers.write_word(addr & 0xFF, data);
                 if cycle.is_non_seq() {8} else {2}
             },
+            0x0400_0180..=0x0400_018F => {  // IPC
+                // The ARM7 might be told to run code that was just written.
+                self.send_arm7_jit_dirty();
+                self.io_write_word(addr, data);
+                if cycle.is_non_seq() {8} else {2}
+            },
+            0x0400_0400..=0x0400_05FF => {  // Geometry commands
+                self.wait_for_geom_fifo();
+                self.io_write_word(addr, data);
+                if cycle.is_non_seq() {8} else {2}
+            },
             0x0400_0000..=0x04FF_FFFF => {
                 self.io_write_word(addr, data);
                 if cycle.is_non_seq() {8} else {2}
@@ -718,7 +934,19 @@ impl<R: Renderer> Mem32 for DS9MemoryBus<R> {
                 if cycle.is_non_seq() {8} else {2}
             },
 
-            // TODO: GBA slot
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => {
+                if self.ex_mem_control.has_gba_access() {
+                    self.gba_slot.write_word(addr, data);
+                }
+                self.ex_mem_control.gba_access().rom_cycles(cycle) + self.ex_mem_control.gba_access().rom_cycles(MemCycleType::S)
+            },
+            0x0A00_0000..=0x0AFF_FFFF => {
+                if self.ex_mem_control.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data as u8);
+                }
+                self.ex_mem_control.gba_access().ram_cycles()
+            },
 
             _ => 1 // Unused
         }
@@ -770,13 +998,21 @@ pub struct DS7MemoryBus {
     interrupt_control:  InterruptControl,
     ex_mem_status:      ExMemStatus,
     card:               DSCardIO,
+    gba_slot:           GBASlot,
 
     // Sync
     inner_counter:      usize,
     counter:            usize,
     v_counter:          usize,
     barrier:            Arc<Barrier>,
-    input_recv:         Receiver<UserInput>
+    input_recv:         Receiver<UserInput>,
+
+    jit:                JITTracker,
+    /// Writes from the ARM9 into memory that the JIT compiles from.
+    arm9_jit_recv:      Receiver<Range<u32>>,
+
+    /// BIOS routine requested by an emulated SWI.
+    bios_call:          Option<u32>,
 }
 
 impl DS7MemoryBus {
@@ -790,6 +1026,11 @@ impl DS7MemoryBus {
         for (n, byte) in buffer.iter().enumerate() {
             self.store_byte(MemCycleType::N, arm7_addr + (n as u32), *byte);
         }
+
+        // Copy user settings from firmware into RAM.
+        for (n, byte) in self.spi.firmware_user_settings().iter().enumerate() {
+            self.main_ram.write_byte((0x3F_FC80 + n) as u32, *byte);
+        }
     }
 
     pub fn enable_audio(&mut self) -> Receiver<SamplePacket> {
@@ -797,6 +1038,23 @@ impl DS7MemoryBus {
         self.audio.enable_audio(sample_tx);
         sample_rx
     }
+
+    /// Get a range of JIT-compiled memory that has been written to since it was last taken,
+    /// by either CPU. Call until None is returned.
+    pub fn take_jit_dirty(&mut self) -> Option<Range<u32>> {
+        self.jit.take_dirty().or_else(|| self.arm9_jit_recv.try_recv().ok())
+    }
+
+    /// Get the BIOS routine that an emulated SWI requested, if any.
+    /// The CPU should call it with `call_bios_routine`.
+    pub fn take_bios_call(&mut self) -> Option<u32> {
+        self.bios_call.take()
+    }
+
+    /// Get the screen backlight state, which can be read from another thread.
+    pub fn backlights(&self) -> Backlights {
+        self.spi.backlights()
+    }
 }
 
 // Internal
@@ -903,6 +1161,8 @@ impl DS7MemoryBus {
             if let Ok(new_input) = self.input_recv.try_recv() {
                 self.set_input(new_input);
                 self.card.flush_save();
+                self.gba_slot.flush_save();
+                self.spi.flush_firmware();
             }
         }
 
@@ -934,7 +1194,7 @@ impl DS7MemoryBus {
             card_interrupt
         } else {
             Interrupts::empty()
-        };
+        } | self.card.take_detect_interrupt();
 
         let v_count_irq = self.video.v_count_irq();
 
@@ -955,6 +1215,14 @@ impl DS7MemoryBus {
             Interrupts::empty()
         };
 
+        self.spi.clock_microphone(cycles);
+
+        let rtc_irq = if self.rtc.clock(cycles) {
+            Interrupts::RTC
+        } else {
+            Interrupts::empty()
+        };
+
         self.interrupt_control.interrupt_request(
             joypad_irq |
             Interrupts::from_bits_truncate(timer_irq.into()) |
@@ -962,7 +1230,8 @@ impl DS7MemoryBus {
             card_interrupt |
             v_count_irq |
             vblank |
-            wifi_irq
+            wifi_irq |
+            rtc_irq
         );
     }
 
@@ -971,9 +1240,18 @@ impl DS7MemoryBus {
     }
 
     fn set_input(&mut self, new_input: UserInput) {
+        let lid_was_closed = self.ds_joypad.lid_closed();
         self.joypad.set_all_buttons(new_input.buttons);
         self.ds_joypad.set_all_buttons(new_input.ds_buttons);
+        if lid_was_closed && !self.ds_joypad.lid_closed() {
+            self.interrupt_control.interrupt_request(Interrupts::SCREEN_UNFOLD);
+        }
         self.spi.write_tsc_values(new_input.touchscreen);
+        self.spi.write_microphone_samples(&new_input.microphone);
+        self.gba_slot.set_guitar_frets(new_input.guitar_frets);
+        if let Some(time) = new_input.rtc_time {
+            self.rtc.set_time(time);
+        }
     }
 }
 
@@ -981,6 +1259,22 @@ impl Mem32 for DS7MemoryBus {
     type Addr = u32;
 
     fn clock(&mut self, cycles: usize) -> Option<arm::ExternalException> {
+        // Check if system is in sleep mode.
+        // A new enabled wake interrupt (usually opening the lid) wakes it up.
+        // The ARM9 is halted by the game itself.
+        if self.power_control.sleep {
+            self.spi.set_sleep(true);
+            self.interrupt_control.start_sleep();
+            while !self.interrupt_control.wake_pending() {
+                // Input only arrives at each thread sync, so skip ahead.
+                // Audio output generates at most one sample per step.
+                let cycles = std::cmp::min(ARM7_THREAD_SYNC_CYCLES - self.counter, self.audio.cycles_to_next_sample());
+                self.do_clock(cycles);
+            }
+            self.power_control.sleep = false;
+            self.spi.set_sleep(false);
+        }
+
         // Check if CPU is halted.
         if self.power_control.halt {
             loop {
@@ -1034,9 +1328,9 @@ impl Mem32 for DS7MemoryBus {
 
             0x0600_0000..=0x06FF_FFFF => (self.vram.read_byte(addr), 1),
 
-            // TODO: GBA slot
-            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_status.has_gba_access() {0xFF} else {0}, 18),
-            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_status.has_gba_access() {0xFF} else {0}, 18),
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_status.has_gba_access() {self.gba_slot.read_byte(addr)} else {0}, self.ex_mem_status.gba_access().rom_cycles(cycle)),
+            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_status.has_gba_access() {self.gba_slot.read_byte(addr)} else {0}, self.ex_mem_status.gba_access().ram_cycles()),
 
             _ => (0, 1) // Unused
         }
@@ -1045,14 +1339,17 @@ impl Mem32 for DS7MemoryBus {
         match addr {
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.main_ram.write_byte(addr & 0x3F_FFFF, data);
+                self.jit.write(addr, 1, &ARM7_MAIN_RAM);
                 if cycle.is_non_seq() {DS7_MAIN_RAM_N} else {DS7_MAIN_RAM_S}
             },
             0x0300_0000..=0x037F_FFFF => {  // Shared RAM
                 self.shared_wram.write_byte(addr, data);
+                self.jit.write(addr, 1, &ARM7_SHARED_WRAM);
                 1
             },
             0x0380_0000..=0x03FF_FFFF => {  // ARM7 WRAM
                 self.wram.write_byte(addr & 0xFFFF, data);
+                self.jit.write(addr, 1, &ARM7_WRAM);
                 1
             },
 
@@ -1066,7 +1363,19 @@ impl Mem32 for DS7MemoryBus {
                 1
             },
 
-            // TODO: GBA slot
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => {
+                if self.ex_mem_status.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data);
+                }
+                self.ex_mem_status.gba_access().rom_cycles(cycle)
+            },
+            0x0A00_0000..=0x0AFF_FFFF => {
+                if self.ex_mem_status.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data);
+                }
+                self.ex_mem_status.gba_access().ram_cycles()
+            },
 
             _ => 1 // Unused
         }
@@ -1086,9 +1395,9 @@ impl Mem32 for DS7MemoryBus {
 
             0x0600_0000..=0x06FF_FFFF => (self.vram.read_halfword(addr), 1),
 
-            // TODO: GBA slot
-            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_status.has_gba_access() {0xFFFF} else {0}, 18),
-            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_status.has_gba_access() {0xFFFF} else {0}, 18),
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_status.has_gba_access() {self.gba_slot.read_halfword(addr)} else {0}, self.ex_mem_status.gba_access().rom_cycles(cycle)),
+            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_status.has_gba_access() {self.gba_slot.read_halfword(addr)} else {0}, self.ex_mem_status.gba_access().ram_cycles()),
 
             _ => (0, 1) // Unused
         }
@@ -1097,14 +1406,17 @@ impl Mem32 for DS7MemoryBus {
         match addr {
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.main_ram.write_halfword(addr & 0x3F_FFFF, data);
+                self.jit.write(addr, 2, &ARM7_MAIN_RAM);
                 if cycle.is_non_seq() {DS7_MAIN_RAM_N} else {DS7_MAIN_RAM_S}
             },
             0x0300_0000..=0x037F_FFFF => {  // Shared RAM
                 self.shared_wram.write_halfword(addr, data);
+                self.jit.write(addr, 2, &ARM7_SHARED_WRAM);
                 1
             },
             0x0380_0000..=0x03FF_FFFF => {  // ARM7 WRAM
                 self.wram.write_halfword(addr & 0xFFFF, data);
+                self.jit.write(addr, 2, &ARM7_WRAM);
                 1
             },
 
@@ -1118,7 +1430,19 @@ impl Mem32 for DS7MemoryBus {
                 1
             },
 
-            // TODO: GBA slot
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => {
+                if self.ex_mem_status.has_gba_access() {
+                    self.gba_slot.write_halfword(addr, data);
+                }
+                self.ex_mem_status.gba_access().rom_cycles(cycle)
+            },
+            0x0A00_0000..=0x0AFF_FFFF => {
+                if self.ex_mem_status.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data as u8);
+                }
+                self.ex_mem_status.gba_access().ram_cycles()
+            },
 
             _ => 1 // Unused
         }
@@ -1139,9 +1463,9 @@ impl Mem32 for DS7MemoryBus {
 
             0x0600_0000..=0x06FF_FFFF => (self.vram.read_word(addr), 2),
 
-            // TODO: GBA slot
-            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_status.has_gba_access() {0xFFFF_FFFF} else {0}, 18),
-            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_status.has_gba_access() {0xFFFF_FFFF} else {0}, 18),
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => (if self.ex_mem_status.has_gba_access() {self.gba_slot.read_word(addr)} else {0}, self.ex_mem_status.gba_access().rom_cycles(cycle) + self.ex_mem_status.gba_access().rom_cycles(MemCycleType::S)),
+            0x0A00_0000..=0x0AFF_FFFF => (if self.ex_mem_status.has_gba_access() {self.gba_slot.read_word(addr)} else {0}, self.ex_mem_status.gba_access().ram_cycles()),
 
             _ => (0, 1) // Unused
         }
@@ -1150,14 +1474,17 @@ impl Mem32 for DS7MemoryBus {
         match addr {
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.main_ram.write_word(addr & 0x3F_FFFF, data);
+                self.jit.write(addr, 4, &ARM7_MAIN_RAM);
                 if cycle.is_non_seq() {DS7_MAIN_RAM_WORD_N} else {DS7_MAIN_RAM_WORD_S}
             },
             0x0300_0000..=0x037F_FFFF => {  // Shared RAM
                 self.shared_wram.write_word(addr, data);
+                self.jit.write(addr, 4, &ARM7_SHARED_WRAM);
                 1
             },
             0x0380_0000..=0x03FF_FFFF => {  // ARM7 WRAM
                 self.wram.write_word(addr & 0xFFFF, data);
+                self.jit.write(addr, 4, &ARM7_WRAM);
                 1
             },
 
@@ -1171,7 +1498,19 @@ impl Mem32 for DS7MemoryBus {
                 2
             },
 
-            // TODO: GBA slot
+            // GBA slot
+            0x0800_0000..=0x09FF_FFFF => {
+                if self.ex_mem_status.has_gba_access() {
+                    self.gba_slot.write_word(addr, data);
+                }
+                self.ex_mem_status.gba_access().rom_cycles(cycle) + self.ex_mem_status.gba_access().rom_cycles(MemCycleType::S)
+            },
+            0x0A00_0000..=0x0AFF_FFFF => {
+                if self.ex_mem_status.has_gba_access() {
+                    self.gba_slot.write_byte(addr, data as u8);
+                }
+                self.ex_mem_status.gba_access().ram_cycles()
+            },
 
             _ => 1 // Unused
         }
@@ -1198,3 +1537,104 @@ impl DS7MemoryBus {
         (0x0480_0000, 0x0480_8FFF, wifi)
     }
 }
+
+impl DSBIOSMem for DS7MemoryBus {
+    fn halt(&mut self) {
+        self.power_control.halt = true;
+    }
+
+    fn irq_check_addr(&self) -> u32 {
+        0x0380_FFF8
+    }
+
+    fn request_intr_wait(&mut self) {
+        self.bios_call = Some(ARM7_INTR_WAIT);
+    }
+}
+
+/// Interrupt wait loop in the emulated ARM9 BIOS.
+pub const ARM9_INTR_WAIT: u32 = 0xFFFF_0200;
+
+/// Construct a minimal ARM9 BIOS, for use when no BIOS file is provided.
+/// 
+/// Contains the IRQ handler, which jumps to the address at the top of DTCM,
+/// and the interrupt wait loop.
+/// Other SWI calls are handled by `emulated_swi_arm9`.
+pub fn construct_arm9_bios() -> BIOS {
+    let mut bios_mem = vec![0; 0x1000];
+
+    write_word_to_mem(&mut bios_mem, 0x18, 0xEA00_0038);    // B 0x100
+    write_word_to_mem(&mut bios_mem, 0x100, 0xE92D_500F);   // STMFD SP! R0-3,R12,R14
+    write_word_to_mem(&mut bios_mem, 0x104, 0xEE19_0F11);   // MRC P15,0,R0,C9,C1,0
+    write_word_to_mem(&mut bios_mem, 0x108, 0xE1A0_0620);   // MOV R0,R0,LSR#12
+    write_word_to_mem(&mut bios_mem, 0x10C, 0xE1A0_0600);   // MOV R0,R0,LSL#12
+    write_word_to_mem(&mut bios_mem, 0x110, 0xE280_0901);   // ADD R0,R0,#0x4000
+    write_word_to_mem(&mut bios_mem, 0x114, 0xE28F_E000);   // ADD R14,R15,0
+    write_word_to_mem(&mut bios_mem, 0x118, 0xE510_F004);   // LDR R15,[R0,#-4]
+    write_word_to_mem(&mut bios_mem, 0x11C, 0xE8BD_500F);   // LDMFD SP! R0-3,R12,R14
+    write_word_to_mem(&mut bios_mem, 0x120, 0xE25E_F004);   // SUBS R15,R14,#4
+
+    // Wait until one of the flags in R0 is set at the address in R1, then clear it.
+    // Called by `call_bios_routine`, which puts the return address in R3.
+    write_word_to_mem(&mut bios_mem, 0x200, 0xE92D_1004);   // STMFD SP! R2,R12
+    write_word_to_mem(&mut bios_mem, 0x204, 0xE591_2000);   // LDR R2,[R1]
+    write_word_to_mem(&mut bios_mem, 0x208, 0xE012_C000);   // ANDS R12,R2,R0
+    write_word_to_mem(&mut bios_mem, 0x20C, 0x1A00_0001);   // BNE 0x218
+    write_word_to_mem(&mut bios_mem, 0x210, 0xEE07_0F90);   // MCR P15,0,R0,C7,C0,4 (halt)
+    write_word_to_mem(&mut bios_mem, 0x214, 0xEAFF_FFFA);   // B 0x204
+    write_word_to_mem(&mut bios_mem, 0x218, 0xE1C2_2000);   // BIC R2,R2,R0
+    write_word_to_mem(&mut bios_mem, 0x21C, 0xE581_2000);   // STR R2,[R1]
+    write_word_to_mem(&mut bios_mem, 0x220, 0xE8BD_1004);   // LDMFD SP! R2,R12
+    write_word_to_mem(&mut bios_mem, 0x224, 0xE12F_FF13);   // BX R3
+
+    BIOS::new_from_data(bios_mem)
+}
+
+/// Returned by the emulated GetBootProcs in place of the BIOS boot procedures.
+const BOOT_PROC_STUB: u32 = 0x120;
+
+/// Interrupt wait loop in the emulated ARM7 BIOS.
+const ARM7_INTR_WAIT: u32 = 0x200;
+
+/// Construct a minimal ARM7 BIOS, for use when no BIOS file is provided.
+/// 
+/// Contains the IRQ handler, which jumps to the address at 0x0380_FFFC,
+/// a stub for GetBootProcs, and the interrupt wait loop.
+/// Other SWI calls are handled by `emulated_swi_arm7`.
+pub fn construct_arm7_bios() -> BIOS {
+    let mut bios_mem = vec![0; 0x4000];
+
+    write_word_to_mem(&mut bios_mem, 0x18, 0xEA00_0038);    // B 0x100
+    write_word_to_mem(&mut bios_mem, 0x100, 0xE92D_500F);   // STMFD SP! R0-3,R12,R14
+    write_word_to_mem(&mut bios_mem, 0x104, 0xE3A0_0301);   // MOV R0,#0400_0000
+    write_word_to_mem(&mut bios_mem, 0x108, 0xE28F_E000);   // ADD R14,R15,0
+    write_word_to_mem(&mut bios_mem, 0x10C, 0xE510_F004);   // LDR R15,[R0,#-4]
+    write_word_to_mem(&mut bios_mem, 0x110, 0xE8BD_500F);   // LDMFD SP! R0-3,R12,R14
+    write_word_to_mem(&mut bios_mem, 0x114, 0xE25E_F004);   // SUBS R15,R14,#4
+
+    write_word_to_mem(&mut bios_mem, BOOT_PROC_STUB as usize, 0xE12F_FF1E);   // BX R14
+
+    // Wait until one of the flags in R0 is set at the address in R1, then clear it.
+    // Called by `call_bios_routine`, which puts the return address in R3.
+    write_word_to_mem(&mut bios_mem, 0x200, 0xE92D_1004);   // STMFD SP! R2,R12
+    write_word_to_mem(&mut bios_mem, 0x204, 0xE591_2000);   // LDR R2,[R1]
+    write_word_to_mem(&mut bios_mem, 0x208, 0xE012_C000);   // ANDS R12,R2,R0
+    write_word_to_mem(&mut bios_mem, 0x20C, 0x1A00_0003);   // BNE 0x220
+    write_word_to_mem(&mut bios_mem, 0x210, 0xE3A0_C301);   // MOV R12,#0400_0000
+    write_word_to_mem(&mut bios_mem, 0x214, 0xE3A0_2080);   // MOV R2,#0x80
+    write_word_to_mem(&mut bios_mem, 0x218, 0xE5CC_2301);   // STRB R2,[R12,#0x301] (halt)
+    write_word_to_mem(&mut bios_mem, 0x21C, 0xEAFF_FFF8);   // B 0x204
+    write_word_to_mem(&mut bios_mem, 0x220, 0xE1C2_2000);   // BIC R2,R2,R0
+    write_word_to_mem(&mut bios_mem, 0x224, 0xE581_2000);   // STR R2,[R1]
+    write_word_to_mem(&mut bios_mem, 0x228, 0xE8BD_1004);   // LDMFD SP! R2,R12
+    write_word_to_mem(&mut bios_mem, 0x22C, 0xE12F_FF13);   // BX R3
+
+    BIOS::new_from_data(bios_mem)
+}
+
+fn write_word_to_mem(mem: &mut [u8], addr: usize, data: u32) {
+    let bytes = data.to_le_bytes();
+    for (dest, byte) in mem[addr..(addr + 4)].iter_mut().zip(&bytes) {
+        *dest = *byte;
+    }
+}
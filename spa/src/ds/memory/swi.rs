@@ -0,0 +1,596 @@
+/// Emulated software interrupts for the DS.
+///
+/// These can be used in place of the ARM7 and ARM9 BIOS ROMs.
+
+use arm::{
+    Mem32,
+    MemCycleType,
+    ARMCore,
+    CPSR
+};
+use crate::{
+    common::swi::*,
+    ds::interrupt::Interrupts
+};
+use super::BOOT_PROC_STUB;
+
+/// Memory that the emulated BIOS can run on.
+///
+/// The ARM7 and ARM9 differ in how they halt,
+/// and where the interrupt check flags are.
+pub trait DSBIOSMem: Mem32<Addr = u32> {
+    /// Halt the CPU until an interrupt occurs.
+    fn halt(&mut self);
+    /// Address of the interrupt check flags, which are set by the game's IRQ handler.
+    fn irq_check_addr(&self) -> u32;
+    /// Request that the CPU runs the BIOS interrupt wait loop once the SWI returns.
+    ///
+    /// The loop has to run on the CPU, so that the game's IRQ handler can run while waiting.
+    fn request_intr_wait(&mut self);
+}
+
+/// Call the BIOS routine requested by an emulated SWI.
+///
+/// The SWI has already returned, so the routine runs in the caller's mode,
+/// and returns to the caller through R3.
+pub fn call_bios_routine<M: Mem32<Addr = u32>>(cpu: &mut impl ARMCore<M>, addr: u32) {
+    let mut cpsr = cpu.read_cpsr();
+    let return_addr = cpu.read_reg(15);
+    if cpsr.contains(CPSR::T) {
+        cpu.write_reg(3, return_addr | 1);
+        cpsr.remove(CPSR::T);
+        cpu.write_cpsr(cpsr);
+    } else {
+        cpu.write_reg(3, return_addr);
+    }
+    cpu.do_branch(addr);
+}
+
+/// Emulated software interrupt for DS ARM9.
+///
+/// Implements the BIOS SWI calls, clocks internally.
+///
+/// Input args are regs 0-3. Output args are regs 0, 1, 3.
+pub fn emulated_swi_arm9(comment: u32, mem: &mut impl DSBIOSMem, regs: &[u32; 4]) -> [u32; 3] {
+    let function = (comment as u8) | ((comment >> 16) as u8);
+    match function {
+        0x16 => {
+            diff_8_unfilter_byte(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x18 => {
+            diff_16_unfilter(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        _ => emulated_swi(function, mem, regs)
+    }
+}
+
+/// Emulated software interrupt for DS ARM7.
+///
+/// Implements the BIOS SWI calls, clocks internally.
+///
+/// Input args are regs 0-3. Output args are regs 0, 1, 3.
+pub fn emulated_swi_arm7(comment: u32, mem: &mut impl DSBIOSMem, regs: &[u32; 4]) -> [u32; 3] {
+    let function = (comment as u8) | ((comment >> 16) as u8);
+    match function {
+        0x07 => {
+            sleep(mem);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x08 => {
+            sound_bias(mem, regs[0]);
+            [regs[0], regs[1], regs[3]]
+        },
+        // Sound tables
+        0x1A => {
+            mem.clock(10);
+            [sine_table(regs[0]), regs[1], regs[3]]
+        },
+        0x1B => {
+            mem.clock(10);
+            [pitch_table(regs[0]), regs[1], regs[3]]
+        },
+        0x1C => {
+            mem.clock(10);
+            [volume_table(regs[0]), regs[1], regs[3]]
+        },
+        0x1D => {
+            // The emulated BIOS has no boot procedures to return,
+            // so the pointers in r0 and r1 are to a stub which returns immediately.
+            mem.clock(10);
+            [BOOT_PROC_STUB, BOOT_PROC_STUB, regs[3]]
+        },
+        _ => emulated_swi(function, mem, regs)
+    }
+}
+
+/// SWI calls that are common to both processors.
+fn emulated_swi(function: u8, mem: &mut impl DSBIOSMem, regs: &[u32; 4]) -> [u32; 3] {
+    match function {
+        // Halt
+        0x03 => {
+            mem.clock((regs[0] as usize) * 4);
+            [0, regs[1], regs[3]]
+        },
+        0x04 => intr_wait(mem, regs[0], regs[1]),
+        0x05 => intr_wait(mem, 1, Interrupts::V_BLANK.bits()),
+        0x06 => {
+            mem.halt();
+            mem.clock(20);
+            [regs[0], regs[1], regs[3]]
+        },
+        // Maths
+        0x09 => {
+            mem.clock(100);
+            divide(regs[0], regs[1])
+        },
+        0x0D => {
+            mem.clock(100);
+            let res = sqrt(regs[0]);
+            [res, regs[1], regs[3]]
+        },
+        // Memset
+        0x0B => {
+            cpu_set(mem, regs[0], regs[1], regs[2]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x0C => {
+            cpu_fast_set(mem, regs[0], regs[1], regs[2]);
+            [regs[0], regs[1], regs[3]]
+        },
+        // Misc
+        0x0E => {
+            let crc = get_crc16(mem, regs[0], regs[1], regs[2]);
+            [crc, regs[1], regs[3]]
+        },
+        0x0F => {
+            // Retail console.
+            mem.clock(10);
+            [0, regs[1], regs[3]]
+        },
+        0x1F => {
+            let cycles = mem.store_byte(MemCycleType::N, 0x0400_0300, regs[0] as u8);
+            mem.clock(cycles);
+            [regs[0], regs[1], regs[3]]
+        },
+        // Decompression
+        0x10 => {
+            bit_unpack(mem, regs[0], regs[1], regs[2]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x11 => {
+            lz77_uncomp_byte(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x14 => {
+            rl_uncomp_byte(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        // The "ReadByCallback" decompressors read through the game's callbacks in r2,
+        // and the emulated BIOS can't call into game code. Nothing is decompressed.
+        0x12 | 0x13 | 0x15 => {
+            println!("SWI 0x{:X} reads through game callbacks, which is not supported without the BIOS", function);
+            mem.clock(10);
+            [regs[0], regs[1], regs[3]]
+        },
+        _ => panic!("unsupported SWI 0x{:X}. This ROM requires the BIOS", function),
+    }
+}
+
+/*** HALT ***/
+
+/// Wait until one of `int_flags` is set in the interrupt check flags.
+///
+/// Like the real BIOS, this halts and lets the game's IRQ handler run
+/// until it sets one of the check flags, which is then cleared.
+/// The loop itself runs in the BIOS, so that the handler can run.
+///
+/// Returns the flags in r0 and the check address in r1, for the loop to use.
+fn intr_wait(mem: &mut impl DSBIOSMem, discard_old_flags: u32, int_flags: u32) -> [u32; 3] {
+    // Set master interrupt flag.
+    let cycles = mem.store_word(MemCycleType::N, 0x0400_0208, 1);
+    mem.clock(cycles);
+
+    let check_addr = mem.irq_check_addr();
+    if discard_old_flags != 0 {
+        // Wait for a new interrupt, even if one has been handled already.
+        let (check_data, load_cycles) = mem.load_word(MemCycleType::N, check_addr);
+        let store_cycles = mem.store_word(MemCycleType::N, check_addr, check_data & !int_flags);
+        mem.clock(load_cycles + store_cycles);
+    }
+
+    mem.request_intr_wait();
+    [int_flags, check_addr, 0]
+}
+
+fn sleep(mem: &mut impl DSBIOSMem) {
+    let cycles = mem.store_byte(MemCycleType::N, 0x0400_0301, 0xC0);
+    mem.clock(cycles + 20);
+}
+
+/*** MISC ***/
+
+fn get_crc16(mem: &mut impl DSBIOSMem, initial: u32, src_addr: u32, len: u32) -> u32 {
+    let mut crc = initial as u16;
+    for addr in (src_addr..(src_addr + len)).step_by(2) {
+        let (data, cycles) = mem.load_halfword(MemCycleType::N, addr);
+        mem.clock(cycles + 16);
+        crc = crc16(crc, &data.to_le_bytes());
+    }
+    crc as u32
+}
+
+/*** SOUND ***/
+
+fn sound_bias(mem: &mut impl DSBIOSMem, level: u32) {
+    let target = if level == 0 {0} else {0x200};
+    // The BIOS moves the level slowly towards the target.
+    // Here it is set in one go.
+    let cycles = mem.store_halfword(MemCycleType::N, 0x0400_0504, target);
+    mem.clock(cycles + 100);
+}
+
+/// Sine from 0 to pi/2, in 64 steps. Signed 1.15 fixed point.
+fn sine_table(index: u32) -> u32 {
+    let angle = ((index & 0x3F) as f64) * std::f64::consts::PI / 128.0;
+    (angle.sin() * (0x8000 as f64)).round() as u32
+}
+
+/// Frequency multiplier for each 1/768 of an octave. 0.16 fixed point, excluding the integer part.
+fn pitch_table(index: u32) -> u32 {
+    let octave = (index.min(0x2FF) as f64) / 768.0;
+    ((octave.exp2() - 1.0) * (0x10000 as f64)) as u32
+}
+
+/// Channel volume for each 0.1dB from -72.3dB to 0dB.
+///
+/// The value is scaled to fit the volume divider that the sound library
+/// selects for the attenuation.
+fn volume_table(index: u32) -> u32 {
+    let decibels = (index.min(723) as i32) - 723;
+    let divider = if decibels < -240 {
+        16.0
+    } else if decibels < -120 {
+        4.0
+    } else if decibels < -60 {
+        2.0
+    } else {
+        1.0
+    };
+    let amplitude = 10_f64.powf((decibels as f64) / 200.0);
+    (amplitude * divider * 127.0).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arm::{ARM7TDMI, ARMDriver, ExternalException};
+    use crate::common::mem::{bios::BIOS, ram::RAM};
+    use super::super::{construct_arm7_bios, ARM7_INTR_WAIT};
+
+    const VBLANK_CYCLES: usize = 1000;
+    const CODE_ADDR: u32 = 0x0200_0000;
+    const HANDLER_ADDR: u32 = 0x0200_0100;
+    const CHECK_ADDR: u32 = 0xFFF8;
+
+    /// Acknowledges V-blank, sets its check flag, and counts in R5.
+    const HANDLER: [u32; 8] = [
+        0xE3A0_0301,    // MOV R0,#0400_0000
+        0xE3A0_1001,    // MOV R1,#1
+        0xE580_1214,    // STR R1,[R0,#0x214]
+        0xE510_3008,    // LDR R3,[R0,#-8]
+        0xE383_3001,    // ORR R3,R3,#1
+        0xE500_3008,    // STR R3,[R0,#-8]
+        0xE285_5001,    // ADD R5,R5,#1
+        0xE12F_FF1E,    // BX R14
+    ];
+
+    /// ARM7 memory with a V-blank interrupt.
+    struct TestMem {
+        bios:       BIOS,
+        main_ram:   RAM,
+        wram:       RAM,
+
+        ime:        u32,
+        ie:         u32,
+        irq_flags:  u32,
+        halt:       bool,
+
+        cycles:     usize,
+        vblanks:    usize,
+        bios_call:  Option<u32>,
+    }
+
+    impl TestMem {
+        fn new() -> Self {
+            Self {
+                bios:       construct_arm7_bios(),
+                main_ram:   RAM::new(0x1_0000),
+                wram:       RAM::new(0x1_0000),
+
+                ime:        0,
+                ie:         Interrupts::V_BLANK.bits(),
+                irq_flags:  0,
+                halt:       false,
+
+                cycles:     0,
+                vblanks:    0,
+                bios_call:  None,
+            }
+        }
+
+        fn read_io(&self, addr: u32) -> u32 {
+            match addr {
+                0x0400_0208 => self.ime,
+                0x0400_0210 => self.ie,
+                0x0400_0214 => self.irq_flags,
+                _ => 0,
+            }
+        }
+
+        fn write_io(&mut self, addr: u32, data: u32) {
+            match addr {
+                0x0400_0208 => self.ime = data,
+                0x0400_0210 => self.ie = data,
+                0x0400_0214 => self.irq_flags &= !data,
+                _ => {},
+            }
+        }
+    }
+
+    impl Mem32 for TestMem {
+        type Addr = u32;
+
+        fn load_byte(&mut self, _cycle: MemCycleType, addr: Self::Addr) -> (u8, usize) {
+            (match addr >> 24 {
+                0x00 => self.bios.read_byte(addr),
+                0x02 => self.main_ram.read_byte(addr & 0xFFFF),
+                0x03 => self.wram.read_byte(addr & 0xFFFF),
+                _ => 0,
+            }, 1)
+        }
+        fn store_byte(&mut self, _cycle: MemCycleType, addr: Self::Addr, data: u8) -> usize {
+            match addr >> 24 {
+                0x02 => self.main_ram.write_byte(addr & 0xFFFF, data),
+                0x03 => self.wram.write_byte(addr & 0xFFFF, data),
+                0x04 if addr == 0x0400_0301 => self.halt = (data & 0x80) != 0,
+                _ => {},
+            }
+            1
+        }
+
+        fn load_halfword(&mut self, _cycle: MemCycleType, addr: Self::Addr) -> (u16, usize) {
+            (match addr >> 24 {
+                0x00 => self.bios.read_halfword(addr),
+                0x02 => self.main_ram.read_halfword(addr & 0xFFFF),
+                0x03 => self.wram.read_halfword(addr & 0xFFFF),
+                _ => 0,
+            }, 1)
+        }
+        fn store_halfword(&mut self, _cycle: MemCycleType, addr: Self::Addr, data: u16) -> usize {
+            match addr >> 24 {
+                0x02 => self.main_ram.write_halfword(addr & 0xFFFF, data),
+                0x03 => self.wram.write_halfword(addr & 0xFFFF, data),
+                _ => {},
+            }
+            1
+        }
+
+        fn load_word(&mut self, _cycle: MemCycleType, addr: Self::Addr) -> (u32, usize) {
+            (match addr >> 24 {
+                0x00 => self.bios.read_word(addr),
+                0x02 => self.main_ram.read_word(addr & 0xFFFF),
+                0x03 => self.wram.read_word(addr & 0xFFFF),
+                0x04 => self.read_io(addr),
+                _ => 0,
+            }, 1)
+        }
+        fn store_word(&mut self, _cycle: MemCycleType, addr: Self::Addr, data: u32) -> usize {
+            match addr >> 24 {
+                0x02 => self.main_ram.write_word(addr & 0xFFFF, data),
+                0x03 => self.wram.write_word(addr & 0xFFFF, data),
+                0x04 => self.write_io(addr, data),
+                _ => {},
+            }
+            1
+        }
+
+        fn clock(&mut self, cycles: usize) -> Option<ExternalException> {
+            // When halted, skip ahead to the next V-blank.
+            let cycles = if self.halt && (self.ie & self.irq_flags) == 0 {
+                VBLANK_CYCLES - (self.cycles % VBLANK_CYCLES)
+            } else {
+                cycles
+            };
+            let new_vblanks = (self.cycles + cycles) / VBLANK_CYCLES - self.cycles / VBLANK_CYCLES;
+            if new_vblanks > 0 {
+                self.vblanks += new_vblanks;
+                self.irq_flags |= Interrupts::V_BLANK.bits();
+            }
+            self.cycles += cycles;
+
+            if (self.ie & self.irq_flags) != 0 {
+                self.halt = false;
+                if (self.ime & 1) != 0 {
+                    return Some(ExternalException::IRQ);
+                }
+            }
+            None
+        }
+    }
+
+    impl DSBIOSMem for TestMem {
+        fn halt(&mut self) {
+            self.halt = true;
+        }
+
+        fn irq_check_addr(&self) -> u32 {
+            0x0380_0000 | CHECK_ADDR
+        }
+
+        fn request_intr_wait(&mut self) {
+            self.bios_call = Some(ARM7_INTR_WAIT);
+        }
+    }
+
+    fn new_test_cpu(code: &[u32]) -> ARM7TDMI<TestMem> {
+        let mut mem = Box::new(TestMem::new());
+        for (n, instr) in code.iter().enumerate() {
+            mem.main_ram.write_word((n * 4) as u32, *instr);
+        }
+        for (n, instr) in HANDLER.iter().enumerate() {
+            mem.main_ram.write_word((HANDLER_ADDR & 0xFFFF) + (n * 4) as u32, *instr);
+        }
+        mem.wram.write_word(0xFFFC, HANDLER_ADDR);
+
+        let mut cpu = ARM7TDMI::new(mem).set_swi_hook(emulated_swi_arm7).build();
+        cpu.do_branch(CODE_ADDR);
+        cpu.write_cpsr(CPSR::IRQ);
+        cpu.write_reg(13, 0x0380_FFB0);
+        cpu.write_cpsr(CPSR::SYS);
+        cpu.write_reg(13, 0x0380_FF00);
+        cpu.write_cpsr(CPSR::USR);
+        cpu
+    }
+
+    /// Step until R4 reaches `count`.
+    fn run_until_count(cpu: &mut ARM7TDMI<TestMem>, count: u32) {
+        for _ in 0..100_000 {
+            if cpu.read_reg(4) == count {
+                return;
+            }
+            cpu.step();
+            if let Some(addr) = cpu.mut_mem().bios_call.take() {
+                call_bios_routine(cpu, addr);
+            }
+        }
+        panic!("R4 did not reach {}", count);
+    }
+
+    #[test]
+    fn vblank_intr_wait() {
+        let mut cpu = new_test_cpu(&[
+            0xEF05_0000,    // SWI 0x05
+            0xE284_4001,    // ADD R4,R4,#1
+            0xEF05_0000,    // SWI 0x05
+            0xE284_4001,    // ADD R4,R4,#1
+            0xEAFF_FFFE,    // B 0x0200_0010
+        ]);
+        // Stale flag from an earlier interrupt should be discarded.
+        cpu.mut_mem().wram.write_word(CHECK_ADDR, 1);
+
+        run_until_count(&mut cpu, 1);
+        assert_eq!(cpu.mut_mem().vblanks, 1);
+        assert_eq!(cpu.read_reg(5), 1);
+        assert_eq!(cpu.mut_mem().wram.read_word(CHECK_ADDR), 0);
+
+        // The second wait must not return until the next V-blank has been handled.
+        run_until_count(&mut cpu, 2);
+        assert_eq!(cpu.mut_mem().vblanks, 2);
+        assert_eq!(cpu.read_reg(5), 2);
+        assert_eq!(cpu.mut_mem().wram.read_word(CHECK_ADDR), 0);
+    }
+
+    #[test]
+    fn intr_wait_already_handled() {
+        let mut cpu = new_test_cpu(&[
+            0xE3A0_0000,    // MOV R0,#0
+            0xE3A0_1001,    // MOV R1,#1
+            0xEF04_0000,    // SWI 0x04
+            0xE284_4001,    // ADD R4,R4,#1
+            0xEAFF_FFFE,    // B 0x0200_0010
+        ]);
+        cpu.mut_mem().wram.write_word(CHECK_ADDR, 1);
+
+        run_until_count(&mut cpu, 1);
+        assert_eq!(cpu.mut_mem().vblanks, 0);
+        assert_eq!(cpu.mut_mem().wram.read_word(CHECK_ADDR), 0);
+    }
+
+    #[test]
+    fn halt() {
+        let mut cpu = new_test_cpu(&[
+            0xEF06_0000,    // SWI 0x06
+            0xE284_4001,    // ADD R4,R4,#1
+            0xEAFF_FFFE,    // B 0x0200_0008
+        ]);
+
+        run_until_count(&mut cpu, 1);
+        assert_eq!(cpu.mut_mem().vblanks, 1);
+    }
+
+    fn write_bytes(mem: &mut TestMem, addr: u32, data: &[u8]) {
+        for (n, byte) in data.iter().enumerate() {
+            mem.store_byte(MemCycleType::N, addr + (n as u32), *byte);
+        }
+    }
+
+    fn read_bytes(mem: &mut TestMem, addr: u32, len: usize) -> Vec<u8> {
+        (0..len).map(|n| mem.load_byte(MemCycleType::N, addr + (n as u32)).0).collect()
+    }
+
+    #[test]
+    fn lz77_decompress() {
+        let mut mem = TestMem::new();
+        write_bytes(&mut mem, 0x0200_1000, &[
+            0x10, 0x08, 0x00, 0x00, // Header: 8 bytes
+            0x10,                   // Flags: 3 bytes, then a reference
+            b'A', b'B', b'C',
+            0x20, 0x02,             // 5 bytes from 3 bytes back
+        ]);
+        emulated_swi_arm7(0x11, &mut mem, &[0x0200_1000, 0x0200_2000, 0, 0]);
+        assert_eq!(read_bytes(&mut mem, 0x0200_2000, 8), b"ABCABCAB");
+    }
+
+    #[test]
+    fn rl_decompress() {
+        let mut mem = TestMem::new();
+        write_bytes(&mut mem, 0x0200_1000, &[
+            0x30, 0x07, 0x00, 0x00, // Header: 7 bytes
+            0x82, b'A',             // 5 repeated bytes
+            0x01, b'B', b'C',       // 2 bytes
+        ]);
+        emulated_swi_arm7(0x14, &mut mem, &[0x0200_1000, 0x0200_2000, 0, 0]);
+        assert_eq!(read_bytes(&mut mem, 0x0200_2000, 7), b"AAAAABC");
+    }
+
+    #[test]
+    fn callback_decompress() {
+        let mut mem = TestMem::new();
+        write_bytes(&mut mem, 0x0200_1000, &[0x10, 0x08, 0x00, 0x00]);
+        for function in [0x12, 0x13, 0x15] {
+            emulated_swi_arm7(function, &mut mem, &[0x0200_1000, 0x0200_2000, 0x0200_3000, 0]);
+        }
+        assert_eq!(read_bytes(&mut mem, 0x0200_2000, 8), vec![0; 8]);
+    }
+
+    #[test]
+    fn sound_tables() {
+        assert_eq!(sine_table(0), 0);
+        assert_eq!(sine_table(0x20), 0x5A82);
+        assert_eq!(sine_table(0x3F), 0x7FF6);
+
+        assert_eq!(pitch_table(0), 0);
+        assert_eq!(pitch_table(0x180), 27145);
+        assert_eq!(pitch_table(0x2FF), 65417);
+
+        assert_eq!(volume_table(0), 0);
+        assert_eq!(volume_table(603), 64);
+        assert_eq!(volume_table(663), 64);
+        assert_eq!(volume_table(723), 127);
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(0xFFFF, b"123456789"), 0x4B37);
+        assert_eq!(crc16(0, b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn square_root() {
+        assert_eq!(sqrt(4), 2);
+        assert_eq!(sqrt(2_000_000), 1414);
+        assert_eq!(sqrt(0xFFFF_FFFF), 0xFFFF);
+    }
+}
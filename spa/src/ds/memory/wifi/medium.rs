@@ -0,0 +1,136 @@
+/// Mediums that wifi frames are sent over.
+
+use crossbeam_channel::{Sender, Receiver, unbounded};
+use parking_lot::Mutex;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::Arc
+};
+
+/// Largest 802.11 frame that can be received.
+const MAX_FRAME_SIZE: usize = 2346;
+
+/// Something that transmits 802.11 frames between consoles.
+///
+/// Frames are passed without the FCS.
+pub trait WifiMedium: Send + Sync {
+    /// Send a frame to every other console on the medium.
+    fn send(&self, frame: &[u8]);
+    /// Take the next frame sent by another console, if there is one.
+    fn receive(&self) -> Option<Vec<u8>>;
+}
+
+/// Connects NDS instances in the same process.
+#[derive(Clone, Default)]
+pub struct LocalWifiHub {
+    stations: Arc<Mutex<Vec<Sender<Vec<u8>>>>>
+}
+
+impl LocalWifiHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a new console to the hub.
+    /// The returned medium should be passed to `MemoryConfig`.
+    pub fn connect(&self) -> Arc<dyn WifiMedium> {
+        let (sender, receiver) = unbounded();
+        let mut stations = self.stations.lock();
+        let id = stations.len();
+        stations.push(sender);
+        Arc::new(LocalWifi {
+            id:         id,
+            stations:   self.stations.clone(),
+            receiver:   receiver
+        })
+    }
+}
+
+struct LocalWifi {
+    id:         usize,
+    stations:   Arc<Mutex<Vec<Sender<Vec<u8>>>>>,
+    receiver:   Receiver<Vec<u8>>
+}
+
+impl WifiMedium for LocalWifi {
+    fn send(&self, frame: &[u8]) {
+        let stations = self.stations.lock();
+        for (id, station) in stations.iter().enumerate() {
+            if id != self.id {
+                let _ = station.send(frame.to_vec());
+            }
+        }
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Connects NDS instances in different processes, over localhost UDP.
+pub struct UdpWifi {
+    socket: UdpSocket,
+    peers:  Vec<SocketAddr>
+}
+
+impl UdpWifi {
+    /// Listen on `port`, and send frames to each of `peer_ports`.
+    pub fn new(port: u16, peer_ports: &[u16]) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: socket,
+            peers:  peer_ports.iter()
+                .map(|peer_port| SocketAddr::from((Ipv4Addr::LOCALHOST, *peer_port)))
+                .collect()
+        })
+    }
+}
+
+impl WifiMedium for UdpWifi {
+    fn send(&self, frame: &[u8]) {
+        for peer in &self.peers {
+            // Peers that aren't running yet just miss the frame.
+            let _ = self.socket.send_to(frame, peer);
+        }
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut buffer = vec![0; MAX_FRAME_SIZE];
+        match self.socket.recv_from(&mut buffer) {
+            Ok((size, _)) => {
+                buffer.truncate(size);
+                Some(buffer)
+            },
+            Err(_) => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_hub() {
+        let hub = LocalWifiHub::new();
+        let a = hub.connect();
+        let b = hub.connect();
+        let c = hub.connect();
+
+        a.send(&[1, 2, 3]);
+        // Not echoed to the sender.
+        assert_eq!(a.receive(), None);
+        assert_eq!(b.receive(), Some(vec![1, 2, 3]));
+        assert_eq!(c.receive(), Some(vec![1, 2, 3]));
+        assert_eq!(b.receive(), None);
+
+        // Frames arrive in order.
+        b.send(&[4]);
+        b.send(&[5]);
+        assert_eq!(a.receive(), Some(vec![4]));
+        assert_eq!(a.receive(), Some(vec![5]));
+        assert_eq!(c.receive(), Some(vec![4]));
+    }
+}
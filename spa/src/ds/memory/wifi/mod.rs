@@ -1,9 +1,38 @@
+/// Wifi hardware.
+
+mod medium;
+mod pcap;
+
 use bitflags::bitflags;
+use std::sync::Arc;
 use crate::utils::bits::u16;
 use crate::utils::bytes::{self, u32, u64};
 use crate::utils::meminterface::MemInterface16;
 use crate::common::mem::ram::RAM;
 
+pub use medium::{WifiMedium, LocalWifiHub, UdpWifi};
+pub use pcap::PcapWriter;
+
+/// ARM7 cycles per second.
+const CYCLES_PER_SECOND: u64 = 6 * 355 * 263 * 60;
+/// Microseconds per time unit (used for beacon intervals).
+const US_PER_TU: u16 = 1024;
+/// How often the medium is checked for new frames, in microseconds.
+const RX_POLL_INTERVAL: u16 = 64;
+
+/// Size of the header before each frame in TX buffers and the RX FIFO.
+const HEADER_SIZE: u32 = 12;
+/// Size of the 802.11 MAC header.
+const MAC_HEADER_SIZE: usize = 24;
+/// Size of the FCS at the end of each frame.
+const FCS_SIZE: u32 = 4;
+/// Transfer rate written into RX headers (2Mbit/s).
+const RX_RATE: u16 = 0x14;
+/// Frame type in the RX header for multiplay commands.
+const RX_TYPE_MULTIPLAY_CMD: u16 = 0xC;
+/// Destination address of multiplay acks.
+const MULTIPLAY_ACK_ADDR: [u8; 6] = [0x03, 0x09, 0xBF, 0x00, 0x00, 0x03];
+
 bitflags! {
     #[derive(Default)]
     pub struct WifiIRQFlags: u16 {
@@ -68,6 +97,32 @@ impl BasebandChip {
     }
 }
 
+/// Where a frame is transmitted from.
+#[derive(Clone, Copy, PartialEq)]
+enum TXSlot {
+    Loc1,
+    Cmd,
+    Loc2,
+    Loc3,
+    Beacon,
+    Reply
+}
+
+impl TXSlot {
+    /// Value written to W_TXSTAT once a frame is sent.
+    fn tx_stat(self) -> u16 {
+        let slot = match self {
+            TXSlot::Loc1    => 0,
+            TXSlot::Cmd     => 1,
+            TXSlot::Loc2    => 2,
+            TXSlot::Loc3    => 3,
+            TXSlot::Beacon  => 4,
+            TXSlot::Reply   => 5,
+        };
+        (slot << 12) | 1
+    }
+}
+
 struct RFChip {
     regs: [u32; 32]
 }
@@ -198,11 +253,23 @@ pub struct Wifi {
     random_gen: u16,
     random_latch: u16,
 
-    ram: RAM
+    ram: RAM,
+
+    medium: Option<Arc<dyn WifiMedium>>,
+    capture: Option<PcapWriter>,
+    /// Time since power-on, in microseconds.
+    time_us: u64,
+    us_cycles: u64,
+    tu_counter: u16,
+    rx_poll_counter: u16,
+    /// Microseconds until the current multiplay command is done.
+    cmd_time_left: Option<u32>,
+    cmd_client_mask: u16,
+    cmd_reply_count: u32,
 }
 
 impl Wifi {
-    pub fn new() -> Self {
+    pub fn new(medium: Option<Arc<dyn WifiMedium>>, capture: Option<PcapWriter>) -> Self {
         Self {
             id: 0x1440, // DS
             tx_master_enable: false,
@@ -313,7 +380,17 @@ impl Wifi {
             random_gen: 0x07FF, // ? start value
             random_latch: 0x07FF, // ? start value
 
-            ram: RAM::new(0x2000)
+            ram: RAM::new(0x2000),
+
+            medium: medium,
+            capture: capture,
+            time_us: 0,
+            us_cycles: 0,
+            tu_counter: 0,
+            rx_poll_counter: 0,
+            cmd_time_left: None,
+            cmd_client_mask: 0,
+            cmd_reply_count: 0,
         }
     }
 
@@ -345,12 +422,19 @@ impl Wifi {
         self.misc_config[18] = 0xFFFF;
     }
 
-    pub fn clock(&mut self, _cycles: usize) -> bool {
+    pub fn clock(&mut self, cycles: usize) -> bool {
         //for _ in 0..cycles {
             let random_rotate = (self.random_gen << 1) | (self.random_gen >> 10);
             self.random_gen = ((self.random_gen & 1) ^ random_rotate) & 0x7FF;
         //}
 
+        self.us_cycles += (cycles as u64) * 1_000_000;
+        let us = self.us_cycles / CYCLES_PER_SECOND;
+        self.us_cycles %= CYCLES_PER_SECOND;
+        for _ in 0..us {
+            self.clock_us();
+        }
+
         self.interrupt_req.insert(self.interrupt_latch);
         let trigger_irq = self.interrupt_latch.intersects(self.interrupt_enable);
         self.interrupt_latch = WifiIRQFlags::empty();
@@ -561,14 +645,29 @@ impl MemInterface16 for Wifi {
             0x0480_8088 => self.listen_counter = data,
             0x0480_808C => self.beacon_interval = data,
             0x0480_808E => self.listen_interval = data,
-            0x0480_8090 => self.tx_cmd = data,
+            0x0480_8090 => {
+                self.tx_cmd = data;
+                self.process_tx_requests();
+            },
             0x0480_8094 => self.tx_buf_reply_1 = data,
-            0x0480_80A0 => self.tx_loc_1 = data,
-            0x0480_80A4 => self.tx_loc_2 = data,
-            0x0480_80A8 => self.tx_loc_3 = data,
+            0x0480_80A0 => {
+                self.tx_loc_1 = data;
+                self.process_tx_requests();
+            },
+            0x0480_80A4 => {
+                self.tx_loc_2 = data;
+                self.process_tx_requests();
+            },
+            0x0480_80A8 => {
+                self.tx_loc_3 = data;
+                self.process_tx_requests();
+            },
 
             0x0480_80AC => self.tx_req_flags &= !(data & 0xF),
-            0x0480_80AE => self.tx_req_flags |= data & 0xF,
+            0x0480_80AE => {
+                self.tx_req_flags |= data & 0xF;
+                self.process_tx_requests();
+            },
 
             0x0480_80B4 => self.tx_reset(data),
             0x0480_80BC => self.preamble_control = data,
@@ -645,6 +744,7 @@ impl MemInterface16 for Wifi {
 impl Wifi {
     fn write_reset(&mut self, data: u16) {
         self.tx_master_enable = u16::test_bit(data, 0);
+        self.process_tx_requests();
         // TODO...
     }
 
@@ -742,4 +842,386 @@ impl Wifi {
             }
         }
     }
-}
\ No newline at end of file
+}
+// Timing
+impl Wifi {
+    fn clock_us(&mut self) {
+        self.time_us += 1;
+        if u16::test_bit(self.counter_control, 0) {
+            self.counter = self.counter.wrapping_add(1);
+            if u16::test_bit(self.counter_compare_control, 0) {
+                self.check_beacon_timeslot();
+            }
+            self.tu_counter += 1;
+            if self.tu_counter >= US_PER_TU {
+                self.tu_counter = 0;
+                self.beacon_counter = self.beacon_counter.saturating_sub(1);
+            }
+        }
+
+        if let Some(time_left) = self.cmd_time_left {
+            if time_left == 0 {
+                self.cmd_time_left = None;
+                self.finish_multiplay_cmd();
+            } else {
+                self.cmd_time_left = Some(time_left - 1);
+            }
+        }
+
+        self.rx_poll_counter += 1;
+        if self.rx_poll_counter >= RX_POLL_INTERVAL {
+            self.rx_poll_counter = 0;
+            self.receive_frames();
+        }
+    }
+
+    /// Trigger IRQs at and just before the beacon timeslot, and send the beacon.
+    fn check_beacon_timeslot(&mut self) {
+        let beacon_time = self.counter_compare & 0xFFFF_FFFF_FFFF_FC00;
+        if self.counter == beacon_time.wrapping_sub(self.pre_beacon_time as u64) {
+            self.interrupt_latch.insert(WifiIRQFlags::PRE_BEACON_TIMESLOT);
+        }
+        if self.counter == beacon_time {
+            self.interrupt_latch.insert(WifiIRQFlags::BEACON_TIMESLOT);
+            self.beacon_counter = self.beacon_interval;
+            let interval = (self.beacon_interval as u64) * (US_PER_TU as u64);
+            self.counter_compare = beacon_time.wrapping_add(interval);
+            if self.tx_master_enable && u16::test_bit(self.tx_beacon, 15) {
+                self.transmit(TXSlot::Beacon);
+            }
+        }
+    }
+}
+
+// Transmit & receive
+impl Wifi {
+    /// Send frames from all slots that are enabled and requested.
+    fn process_tx_requests(&mut self) {
+        if !self.tx_master_enable {
+            return;
+        }
+        if u16::test_bit(self.tx_req_flags, 0) && u16::test_bit(self.tx_loc_1, 15) {
+            self.transmit(TXSlot::Loc1);
+        }
+        if u16::test_bit(self.tx_req_flags, 1) && u16::test_bit(self.tx_cmd, 15) && self.cmd_time_left.is_none() {
+            self.transmit(TXSlot::Cmd);
+        }
+        if u16::test_bit(self.tx_req_flags, 2) && u16::test_bit(self.tx_loc_2, 15) {
+            self.transmit(TXSlot::Loc2);
+        }
+        if u16::test_bit(self.tx_req_flags, 3) && u16::test_bit(self.tx_loc_3, 15) {
+            self.transmit(TXSlot::Loc3);
+        }
+    }
+
+    fn tx_slot_addr(&self, slot: TXSlot) -> u32 {
+        let slot_reg = match slot {
+            TXSlot::Loc1    => self.tx_loc_1,
+            TXSlot::Cmd     => self.tx_cmd,
+            TXSlot::Loc2    => self.tx_loc_2,
+            TXSlot::Loc3    => self.tx_loc_3,
+            TXSlot::Beacon  => self.tx_beacon,
+            TXSlot::Reply   => self.tx_buf_reply_2,
+        };
+        ((slot_reg & 0xFFF) as u32) << 1
+    }
+
+    /// Send the frame in the TX buffer of the slot over the medium.
+    fn transmit(&mut self, slot: TXSlot) {
+        let header_addr = self.tx_slot_addr(slot);
+        let frame_addr = header_addr + HEADER_SIZE;
+        let length = (self.ram.read_halfword((header_addr + 0xA) & 0x1FFE) as u32).saturating_sub(FCS_SIZE);
+
+        if length >= MAC_HEADER_SIZE as u32 {
+            self.ram.write_halfword((frame_addr + 22) & 0x1FFE, self.tx_seq_number << 4);
+            self.tx_seq_number = (self.tx_seq_number + 1) & 0xFFF;
+        }
+        if slot == TXSlot::Beacon {
+            // Beacons start with the timestamp.
+            let timestamp_addr = frame_addr + MAC_HEADER_SIZE as u32;
+            for n in 0..4 {
+                let timestamp = (self.counter >> (n * 16)) as u16;
+                self.ram.write_halfword((timestamp_addr + (n * 2)) & 0x1FFE, timestamp);
+            }
+        }
+
+        let frame = (0..length).map(|n| self.ram.read_byte((frame_addr + n) & 0x1FFF)).collect::<Vec<_>>();
+        if let Some(medium) = &self.medium {
+            medium.send(&frame);
+        }
+        if let Some(capture) = &mut self.capture {
+            let rate = self.ram.read_byte(header_addr + 0x8) as u16;
+            capture.write_frame(self.time_us, rate, &frame);
+        }
+
+        self.ram.write_halfword(header_addr, 1);
+        self.tx_stat = slot.tx_stat();
+        self.interrupt_latch.insert(WifiIRQFlags::TX_START);
+        match slot {
+            TXSlot::Loc1    => self.tx_loc_1 &= 0x7FFF,
+            TXSlot::Loc2    => self.tx_loc_2 &= 0x7FFF,
+            TXSlot::Loc3    => self.tx_loc_3 &= 0x7FFF,
+            TXSlot::Reply   => self.tx_buf_reply_2 &= 0x7FFF,
+            TXSlot::Beacon  => {},
+            TXSlot::Cmd     => {
+                // Wait for clients to reply.
+                self.cmd_time_left = Some(self.cmd_total_time as u32);
+                self.cmd_client_mask = frame.get((MAC_HEADER_SIZE + 2)..(MAC_HEADER_SIZE + 4))
+                    .map_or(0, |mask| bytes::u16::make(mask[1], mask[0]));
+                self.cmd_reply_count = 0;
+                return;
+            },
+        }
+        self.interrupt_latch.insert(WifiIRQFlags::TX_COMPLETE);
+    }
+
+    fn finish_multiplay_cmd(&mut self) {
+        self.tx_cmd &= 0x7FFF;
+        self.send_multiplay_ack();
+        self.interrupt_latch.insert(WifiIRQFlags::MULTIPLAY_CMD_DONE | WifiIRQFlags::TX_COMPLETE);
+    }
+
+    /// The host automatically acks the replies once the reply window closes.
+    fn send_multiplay_ack(&mut self) {
+        // Replies can't be matched to client IDs here, so if any are missing
+        // then every polled client is reported as failed.
+        let failed_mask = if self.cmd_reply_count >= self.cmd_client_mask.count_ones() {
+            0
+        } else {
+            self.cmd_client_mask
+        };
+        let mut frame = Vec::with_capacity(MAC_HEADER_SIZE + 4);
+        frame.extend_from_slice(&0x0218_u16.to_le_bytes());    // Data + CF-Ack, FromDS
+        frame.extend_from_slice(&0_u16.to_le_bytes());         // Duration
+        frame.extend_from_slice(&MULTIPLAY_ACK_ADDR);
+        frame.extend(self.bssid.iter().flat_map(|h| h.to_le_bytes()));
+        frame.extend(self.mac_addr.iter().flat_map(|h| h.to_le_bytes()));
+        frame.extend_from_slice(&(self.tx_seq_number << 4).to_le_bytes());
+        frame.extend_from_slice(&0x0033_u16.to_le_bytes());
+        frame.extend_from_slice(&failed_mask.to_le_bytes());
+        self.tx_seq_number = (self.tx_seq_number + 1) & 0xFFF;
+
+        if let Some(medium) = &self.medium {
+            medium.send(&frame);
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.write_frame(self.time_us, RX_RATE, &frame);
+        }
+    }
+
+    fn receive_frames(&mut self) {
+        if let Some(medium) = self.medium.clone() {
+            while let Some(frame) = medium.receive() {
+                if !self.should_receive(&frame) {
+                    continue;
+                }
+                if !self.write_rx_frame(&frame) {
+                    continue;
+                }
+                let frame_control = bytes::u16::make(frame[1], frame[0]);
+                match rx_frame_type(frame_control) {
+                    RX_TYPE_MULTIPLAY_CMD => self.reply_to_multiplay_cmd(&frame),
+                    0xE | 0xF if self.cmd_time_left.is_some() => self.cmd_reply_count += 1,
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    /// Only frames addressed to this console or to a group are received.
+    fn should_receive(&self, frame: &[u8]) -> bool {
+        if !u16::test_bit(self.rx_control, 15) || frame.len() < MAC_HEADER_SIZE {
+            return false;
+        }
+        let dest = &frame[4..10];
+        let is_group = (dest[0] & 1) != 0;
+        is_group || dest.iter().copied().eq(self.mac_addr.iter().flat_map(|h| h.to_le_bytes()))
+    }
+
+    fn write_rx_halfword(&mut self, data: u16) {
+        self.ram.write_halfword(((self.rx_fifo_write_cursor << 1) & 0x1FFE) as u32, data);
+        self.rx_fifo_write_cursor = (self.rx_fifo_write_cursor + 1) & 0xFFF;
+        if (self.rx_fifo_write_cursor << 1) == (self.rx_fifo_end_addr & 0x1FFE) {
+            self.rx_fifo_write_cursor = (self.rx_fifo_start_addr & 0x1FFE) >> 1;
+        }
+    }
+
+    /// Halfwords free in the RX FIFO before the write cursor reaches the read cursor.
+    fn rx_fifo_space(&self) -> u16 {
+        let start = (self.rx_fifo_start_addr & 0x1FFE) >> 1;
+        let end = (self.rx_fifo_end_addr & 0x1FFE) >> 1;
+        if end <= start {
+            return 0;
+        }
+        let size = end - start;
+        let write = self.rx_fifo_write_cursor.wrapping_sub(start) % size;
+        let read = self.rx_fifo_read_cursor.wrapping_sub(start) % size;
+        size - ((write + size - read) % size)
+    }
+
+    /// Write a frame with an RX header into the RX FIFO.
+    ///
+    /// If the frame doesn't fit before the read cursor, it is dropped
+    /// and false is returned.
+    fn write_rx_frame(&mut self, frame: &[u8]) -> bool {
+        // Header + data, rounded up to a word.
+        let size = ((HEADER_SIZE as u16 / 2) + ((frame.len() as u16 + 1) / 2) + 1) & !1;
+        // The write cursor must never catch up with the read cursor.
+        if size >= self.rx_fifo_space() {
+            // Frames dropped because the RX FIFO is full.
+            self.rx_stats[1] = self.rx_stats[1].wrapping_add(1);
+            if u16::test_bit(self.rx_stats_inc_irq, 1) {
+                self.interrupt_latch.insert(WifiIRQFlags::RX_EVENT_INC);
+            }
+            return false;
+        }
+
+        let frame_control = bytes::u16::make(frame[1], frame[0]);
+        self.write_rx_halfword(rx_frame_type(frame_control));
+        self.write_rx_halfword(0x0040);
+        self.write_rx_halfword(0);
+        self.write_rx_halfword(RX_RATE);
+        self.write_rx_halfword(frame.len() as u16);
+        self.write_rx_halfword(0x1010); // RSSI
+        for data in frame.chunks(2) {
+            let hi = data.get(1).copied().unwrap_or(0);
+            self.write_rx_halfword(bytes::u16::make(hi, data[0]));
+        }
+        // Each frame starts word-aligned.
+        if u16::test_bit(self.rx_fifo_write_cursor, 0) {
+            self.write_rx_halfword(0);
+        }
+
+        if let Some(capture) = &mut self.capture {
+            capture.write_frame(self.time_us, RX_RATE, frame);
+        }
+
+        self.rx_ok_count = self.rx_ok_count.wrapping_add(1);
+        self.interrupt_latch.insert(WifiIRQFlags::RX_START | WifiIRQFlags::RX_COMPLETE);
+        true
+    }
+
+    /// Clients automatically reply to multiplay commands that include them.
+    fn reply_to_multiplay_cmd(&mut self, frame: &[u8]) {
+        self.tx_buf_reply_2 = self.tx_buf_reply_1;
+        self.tx_buf_reply_1 = 0;
+        if frame.len() < MAC_HEADER_SIZE + 4 || self.aid_low == 0 {
+            return;
+        }
+        let client_mask = bytes::u16::make(frame[MAC_HEADER_SIZE + 3], frame[MAC_HEADER_SIZE + 2]);
+        let included = u16::test_bit(client_mask, (self.aid_low & 0xF) as usize);
+        if included && self.tx_master_enable && u16::test_bit(self.tx_buf_reply_2, 15) {
+            self.transmit(TXSlot::Reply);
+        }
+    }
+}
+
+/// Frame type written into the RX header.
+fn rx_frame_type(frame_control: u16) -> u16 {
+    let from_ds = u16::test_bit(frame_control, 9);
+    match frame_control & 0xFC {
+        0x80 => 0x1,                // Beacon
+        0x28 => RX_TYPE_MULTIPLAY_CMD,
+        0x18 if from_ds => 0xD,     // Multiplay ack
+        0x18 => 0xE,                // Multiplay reply
+        0x58 => 0xF,                // Empty multiplay reply
+        t if (t & 0xC) == 0 => 0x0, // Management
+        _ => 0x8,                   // Data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_control: u16, len: usize) -> Vec<u8> {
+        let mut frame = (0..len).map(|n| n as u8).collect::<Vec<_>>();
+        frame[0..2].copy_from_slice(&frame_control.to_le_bytes());
+        frame
+    }
+
+    fn rx_wifi() -> Wifi {
+        let mut wifi = Wifi::new(None, None);
+        wifi.rx_fifo_start_addr = 0x4000;
+        wifi.rx_fifo_end_addr = 0x5000;
+        wifi
+    }
+
+    #[test]
+    fn frame_types() {
+        assert_eq!(rx_frame_type(0x0080), 0x1);
+        assert_eq!(rx_frame_type(0x0228), RX_TYPE_MULTIPLAY_CMD);
+        assert_eq!(rx_frame_type(0x0218), 0xD);
+        assert_eq!(rx_frame_type(0x0118), 0xE);
+        assert_eq!(rx_frame_type(0x0158), 0xF);
+        assert_eq!(rx_frame_type(0x0040), 0x0);
+        assert_eq!(rx_frame_type(0x00B0), 0x0);
+        assert_eq!(rx_frame_type(0x0108), 0x8);
+    }
+
+    #[test]
+    fn rx_header() {
+        let mut wifi = rx_wifi();
+        let frame = frame(0x0080, 25);
+        assert!(wifi.write_rx_frame(&frame));
+
+        let header = (0..6).map(|n| wifi.ram.read_halfword(n * 2)).collect::<Vec<_>>();
+        assert_eq!(header, vec![0x1, 0x0040, 0, RX_RATE, 25, 0x1010]);
+        assert_eq!(wifi.ram.read_halfword(12), 0x0080);
+        assert_eq!(wifi.ram.read_halfword(14), 0x0302);
+        // Last byte is padded, then the next frame is word-aligned.
+        assert_eq!(wifi.ram.read_halfword(12 + 24), 0x0018);
+        assert_eq!(wifi.rx_fifo_write_cursor, 20);
+        assert_eq!(wifi.rx_ok_count, 1);
+        assert!(wifi.interrupt_latch.contains(WifiIRQFlags::RX_COMPLETE));
+    }
+
+    #[test]
+    fn rx_overflow() {
+        let mut wifi = rx_wifi();
+        let frame = frame(0x0080, 25);
+        wifi.rx_fifo_read_cursor = 10;
+        assert!(!wifi.write_rx_frame(&frame));
+        assert_eq!(wifi.rx_fifo_write_cursor, 0);
+        assert_eq!(wifi.rx_stats[1], 1);
+        assert_eq!(wifi.rx_ok_count, 0);
+
+        // Fits with a halfword to spare.
+        wifi.rx_fifo_read_cursor = 21;
+        assert!(wifi.write_rx_frame(&frame));
+        assert_eq!(wifi.rx_fifo_write_cursor, 20);
+    }
+
+    #[test]
+    fn multiplay_ack() {
+        let hub = LocalWifiHub::new();
+        let mut wifi = Wifi::new(Some(hub.connect()), None);
+        let client = hub.connect();
+
+        // Poll client 1.
+        let mut cmd = frame(0x0228, MAC_HEADER_SIZE + 4);
+        cmd[(MAC_HEADER_SIZE + 2)..].copy_from_slice(&0x0002_u16.to_le_bytes());
+        wifi.ram.write_halfword(0xA, (cmd.len() as u32 + FCS_SIZE) as u16);
+        for (n, data) in cmd.chunks(2).enumerate() {
+            wifi.ram.write_halfword(HEADER_SIZE + (n as u32) * 2, bytes::u16::make(data[1], data[0]));
+        }
+        wifi.mac_addr = [0x0900, 0x12BF, 0x5634];
+        wifi.tx_master_enable = true;
+        wifi.tx_cmd = 0x8000;
+        wifi.cmd_total_time = 2;
+        wifi.transmit(TXSlot::Cmd);
+        assert_eq!(client.receive().map(|f| f.len()), Some(cmd.len()));
+
+        for _ in 0..3 {
+            wifi.clock_us();
+        }
+        let ack = client.receive().expect("no multiplay ack");
+        assert_eq!(&ack[0..2], &[0x18, 0x02]);
+        assert_eq!(&ack[4..10], &MULTIPLAY_ACK_ADDR);
+        assert_eq!(&ack[16..22], &[0x00, 0x09, 0xBF, 0x12, 0x34, 0x56]);
+        // No replies arrived.
+        assert_eq!(&ack[MAC_HEADER_SIZE..], &[0x33, 0x00, 0x02, 0x00]);
+        assert_eq!(wifi.tx_cmd, 0);
+        assert!(wifi.interrupt_latch.contains(WifiIRQFlags::MULTIPLAY_CMD_DONE));
+    }
+}
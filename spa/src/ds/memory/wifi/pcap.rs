@@ -0,0 +1,100 @@
+/// Packet capture of wifi frames.
+
+use std::{
+    fs::File,
+    io::{Result, Write},
+    path::Path
+};
+
+/// LINKTYPE_IEEE802_11_RADIOTAP
+const LINK_TYPE: u32 = 127;
+const SNAP_LEN: u32 = 65535;
+
+/// Radiotap header with only the rate field present.
+const RADIOTAP_LEN: u16 = 9;
+const RADIOTAP_PRESENT_RATE: u32 = 1 << 2;
+
+/// Writes frames to a pcap file, which can be opened in packet analysers.
+pub struct PcapWriter {
+    file: File
+}
+
+impl PcapWriter {
+    pub fn new(path: &Path) -> Result<Self> {
+        let mut file = File::create(path)?;
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&0xA1B2_C3D4_u32.to_le_bytes());
+        header.extend_from_slice(&2_u16.to_le_bytes());    // Major version
+        header.extend_from_slice(&4_u16.to_le_bytes());    // Minor version
+        header.extend_from_slice(&0_i32.to_le_bytes());    // Time zone
+        header.extend_from_slice(&0_u32.to_le_bytes());    // Timestamp accuracy
+        header.extend_from_slice(&SNAP_LEN.to_le_bytes());
+        header.extend_from_slice(&LINK_TYPE.to_le_bytes());
+        file.write_all(&header)?;
+        Ok(Self {
+            file: file
+        })
+    }
+
+    /// Record a frame (without FCS).
+    ///
+    /// `time_us` is the emulated time in microseconds,
+    /// and `rate` is the transfer rate in 100kbit/s units.
+    pub fn write_frame(&mut self, time_us: u64, rate: u16, frame: &[u8]) {
+        let packet_len = (RADIOTAP_LEN as usize + frame.len()) as u32;
+        let mut record = Vec::with_capacity(16 + packet_len as usize);
+        record.extend_from_slice(&((time_us / 1_000_000) as u32).to_le_bytes());
+        record.extend_from_slice(&((time_us % 1_000_000) as u32).to_le_bytes());
+        record.extend_from_slice(&packet_len.to_le_bytes());
+        record.extend_from_slice(&packet_len.to_le_bytes());
+
+        record.push(0); // Version
+        record.push(0); // Padding
+        record.extend_from_slice(&RADIOTAP_LEN.to_le_bytes());
+        record.extend_from_slice(&RADIOTAP_PRESENT_RATE.to_le_bytes());
+        record.push((rate / 5) as u8);  // 500kbit/s units
+        record.extend_from_slice(frame);
+
+        if let Err(e) = self.file.write_all(&record) {
+            println!("could not write wifi capture: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_layout() {
+        let path = std::env::temp_dir().join(format!("spa_wifi_capture_{}.pcap", std::process::id()));
+        {
+            let mut capture = PcapWriter::new(&path).unwrap();
+            capture.write_frame(2_000_123, 0x14, &[0x80, 0x00, 0xAB]);
+        }
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), 24 + 16 + 9 + 3);
+
+        // Global header.
+        assert_eq!(&data[0..4], &[0xD4, 0xC3, 0xB2, 0xA1]);
+        assert_eq!(&data[4..8], &[2, 0, 4, 0]);
+        assert_eq!(&data[8..16], &[0; 8]);
+        assert_eq!(&data[16..20], &SNAP_LEN.to_le_bytes());
+        assert_eq!(&data[20..24], &LINK_TYPE.to_le_bytes());
+
+        // Record header.
+        let record = &data[24..];
+        assert_eq!(&record[0..4], &2_u32.to_le_bytes());
+        assert_eq!(&record[4..8], &123_u32.to_le_bytes());
+        assert_eq!(&record[8..12], &12_u32.to_le_bytes());
+        assert_eq!(&record[12..16], &12_u32.to_le_bytes());
+
+        // Radiotap header, then the frame.
+        let packet = &record[16..];
+        assert_eq!(&packet[0..4], &[0, 0, 9, 0]);
+        assert_eq!(&packet[4..8], &[0x04, 0, 0, 0]);
+        assert_eq!(packet[8], 4);
+        assert_eq!(&packet[9..], &[0x80, 0x00, 0xAB]);
+    }
+}
@@ -17,6 +17,11 @@ use arm::{
 };
 use crossbeam_channel::{Receiver, unbounded};
 
+use std::{
+    ops::Range,
+    path::{Path, PathBuf}
+};
+
 pub static DEBUG_TRIGGER: std::sync::LazyLock<std::sync::Arc<std::sync::atomic::AtomicBool>> = std::sync::LazyLock::new(|| {
     std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false))
 });
@@ -27,16 +32,24 @@ use crate::common::video::framecomms::{new_frame_comms, FrameRequester};
 use crate::common::resampler::*;
 use internal::DS9InternalMem;
 use memory::{
-    DS9MemoryBus, DS7MemoryBus
+    DS9MemoryBus, DS7MemoryBus,
+    emulated_swi_arm9, emulated_swi_arm7, call_bios_routine
 };
+use card::CardSlot;
+use spi::Backlights;
 use video::Renderer;
 use input::UserInput;
 use audio::REAL_BASE_SAMPLE_RATE;
 
-pub use memory::MemoryConfig;
+pub use memory::{
+    MemoryConfig, Slot2Device, DEFAULT_ARM7_JIT_RANGES, MAX_RENDER_SCALE,
+    WifiMedium, LocalWifiHub, UdpWifi
+};
+pub use spi::{UserProfile, Language, DEFAULT_MAC_ADDRESS};
+pub use rtc::RTCTime;
 
 use crate::{
-    Device, Button, AudioHandler, Coords
+    Device, Button, AudioHandler, Coords, Event, GuitarFret
 };
 
 type RendererType = video::ProceduralRenderer;
@@ -44,20 +57,30 @@ type RendererType = video::ProceduralRenderer;
 pub struct NDS {
     frame_receiver: FrameRequester<UserInput>,
     audio_channel:  Option<Receiver<SamplePacket>>,
+    event_receiver: Receiver<Event>,
+    backlights:     Backlights,
+    card_slot:      CardSlot,
+    render_scale:   usize,
     current_input:  UserInput
 }
 
 impl NDS {
-    pub fn new(config: MemoryConfig) -> Self {
-        let (render_width, render_height) = RendererType::render_size();
+    pub fn new(config: MemoryConfig) -> std::io::Result<Self> {
+        let (render_width, render_height) = RendererType::render_size(config.render_scale());
         let (frame_sender, frame_receiver) = new_frame_comms(render_width * render_height * 4, 2);
         // The below is a bit dumb but it avoids sending the CPU (which introduces a ton of problems).
         // We have to extract the audio receivers from the CPU and get them in the main thread to use
         //   for the audio handler.
         let (channel_sender, channel_receiver) = unbounded();
-        let (mut arm9_bus, mut arm7_bus) = DS9MemoryBus::<RendererType>::new(&config, frame_sender);
-
-        let fast_boot = config.fast_boot;
+        let (event_sender, event_receiver) = unbounded();
+        let (mut arm9_bus, mut arm7_bus) = DS9MemoryBus::<RendererType>::new(&config, frame_sender, event_sender)?;
+        let backlights = arm7_bus.backlights();
+        let card_slot = arm9_bus.card_slot();
+        let render_scale = config.render_scale();
+
+        let fast_boot = config.skip_boot();
+        let arm9_no_bios = config.ds9_bios_path.is_none();
+        let arm7_no_bios = config.ds7_bios_path.is_none();
         let (fast_entry_arm9, fast_entry_arm7) = if fast_boot {
             let card_header = arm9_bus.get_header();
             arm9_bus.setup_boot_area(&card_header);
@@ -72,39 +95,61 @@ impl NDS {
             if fast_boot {
                 internal_mem.setup_init();
             }
-            let mut cpu = new_arm9_cpu(internal_mem, fast_entry_arm9);
+            let mut cpu = new_arm9_cpu(internal_mem, fast_entry_arm9, arm9_no_bios);
             loop {
                 cpu.step();
+                if let Some(addr) = cpu.mut_mem().take_bios_call() {
+                    call_bios_routine(&mut cpu, addr);
+                }
             }
         }).unwrap();
 
-        //let arm7_no_bios = config.ds7_bios_path.is_none();
+        let arm7_jit_ranges = config.jit_ranges();
         std::thread::Builder::new().name("ARM7-CPU".to_string()).spawn(move || {
-            let mut cpu = new_arm7_cpu(arm7_bus, fast_entry_arm7, false);
+            let mut cpu = new_arm7_cpu(arm7_bus, fast_entry_arm7, arm7_no_bios, arm7_jit_ranges);
             let audio_channels = cpu.mut_mem().enable_audio();
             channel_sender.send(audio_channels).unwrap();
             loop {
                 cpu.step();
+                while let Some(range) = cpu.mut_mem().take_jit_dirty() {
+                    cpu.invalidate_jit_range(range);
+                }
+                if let Some(addr) = cpu.mut_mem().take_bios_call() {
+                    call_bios_routine(&mut cpu, addr);
+                }
             }
         }).unwrap();
 
         let audio_channel = channel_receiver.recv().unwrap();
-        Self {
+        Ok(Self {
             frame_receiver: frame_receiver,
             audio_channel:  Some(audio_channel),
-//
+            event_receiver: event_receiver,
+            backlights:     backlights,
+            card_slot:      card_slot,
+            render_scale:   render_scale,
             current_input:  UserInput::default()
-        }
+        })
     }
 }
 
 impl Device for NDS {
     fn frame(&mut self, upper_frame: &mut [u8], lower_frame: &mut [u8]) {
         self.frame_receiver.get_frame(&mut [upper_frame, lower_frame], self.current_input.clone());
+        self.current_input.rtc_time = None;
+        self.current_input.microphone.clear();
+
+        // Unlit screens are blanked.
+        if !self.backlights.upper() {
+            blank_frame(upper_frame);
+        }
+        if !self.backlights.lower() {
+            blank_frame(lower_frame);
+        }
     }
 
     fn render_size(&self) -> [Coords<usize>; 2] {
-        let render_size = RendererType::render_size();
+        let render_size = RendererType::render_size(self.render_scale);
         [Coords {x: render_size.0, y: render_size.1}, Coords {x: render_size.0, y: render_size.1}]
     }
 
@@ -131,6 +176,34 @@ impl Device for NDS {
         }
     }
 
+    fn set_time(&mut self, time: chrono::NaiveDateTime) {
+        self.current_input.rtc_time = Some(time);
+    }
+
+    fn microphone_samples(&mut self, samples: &[i16]) {
+        self.current_input.microphone.extend_from_slice(samples);
+    }
+
+    fn set_guitar_fret(&mut self, fret: GuitarFret, pressed: bool) {
+        self.current_input.set_guitar_fret(fret, pressed);
+    }
+
+    fn set_lid_closed(&mut self, closed: bool) {
+        self.current_input.set_lid_closed(closed);
+    }
+
+    fn insert_cart(&mut self, rom_path: &Path, save_path: Option<PathBuf>) -> std::io::Result<()> {
+        self.card_slot.insert(rom_path, save_path)
+    }
+
+    fn eject_cart(&mut self) {
+        self.card_slot.eject();
+    }
+
+    fn take_events(&mut self) -> Vec<Event> {
+        self.event_receiver.try_iter().collect()
+    }
+
     fn trigger_debug(&mut self) {
         DEBUG_TRIGGER.store(true, std::sync::atomic::Ordering::Relaxed);
     }
@@ -142,16 +215,20 @@ impl NDS {
     /// Make a new debuggable NDS.
     /// 
     /// Steps through the ARM7 CPU.
-    pub fn new_debug_7(config: MemoryConfig) -> DebugInterface<UserInput> {
+    pub fn new_debug_7(config: MemoryConfig) -> std::io::Result<DebugInterface<UserInput>> {
         use crate::common::video::framecomms::debug::new_debug_frame_comms;
 
-        let (render_width, render_height) = RendererType::render_size();
+        let (render_width, render_height) = RendererType::render_size(config.render_scale());
         let (frame_sender, frame_receiver) = new_debug_frame_comms(render_width * render_height * 4, 2);
         let (debug_interface, debug_wrapper) = DebugInterface::new(frame_receiver, UserInput::default());
+        // Events are dropped in debug mode.
+        let (event_sender, _) = unbounded();
 
-        let (mut arm9_bus, mut arm7_bus) = DS9MemoryBus::<RendererType>::new(&config, frame_sender);
+        let (mut arm9_bus, mut arm7_bus) = DS9MemoryBus::<RendererType>::new(&config, frame_sender, event_sender)?;
 
-        let fast_boot = config.fast_boot;
+        let fast_boot = config.skip_boot();
+        let arm9_no_bios = config.ds9_bios_path.is_none();
+        let arm7_no_bios = config.ds7_bios_path.is_none();
         let (fast_entry_arm9, fast_entry_arm7) = if fast_boot {
             let card_header = arm9_bus.get_header();
             arm9_bus.setup_boot_area(&card_header);
@@ -166,36 +243,42 @@ impl NDS {
             if fast_boot {
                 internal_mem.setup_init();
             }
-            let mut cpu = new_arm9_cpu(internal_mem, fast_entry_arm9);
+            let mut cpu = new_arm9_cpu(internal_mem, fast_entry_arm9, arm9_no_bios);
             loop {
                 cpu.step();
+                if let Some(addr) = cpu.mut_mem().take_bios_call() {
+                    call_bios_routine(&mut cpu, addr);
+                }
             }
         }).unwrap();
 
-        //let arm7_no_bios = config.ds7_bios_path.is_none();
         std::thread::Builder::new().name("ARM7-CPU".to_string()).spawn(move || {
-            let cpu = new_arm7_cpu(arm7_bus, fast_entry_arm7, false);
+            let cpu = new_arm7_cpu(arm7_bus, fast_entry_arm7, arm7_no_bios, Vec::new());
             //let audio_channels = cpu.mut_mem().enable_audio();
             //channel_sender.send(audio_channels).unwrap();
             debug_wrapper.run_debug(cpu);
         }).unwrap();
 
-        debug_interface
+        Ok(debug_interface)
     }
 
     /// Make a new debuggable NDS.
     /// 
     /// Steps through the ARM9 CPU.
-    pub fn new_debug_9(config: MemoryConfig) -> DebugInterface<UserInput> {
+    pub fn new_debug_9(config: MemoryConfig) -> std::io::Result<DebugInterface<UserInput>> {
         use crate::common::video::framecomms::debug::new_debug_frame_comms;
 
-        let (render_width, render_height) = RendererType::render_size();
+        let (render_width, render_height) = RendererType::render_size(config.render_scale());
         let (frame_sender, frame_receiver) = new_debug_frame_comms(render_width * render_height * 4, 2);
         let (debug_interface, debug_wrapper) = DebugInterface::new(frame_receiver, UserInput::default());
+        // Events are dropped in debug mode.
+        let (event_sender, _) = unbounded();
 
-        let (mut arm9_bus, mut arm7_bus) = DS9MemoryBus::<RendererType>::new(&config, frame_sender);
+        let (mut arm9_bus, mut arm7_bus) = DS9MemoryBus::<RendererType>::new(&config, frame_sender, event_sender)?;
 
-        let fast_boot = config.fast_boot;
+        let fast_boot = config.skip_boot();
+        let arm9_no_bios = config.ds9_bios_path.is_none();
+        let arm7_no_bios = config.ds7_bios_path.is_none();
         let (fast_entry_arm9, fast_entry_arm7) = if fast_boot {
             let card_header = arm9_bus.get_header();
             arm9_bus.setup_boot_area(&card_header);
@@ -211,27 +294,43 @@ impl NDS {
             if fast_boot {
                 internal_mem.setup_init();
             }
-            let cpu = new_arm9_cpu(internal_mem, fast_entry_arm9);
+            let cpu = new_arm9_cpu(internal_mem, fast_entry_arm9, arm9_no_bios);
             debug_wrapper.run_debug(cpu);
         }).unwrap();
 
         std::thread::Builder::new().name("ARM7-CPU".to_string()).spawn(move || {
-            let mut cpu = new_arm7_cpu(arm7_bus, fast_entry_arm7, false);
+            let mut cpu = new_arm7_cpu(arm7_bus, fast_entry_arm7, arm7_no_bios, Vec::new());
             //let audio_channels = cpu.mut_mem().enable_audio();
             //channel_sender.send(audio_channels).unwrap();
             loop {
                 cpu.step();
+                if let Some(addr) = cpu.mut_mem().take_bios_call() {
+                    call_bios_routine(&mut cpu, addr);
+                }
             }
         }).unwrap();
 
-        debug_interface
+        Ok(debug_interface)
+    }
+}
+
+/// Fill a R8G8B8A8 frame with black.
+fn blank_frame(frame: &mut [u8]) {
+    for pixel in frame.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[0, 0, 0, 0xFF]);
     }
 }
 
-fn new_arm7_cpu(mem_bus: Box<DS7MemoryBus>, fast_entry: Option<u32>, use_jit: bool) -> ARM7TDMI<DS7MemoryBus> {
+/// Create the ARM7 CPU. If `jit_ranges` is empty, the JIT is disabled.
+/// 
+/// If `no_bios` is set, SWI calls are emulated.
+fn new_arm7_cpu(mem_bus: Box<DS7MemoryBus>, fast_entry: Option<u32>, no_bios: bool, jit_ranges: Vec<Range<u32>>) -> ARM7TDMI<DS7MemoryBus> {
     let mut cpu_builder = ARM7TDMI::new(mem_bus);
-    if use_jit {
-        cpu_builder = cpu_builder.enable_jit_in_ranges(vec![0..0x4000, 0x0800_0000..0x0E00_0000]);
+    if !jit_ranges.is_empty() {
+        cpu_builder = cpu_builder.enable_jit_in_ranges(jit_ranges);
+    }
+    if no_bios {
+        cpu_builder = cpu_builder.set_swi_hook(emulated_swi_arm7);
     }
     if let Some(entry_point) = fast_entry {
         // Setup stack pointers.
@@ -250,8 +349,14 @@ fn new_arm7_cpu(mem_bus: Box<DS7MemoryBus>, fast_entry: Option<u32>, use_jit: bo
     }
 }
 
-fn new_arm9_cpu<R: Renderer>(mem_bus: Box<DS9InternalMem<R>>, fast_entry: Option<u32>) -> ARM9ES<DS9InternalMem<R>> {
-    let cpu_builder = ARM9ES::new(mem_bus);
+/// Create the ARM9 CPU.
+/// 
+/// If `no_bios` is set, SWI calls are emulated.
+fn new_arm9_cpu<R: Renderer>(mem_bus: Box<DS9InternalMem<R>>, fast_entry: Option<u32>, no_bios: bool) -> ARM9ES<DS9InternalMem<R>> {
+    let mut cpu_builder = ARM9ES::new(mem_bus);
+    if no_bios {
+        cpu_builder = cpu_builder.set_swi_hook(emulated_swi_arm9);
+    }
     if let Some(entry_point) = fast_entry {
         // Setup stack pointers.
         let mut cpu = cpu_builder.build();
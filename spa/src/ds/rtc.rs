@@ -2,13 +2,70 @@
 
 use bitflags::bitflags;
 use chrono::{
-    Datelike, Timelike, Local
+    Datelike, Timelike, Local,
+    Duration, NaiveDate, NaiveDateTime
 };
 use crate::utils::{
     meminterface::MemInterface8,
     bits::u8, bcd::Bcd8,
 };
 
+/// The emulated clock advances in steps of 1/16 second.
+const TICKS_PER_SECOND: usize = 16;
+const CYCLES_PER_TICK: usize = (6 * 355 * 263 * 60) / TICKS_PER_SECOND;
+
+/// Set in each alarm register to compare it with the current time.
+const ALARM_ENABLE: u8 = u8::bit(7);
+/// Set in the hour register for PM.
+const HOUR_PM: u8 = u8::bit(6);
+
+/// Time that the RTC starts at.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RTCTime {
+    /// Local time of the host.
+    #[default]
+    Host,
+    /// Local time of the host, plus an offset.
+    Offset(Duration),
+    /// Start at a fixed time, and advance with emulated time.
+    Fixed(NaiveDateTime),
+    /// Stay at a fixed time. Per-minute and alarm interrupts never fire.
+    Frozen(NaiveDateTime),
+}
+
+/// Where the current time comes from.
+enum TimeSource {
+    /// Local time of the host, plus an offset.
+    Host(Duration),
+    /// Advanced by the RTC each second.
+    Emulated(NaiveDateTime),
+    Frozen(NaiveDateTime),
+}
+
+impl TimeSource {
+    fn now(&self) -> NaiveDateTime {
+        match self {
+            TimeSource::Host(offset) => Local::now().naive_local() + *offset,
+            TimeSource::Emulated(time) => *time,
+            TimeSource::Frozen(time) => *time,
+        }
+    }
+
+    fn set(&mut self, new_time: NaiveDateTime) {
+        match self {
+            TimeSource::Host(offset) => *offset = new_time - Local::now().naive_local(),
+            TimeSource::Emulated(time) => *time = new_time,
+            TimeSource::Frozen(time) => *time = new_time,
+        }
+    }
+
+    fn advance_second(&mut self) {
+        if let TimeSource::Emulated(time) = self {
+            *time += Duration::seconds(1);
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum RTCState {
     Idle,
@@ -47,6 +104,18 @@ bitflags! {
     }
 }
 
+/// Output mode of the INT1 pin.
+#[derive(Clone, Copy, PartialEq)]
+enum Int1Mode {
+    Disabled,
+    /// Steady interrupts, at the frequency selected by the INT1 register.
+    Frequency,
+    PerMinute,
+    Alarm,
+    /// 32kHz clock output. Doesn't raise interrupts.
+    Output32k,
+}
+
 pub struct RealTimeClock {
     state:      RTCState,
     transfer:   u8, /// How many bits have been transferred in the current state?
@@ -66,20 +135,35 @@ pub struct RealTimeClock {
     minute:     Bcd8,
     second:     Bcd8,
 
-    alarm1_weekday: Bcd8,
-    alarm1_hour:    Bcd8,
-    alarm1_minute:  Bcd8,
+    // Alarm registers have enable flags, so aren't stored as BCD.
+    alarm1_weekday: u8,
+    alarm1_hour:    u8,
+    /// Also used as the frequency select register.
+    alarm1_minute:  u8,
 
-    alarm2_weekday: Bcd8,
-    alarm2_hour:    Bcd8,
-    alarm2_minute:  Bcd8,
+    alarm2_weekday: u8,
+    alarm2_hour:    u8,
+    alarm2_minute:  u8,
 
     clock:      u8,
     free:       u8,
+
+    source:         TimeSource,
+    cycle_count:    usize,
+    tick:           usize,
+    /// Date, hour and minute when the minute interrupts were last checked.
+    last_minute:    (NaiveDate, u32, u32),
 }
 
 impl RealTimeClock {
-    pub fn new() -> Self {
+    pub fn new(start_time: RTCTime) -> Self {
+        let source = match start_time {
+            RTCTime::Host => TimeSource::Host(Duration::zero()),
+            RTCTime::Offset(offset) => TimeSource::Host(offset),
+            RTCTime::Fixed(time) => TimeSource::Emulated(time),
+            RTCTime::Frozen(time) => TimeSource::Frozen(time),
+        };
+        let now = source.now();
         let mut rtc = Self {
             state:      RTCState::Idle,
             transfer:   0,
@@ -99,16 +183,21 @@ impl RealTimeClock {
             minute:     Bcd8::from_binary(0),
             second:     Bcd8::from_binary(0),
 
-            alarm1_weekday: Bcd8::default(),
-            alarm1_hour:    Bcd8::default(),
-            alarm1_minute:  Bcd8::default(),
+            alarm1_weekday: 0,
+            alarm1_hour:    0,
+            alarm1_minute:  0,
 
-            alarm2_weekday: Bcd8::default(),
-            alarm2_hour:    Bcd8::default(),
-            alarm2_minute:  Bcd8::default(),
+            alarm2_weekday: 0,
+            alarm2_hour:    0,
+            alarm2_minute:  0,
 
             clock:      0,
             free:       0,
+
+            source:         source,
+            cycle_count:    0,
+            tick:           0,
+            last_minute:    (now.date(), now.hour(), now.minute()),
         };
 
         rtc.set_current_time();
@@ -120,9 +209,48 @@ impl RealTimeClock {
         rtc
     }
 
+    /// Change the current time.
+    pub fn set_time(&mut self, time: NaiveDateTime) {
+        self.source.set(time);
+    }
+
     /// Advance RTC and return true if interrupt occurred.
-    pub fn _clock(&mut self, _cycles: usize) -> bool {
-        false
+    pub fn clock(&mut self, cycles: usize) -> bool {
+        self.cycle_count += cycles;
+        if self.cycle_count < CYCLES_PER_TICK {
+            return false;
+        }
+        self.cycle_count -= CYCLES_PER_TICK;
+        self.tick = (self.tick + 1) % TICKS_PER_SECOND;
+
+        let int_1_mode = self.int_1_mode();
+        let mut int_1 = int_1_mode == Int1Mode::Frequency && self.frequency_interrupt();
+        let mut int_2 = false;
+
+        if self.tick == 0 {
+            self.source.advance_second();
+            let now = self.source.now();
+            let minute = (now.date(), now.hour(), now.minute());
+            if minute != self.last_minute {
+                self.last_minute = minute;
+                match int_1_mode {
+                    Int1Mode::PerMinute => int_1 = true,
+                    Int1Mode::Alarm => int_1 = self.alarm_matches(&now, self.alarm1_weekday, self.alarm1_hour, self.alarm1_minute),
+                    _ => {},
+                }
+                if self.status_2.contains(Status2::INT_2_ENABLE) {
+                    int_2 = self.alarm_matches(&now, self.alarm2_weekday, self.alarm2_hour, self.alarm2_minute);
+                }
+            }
+        }
+
+        if int_1 {
+            self.status_1.insert(Status1::INT_1);
+        }
+        if int_2 {
+            self.status_1.insert(Status1::INT_2);
+        }
+        int_1 || int_2
     }
 
     fn process_command(&mut self) {
@@ -140,7 +268,7 @@ impl RealTimeClock {
                 self.set_current_time();
                 DateTime(3)
             },
-            4 => if u8::test_bit(self.status_2.bits(), 2) {Int1(1)} else {Int1(3)},
+            4 => if self.int_1_mode() == Int1Mode::Alarm {Int1(3)} else {Int1(1)},
             5 => Int2(3),
             6 => ClockAdjust,
             7 => Free,
@@ -162,12 +290,12 @@ impl RealTimeClock {
             DateTime(3) => self.hour.binary(),
             DateTime(2) => self.minute.binary(),
             DateTime(1) => self.second.binary(),
-            Int1(3) => self.alarm1_weekday.binary(),
-            Int1(2) => self.alarm1_hour.binary(),
-            Int1(1) => self.alarm1_minute.binary(),
-            Int2(3) => self.alarm2_weekday.binary(),
-            Int2(2) => self.alarm2_hour.binary(),
-            Int2(1) => self.alarm2_minute.binary(),
+            Int1(3) => self.alarm1_weekday,
+            Int1(2) => self.alarm1_hour,
+            Int1(1) => self.alarm1_minute,
+            Int2(3) => self.alarm2_weekday,
+            Int2(2) => self.alarm2_hour,
+            Int2(1) => self.alarm2_minute,
             ClockAdjust => self.clock,
             Free => self.free,
             _ => panic!("reading bit from RTC in unsupported state {:?}", self.state),
@@ -196,13 +324,19 @@ impl RealTimeClock {
             DateTime(4) => self.weekday = Bcd8::from_bcd(self.write_buf),
             DateTime(3) => self.hour = Bcd8::from_bcd(self.write_buf),
             DateTime(2) => self.minute = Bcd8::from_bcd(self.write_buf),
-            DateTime(1) => self.second = Bcd8::from_bcd(self.write_buf),
-            Int1(3) => self.alarm1_weekday = Bcd8::from_bcd(self.write_buf),
-            Int1(2) => self.alarm1_hour = Bcd8::from_bcd(self.write_buf),
-            Int1(1) => self.alarm1_minute = Bcd8::from_bcd(self.write_buf),
-            Int2(3) => self.alarm2_weekday = Bcd8::from_bcd(self.write_buf),
-            Int2(2) => self.alarm2_hour = Bcd8::from_bcd(self.write_buf),
-            Int2(1) => self.alarm2_minute = Bcd8::from_bcd(self.write_buf),
+            DateTime(1) => {
+                self.second = Bcd8::from_bcd(self.write_buf);
+                // Last byte of time: apply the new time.
+                if let Some(time) = self.register_time() {
+                    self.source.set(time);
+                }
+            },
+            Int1(3) => self.alarm1_weekday = self.write_buf,
+            Int1(2) => self.alarm1_hour = self.write_buf,
+            Int1(1) => self.alarm1_minute = self.write_buf,
+            Int2(3) => self.alarm2_weekday = self.write_buf,
+            Int2(2) => self.alarm2_hour = self.write_buf,
+            Int2(1) => self.alarm2_minute = self.write_buf,
             ClockAdjust => self.clock = self.write_buf,
             Free => self.free = self.write_buf,
             _ => panic!("writing bit to RTC in unsupported state"),
@@ -226,10 +360,9 @@ impl RealTimeClock {
         self.transfer = 8;
     }
 
-    /// Set the time.
+    /// Set the time registers from the current time.
     fn set_current_time(&mut self) {
-        // TODO: offset from configured time.
-        let time = Local::now();
+        let time = self.source.now();
         let year = time.year() % 100;
         let weekday = time.weekday().num_days_from_sunday(); // Appears to use Monday = 1, ...
 
@@ -238,16 +371,70 @@ impl RealTimeClock {
         self.day = Bcd8::from_binary(time.day() as u8);
         self.weekday = Bcd8::from_binary(weekday as u8);
 
-        let hour = if self.status_1.contains(Status1::HOUR_24) {
-            time.hour()
-        } else {
-            let (pm, hour) = time.hour12();
-            (hour - 1) | (if pm {0x40} else {0x00})
-        };
-        self.hour = Bcd8::from_binary(hour as u8);
+        self.hour = self.hour_register(time.hour());
         self.minute = Bcd8::from_binary(time.minute() as u8);
         self.second = Bcd8::from_binary(time.second() as u8);
     }
+
+    /// Convert an hour (0-23) into the hour register format.
+    /// The PM flag is set in both 12 and 24-hour mode.
+    fn hour_register(&self, hour: u32) -> Bcd8 {
+        let pm = if hour >= 12 {HOUR_PM} else {0};
+        let hour = if self.status_1.contains(Status1::HOUR_24) {hour} else {hour % 12};
+        Bcd8::from_bcd(Bcd8::from_binary(hour as u8).binary() | pm)
+    }
+
+    /// Get the time written into the time registers.
+    /// Returns None if the registers are not a valid time.
+    fn register_time(&self) -> Option<NaiveDateTime> {
+        let hour_bcd = self.hour.binary();
+        let hour = Bcd8::from_bcd(hour_bcd & 0x3F).decimal() as u32;
+        let hour = if !self.status_1.contains(Status1::HOUR_24) && (hour_bcd & HOUR_PM) != 0 {
+            hour + 12
+        } else {
+            hour
+        };
+        NaiveDate::from_ymd_opt(
+            2000 + (self.year.decimal() as i32),
+            self.month.decimal() as u32,
+            self.day.decimal() as u32
+        )?.and_hms_opt(hour, self.minute.decimal() as u32, self.second.decimal() as u32)
+    }
+
+    fn int_1_mode(&self) -> Int1Mode {
+        match (self.status_2 & Status2::INT_1_MODE).bits() {
+            0 => Int1Mode::Disabled,
+            1 | 5 => Int1Mode::Frequency,
+            2 | 3 | 6 | 7 => Int1Mode::PerMinute,
+            4 => Int1Mode::Alarm,
+            _ => Int1Mode::Output32k,
+        }
+    }
+
+    /// Check if the selected frequency interrupt fires on this tick.
+    ///
+    /// Bits 0-4 of the frequency register select 1, 2, 4, 8, 16Hz.
+    /// The highest selected frequency is used.
+    fn frequency_interrupt(&self) -> bool {
+        let select = self.alarm1_minute & 0x1F;
+        if select == 0 {
+            return false;
+        }
+        let frequency = 1 << (7 - select.leading_zeros());
+        self.tick % (TICKS_PER_SECOND / frequency) == 0
+    }
+
+    /// Compare the current time with the enabled parts of an alarm.
+    fn alarm_matches(&self, now: &NaiveDateTime, weekday: u8, hour: u8, minute: u8) -> bool {
+        let hour_mask = if self.status_1.contains(Status1::HOUR_24) {0x3F} else {0x7F};
+        let weekday_match = (weekday & ALARM_ENABLE) == 0 ||
+            (weekday & 0x7) == (now.weekday().num_days_from_sunday() as u8);
+        let hour_match = (hour & ALARM_ENABLE) == 0 ||
+            (hour & hour_mask) == (self.hour_register(now.hour()).binary() & hour_mask);
+        let minute_match = (minute & ALARM_ENABLE) == 0 ||
+            (minute & 0x7F) == Bcd8::from_binary(now.minute() as u8).binary();
+        weekday_match && hour_match && minute_match
+    }
 }
 
 impl MemInterface8 for RealTimeClock {
@@ -262,6 +449,10 @@ impl MemInterface8 for RealTimeClock {
         let shift = 7 - self.transfer;
         let bit = (data >> shift) & 1;
         if self.transfer == 0 {
+            if self.state == RTCState::StatusReg1 {
+                // Interrupt flags are cleared on read.
+                self.status_1.remove(Status1::INT_1 | Status1::INT_2);
+            }
             self.finish_param();
         }
         bit
@@ -325,3 +516,92 @@ impl MemInterface8 for RealTimeClock {
         self.write_byte(addr, data as u8);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday.
+    fn test_time(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2021, 1, 4).unwrap().and_hms_opt(hour, minute, second).unwrap()
+    }
+
+    /// Clock for `ticks` ticks, and count the interrupts.
+    fn count_interrupts(rtc: &mut RealTimeClock, ticks: usize) -> usize {
+        (0..ticks).filter(|_| rtc.clock(CYCLES_PER_TICK)).count()
+    }
+
+    #[test]
+    fn frequency_interrupts() {
+        let mut rtc = RealTimeClock::new(RTCTime::Frozen(test_time(12, 0, 0)));
+        rtc.status_2 = Status2::from_bits_truncate(1);
+        for (select, per_second) in [(0x01, 1), (0x02, 2), (0x04, 4), (0x08, 8), (0x10, 16), (0x05, 4)] {
+            rtc.alarm1_minute = select;
+            assert_eq!(count_interrupts(&mut rtc, TICKS_PER_SECOND), per_second, "select {:X}", select);
+        }
+        rtc.alarm1_minute = 0;
+        assert_eq!(count_interrupts(&mut rtc, TICKS_PER_SECOND), 0);
+    }
+
+    #[test]
+    fn per_minute_interrupt() {
+        let mut rtc = RealTimeClock::new(RTCTime::Fixed(test_time(12, 0, 58)));
+        rtc.status_2 = Status2::from_bits_truncate(2);
+        assert_eq!(count_interrupts(&mut rtc, TICKS_PER_SECOND), 0);
+        assert_eq!(count_interrupts(&mut rtc, TICKS_PER_SECOND), 1);
+        assert!(rtc.status_1.contains(Status1::INT_1));
+        assert_eq!(rtc.source.now(), test_time(12, 1, 0));
+    }
+
+    #[test]
+    fn alarm_interrupts() {
+        let mut rtc = RealTimeClock::new(RTCTime::Fixed(test_time(9, 59, 59)));
+        rtc.status_1 = Status1::HOUR_24;
+        rtc.status_2 = Status2::INT_2_ENABLE | Status2::from_bits_truncate(4);
+        // INT1 at 10:00, INT2 on Monday at 10:01.
+        rtc.alarm1_hour = ALARM_ENABLE | 0x10;
+        rtc.alarm1_minute = ALARM_ENABLE | 0x00;
+        rtc.alarm2_weekday = ALARM_ENABLE | 1;
+        rtc.alarm2_minute = ALARM_ENABLE | 0x01;
+
+        assert_eq!(count_interrupts(&mut rtc, TICKS_PER_SECOND), 1);
+        assert_eq!(rtc.status_1 & (Status1::INT_1 | Status1::INT_2), Status1::INT_1);
+        rtc.status_1.remove(Status1::INT_1);
+
+        assert_eq!(count_interrupts(&mut rtc, 60 * TICKS_PER_SECOND), 1);
+        assert_eq!(rtc.status_1 & (Status1::INT_1 | Status1::INT_2), Status1::INT_2);
+    }
+
+    #[test]
+    fn alarm_matches() {
+        let rtc = RealTimeClock::new(RTCTime::Frozen(test_time(12, 0, 0)));
+        let now = test_time(15, 30, 0);
+        // 12-hour mode: 3PM.
+        assert!(rtc.alarm_matches(&now, 0, ALARM_ENABLE | HOUR_PM | 0x03, ALARM_ENABLE | 0x30));
+        assert!(!rtc.alarm_matches(&now, 0, ALARM_ENABLE | 0x03, ALARM_ENABLE | 0x30));
+        assert!(!rtc.alarm_matches(&now, 0, 0, ALARM_ENABLE | 0x31));
+        // Weekday
+        assert!(rtc.alarm_matches(&now, ALARM_ENABLE | 1, 0, 0));
+        assert!(!rtc.alarm_matches(&now, ALARM_ENABLE | 2, 0, 0));
+        // Disabled parts are ignored.
+        assert!(rtc.alarm_matches(&now, 2, 0x03, 0x31));
+    }
+
+    #[test]
+    fn frozen_time() {
+        let time = test_time(12, 0, 58);
+        let mut rtc = RealTimeClock::new(RTCTime::Frozen(time));
+        rtc.status_2 = Status2::from_bits_truncate(2);
+        assert_eq!(count_interrupts(&mut rtc, 61 * TICKS_PER_SECOND), 0);
+        assert_eq!(rtc.source.now(), time);
+    }
+
+    #[test]
+    fn fixed_time() {
+        let mut rtc = RealTimeClock::new(RTCTime::Fixed(test_time(23, 59, 59)));
+        assert_eq!(count_interrupts(&mut rtc, TICKS_PER_SECOND), 0);
+        assert_eq!(rtc.source.now(), NaiveDate::from_ymd_opt(2021, 1, 5).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        rtc.set_current_time();
+        assert_eq!((rtc.day.binary(), rtc.hour.binary(), rtc.minute.binary()), (0x05, 0x00, 0x00));
+    }
+}
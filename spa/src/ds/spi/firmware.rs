@@ -1,59 +1,185 @@
-
+use bitflags::bitflags;
 use std::{
     io::{
         Result,
         Read,
+        Write,
         Seek,
         SeekFrom
     },
     fs::File,
-    path::Path
+    path::{Path, PathBuf}
+};
+
+use crate::{
+    common::swi::crc16,
+    utils::bits::u8
 };
+use super::profile::UserProfile;
 
 const FIRMWARE_SIZE: u32 = 256 * 1024;
+const ADDR_MASK: u32 = FIRMWARE_SIZE - 1;
+const PAGE_MASK: u32 = 0xFF;
+const SECTOR_MASK: u32 = 0xFFFF;
+
+/// JEDEC ID: manufacturer, memory type, capacity.
+const FLASH_ID: [u8; 3] = [0x20, 0x40, 0x12];
+
+/// Offset of the user settings. There are two slots, the newest is used.
+const USER_SETTINGS_ADDR: usize = 0x3_FE00;
+const USER_SETTINGS_SLOT_SIZE: usize = 0x100;
+/// Size of the user settings that are copied into RAM at boot.
+pub const USER_SETTINGS_SIZE: usize = 0x70;
+
+/// Offset of the Wi-Fi access point settings. There are three.
+const WIFI_AP_ADDR: usize = 0x3_FA00;
+const WIFI_AP_SIZE: usize = 0x100;
+
+/// MAC address used for generated firmware: Nintendo's prefix.
+pub const DEFAULT_MAC_ADDRESS: [u8; 6] = [0x00, 0x09, 0xBF, 0x12, 0x34, 0x56];
+
+bitflags!{
+    #[derive(Default)]
+    struct Status: u8 {
+        const WRITE_ENABLE      = u8::bit(1);
+        const WRITE_IN_PROGRESS = u8::bit(0);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum WriteOp {
+    /// Replace bytes in a page.
+    PageWrite,
+    /// Clear bits in a page.
+    PageProgram,
+    /// Set a page to 0xFF.
+    PageErase,
+    /// Set a 64kB sector to 0xFF.
+    SectorErase,
+}
 
 enum Instruction {
     None,
     Read(u8),
-    ReadStatus
+    FastRead(u8),
+    ReadStatus,
+    ReadID(usize),
+    Write(WriteOp, u8),
+    PowerDown,
+    /// Ignore the rest of the transfer.
+    Ignore,
 }
 
 /// Internal NDS firmware
+/// 
+/// 256kB SPI FLASH.
 pub struct Firmware {
     instr:      Instruction,
     addr:       u32,
 
     data:       Vec<u8>,
     read_buffer: u8,
-    can_write:  bool,
+    status:     Status,
+
+    /// Modified firmware is written here.
+    save_path:  Option<PathBuf>,
+    dirty:      bool,
 }
 
 impl Firmware {
-    pub fn new(path: Option<&Path>) -> Result<Self> {
-        let data = if let Some(path) = path {
+    /// If `save_path` exists, it is loaded in place of `path`.
+    /// If neither is provided, a firmware image is generated.
+    /// 
+    /// If `mac_address` is provided, it replaces the MAC address in the firmware.
+    /// If `profile` is provided, it replaces the user settings in the firmware.
+    pub fn new(path: Option<&Path>, save_path: Option<PathBuf>, mac_address: Option<[u8; 6]>, profile: Option<&UserProfile>) -> Result<Self> {
+        let load_path = save_path.as_ref()
+            .map(|p| p.as_path())
+            .filter(|p| p.exists())
+            .or(path);
+        let data = if let Some(path) = load_path {
             let mut firmware_file = File::open(path)?;
             let mut buffer = vec![0; FIRMWARE_SIZE as usize];
 
             firmware_file.seek(SeekFrom::Start(0))?;
             firmware_file.read(&mut buffer)?;
 
+            if let Some(mac_address) = mac_address {
+                write_mac_address(&mut buffer, mac_address);
+            }
             buffer
         } else {
-            Vec::new()
+            construct_firmware(mac_address.unwrap_or(DEFAULT_MAC_ADDRESS))
         };
 
-        Ok(Self {
+        let mut firmware = Self {
             instr:      Instruction::None,
             addr:       0,
 
             data:       data,
             read_buffer: 0,
-            can_write:  false,
-        })
+            status:     Status::default(),
+
+            save_path:  save_path,
+            dirty:      false,
+        };
+        if let Some(profile) = profile {
+            firmware.set_user_profile(profile);
+        }
+        Ok(firmware)
+    }
+
+    /// Get the most recent valid user settings.
+    pub fn user_settings(&self) -> &[u8] {
+        let addr = self.user_settings_addr();
+        &self.data[addr..(addr + USER_SETTINGS_SIZE)]
     }
 
+    /// Write a new copy of the user settings with the profile applied.
+    /// 
+    /// The firmware menu does the same: the older slot is replaced.
+    pub fn set_user_profile(&mut self, profile: &UserProfile) {
+        let current_addr = self.user_settings_addr();
+        let new_addr = if current_addr == USER_SETTINGS_ADDR {
+            USER_SETTINGS_ADDR + USER_SETTINGS_SLOT_SIZE
+        } else {
+            USER_SETTINGS_ADDR
+        };
+        let count = (u16::from_le_bytes([self.data[current_addr + 0x70], self.data[current_addr + 0x71]]).wrapping_add(1)) & 0x7F;
+
+        let mut settings = self.data[current_addr..(current_addr + USER_SETTINGS_SIZE)].to_vec();
+        profile.write(&mut settings);
+
+        let slot = &mut self.data[new_addr..(new_addr + USER_SETTINGS_SLOT_SIZE)];
+        slot[0..USER_SETTINGS_SIZE].copy_from_slice(&settings);
+        write_user_settings_footer(slot, count);
+    }
+
+    /// Write modified firmware to disk.
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.dirty = false;
+            if let Some(path) = &self.save_path {
+                let mut file = File::create(path).expect("Couldn't create firmware file!");
+                file.write_all(&self.data).expect("Couldn't write to firmware file!");
+            }
+        }
+    }
+
+    /// Writes and erases are done when the chip is deselected.
     pub fn deselect(&mut self) {
-        self.instr = Instruction::None;
+        if let Instruction::Write(op, 0) = self.instr {
+            if let WriteOp::PageErase | WriteOp::SectorErase = op {
+                self.erase(op);
+            }
+            self.status.remove(Status::WRITE_ENABLE);
+            self.status.insert(Status::WRITE_IN_PROGRESS);
+            self.dirty = true;
+        }
+        self.instr = match self.instr {
+            Instruction::PowerDown => Instruction::PowerDown,
+            _ => Instruction::None
+        };
         self.addr = 0;
     }
 
@@ -66,16 +192,30 @@ impl Firmware {
         match self.instr {
             None => match data {
                 0x03 => self.instr = Read(3),
+                0x0B => self.instr = FastRead(4),
                 0x05 => self.instr = ReadStatus,
-                0x06 => self.can_write = true,
-                0x04 => self.can_write = false,
+                0x9F => self.instr = ReadID(0),
+                0x06 => self.status.insert(Status::WRITE_ENABLE),
+                0x04 => self.status.remove(Status::WRITE_ENABLE),
+                0x0A => self.start_write(WriteOp::PageWrite),
+                0x02 => self.start_write(WriteOp::PageProgram),
+                0xDB => self.start_write(WriteOp::PageErase),
+                0xD8 => self.start_write(WriteOp::SectorErase),
+                0xB9 => self.instr = PowerDown,
+                0xAB => self.instr = Ignore,
                 _ => panic!("unsupported instr {:X}", data),
             },
             ReadStatus => {
-                self.read_buffer = if self.can_write {1} else {0};
+                self.read_buffer = self.status.bits();
+                // Writes complete instantly, but the game might poll for this.
+                self.status.remove(Status::WRITE_IN_PROGRESS);
+            },
+            ReadID(n) => {
+                self.read_buffer = FLASH_ID.get(n).cloned().unwrap_or(0xFF);
+                self.instr = ReadID(n + 1);
             },
-            Read(0) => { // Strobe
-                self.read_buffer = self.data[self.addr as usize];
+            Read(0) | FastRead(0) => { // Strobe
+                self.read_buffer = self.data[(self.addr & ADDR_MASK) as usize];
                 self.addr += 1;
             },
             Read(n) => {
@@ -83,6 +223,357 @@ impl Firmware {
                 self.addr |= (data as u32) << ((n - 1) * 8);
                 self.instr = Read(n-1);
             },
+            FastRead(1) => self.instr = FastRead(0), // Dummy byte
+            FastRead(n) => {
+                self.addr |= (data as u32) << ((n - 2) * 8);
+                self.instr = FastRead(n-1);
+            },
+            Write(op, 0) => {
+                let addr = (self.addr & ADDR_MASK) as usize;
+                match op {
+                    WriteOp::PageWrite => self.data[addr] = data,
+                    WriteOp::PageProgram => self.data[addr] &= data,
+                    _ => {},
+                }
+                // Address wraps around within the page.
+                self.addr = (self.addr & !PAGE_MASK) | (self.addr.wrapping_add(1) & PAGE_MASK);
+            },
+            Write(op, n) => {
+                self.addr |= (data as u32) << ((n - 1) * 8);
+                self.instr = Write(op, n-1);
+            },
+            PowerDown => if data == 0xAB {
+                self.instr = Ignore;
+            },
+            Ignore => {},
         }
     }
 }
+
+impl Firmware {
+    /// Address of the most recent valid user settings slot.
+    fn user_settings_addr(&self) -> usize {
+        let addr_0 = USER_SETTINGS_ADDR;
+        let addr_1 = USER_SETTINGS_ADDR + USER_SETTINGS_SLOT_SIZE;
+        let slot_0 = &self.data[addr_0..addr_1];
+        let slot_1 = &self.data[addr_1..(addr_1 + USER_SETTINGS_SLOT_SIZE)];
+        match (user_settings_valid(slot_0), user_settings_valid(slot_1)) {
+            (true, true) => {
+                // Count is mod 0x80.
+                let count_0 = slot_0[0x70];
+                let count_1 = slot_1[0x70];
+                if count_1 == (count_0.wrapping_add(1) & 0x7F) {addr_1} else {addr_0}
+            },
+            (false, true) => addr_1,
+            _ => addr_0,
+        }
+    }
+
+    /// Writes are ignored if write enable isn't set.
+    fn start_write(&mut self, op: WriteOp) {
+        self.instr = if self.status.contains(Status::WRITE_ENABLE) {
+            Instruction::Write(op, 3)
+        } else {
+            Instruction::Ignore
+        };
+    }
+
+    fn erase(&mut self, op: WriteOp) {
+        let mask = match op {
+            WriteOp::SectorErase => SECTOR_MASK,
+            _ => PAGE_MASK,
+        };
+        let start = (self.addr & ADDR_MASK & !mask) as usize;
+        let end = start + (mask as usize) + 1;
+        self.data[start..end].fill(0xFF);
+    }
+}
+
+fn user_settings_valid(slot: &[u8]) -> bool {
+    let crc = u16::from_le_bytes([slot[0x72], slot[0x73]]);
+    crc == crc16(0xFFFF, &slot[0..USER_SETTINGS_SIZE])
+}
+
+/// Generate a firmware image, for when no firmware file is provided.
+/// 
+/// There is no boot code, so it can only be used with fast boot.
+fn construct_firmware(mac_address: [u8; 6]) -> Vec<u8> {
+    let mut data = vec![0xFF; FIRMWARE_SIZE as usize];
+
+    write_header(&mut data);
+    write_wifi_calibration(&mut data, mac_address);
+
+    for ap in 0..3 {
+        let start = WIFI_AP_ADDR + ap * WIFI_AP_SIZE;
+        write_wifi_access_point(&mut data[start..(start + WIFI_AP_SIZE)]);
+    }
+
+    for (count, slot) in data[USER_SETTINGS_ADDR..].chunks_exact_mut(USER_SETTINGS_SLOT_SIZE).enumerate() {
+        write_user_settings(slot, count as u16);
+    }
+
+    data
+}
+
+fn write_header(data: &mut [u8]) {
+    // Boot code (none).
+    data[0x00..0x08].fill(0);
+    data[0x08..0x0C].copy_from_slice(b"MACP");
+    data[0x0C..0x18].fill(0);
+    // Build timestamp (BCD): 2005-01-01 00:00
+    data[0x18..0x1D].copy_from_slice(&[0x00, 0x00, 0x01, 0x01, 0x05]);
+    // Console type: DS
+    data[0x1D] = 0xFF;
+    // User settings offset / 8.
+    data[0x20..0x22].copy_from_slice(&((USER_SETTINGS_ADDR / 8) as u16).to_le_bytes());
+    data[0x22..0x24].copy_from_slice(&0x7EC0_u16.to_le_bytes());
+    data[0x24..0x26].copy_from_slice(&0x7E40_u16.to_le_bytes());
+    data[0x26..0x28].fill(0);
+}
+
+/// Typical values for an RF type 2 console.
+/// 
+/// Only the MAC address and enabled channels are used by the emulated Wi-Fi.
+fn write_wifi_calibration(data: &mut [u8], mac_address: [u8; 6]) {
+    const CONFIG_LEN: usize = 0x138;
+    const W_CONFIG: [u16; 16] = [
+        0x0002, 0x0017, 0x0026, 0x1818, 0x0048, 0x4840, 0x0058, 0x0042,
+        0x0140, 0x8064, 0xE0E0, 0x2443, 0x000E, 0x0032, 0x01F4, 0x0202
+    ];
+    const BB_INIT: [u8; 0x10] = [
+        0x03, 0x17, 0x40, 0x00, 0x1B, 0x6C, 0x48, 0x80, 0x38, 0x00, 0x35, 0x07, 0x00, 0x00, 0x00, 0x00
+    ];
+
+    data[0x2C..0x2E].copy_from_slice(&(CONFIG_LEN as u16).to_le_bytes());
+    data[0x2E] = 0;
+    data[0x2F] = 0; // Wi-Fi version
+    // Channels 1-13
+    data[0x3C..0x3E].copy_from_slice(&0x3FFE_u16.to_le_bytes());
+    // RF chip type, bits per entry, number of entries.
+    data[0x40..0x44].copy_from_slice(&[0x02, 0x18, 0x0C, 0x01]);
+    for (n, value) in W_CONFIG.iter().enumerate() {
+        let addr = 0x44 + n * 2;
+        data[addr..(addr + 2)].copy_from_slice(&value.to_le_bytes());
+    }
+    data[0x64..0xCE].fill(0);
+    data[0x64..(0x64 + BB_INIT.len())].copy_from_slice(&BB_INIT);
+    // RF initial values, per-channel RF and BB values.
+    data[0xCE..0x164].fill(0);
+
+    write_mac_address(data, mac_address);
+}
+
+/// Replace the MAC address, and update the CRC of the Wi-Fi config.
+fn write_mac_address(data: &mut [u8], mac_address: [u8; 6]) {
+    data[0x36..0x3C].copy_from_slice(&mac_address);
+    let config_len = u16::from_le_bytes([data[0x2C], data[0x2D]]) as usize;
+    let config_end = std::cmp::min(0x2C + config_len, data.len());
+    let crc = crc16(0, &data[0x2C..config_end]);
+    data[0x2A..0x2C].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// An unconfigured access point.
+fn write_wifi_access_point(ap: &mut [u8]) {
+    ap.fill(0);
+    // Status: not configured
+    ap[0xE7] = 0xFF;
+    let crc = crc16(0, &ap[0..0xFE]);
+    ap[0xFE..0x100].copy_from_slice(&crc.to_le_bytes());
+}
+
+fn write_user_settings(slot: &mut [u8], count: u16) {
+    slot.fill(0);
+    slot[0x00..0x02].copy_from_slice(&5_u16.to_le_bytes()); // Version
+
+    // Touchscreen calibration:
+    slot[0x58..0x5A].copy_from_slice(&0_u16.to_le_bytes());     // ADC.X1
+    slot[0x5A..0x5C].copy_from_slice(&0_u16.to_le_bytes());     // ADC.Y1
+    slot[0x5C] = 0;     // SCR.X1
+    slot[0x5D] = 0;     // SCR.Y1
+    slot[0x5E..0x60].copy_from_slice(&0xFF0_u16.to_le_bytes()); // ADC.X2
+    slot[0x60..0x62].copy_from_slice(&0xBF0_u16.to_le_bytes()); // ADC.Y2
+    slot[0x62] = 255;   // SCR.X2
+    slot[0x63] = 191;   // SCR.Y2
+
+    // Settings flags.
+    slot[0x64..0x66].copy_from_slice(&0xEC40_u16.to_le_bytes());
+    slot[0x6C..0x70].fill(0xFF);
+
+    UserProfile::default().write(slot);
+    write_user_settings_footer(slot, count);
+}
+
+/// Write the update count and CRC.
+fn write_user_settings_footer(slot: &mut [u8], count: u16) {
+    slot[0x70..0x72].copy_from_slice(&count.to_le_bytes());
+    let crc = crc16(0xFFFF, &slot[0..USER_SETTINGS_SIZE]);
+    slot[0x72..0x74].copy_from_slice(&crc.to_le_bytes());
+    slot[0x74..].fill(0xFF);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOT_0: usize = USER_SETTINGS_ADDR;
+    const SLOT_1: usize = USER_SETTINGS_ADDR + USER_SETTINGS_SLOT_SIZE;
+
+    fn read_u16(data: &[u8], addr: usize) -> u16 {
+        u16::from_le_bytes([data[addr], data[addr + 1]])
+    }
+
+    /// Send a command, and return the bytes read back.
+    fn command(firmware: &mut Firmware, bytes: &[u8]) -> Vec<u8> {
+        let out = bytes.iter().map(|byte| {
+            firmware.write(*byte);
+            firmware.read()
+        }).collect();
+        firmware.deselect();
+        out
+    }
+
+    fn read_status(firmware: &mut Firmware) -> u8 {
+        command(firmware, &[0x05, 0])[1]
+    }
+
+    #[test]
+    fn generated_crcs() {
+        let data = construct_firmware(DEFAULT_MAC_ADDRESS);
+
+        // Header
+        assert_eq!(&data[0x08..0x0C], b"MACP");
+        assert_eq!((read_u16(&data, 0x20) as usize) * 8, USER_SETTINGS_ADDR);
+
+        // Wi-Fi calibration
+        let config_len = read_u16(&data, 0x2C) as usize;
+        assert_eq!(read_u16(&data, 0x2A), crc16(0, &data[0x2C..(0x2C + config_len)]));
+        assert_eq!(&data[0x36..0x3C], &DEFAULT_MAC_ADDRESS);
+
+        // Wi-Fi access points
+        for ap in data[WIFI_AP_ADDR..].chunks_exact(WIFI_AP_SIZE).take(3) {
+            assert_eq!(read_u16(ap, 0xFE), crc16(0, &ap[0..0xFE]));
+        }
+
+        // User settings
+        for slot in data[USER_SETTINGS_ADDR..].chunks_exact(USER_SETTINGS_SLOT_SIZE) {
+            assert_eq!(read_u16(slot, 0x72), crc16(0xFFFF, &slot[0..USER_SETTINGS_SIZE]));
+            assert!(user_settings_valid(slot));
+        }
+    }
+
+    #[test]
+    fn newest_user_settings() {
+        let mut firmware = Firmware::new(None, None, None, None).unwrap();
+        // Generated with counts 0 and 1.
+        assert_eq!(firmware.user_settings_addr(), SLOT_1);
+
+        // Count wraps at 0x80.
+        write_user_settings_footer(&mut firmware.data[SLOT_0..SLOT_1], 0x7F);
+        write_user_settings_footer(&mut firmware.data[SLOT_1..(SLOT_1 + USER_SETTINGS_SLOT_SIZE)], 0);
+        assert_eq!(firmware.user_settings_addr(), SLOT_1);
+        write_user_settings_footer(&mut firmware.data[SLOT_0..SLOT_1], 0);
+        write_user_settings_footer(&mut firmware.data[SLOT_1..(SLOT_1 + USER_SETTINGS_SLOT_SIZE)], 0x7F);
+        assert_eq!(firmware.user_settings_addr(), SLOT_0);
+
+        // An invalid slot is ignored, even if it is newer.
+        write_user_settings_footer(&mut firmware.data[SLOT_1..(SLOT_1 + USER_SETTINGS_SLOT_SIZE)], 1);
+        assert_eq!(firmware.user_settings_addr(), SLOT_1);
+        firmware.data[SLOT_1] ^= 0xFF;
+        assert_eq!(firmware.user_settings_addr(), SLOT_0);
+    }
+
+    #[test]
+    fn set_user_profile_replaces_older_slot() {
+        let mut firmware = Firmware::new(None, None, None, None).unwrap();
+        let profile = UserProfile {
+            favourite_colour: 7,
+            ..Default::default()
+        };
+        firmware.set_user_profile(&profile);
+
+        assert_eq!(firmware.user_settings_addr(), SLOT_0);
+        assert!(user_settings_valid(&firmware.data[SLOT_0..SLOT_1]));
+        assert_eq!(read_u16(&firmware.data, SLOT_0 + 0x70), 2);
+        assert_eq!(firmware.user_settings()[0x02], 7);
+    }
+
+    #[test]
+    fn write_enable() {
+        let mut firmware = Firmware::new(None, None, None, None).unwrap();
+        assert_eq!(read_status(&mut firmware), 0);
+        command(&mut firmware, &[0x06]);
+        assert_eq!(read_status(&mut firmware), Status::WRITE_ENABLE.bits());
+        command(&mut firmware, &[0x04]);
+        assert_eq!(read_status(&mut firmware), 0);
+
+        // Writes are ignored without write enable.
+        command(&mut firmware, &[0x0A, 0x02, 0x00, 0x00, 0x12]);
+        assert_eq!(firmware.data[0x2_0000], 0xFF);
+    }
+
+    #[test]
+    fn page_write_and_program() {
+        let mut firmware = Firmware::new(None, None, None, None).unwrap();
+        command(&mut firmware, &[0x06]);
+        // Wraps around within the page.
+        command(&mut firmware, &[0x0A, 0x02, 0x00, 0xFE, 0x11, 0x22, 0x33]);
+        assert_eq!(&firmware.data[0x2_00FE..0x2_0100], &[0x11, 0x22]);
+        assert_eq!(firmware.data[0x2_0000], 0x33);
+        assert_eq!(firmware.data[0x2_0100], 0xFF);
+        // Write enable is cleared once the write is done.
+        assert_eq!(read_status(&mut firmware), Status::WRITE_IN_PROGRESS.bits());
+        assert_eq!(read_status(&mut firmware), 0);
+
+        // Program only clears bits.
+        command(&mut firmware, &[0x06]);
+        command(&mut firmware, &[0x02, 0x02, 0x00, 0x00, 0x0F]);
+        assert_eq!(firmware.data[0x2_0000], 0x03);
+        assert_eq!(command(&mut firmware, &[0x03, 0x02, 0x00, 0x00, 0])[4], 0x03);
+    }
+
+    #[test]
+    fn sector_erase() {
+        let mut firmware = Firmware::new(None, None, None, None).unwrap();
+        firmware.data[0x1_0000..0x2_0000].fill(0);
+        command(&mut firmware, &[0x06]);
+        command(&mut firmware, &[0xD8, 0x01, 0x80, 0x00]);
+        assert!(firmware.data[0x1_0000..0x2_0000].iter().all(|b| *b == 0xFF));
+        // Other sectors are untouched.
+        assert_eq!(firmware.data[0x0_FFFF], 0);
+    }
+
+    #[test]
+    fn flush_round_trip() {
+        let save_path = std::env::temp_dir().join(format!("spa_firmware_test_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&save_path);
+
+        let mut firmware = Firmware::new(None, Some(save_path.clone()), None, None).unwrap();
+        // Nothing is written until the firmware is modified.
+        firmware.flush();
+        assert!(!save_path.exists());
+
+        command(&mut firmware, &[0x06]);
+        command(&mut firmware, &[0x0A, 0x02, 0x00, 0x00, 0x12, 0x34]);
+        firmware.flush();
+
+        let loaded = Firmware::new(None, Some(save_path.clone()), None, None).unwrap();
+        std::fs::remove_file(&save_path).unwrap();
+        assert_eq!(&loaded.data[0x2_0000..0x2_0002], &[0x12, 0x34]);
+        assert!(loaded.data == firmware.data);
+    }
+
+    #[test]
+    fn mac_address_override() {
+        const MAC_ADDRESS: [u8; 6] = [0x00, 0x09, 0xBF, 0x00, 0x1F, 0x90];
+        let path = std::env::temp_dir().join(format!("spa_firmware_mac_test_{}.bin", std::process::id()));
+        std::fs::write(&path, construct_firmware(DEFAULT_MAC_ADDRESS)).unwrap();
+
+        let unchanged = Firmware::new(Some(&path), None, None, None).unwrap();
+        let loaded = Firmware::new(Some(&path), None, Some(MAC_ADDRESS), None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&unchanged.data[0x36..0x3C], &DEFAULT_MAC_ADDRESS);
+        assert_eq!(&loaded.data[0x36..0x3C], &MAC_ADDRESS);
+        let config_len = read_u16(&loaded.data, 0x2C) as usize;
+        assert_eq!(read_u16(&loaded.data, 0x2A), crc16(0, &loaded.data[0x2C..(0x2C + config_len)]));
+    }
+}
@@ -0,0 +1,105 @@
+/// Microphone input.
+
+use std::collections::VecDeque;
+
+/// ARM7 cycles in a frame.
+const FRAME_CYCLES: usize = 6 * 355 * 263;
+/// Level of the microphone when there is no input.
+const SILENCE: u16 = 0x800;
+
+/// Buffers PCM samples, which are read through the touchscreen AUX channel.
+/// 
+/// Samples are provided once per frame. Everything in the buffer is
+/// resampled to play back over the next frame, so the input can be at any rate.
+pub struct Microphone {
+    buffer:     VecDeque<i16>,
+    /// Position between the first two samples in the buffer.
+    position:   f64,
+    /// Samples to advance per cycle.
+    step:       f64,
+}
+
+impl Microphone {
+    pub fn new() -> Self {
+        Self {
+            buffer:     VecDeque::new(),
+            position:   0.0,
+            step:       0.0,
+        }
+    }
+
+    /// Add samples to the buffer.
+    pub fn push_samples(&mut self, samples: &[i16]) {
+        self.buffer.extend(samples);
+        self.step = (self.buffer.len() as f64) / (FRAME_CYCLES as f64);
+    }
+
+    pub fn clock(&mut self, cycles: usize) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        self.position += (cycles as f64) * self.step;
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            self.buffer.pop_front();
+            if self.buffer.is_empty() {
+                self.position = 0.0;
+                return;
+            }
+        }
+    }
+
+    /// Get the current level as a 12-bit unsigned value.
+    pub fn sample(&self) -> u16 {
+        if let Some(current) = self.buffer.front() {
+            let current = *current as f64;
+            let next = self.buffer.get(1).map(|s| *s as f64).unwrap_or(current);
+            let sample = current + (next - current) * self.position;
+            ((sample as i32 + 0x8000) >> 4) as u16
+        } else {
+            SILENCE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(sample: u16, expected: u16) {
+        assert!((sample as i32 - expected as i32).abs() <= 1, "sample {:X} expected {:X}", sample, expected);
+    }
+
+    #[test]
+    fn resample_over_frame() {
+        let mut mic = Microphone::new();
+        mic.push_samples(&[-0x8000, -0x4000, 0, 0x4000, 0x7FF0]);
+        assert_eq!(mic.sample(), 0x000);
+
+        // Each sample lasts for a fifth of a frame. Check between each pair.
+        let sample_cycles = FRAME_CYCLES / 5;
+        mic.clock(sample_cycles / 2);
+        assert_near(mic.sample(), 0x200);
+        for expected in [0x600, 0xA00, 0xDFF, 0xFFF] {
+            mic.clock(sample_cycles);
+            assert_near(mic.sample(), expected);
+        }
+
+        // Run out of samples by the end of the frame.
+        mic.clock(sample_cycles);
+        assert_eq!(mic.sample(), SILENCE);
+    }
+
+    #[test]
+    fn underrun() {
+        let mut mic = Microphone::new();
+        assert_eq!(mic.sample(), SILENCE);
+        mic.clock(FRAME_CYCLES);
+        assert_eq!(mic.sample(), SILENCE);
+
+        mic.push_samples(&[0x1000, 0x1000]);
+        assert_eq!(mic.sample(), 0x900);
+        mic.clock(FRAME_CYCLES * 2);
+        assert_eq!(mic.sample(), SILENCE);
+    }
+}
@@ -2,17 +2,27 @@
 
 mod power;
 mod firmware;
+mod microphone;
+mod profile;
 mod touchscreen;
 
 use bitflags::bitflags;
-use crate::utils::{
-    meminterface::MemInterface16,
-    bits::u16,
-    bytes
+use crossbeam_channel::Sender;
+use crate::{
+    utils::{
+        meminterface::MemInterface16,
+        bits::u16,
+        bytes
+    },
+    Event
 };
 
 use power::PowerManager;
+pub use power::Backlights;
 use firmware::Firmware;
+use microphone::Microphone;
+pub use firmware::DEFAULT_MAC_ADDRESS;
+pub use profile::{UserProfile, Language};
 use touchscreen::Touchscreen;
 
 bitflags!{
@@ -34,26 +44,55 @@ pub struct SPI {
     power_man:      PowerManager,
     firmware:       Firmware,
     touchscreen:    Touchscreen,
+    microphone:     Microphone,
 
     countdown: usize,
 }
 
 impl SPI {
-    pub fn new(firmware_path: Option<&std::path::Path>) -> Self {
+    pub fn new(firmware_path: Option<&std::path::Path>, firmware_save_path: Option<std::path::PathBuf>, mac_address: Option<[u8; 6]>, profile: Option<&UserProfile>, events: Sender<Event>) -> Self {
         Self {
             control:    SPIControl::default(),
 
-            power_man:      PowerManager::new(),
-            firmware:       Firmware::new(firmware_path).unwrap(),
+            power_man:      PowerManager::new(events),
+            firmware:       Firmware::new(firmware_path, firmware_save_path, mac_address, profile).unwrap(),
             touchscreen:    Touchscreen::new(),
+            microphone:     Microphone::new(),
 
             countdown: 0,
         }
     }
 
+    /// User settings from firmware, which are copied into RAM at boot.
+    pub fn firmware_user_settings(&self) -> &[u8] {
+        self.firmware.user_settings()
+    }
+
+    /// Write modified firmware to disk.
+    pub fn flush_firmware(&mut self) {
+        self.firmware.flush();
+    }
+
     pub fn write_tsc_values(&mut self, coords: Option<(f64, f64)>) {
         self.touchscreen.write_tsc_values(coords);
     }
+
+    pub fn backlights(&self) -> Backlights {
+        self.power_man.backlights()
+    }
+
+    pub fn set_sleep(&mut self, sleep: bool) {
+        self.power_man.set_sleep(sleep);
+    }
+
+    /// Add PCM samples to the microphone buffer.
+    pub fn write_microphone_samples(&mut self, samples: &[i16]) {
+        self.microphone.push_samples(samples);
+    }
+
+    pub fn clock_microphone(&mut self, cycles: usize) {
+        self.microphone.clock(cycles);
+    }
 }
 
 impl MemInterface16 for SPI {
@@ -116,6 +155,7 @@ impl MemInterface16 for SPI {
                         }
                     },
                     2 => {
+                        self.touchscreen.write_aux_value(self.microphone.sample());
                         self.touchscreen.write(bytes::u16::lo(data));
                         if !self.control.contains(SPIControl::CHIP_HOLD) {
                             self.touchscreen.deselect();
@@ -1,5 +1,12 @@
 use bitflags::bitflags;
-use crate::utils::bits::u8;
+use crossbeam_channel::Sender;
+use std::sync::{
+    Arc, atomic::{AtomicU8, Ordering}
+};
+use crate::{
+    utils::bits::u8,
+    Event
+};
 
 bitflags! {
     #[derive(Default)]
@@ -14,6 +21,33 @@ bitflags! {
     }
 }
 
+/// Which screens are lit. Shared with the frontend thread, so that unlit screens can be blanked.
+#[derive(Clone)]
+pub struct Backlights(Arc<AtomicU8>);
+
+impl Backlights {
+    const UPPER: u8 = u8::bit(1);
+    const LOWER: u8 = u8::bit(0);
+
+    fn new() -> Self {
+        Self(Arc::new(AtomicU8::new(Self::UPPER | Self::LOWER)))
+    }
+
+    fn set(&self, upper: bool, lower: bool) {
+        let upper = if upper {Self::UPPER} else {0};
+        let lower = if lower {Self::LOWER} else {0};
+        self.0.store(upper | lower, Ordering::Release);
+    }
+
+    pub fn upper(&self) -> bool {
+        (self.0.load(Ordering::Acquire) & Self::UPPER) != 0
+    }
+
+    pub fn lower(&self) -> bool {
+        (self.0.load(Ordering::Acquire) & Self::LOWER) != 0
+    }
+}
+
 enum State {
     Idle,
     /// Read from a register
@@ -29,16 +63,50 @@ pub struct PowerManager {
     control:        PowerControl,
     mic_amp_enable: bool,
     mic_amp_gain:   u8,
+
+    sleep:          bool,
+    backlights:     Backlights,
+    events:         Sender<Event>,
 }
 
 impl PowerManager {
-    pub fn new() -> Self {
+    pub fn new(events: Sender<Event>) -> Self {
         Self {
             state:          State::Idle,
             read_buffer:    0,
-            control:        PowerControl::SOUND_AMP_ENABLE,
+            control:        PowerControl::SOUND_AMP_ENABLE | PowerControl::UPPER_BACKLIGHT | PowerControl::LOW_BACKLIGHT,
             mic_amp_enable: false,
             mic_amp_gain:   0,
+
+            sleep:          false,
+            backlights:     Backlights::new(),
+            events:         events,
+        }
+    }
+
+    pub fn backlights(&self) -> Backlights {
+        self.backlights.clone()
+    }
+
+    /// Screens are turned off during sleep mode.
+    pub fn set_sleep(&mut self, sleep: bool) {
+        self.sleep = sleep;
+        self.update_backlights();
+    }
+
+    fn update_backlights(&self) {
+        self.backlights.set(
+            !self.sleep && self.control.contains(PowerControl::UPPER_BACKLIGHT),
+            !self.sleep && self.control.contains(PowerControl::LOW_BACKLIGHT)
+        );
+    }
+
+    fn write_control(&mut self, data: u8) {
+        let was_powered_off = self.control.contains(PowerControl::DS_SYS_POWER);
+        self.control = PowerControl::from_bits_truncate(data);
+        self.update_backlights();
+        if !was_powered_off && self.control.contains(PowerControl::DS_SYS_POWER) {
+            let _ = self.events.send(Event::PowerOff);
         }
     }
 
@@ -70,7 +138,7 @@ impl PowerManager {
                 };
             },
             State::Write(n) => match n {
-                0 => self.control = PowerControl::from_bits_truncate(data),
+                0 => self.write_control(data),
                 2 => self.mic_amp_enable = u8::test_bit(data, 0),
                 3 => self.mic_amp_gain = data & 0x3,
                 _ => {}
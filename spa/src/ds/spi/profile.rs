@@ -0,0 +1,79 @@
+/// User settings stored in firmware.
+
+/// Firmware language setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Japanese    = 0,
+    English     = 1,
+    French      = 2,
+    German      = 3,
+    Italian     = 4,
+    Spanish     = 5,
+}
+
+/// User profile, as set in the firmware menu.
+#[derive(Clone, Debug)]
+pub struct UserProfile {
+    /// Up to 10 characters.
+    pub nickname:           String,
+    /// Up to 26 characters.
+    pub message:            String,
+    /// Month, day.
+    pub birthday:           (u8, u8),
+    /// 0-15.
+    pub favourite_colour:   u8,
+    pub language:           Language,
+    /// Hour, minute. None if the alarm is disabled.
+    pub alarm:              Option<(u8, u8)>,
+}
+
+impl Default for UserProfile {
+    fn default() -> Self {
+        Self {
+            nickname:           "SPA".to_string(),
+            message:            String::new(),
+            birthday:           (1, 1),
+            favourite_colour:   0,
+            language:           Language::English,
+            alarm:              None,
+        }
+    }
+}
+
+impl UserProfile {
+    const NICKNAME_LEN: usize = 10;
+    const MESSAGE_LEN: usize = 26;
+
+    /// Write the profile into user settings.
+    /// Other settings (touchscreen calibration, flags) are kept.
+    pub fn write(&self, settings: &mut [u8]) {
+        settings[0x02] = self.favourite_colour & 0xF;
+        settings[0x03] = self.birthday.0;
+        settings[0x04] = self.birthday.1;
+
+        let nickname_len = write_utf16(&mut settings[0x06..0x1A], &self.nickname, Self::NICKNAME_LEN);
+        settings[0x1A..0x1C].copy_from_slice(&nickname_len.to_le_bytes());
+        let message_len = write_utf16(&mut settings[0x1C..0x50], &self.message, Self::MESSAGE_LEN);
+        settings[0x50..0x52].copy_from_slice(&message_len.to_le_bytes());
+
+        let (alarm_hour, alarm_minute) = self.alarm.unwrap_or((0, 0));
+        settings[0x52] = alarm_hour;
+        settings[0x53] = alarm_minute;
+        settings[0x56] = if self.alarm.is_some() {1} else {0};
+
+        let flags = u16::from_le_bytes([settings[0x64], settings[0x65]]);
+        let flags = (flags & 0xFFF8) | (self.language as u16);
+        settings[0x64..0x66].copy_from_slice(&flags.to_le_bytes());
+    }
+}
+
+/// Write a UTF-16 string, and return the length in characters.
+fn write_utf16(dest: &mut [u8], text: &str, max_len: usize) -> u16 {
+    dest.fill(0);
+    let mut len = 0;
+    for (chunk, c) in dest.chunks_exact_mut(2).zip(text.encode_utf16().take(max_len)) {
+        chunk.copy_from_slice(&c.to_le_bytes());
+        len += 1;
+    }
+    len
+}
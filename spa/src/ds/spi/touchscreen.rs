@@ -35,6 +35,7 @@ pub struct Touchscreen {
 
     x:      u16,
     y:      u16,
+    /// 12-bit microphone level.
     aux:    u16,
 }
 
@@ -47,7 +48,7 @@ impl Touchscreen {
 
             x:          X_RELEASED,
             y:          Y_RELEASED,
-            aux:        255, // 0
+            aux:        0x800,
         }
     }
 
@@ -109,7 +110,7 @@ impl Touchscreen {
                 TouchscreenZ2 => 0,
                 TouchscreenX => self.x << 3,
                 AUX => if self.control.contains(TSCControl::CONV_MODE) {
-                    self.aux << 7
+                    (self.aux >> 4) << 7
                 } else {
                     self.aux << 3
                 },
@@ -118,3 +119,31 @@ impl Touchscreen {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start a conversion and read back both bytes.
+    fn convert(tsc: &mut Touchscreen, control: u8) -> u16 {
+        tsc.write(control);
+        let lo = tsc.read();
+        let hi = tsc.read();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    #[test]
+    fn aux_conversion() {
+        let mut tsc = Touchscreen::new();
+        tsc.write_aux_value(0xABC);
+        // 12-bit
+        assert_eq!(convert(&mut tsc, 0xE0), 0xABC << 3);
+        // 8-bit: top bits of the 12-bit value.
+        assert_eq!(convert(&mut tsc, 0xE8), 0xAB << 7);
+
+        tsc.write_aux_value(0xFFF);
+        assert_eq!(convert(&mut tsc, 0xE8), 0xFF << 7);
+        tsc.write_aux_value(0x00F);
+        assert_eq!(convert(&mut tsc, 0xE8), 0);
+    }
+}
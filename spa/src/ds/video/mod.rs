@@ -20,6 +20,7 @@ use crate::ds::interrupt::Interrupts;
 pub use render::*;
 use memory::DSVideoMemory;
 pub use memory::{ARM7VRAM, VRAMRegion};
+pub use video3d::TextureConfig;
 
 use constants::*;
 
@@ -55,10 +56,10 @@ pub struct DSVideo<R: Renderer> {
 }
 
 impl<R: Renderer> DSVideo<R> {
-    pub fn new(upper: Arc<Mutex<FrameBuffer>>, lower: Arc<Mutex<FrameBuffer>>) -> (Self, ARM7Video, ARM7VRAM) {
-        let video_3d = Video3D::new();
+    pub fn new(upper: Arc<Mutex<FrameBuffer>>, lower: Arc<Mutex<FrameBuffer>>, render_scale: usize, textures: TextureConfig) -> (Self, ARM7Video, ARM7VRAM) {
+        let video_3d = Video3D::new(render_scale);
         let (arm9_mem, arm7_vram, renderer_vram) = DSVideoMemory::new(video_3d.rendering_engine.clone());
-        let renderer = R::new(upper, lower, renderer_vram);
+        let renderer = R::new(upper, lower, renderer_vram, render_scale, textures);
         let v_count = Arc::new(AtomicU16::new(0));
         (Self {
             state:          VideoState::Init,
@@ -111,6 +112,11 @@ impl<R: Renderer> DSVideo<R> {
 
         (signal, irq | irq_3d, geom_fifo_dma)
     }
+
+    /// Check if writes to the geometry engine should stall.
+    pub fn geom_fifo_full(&self) -> bool {
+        self.video_3d.geom_fifo_full()
+    }
 }
 
 // Interface refers to engine A registers + LCD status + 3D regs
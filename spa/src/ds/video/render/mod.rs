@@ -12,7 +12,7 @@ use crate::common::video::{
 use super::memory::ARM9VRAM;
 use super::{
     memory::{RendererVRAM, GraphicsPowerControl},
-    video3d::Software3DRenderer,
+    video3d::{Software3DRenderer, TextureConfig},
     constants::*
 };
 
@@ -20,7 +20,9 @@ pub type RenderTarget = Arc<Mutex<Box<[u8]>>>;
 
 /// Renderer trait. The renderer should implement this.
 pub trait Renderer {
-    fn new(upper: RenderTarget, lower: RenderTarget, vram: RendererVRAM) -> Self;
+    /// `render_scale` is the internal resolution multiplier for 3D content.
+    /// `textures` sets up 3D texture dumping and replacement.
+    fn new(upper: RenderTarget, lower: RenderTarget, vram: RendererVRAM, render_scale: usize, textures: TextureConfig) -> Self;
 
     /// Render 3D content.
     fn render_3d(&mut self);
@@ -31,7 +33,7 @@ pub trait Renderer {
     /// Complete rendering the frame.
     fn finish_frame(&mut self);
     /// Get the size of each render target in pixels.
-    fn render_size() -> (usize, usize);
+    fn render_size(render_scale: usize) -> (usize, usize);
 }
 
 enum RenderCommand {
@@ -52,6 +54,8 @@ pub struct ProceduralRendererThread {
     upper:      RenderTarget,
     lower:      RenderTarget,
     vram:       RendererVRAM,
+    /// Output lines per native line.
+    scale:      usize,
 
     /// Indicates whether this frame should be captured.
     capture:    bool,
@@ -62,6 +66,8 @@ pub struct ProceduralRendererThread {
 
     /// Line cache for engine A blending and engine B.
     line_cache_b:   Vec<Colour>,
+    /// Upscaled line cache for engine A.
+    upscaled_line_cache:    Vec<Colour>,
 }
 
 struct CaptureWriteData {
@@ -75,23 +81,28 @@ struct CaptureWriteData {
 }
 
 impl Renderer for ProceduralRenderer {
-    fn new(upper: RenderTarget, lower: RenderTarget, vram: RendererVRAM) -> Self {
+    fn new(upper: RenderTarget, lower: RenderTarget, vram: RendererVRAM, render_scale: usize, textures: TextureConfig) -> Self {
 
         let (command_tx, command_rx) = bounded(1);
         //let (reply_tx, reply_rx) = bounded(1);
         std::thread::spawn(move || {
 
+            let mut engine_a = SoftwareRenderer::new(RendererMode::NDSA);
+            engine_a.set_3d_scale(render_scale);
+
             let mut data = ProceduralRendererThread {
-                engine_a:   SoftwareRenderer::new(RendererMode::NDSA),
+                engine_a:   engine_a,
                 engine_b:   SoftwareRenderer::new(RendererMode::NDSB),
-                engine_3d:  Software3DRenderer::new(),
+                engine_3d:  Software3DRenderer::new(render_scale, textures),
 
                 upper, lower, vram,
+                scale:      render_scale,
 
                 capture:    false,
                 line_cache: vec![Colour::black(); H_RES],
                 write_data: None,
                 line_cache_b: vec![Colour::black(); H_RES],
+                upscaled_line_cache: vec![Colour::black(); H_RES * render_scale * render_scale],
             };
 
             //reply_tx.send(()).unwrap();
@@ -134,8 +145,8 @@ impl Renderer for ProceduralRenderer {
         //println!("Finish frame");
     }
 
-    fn render_size() -> (usize, usize) {
-        (H_RES, V_RES)
+    fn render_size(render_scale: usize) -> (usize, usize) {
+        (H_RES * render_scale, V_RES * render_scale)
     }
 }
 
@@ -191,8 +202,9 @@ impl ProceduralRendererThread {
 
     /// Draw a full line for NDS A engine. Also applies master brightness.
     fn engine_a_line(&mut self, line: u8, display_swap: bool) {
-        let start_offset = (line as usize) * (H_RES * 4);
-        let end_offset = start_offset + (H_RES * 4);
+        let line_size = H_RES * self.scale * self.scale * 4;
+        let start_offset = (line as usize) * line_size;
+        let end_offset = start_offset + line_size;
 
         let mut screen = if display_swap {
             self.upper.lock()
@@ -213,26 +225,20 @@ impl ProceduralRendererThread {
         } else {
             match engine_a_mem.registers.display_mode() {
                 0 => self.engine_a.draw_blank_line(target),
+                1 if self.scale > 1 => {
+                    self.engine_a.draw_upscaled(&engine_a_mem, &mut self.upscaled_line_cache, line);
+                    Self::output_line(&engine_a_mem.registers, &self.upscaled_line_cache, target, self.scale);
+                },
                 1 => {
                     self.engine_a.draw(&engine_a_mem, &mut self.line_cache, line);
-                    for (colour, out) in self.line_cache.iter().zip(target.chunks_exact_mut(4)) {
-                        let colour = engine_a_mem.registers.apply_brightness(*colour);
-                        out[0] = colour.r;
-                        out[1] = colour.g;
-                        out[2] = colour.b;
-                    }
+                    Self::output_line(&engine_a_mem.registers, &self.line_cache, target, self.scale);
                     drawn = true;
                 },
                 2 => {
                     let lcdc = self.vram.lcdc_vram.lock();
                     let read_offset = (line as u32) * (H_RES as u32) * 2;
                     Self::draw_from_vram(&lcdc, &engine_a_mem.registers, &mut self.line_cache, read_offset);
-                    for (colour, out) in self.line_cache.iter().zip(target.chunks_exact_mut(4)) {
-                        let colour = engine_a_mem.registers.apply_brightness(*colour);
-                        out[0] = colour.r;
-                        out[1] = colour.g;
-                        out[2] = colour.b;
-                    }
+                    Self::output_line(&engine_a_mem.registers, &self.line_cache, target, self.scale);
                 },
                 3 => panic!("main mem display not implemented yet!"),
                 _ => unreachable!()
@@ -253,13 +259,7 @@ impl ProceduralRendererThread {
                             self.engine_a.draw(&engine_a_mem, &mut self.line_cache, line);
                         }
                     },
-                    DispCapSourceA::_3D => {
-                        let start = (line as usize) * H_RES;
-                        let end = start + H_RES;
-                        for (a, b) in self.line_cache.iter_mut().zip(&self.engine_a.frame_3d[start..end]) {
-                            *a = b.col;
-                        }
-                    }
+                    DispCapSourceA::_3D => Self::capture_3d_line(&self.engine_a, &mut self.line_cache, line),
                 },
                 DispCapMode::B(src_b) => match src_b {
                     DispCapSourceB::VRAM => {
@@ -276,13 +276,7 @@ impl ProceduralRendererThread {
                                 self.engine_a.draw(&engine_a_mem, &mut self.line_cache, line);
                             }
                         },
-                        DispCapSourceA::_3D => {
-                            let start = (line as usize) * H_RES;
-                            let end = start + H_RES;
-                            for (a, b) in self.line_cache.iter_mut().zip(&self.engine_a.frame_3d[start..end]) {
-                                *a = b.col;
-                            }
-                        }
+                        DispCapSourceA::_3D => Self::capture_3d_line(&self.engine_a, &mut self.line_cache, line),
                     }
                     match src_b {
                         DispCapSourceB::VRAM => {
@@ -311,8 +305,9 @@ impl ProceduralRendererThread {
 
     /// Draw a full line for NDS B engine. Also applies master brightness.
     fn engine_b_line(&mut self, line: u8, display_swap: bool) {
-        let start_offset = (line as usize) * (H_RES * 4);
-        let end_offset = start_offset + (H_RES * 4);
+        let line_size = H_RES * self.scale * self.scale * 4;
+        let start_offset = (line as usize) * line_size;
+        let end_offset = start_offset + line_size;
 
         let mut screen = if display_swap {
             self.lower.lock()
@@ -331,12 +326,7 @@ impl ProceduralRendererThread {
                 0 => self.draw_empty_line(target),
                 1 => {
                     self.engine_b.draw(&engine_b_mem, &mut self.line_cache_b, line as u8);
-                    for (colour, out) in self.line_cache_b.iter().zip(target.chunks_exact_mut(4)) {
-                        let colour = engine_b_mem.registers.apply_brightness(*colour);
-                        out[0] = colour.r;
-                        out[1] = colour.g;
-                        out[2] = colour.b;
-                    }
+                    Self::output_line(&engine_b_mem.registers, &self.line_cache_b, target, self.scale);
                 },
                 _ => unreachable!()
             }
@@ -345,6 +335,37 @@ impl ProceduralRendererThread {
         engine_b_mem.registers.inc_v_count();
     }
 
+    /// Capture a native resolution line of the 3D layer.
+    fn capture_3d_line(engine_a: &SoftwareRenderer, target: &mut [Colour], line: u8) {
+        for (x, out) in target.iter_mut().enumerate() {
+            *out = engine_a.native_3d_pixel(x, line as usize).col;
+        }
+    }
+
+    /// Write colours to the screen, applying master brightness.
+    /// 
+    /// The target is `scale` screen lines. Colours are either a native line,
+    /// which is stretched to fit, or already at the target resolution.
+    fn output_line(registers: &VideoRegisters, colours: &[Colour], target: &mut [u8], scale: usize) {
+        let width = H_RES * scale;
+        for (n, target_line) in target.chunks_exact_mut(width * 4).enumerate() {
+            let source = if colours.len() == H_RES {
+                colours
+            } else {
+                &colours[(n * width)..((n + 1) * width)]
+            };
+            let pixel_size = (width / source.len()) * 4;
+            for (colour, out) in source.iter().zip(target_line.chunks_exact_mut(pixel_size)) {
+                let colour = registers.apply_brightness(*colour);
+                for p in out.chunks_exact_mut(4) {
+                    p[0] = colour.r;
+                    p[1] = colour.g;
+                    p[2] = colour.b;
+                }
+            }
+        }
+    }
+
     /// For when drawing mode is disabled.
     fn draw_empty_line(&self, target: &mut [u8]) {
         for p in target {
@@ -392,7 +413,7 @@ pub struct DebugTileRenderer {
 }
 
 impl Renderer for DebugTileRenderer {
-    fn new(upper: RenderTarget, lower: RenderTarget, vram: RendererVRAM) -> Self {
+    fn new(upper: RenderTarget, lower: RenderTarget, vram: RendererVRAM, _render_scale: usize, _textures: TextureConfig) -> Self {
         Self {
             engine_a:   SoftwareRenderer::new(RendererMode::NDSA),
             engine_b:   SoftwareRenderer::new(RendererMode::NDSB),
@@ -429,7 +450,7 @@ impl Renderer for DebugTileRenderer {
         //println!("Finish frame");
     }
 
-    fn render_size() -> (usize, usize) {
+    fn render_size(_render_scale: usize) -> (usize, usize) {
         (256, 384)
     }
 }
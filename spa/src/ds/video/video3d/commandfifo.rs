@@ -8,6 +8,8 @@ enum CommandFifoInterruptCond {
 }
 
 const COMMAND_FIFO_LEN: usize = 256;
+/// Entries are moved into the PIPE when there is space, and executed from there.
+const COMMAND_PIPE_LEN: usize = 4;
 
 pub struct GeomCommandFifo {
     command_fifo:           VecDeque<u32>,
@@ -20,7 +22,7 @@ pub struct GeomCommandFifo {
 impl GeomCommandFifo {
     pub fn new() -> Self {
         Self {
-            command_fifo:           VecDeque::with_capacity(COMMAND_FIFO_LEN),
+            command_fifo:           VecDeque::with_capacity(COMMAND_FIFO_LEN + COMMAND_PIPE_LEN),
             current_command_args:   0,
             interrupt_cond:         CommandFifoInterruptCond::Never,
             status_bits:            GeometryEngineStatus::empty(),
@@ -28,10 +30,9 @@ impl GeomCommandFifo {
     }
 
     /// Push directly to the command buffer.
+    /// 
+    /// The memory bus should stall writes while the buffer is full.
     pub fn push_command_buffer(&mut self, data: u32) {
-        if self.command_fifo.len() == COMMAND_FIFO_LEN {
-            //panic!("GPU command fifo full");   // TODO: handle (ignore incoming data? / freeze)
-        }
         //println!("GX PUSH: {:X}", data);
         self.command_fifo.push_back(data);
     }
@@ -72,8 +73,9 @@ impl GeomCommandFifo {
         }
     }
 
-    pub fn len(&self) -> u32 {
-        self.command_fifo.len() as u32
+    /// Number of entries in the FIFO, not including the PIPE.
+    pub fn fifo_len(&self) -> u32 {
+        self.command_fifo.len().saturating_sub(COMMAND_PIPE_LEN) as u32
     }
 
     pub fn is_empty(&self) -> bool {
@@ -82,11 +84,11 @@ impl GeomCommandFifo {
 
     pub fn under_half_full(&self) -> bool {
         const HALF_COMMAND_FIFO_LEN: usize = COMMAND_FIFO_LEN / 2;
-        self.command_fifo.len() < HALF_COMMAND_FIFO_LEN
+        (self.fifo_len() as usize) < HALF_COMMAND_FIFO_LEN
     }
     
     pub fn is_full(&self) -> bool {
-        self.command_fifo.len() == COMMAND_FIFO_LEN
+        self.command_fifo.len() >= COMMAND_FIFO_LEN + COMMAND_PIPE_LEN
     }
 
     pub fn set_interrupt_cond(&mut self, val: GeometryEngineStatus) {
@@ -110,3 +112,70 @@ impl GeomCommandFifo {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full() {
+        let mut fifo = GeomCommandFifo::new();
+        assert!(fifo.is_empty());
+        for n in 0..COMMAND_PIPE_LEN {
+            fifo.push_command_buffer(n as u32);
+        }
+        // Entries in the PIPE aren't counted.
+        assert_eq!(fifo.fifo_len(), 0);
+        assert!(fifo.under_half_full());
+
+        for n in 0..(COMMAND_FIFO_LEN / 2) {
+            assert!(!fifo.is_full());
+            fifo.push_command_buffer(n as u32);
+        }
+        assert_eq!(fifo.fifo_len(), 128);
+        assert!(!fifo.under_half_full());
+
+        for n in 0..(COMMAND_FIFO_LEN / 2) {
+            assert!(!fifo.is_full());
+            fifo.push_command_buffer(n as u32);
+        }
+        assert_eq!(fifo.fifo_len(), 256);
+        assert!(fifo.is_full());
+
+        assert_eq!(fifo.pop(), Some(0));
+        assert!(!fifo.is_full());
+        assert_eq!(fifo.fifo_len(), 255);
+    }
+
+    #[test]
+    fn interrupt_cond() {
+        let mut fifo = GeomCommandFifo::new();
+        assert!(!fifo.interrupt());
+        fifo.set_interrupt_cond(GeometryEngineStatus::from_bits_truncate(1 << 30));
+        assert!(fifo.interrupt());
+        for n in 0..(COMMAND_PIPE_LEN + COMMAND_FIFO_LEN / 2) {
+            fifo.push_command_buffer(n as u32);
+        }
+        assert!(!fifo.interrupt());
+
+        fifo.set_interrupt_cond(GeometryEngineStatus::from_bits_truncate(2 << 30));
+        assert_eq!(fifo.get_interrupt_cond().bits(), 2 << 30);
+        assert!(!fifo.interrupt());
+        while fifo.pop().is_some() {}
+        assert!(fifo.interrupt());
+    }
+
+    #[test]
+    fn cpu_commands() {
+        let mut fifo = GeomCommandFifo::new();
+        // MTX_TRANS: 3 args.
+        for arg in 1..=3 {
+            fifo.push_command_cpu(arg, 0x1C, 3);
+        }
+        // MTX_IDENTITY: no args.
+        fifo.push_command_cpu(0, 0x15, 0);
+        assert_eq!(fifo.pop_n(5).unwrap().collect::<Vec<_>>(), vec![0x1C, 1, 2, 3, 0x15]);
+        assert!(fifo.peek_n(1).is_none());
+    }
+}
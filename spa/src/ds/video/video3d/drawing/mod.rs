@@ -1,4 +1,5 @@
 mod palette;
+mod textures;
 
 use super::{
     render::RenderingEngine,
@@ -13,6 +14,8 @@ use crate::{
 };
 
 use palette::TexPaletteCache;
+use textures::{TextureReplacer, Replacement};
+pub use textures::TextureConfig;
 
 #[derive(Copy, Clone, Default)]
 struct Attributes {
@@ -21,6 +24,15 @@ struct Attributes {
     trans_id:   Option<u8>,
     fog:        bool,
     edge:       bool,
+    /// Anti-aliasing coverage. 0x1F is fully covered.
+    coverage:   u8,
+}
+
+/// A polygon edge in screen space.
+#[derive(Clone, Copy)]
+struct Edge {
+    top:    Coords,
+    bottom: Coords,
 }
 
 /*#[derive(Clone)]
@@ -51,65 +63,207 @@ struct InterpolatedLine {
     step: VertexStep
 }*/
 
+/// Maximum number of threads to render a frame with.
+const MAX_RENDER_THREADS: usize = 8;
+
 /// Render NDS 3D graphics.
+///
+/// The frame is split into horizontal bands, one for each thread, which are drawn in parallel.
 pub struct Software3DRenderer {
-    palette_cache:  TexPaletteCache,
+    rasteriser:     Rasteriser,
+    /// Number of lines in each band.
+    band_height:    usize,
+
     stencil_buffer: Vec<bool>,
     attr_buffer:    Vec<Attributes>,
     depth_buffer:   Vec<Depth>,
+    /// Colour of the pixel behind the top-most opaque fragment.
+    below_buffer:   Vec<ColourAlpha>,
 }
 
 impl Software3DRenderer {
-    pub fn new() -> Self {
+    /// Render at `scale` times the native resolution.
+    pub fn new(scale: usize, textures: TextureConfig) -> Self {
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_RENDER_THREADS);
+        Self::with_threads(scale, textures, threads)
+    }
+
+    /// Render at `scale` times the native resolution, using `threads` threads.
+    fn with_threads(scale: usize, textures: TextureConfig, threads: usize) -> Self {
+        let (width, height) = (256 * scale, 192 * scale);
         Self {
-            palette_cache:  TexPaletteCache::new(),
-            stencil_buffer: vec![false; 256 * 192],
-            attr_buffer:    vec![Default::default(); 256 * 192],
-            depth_buffer:   vec![Depth::ZERO; 256 * 192],
+            rasteriser:     Rasteriser {
+                scale:          scale,
+                width:          width,
+                height:         height,
+                palette_cache:  TexPaletteCache::new(),
+                textures:       TextureReplacer::new(textures),
+            },
+            band_height:    height.div_ceil(threads),
+
+            stencil_buffer: vec![false; width * height],
+            attr_buffer:    vec![Default::default(); width * height],
+            depth_buffer:   vec![Depth::ZERO; width * height],
+            below_buffer:   vec![ColourAlpha::transparent(); width * height],
         }
     }
 
     pub fn setup_caches(&mut self, vram: &mut Engine3DVRAM) {
-        self.palette_cache.update_tex(&vram.ref_tex_palette());
+        self.rasteriser.palette_cache.update_tex(&vram.ref_tex_palette());
     }
 
     pub fn draw(&mut self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, target: &mut [ColourAlpha]) {
-        self.clear_buffers(render_engine, vram, target);
+        self.rasteriser.prepare_textures(render_engine, vram);
+
+        let rasteriser = &self.rasteriser;
+        let band_height = self.band_height;
+        let band_size = band_height * rasteriser.width;
+
+        // Polygons are drawn into each band independently.
+        let bands = self.stencil_buffer.chunks_mut(band_size)
+            .zip(self.attr_buffer.chunks_mut(band_size))
+            .zip(self.depth_buffer.chunks_mut(band_size))
+            .zip(self.below_buffer.chunks_mut(band_size))
+            .zip(target.chunks_mut(band_size))
+            .enumerate()
+            .map(|(n, ((((stencil_buffer, attr_buffer), depth_buffer), below_buffer), target))| {
+                let y_start = n * band_height;
+                Band {
+                    y_start:        y_start as u16,
+                    y_end:          (y_start + target.len() / rasteriser.width) as u16,
+                    stencil_buffer, attr_buffer, depth_buffer, below_buffer, target
+                }
+            });
+        run_parallel(bands, |mut band| rasteriser.draw_polygons(render_engine, vram, &mut band));
+
+        // Edge marking looks at neighbouring lines, so it can only start once all bands are complete.
+        let buffers = Buffers {
+            attr_buffer:    &self.attr_buffer,
+            depth_buffer:   &self.depth_buffer,
+            below_buffer:   &self.below_buffer,
+        };
+        let bands = target.chunks_mut(band_size).enumerate();
+        run_parallel(bands, |(n, target)| rasteriser.post_process(render_engine, buffers, n * band_height, target));
+    }
+}
 
-        self.draw_opaque_polygons(render_engine, vram, target);
+/// Call `f` for each item in parallel, and wait for all calls to finish.
+///
+/// The first item is processed on the calling thread.
+fn run_parallel<T: Send>(mut items: impl Iterator<Item = T>, f: impl Fn(T) + Sync) {
+    let f = &f;
+    let first = items.next();
+    // Panics in other threads are passed on when the scope ends.
+    std::thread::scope(|scope| {
+        for item in items {
+            scope.spawn(move || f(item));
+        }
+        if let Some(item) = first {
+            f(item);
+        }
+    });
+}
 
-        self.draw_trans_polygons(render_engine, vram, target);
+/// A horizontal band of the drawing buffers and target.
+struct Band<'a> {
+    /// First line of the band.
+    y_start:        u16,
+    /// Line after the last line of the band.
+    y_end:          u16,
+
+    stencil_buffer: &'a mut [bool],
+    attr_buffer:    &'a mut [Attributes],
+    depth_buffer:   &'a mut [Depth],
+    below_buffer:   &'a mut [ColourAlpha],
+    target:         &'a mut [ColourAlpha],
+}
 
-        if render_engine.control.contains(Display3DControl::EDGE_MARKING) {
-            self.mark_edges(render_engine, target);
+/// The complete drawing buffers, once all polygons are drawn.
+#[derive(Clone, Copy)]
+struct Buffers<'a> {
+    attr_buffer:    &'a [Attributes],
+    depth_buffer:   &'a [Depth],
+    below_buffer:   &'a [ColourAlpha],
+}
+
+/// Draws polygons into bands of the frame.
+struct Rasteriser {
+    /// Resolution multiplier.
+    scale:          usize,
+    width:          usize,
+    height:         usize,
+
+    palette_cache:  TexPaletteCache,
+    textures:       TextureReplacer,
+}
+
+impl Rasteriser {
+    /// Hash the textures used by this frame.
+    /// New textures are dumped, and replacements are found for them.
+    fn prepare_textures(&mut self, render_engine: &RenderingEngine, vram: &Engine3DVRAM) {
+        if !self.textures.is_enabled() {
+            return;
+        }
+        self.textures.start_frame();
+        for polygon in render_engine.polygon_ram.polygons.iter() {
+            if polygon.tex.format() == 0 || self.textures.is_prepared(polygon.tex, polygon.palette) {
+                continue;
+            }
+            let hash = textures::hash_texture(polygon.tex, polygon.palette, vram, &self.palette_cache);
+            if self.textures.should_dump(hash) {
+                let texels = self.decode_texture(polygon.tex, polygon.palette, vram);
+                self.textures.dump(hash, polygon.tex, &texels);
+            }
+            self.textures.set_texture(polygon.tex, polygon.palette, hash);
         }
+    }
 
-        if render_engine.control.contains(Display3DControl::FOG_ENABLE) {
-            self.draw_fog(render_engine, target);
+    /// Draw all polygons that lie within the band.
+    fn draw_polygons(&self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, band: &mut Band) {
+        self.clear_buffers(render_engine, vram, band);
+
+        self.draw_opaque_polygons(render_engine, vram, band);
+
+        self.draw_trans_polygons(render_engine, vram, band);
+    }
+
+    /// Apply edge marking, anti-aliasing and fog to the lines of `target`, starting at `y_start`.
+    ///
+    /// Fog is applied after anti-aliasing, so the pixel behind an edge is fogged too.
+    fn post_process(&self, render_engine: &RenderingEngine, buffers: Buffers, y_start: usize, target: &mut [ColourAlpha]) {
+        if render_engine.control.contains(Display3DControl::EDGE_MARKING) {
+            self.mark_edges(render_engine, buffers, y_start, target);
         }
 
-        // Anti-aliasing (after 2d-blend?)
+        if render_engine.control.contains(Display3DControl::ANTI_ALIAS) {
+            self.anti_alias(buffers, y_start, target);
+        }
 
+        if render_engine.control.contains(Display3DControl::FOG_ENABLE) {
+            self.draw_fog(render_engine, buffers, y_start, target);
+        }
     }
 }
 
-impl Software3DRenderer {
+impl Rasteriser {
     /// Fill the drawing buffers with clear values or clear image.
-    fn clear_buffers(&mut self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, target: &mut [ColourAlpha]) {
-        self.stencil_buffer.fill(false);
+    fn clear_buffers(&self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, band: &mut Band) {
+        band.stencil_buffer.fill(false);
 
         if render_engine.control.contains(Display3DControl::CLEAR_IMAGE) {
             let clear_colour_image = vram.tex_2.as_ref().expect("using clear colour image without mapped vram");
             let clear_depth_image = vram.tex_3.as_ref().expect("using clear depth image without mapped vram");
 
-            for y in 0..192_u8 {
-                let y_idx_base = (y as usize) * 256;
+            for y in (band.y_start as usize)..(band.y_end as usize) {
+                let y_idx_base = (y - band.y_start as usize) * self.width;
 
-                let image_y = y.wrapping_add(render_engine.clear_image_y);
+                let image_y = ((y / self.scale) as u8).wrapping_add(render_engine.clear_image_y);
                 let image_y_addr = (image_y as u32) * 256 * 2;
     
-                for x in 0..=255_u8 {
-                    let image_x = x.wrapping_add(render_engine.clear_image_x);
+                for x in 0..self.width {
+                    let image_x = ((x / self.scale) as u8).wrapping_add(render_engine.clear_image_x);
                     let addr = image_y_addr + (image_x as u32) * 2;
                     let colour = clear_colour_image.read_halfword(addr);
                     let depth = clear_depth_image.read_halfword(addr);
@@ -118,13 +272,14 @@ impl Software3DRenderer {
                         trans_id:   None,
                         fog:        u16::test_bit(depth, 15),
                         edge:       false,
+                        coverage:   0x1F,
                     };
     
-                    let idx = y_idx_base + (x as usize);
-                    self.attr_buffer[idx] = clear_attrs;
-                    self.depth_buffer[idx] = Depth::from_bits(((depth & 0x7FFF) as i32) << 9);   // TODO: frac part.
-                    target[idx].col = Colour::from_555(colour);
-                    target[idx].alpha = if u16::test_bit(colour, 15) {0x1F} else {0};
+                    let idx = y_idx_base + x;
+                    band.attr_buffer[idx] = clear_attrs;
+                    band.depth_buffer[idx] = Depth::from_bits(((depth & 0x7FFF) as i32) << 9);   // TODO: frac part.
+                    band.target[idx].col = Colour::from_555(colour);
+                    band.target[idx].alpha = if u16::test_bit(colour, 15) {0x1F} else {0};
                 }
             }
         } else {
@@ -133,14 +288,16 @@ impl Software3DRenderer {
                 trans_id:   None,
                 fog:        render_engine.fog_enabled,
                 edge:       false,
+                coverage:   0x1F,
             };
-            self.attr_buffer.fill(clear_attrs);
-            self.depth_buffer.fill(render_engine.clear_depth);
-            target.fill(ColourAlpha { col: render_engine.clear_colour, alpha: render_engine.clear_alpha });
+            band.attr_buffer.fill(clear_attrs);
+            band.depth_buffer.fill(render_engine.clear_depth);
+            band.target.fill(ColourAlpha { col: render_engine.clear_colour, alpha: render_engine.clear_alpha });
         }
     }
     
-    fn draw_opaque_polygons(&mut self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, target: &mut [ColourAlpha]) {
+    fn draw_opaque_polygons(&self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, band: &mut Band) {
+        let anti_alias = render_engine.control.contains(Display3DControl::ANTI_ALIAS);
         //use std::hash::{Hash, Hasher};
         for p in render_engine.polygon_ram.opaque_polygons.iter() {
             /*let mut hash_state = std::hash::DefaultHasher::new();
@@ -159,17 +316,20 @@ impl Software3DRenderer {
                 println!("{} VTX: {:X}, {:X} TEX: {:X}, {:X}", n, vertex.screen_p.x, vertex.screen_p.y, vertex.tex_coords.s, vertex.tex_coords.t);
             }*/
             
-            let (mut x_min_prev, mut x_max_prev) = (256, 0);
-            let (y_min, y_max) = (p.y_min.to_num::<u8>(), p.y_max.to_num::<u8>());
+            let (mut x_min_prev, mut x_max_prev) = (self.width as i16, 0);
+            let (y_min, y_max) = Self::y_range(render_engine, polygon);
+            if y_max <= band.y_start {
+                continue;
+            }
 
-            for y_idx in y_min..y_max {
-                let Some([left, right]) = Self::find_intersect_points(render_engine, polygon, y_idx.to_fixed()) else {
+            // Lines above the band are still visited to find the edges of the previous line.
+            for y_idx in y_min..y_max.min(band.y_end) {
+                let Some([(left, left_edge), (right, right_edge)]) = Self::find_intersect_points(render_engine, polygon, y_idx.to_fixed()) else {
                     continue;
                 };
 
                 //let half = I16F0::ONE / 2;
                 //let y = I16F0::from_num(y_idx);// + half;
-                let y_idx_base = (y_idx as usize) * 256;
 
                 //println!("Draw line {:X}", y);
                 if left.screen_p.x == right.screen_p.x {
@@ -209,12 +369,16 @@ impl Software3DRenderer {
 
                 let x_min = left.screen_p.x.round().to_num::<i16>();
                 let x_max = right.screen_p.x.round().to_num::<i16>();
+                let x_mid = (x_min + x_max) / 2;
 
                 if x_min == 0 && x_max == 256 {
                     //println!("Line {:X} | x: {:X} to {:X} | tex ({:X}, {:X}) to ({:X}, {:X})", y_idx, x_min, x_max, left.tex_coords.s, left.tex_coords.t, right.tex_coords.s, right.tex_coords.t/* , line.step.tex_s, line.step.tex_t*/);
                 }
 
-                for x_idx in x_min..x_max {
+                let x_range = if y_idx >= band.y_start {x_min..x_max} else {0..0};
+                let y_idx_base = (y_idx.saturating_sub(band.y_start) as usize) * self.width;
+
+                for x_idx in x_range {
 
                     let factor = if !Self::similar_depth(left.depth, right.depth) {
                         let factor_over = (x_idx.to_fixed::<I16F0>() - left.screen_p.x).to_fixed::<I23F9>() * left.depth;
@@ -251,7 +415,7 @@ impl Software3DRenderer {
                     let tex_coords = TexCoords { s: current.tex_s.to_fixed(), t: current.tex_t.to_fixed() };*/
 
                     let idx = y_idx_base + (x_idx as usize);
-                    if !Self::test_depth(polygon.render_eq_depth(), self.depth_buffer[idx], depth) {
+                    if !Self::test_depth(polygon.render_eq_depth(), band.depth_buffer[idx], depth) {
                         continue;
                     }
 
@@ -277,11 +441,22 @@ impl Software3DRenderer {
                     };
                     //let frag_colour = poly_colour;
                     if frag_colour.alpha > 0 {
-                        target[idx] = frag_colour;
-                        self.depth_buffer[idx] = depth;
-                        self.attr_buffer[idx].opaque_id = polygon.attrs.id();
-                        self.attr_buffer[idx].fog = polygon.attrs.contains(PolygonAttrs::FOG_BLEND_ENABLE);
-                        self.attr_buffer[idx].edge = edge;
+                        band.below_buffer[idx] = band.target[idx];
+                        band.target[idx] = frag_colour;
+                        band.depth_buffer[idx] = depth;
+                        band.attr_buffer[idx].opaque_id = polygon.attrs.id();
+                        band.attr_buffer[idx].fog = polygon.attrs.contains(PolygonAttrs::FOG_BLEND_ENABLE);
+                        band.attr_buffer[idx].edge = edge;
+                        // Wireframe polygons are not anti-aliased.
+                        band.attr_buffer[idx].coverage = if anti_alias && edge && !polygon.is_wireframe() {
+                            if x_idx < x_mid {
+                                Self::edge_coverage(left_edge, x_idx, y_idx, true)
+                            } else {
+                                Self::edge_coverage(right_edge, x_idx, y_idx, false)
+                            }
+                        } else {
+                            0x1F
+                        };
                     }
                 }
 
@@ -292,17 +467,17 @@ impl Software3DRenderer {
         }
     }
     
-    fn draw_trans_polygons(&mut self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, target: &mut [ColourAlpha]) {
+    fn draw_trans_polygons(&self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, band: &mut Band) {
         if render_engine.polygon_ram.use_manual_mode {
             render_engine.polygon_ram.trans_polygon_manual.iter()
-                .for_each(|p| self.draw_trans_polygon(render_engine, vram, target, p));
+                .for_each(|p| self.draw_trans_polygon(render_engine, vram, band, p));
         } else {
             render_engine.polygon_ram.trans_polygon_auto.iter()
-                .for_each(|p| self.draw_trans_polygon(render_engine, vram, target, p));
+                .for_each(|p| self.draw_trans_polygon(render_engine, vram, band, p));
         }
     }
 
-    fn draw_trans_polygon(&mut self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, target: &mut [ColourAlpha], p: &PolygonOrder) {
+    fn draw_trans_polygon(&self, render_engine: &RenderingEngine, vram: &Engine3DVRAM, band: &mut Band, p: &PolygonOrder) {
         let polygon = &render_engine.polygon_ram.polygons[p.polygon_index];
         let mode = polygon.attrs.mode();
         let id = polygon.attrs.id();
@@ -314,17 +489,17 @@ impl Software3DRenderer {
         }*/
 
         //let (mut x_min_prev, mut x_max_prev) = (256, 0);
-        let (y_min, y_max) = (p.y_min.to_num::<u8>(), p.y_max.to_num::<u8>());
+        let (y_min, y_max) = Self::y_range(render_engine, polygon);
 
-        for y_idx in y_min..y_max {
+        for y_idx in y_min.max(band.y_start)..y_max.min(band.y_end) {
 
-            let Some([left, right]) = Self::find_intersect_points(render_engine, polygon, y_idx.to_fixed()) else {
+            let Some([(left, _), (right, _)]) = Self::find_intersect_points(render_engine, polygon, y_idx.to_fixed()) else {
                 continue;
             };
 
             //let half = I16F0::ONE / 2;
             //let y = I16F0::from_num(y_idx);// + half;
-            let y_idx_base = (y_idx as usize) * 256;
+            let y_idx_base = ((y_idx - band.y_start) as usize) * self.width;
 
             //println!("Draw line {:X}", y);
 
@@ -372,7 +547,7 @@ impl Software3DRenderer {
                 let idx = y_idx_base + (x_idx as usize);
                 // TODO: only extract for shadow polygons?
                 let stencil_mask = if mode == PolygonMode::Shadow && id != 0 {
-                    std::mem::replace(&mut self.stencil_buffer[idx], false)
+                    std::mem::replace(&mut band.stencil_buffer[idx], false)
                 } else {
                     false
                 };
@@ -411,10 +586,10 @@ impl Software3DRenderer {
                 let colour = Colour { r: current.colour_r.to_num(), g: current.colour_g.to_num(), b: current.colour_b.to_num() };
                 let tex_coords = TexCoords { s: current.tex_s.to_fixed(), t: current.tex_t.to_fixed() };*/
 
-                if !Self::test_depth(polygon.render_eq_depth(), self.depth_buffer[idx], depth) {
+                if !Self::test_depth(polygon.render_eq_depth(), band.depth_buffer[idx], depth) {
                     if id == 0 && mode == PolygonMode::Shadow {
                         // Shadow polygon mask
-                        self.stencil_buffer[idx] = true;
+                        band.stencil_buffer[idx] = true;
                     }
                     continue;
                 }
@@ -424,7 +599,7 @@ impl Software3DRenderer {
                         // Ignore masks
                         continue;
                     }
-                    if !stencil_mask || self.attr_buffer[idx].opaque_id == id {
+                    if !stencil_mask || band.attr_buffer[idx].opaque_id == id {
                         // We only want to draw the shadow if it passes depth,
                         // is masked, and doesn't match the IDs
                         continue;
@@ -448,7 +623,7 @@ impl Software3DRenderer {
                 } else if render_engine.control.contains(Display3DControl::ALPHA_TEST_ENABLE) && frag_colour.alpha < render_engine.alpha_test {
                     continue;
                 } else if frag_colour.alpha != 0x1F {
-                    if let Some(existing_id) = self.attr_buffer[idx].trans_id {
+                    if let Some(existing_id) = band.attr_buffer[idx].trans_id {
                         if existing_id == id {
                             continue;
                         }
@@ -456,44 +631,49 @@ impl Software3DRenderer {
                 }
 
                 // We are sure that we want to render this fragment.
+                let buffer_colour = band.target[idx];
                 if render_engine.control.contains(Display3DControl::BLENDING_ENABLE) {
-                    target[idx] = Self::blend_buffer_colour(
-                        frag_colour, target[idx],
+                    band.target[idx] = Self::blend_buffer_colour(
+                        frag_colour, band.target[idx],
                         mode == PolygonMode::Shadow
                     );
                 } else {
-                    target[idx] = frag_colour;
+                    band.target[idx] = frag_colour;
                 }
 
                 if frag_colour.alpha != 0x1F {
                     if polygon.attrs.contains(PolygonAttrs::ALPHA_DEPTH) {
-                        self.depth_buffer[idx] = depth;
+                        band.depth_buffer[idx] = depth;
                     }
-                    self.attr_buffer[idx].trans_id = Some(id);
-                    self.attr_buffer[idx].fog = self.attr_buffer[idx].fog && polygon.attrs.contains(PolygonAttrs::FOG_BLEND_ENABLE);
+                    band.attr_buffer[idx].trans_id = Some(id);
+                    band.attr_buffer[idx].fog = band.attr_buffer[idx].fog && polygon.attrs.contains(PolygonAttrs::FOG_BLEND_ENABLE);
                 } else {
-                    self.depth_buffer[idx] = depth;
-                    self.attr_buffer[idx].opaque_id = id;
-                    self.attr_buffer[idx].fog = polygon.attrs.contains(PolygonAttrs::FOG_BLEND_ENABLE);
+                    // Translucent polygons are not anti-aliased.
+                    band.below_buffer[idx] = buffer_colour;
+                    band.depth_buffer[idx] = depth;
+                    band.attr_buffer[idx].opaque_id = id;
+                    band.attr_buffer[idx].fog = polygon.attrs.contains(PolygonAttrs::FOG_BLEND_ENABLE);
+                    band.attr_buffer[idx].coverage = 0x1F;
                 }
             }
         }
         
     }
 
-    fn draw_fog(&mut self, render_engine: &RenderingEngine, target: &mut [ColourAlpha]) {
+    fn draw_fog(&self, render_engine: &RenderingEngine, buffers: Buffers, y_start: usize, target: &mut [ColourAlpha]) {
         let fog_shift = (render_engine.control & Display3DControl::FOG_SHIFT).bits() >> 8;
         let fog_interval = 0x400 >> fog_shift;
         let fog_min = render_engine.fog_offset + fog_interval;
         let fog_max = render_engine.fog_offset + (fog_interval << 5);
         let fog_diff = Depth::from_num(fog_interval);
-        for idx in 0..(256 * 192) {
-            if !self.attr_buffer[idx].fog {
+        let buffer_offset = y_start * self.width;
+        for idx in 0..target.len() {
+            if !buffers.attr_buffer[buffer_offset + idx].fog {
                 continue;
             }
 
             // Upper 15 bits of depth.
-            let depth = (self.depth_buffer[idx].to_num::<i32>() & 0x7FFF) as u16;
+            let depth = (buffers.depth_buffer[buffer_offset + idx].to_num::<i32>() & 0x7FFF) as u16;
             let fog_density = if depth < fog_min {
                 render_engine.fog_table[0]
             } else if depth >= fog_max  {
@@ -540,45 +720,80 @@ impl Software3DRenderer {
         }
     }
 
-    fn mark_edges(&mut self, render_engine: &RenderingEngine, target: &mut [ColourAlpha]) {
-        for y in 0..=191 {
-            let offset = y * 256;
-            for x in 0..=255 {
+    fn mark_edges(&self, render_engine: &RenderingEngine, buffers: Buffers, y_start: usize, target: &mut [ColourAlpha]) {
+        let (width, height) = (self.width, self.height);
+        let (attr_buffer, depth_buffer) = (buffers.attr_buffer, buffers.depth_buffer);
+        for y in y_start..(y_start + target.len() / width) {
+            let offset = y * width;
+            for x in 0..width {
                 let index = offset + x;
                 
-                if !self.attr_buffer[index].edge {
+                if !attr_buffer[index].edge {
                     continue;
                 }
-                let this = (self.attr_buffer[index].opaque_id, self.depth_buffer[index]);
+                let this = (attr_buffer[index].opaque_id, depth_buffer[index]);
                 let left = if x == 0 {
                     (render_engine.clear_poly_id, render_engine.clear_depth)
-                } else {(self.attr_buffer[index - 1].opaque_id, self.depth_buffer[index - 1])};
-                let right = if x == 255 {
+                } else {(attr_buffer[index - 1].opaque_id, depth_buffer[index - 1])};
+                let right = if x == width - 1 {
                     (render_engine.clear_poly_id, render_engine.clear_depth)
-                } else {(self.attr_buffer[index + 1].opaque_id, self.depth_buffer[index + 1])};
+                } else {(attr_buffer[index + 1].opaque_id, depth_buffer[index + 1])};
                 let top = if y == 0 {
                     (render_engine.clear_poly_id, render_engine.clear_depth)
-                } else {(self.attr_buffer[index - 256].opaque_id, self.depth_buffer[index - 256])};
-                let bottom = if y == 191 {
+                } else {(attr_buffer[index - width].opaque_id, depth_buffer[index - width])};
+                let bottom = if y == height - 1 {
                     (render_engine.clear_poly_id, render_engine.clear_depth)
-                } else {(self.attr_buffer[index + 256].opaque_id, self.depth_buffer[index + 256])};
+                } else {(attr_buffer[index + width].opaque_id, depth_buffer[index + width])};
 
                 if Self::check_edge(this, left, right, top, bottom) {
                     let edge_index = (this.0 >> 3) as usize;
-                    target[index].col = render_engine.edge_colour[edge_index];
+                    target[index - y_start * width].col = render_engine.edge_colour[edge_index];
                 }
             }
         }
     }
 
+    /// Get the range of lines a polygon covers, at the render resolution.
+    fn y_range(render_engine: &RenderingEngine, polygon: &Polygon) -> (u16, u16) {
+        polygon.vertex_indices.iter().take(polygon.num_vertices as usize)
+            .map(|idx| render_engine.polygon_ram.vertices[*idx as usize].screen_p.y.to_num::<u16>())
+            .fold((u16::MAX, 0), |(y_min, y_max), y| (y_min.min(y), y_max.max(y)))
+    }
+
+    /// Blend partially covered edge pixels with the pixel behind.
+    fn anti_alias(&self, buffers: Buffers, y_start: usize, target: &mut [ColourAlpha]) {
+        let buffer_offset = y_start * self.width;
+        let attrs = &buffers.attr_buffer[buffer_offset..(buffer_offset + target.len())];
+        for (idx, attrs) in attrs.iter().enumerate() {
+            if attrs.coverage == 0x1F {
+                continue;
+            }
+            let below = buffers.below_buffer[buffer_offset + idx];
+            let coverage = attrs.coverage as u16;
+            let below_coverage = 0x1F - coverage;
+            let blend = |a: u8, b: u8| (((a as u16) * coverage + (b as u16) * below_coverage) / 0x1F) as u8;
+            target[idx] = ColourAlpha {
+                col: Colour {
+                    r: blend(target[idx].col.r, below.col.r),
+                    g: blend(target[idx].col.g, below.col.g),
+                    b: blend(target[idx].col.b, below.col.b),
+                },
+                alpha: blend(target[idx].alpha, below.alpha)
+            };
+        }
+    }
+
 }
 
 // Static helpers.
-impl Software3DRenderer {
+impl Rasteriser {
     /// Find the first two points where this polygon intersects the render line.
     /// 
-    /// Returns the two points with interpolated attributes, in order of x position.
-    fn find_intersect_points(render_engine: &RenderingEngine, polygon: &Polygon, y: I16F0) -> Option<[Vertex; 2]> {
+    /// Returns the two points with interpolated attributes, in order of x position,
+    /// along with the polygon edges they lie on.
+    /// 
+    /// `y` and the returned points are at the render resolution.
+    fn find_intersect_points(render_engine: &RenderingEngine, polygon: &Polygon, y: I16F0) -> Option<[(Vertex, Edge); 2]> {
         // Find start and end points.
         let mut points = [None, None];
         let mut line_a_points = None;
@@ -589,44 +804,45 @@ impl Software3DRenderer {
 
             let vtx_a = &render_engine.polygon_ram.vertices[v_index_a as usize];
             let vtx_b = &render_engine.polygon_ram.vertices[v_index_b as usize];
+            let (a_p, b_p) = (vtx_a.screen_p, vtx_b.screen_p);
 
-            if (y > vtx_a.screen_p.y && y > vtx_b.screen_p.y) || (y < vtx_a.screen_p.y && y < vtx_b.screen_p.y) || (vtx_a.screen_p.y == vtx_b.screen_p.y) {
+            if (y > a_p.y && y > b_p.y) || (y < a_p.y && y < b_p.y) || (a_p.y == b_p.y) {
                 // This line does not intersect the render line.
                 continue;
             }
 
             // Bottom = higher Y value
-            let (top, bottom) = if vtx_a.screen_p.y > vtx_b.screen_p.y {
-                (&vtx_b, &vtx_a)
+            let ((top, top_p), (bottom, bottom_p)) = if a_p.y > b_p.y {
+                ((&vtx_b, b_p), (&vtx_a, a_p))
             } else {
-                (&vtx_a, &vtx_b)
+                ((&vtx_a, a_p), (&vtx_b, b_p))
             };
 
             if let Some((top_a, bottom_a)) = line_a_points {
-                if top.screen_p.y == bottom_a || bottom.screen_p.y == top_a {
+                if top_p.y == bottom_a || bottom_p.y == top_a {
                     continue;
                 }
             } else {
-                line_a_points = Some((top.screen_p.y, bottom.screen_p.y));
+                line_a_points = Some((top_p.y, bottom_p.y));
             }
 
             // TODO: special case for single-pixel point...
 
             let x_factor = {
-                let factor_over = (y - top.screen_p.y).to_fixed::<I23F9>();
-                let factor_under = (bottom.screen_p.y - y).to_fixed::<I23F9>() + factor_over;
+                let factor_over = (y - top_p.y).to_fixed::<I23F9>();
+                let factor_under = (bottom_p.y - y).to_fixed::<I23F9>() + factor_over;
                 factor_over / factor_under
             };
             let factor = if !Self::similar_depth(top.depth, bottom.depth) {
-                let factor_over = (y - top.screen_p.y).to_fixed::<I23F9>() * top.depth;
-                let factor_under = (bottom.screen_p.y - y).to_fixed::<I23F9>() * bottom.depth + factor_over;
+                let factor_over = (y - top_p.y).to_fixed::<I23F9>() * top.depth;
+                let factor_under = (bottom_p.y - y).to_fixed::<I23F9>() * bottom.depth + factor_over;
                 factor_over / factor_under
             } else {
                 x_factor
             };
 
             // TODO: calculate this differently
-            let x_offset = (bottom.screen_p.x - top.screen_p.x).to_fixed::<Depth>() * x_factor;
+            let x_offset = (bottom_p.x - top_p.x).to_fixed::<Depth>() * x_factor;
             let depth_offset = (bottom.depth - top.depth) * factor;
             let r_offset = (bottom.colour.r.to_fixed::<Depth>() - top.colour.r.to_fixed::<Depth>()) * factor;
             let g_offset = (bottom.colour.g.to_fixed::<Depth>() - top.colour.g.to_fixed::<Depth>()) * factor;
@@ -636,7 +852,7 @@ impl Software3DRenderer {
 
             let vertex = Vertex {
                 screen_p: Coords {
-                    x: (top.screen_p.x.to_fixed::<Depth>() + x_offset).round().to_fixed(),
+                    x: (top_p.x.to_fixed::<Depth>() + x_offset).round().to_fixed(),
                     y
                 },
                 depth: top.depth + depth_offset,
@@ -651,18 +867,23 @@ impl Software3DRenderer {
                 },
             };
 
+            let edge = Edge {
+                top:    top_p,
+                bottom: bottom_p,
+            };
+
             if points[0].is_none() {
                 // First line.
-                points[0] = Some(vertex);
+                points[0] = Some((vertex, edge));
             } else if points[1].is_none() {
                 // Second line - we are done.
-                points[1] = Some(vertex);
+                points[1] = Some((vertex, edge));
                 break;
             }
         }
 
         if let [Some(vtx_a), Some(vtx_b)] = points {
-            if vtx_a.screen_p.x < vtx_b.screen_p.x {
+            if vtx_a.0.screen_p.x < vtx_b.0.screen_p.x {
                 Some([vtx_a, vtx_b])
             } else {
                 Some([vtx_b, vtx_a])
@@ -672,6 +893,29 @@ impl Software3DRenderer {
         }
     }
 
+    /// Find how much of a pixel is covered by the polygon, from 0 to 0x1F.
+    /// 
+    /// Uses the distance from the pixel centre to the edge, along the minor axis of the edge.
+    /// `left_side` is true if the polygon is to the right of the edge.
+    fn edge_coverage(edge: Edge, x: i16, y: u16, left_side: bool) -> u8 {
+        let dx = (edge.bottom.x - edge.top.x).to_num::<i64>();
+        let dy = (edge.bottom.y - edge.top.y).to_num::<i64>();
+        let major = std::cmp::max(dx.abs(), dy.abs());
+        if major == 0 {
+            return 0x1F;
+        }
+        // Pixel centre and edge top, in half-pixels.
+        let centre_x = (x as i64) * 2 + 1;
+        let centre_y = (y as i64) * 2 + 1;
+        let top_x = edge.top.x.to_num::<i64>() * 2;
+        let top_y = edge.top.y.to_num::<i64>() * 2;
+        // Distance to edge is `dist / (2 * major)` pixels.
+        let dist = (centre_x - top_x) * dy - (centre_y - top_y) * dx;
+        let inside_dist = if left_side {dist} else {-dist};
+        let coverage = (0x1F * (major + inside_dist)) / (2 * major);
+        coverage.clamp(0, 0x1F) as u8
+    }
+
     /// Returns true if the fragment passes the depth test.
     fn test_depth(render_eq: bool, buffer_depth: Depth, frag_depth: Depth) -> bool {
         if render_eq {
@@ -691,7 +935,17 @@ impl Software3DRenderer {
     }
 
     /// Lookup texture colour.
+    ///
+    /// If the texture has a replacement, it is sampled instead of VRAM.
     fn lookup_tex_colour(&self, tex_coords: TexCoords, tex_attrs: TextureAttrs, palette: u16, vram: &Engine3DVRAM) -> Option<ColourAlpha> {
+        if let Some(replacement) = self.textures.replacement(tex_attrs, palette) {
+            return Some(Self::lookup_replacement_colour(replacement, tex_coords, tex_attrs));
+        }
+        self.lookup_vram_tex_colour(tex_coords, tex_attrs, palette, vram)
+    }
+
+    /// Lookup texture colour from VRAM.
+    fn lookup_vram_tex_colour(&self, tex_coords: TexCoords, tex_attrs: TextureAttrs, palette: u16, vram: &Engine3DVRAM) -> Option<ColourAlpha> {
         match tex_attrs.format() {
             1 => Some(self.lookup_a3i5_tex(tex_coords, tex_attrs, palette, vram)),
             2 => Some(self.lookup_2bpp_tex(tex_coords, tex_attrs, palette, vram)),
@@ -704,47 +958,59 @@ impl Software3DRenderer {
         }
     }
 
+    /// Decode a whole texture from VRAM.
+    fn decode_texture(&self, tex_attrs: TextureAttrs, palette: u16, vram: &Engine3DVRAM) -> Vec<ColourAlpha> {
+        let (width, height) = (tex_attrs.width(), tex_attrs.height());
+        (0..height).flat_map(|t| (0..width).map(move |s| (s, t)))
+            .map(|(s, t)| {
+                let tex_coords = TexCoords { s: I12F4::from_num(s), t: I12F4::from_num(t) };
+                self.lookup_vram_tex_colour(tex_coords, tex_attrs, palette, vram).unwrap_or_else(ColourAlpha::transparent)
+            })
+            .collect()
+    }
+
+    /// Lookup replacement texel colour, using the coordinates of the original texture.
+    fn lookup_replacement_colour(replacement: &Replacement, tex_coords: TexCoords, tex_attrs: TextureAttrs) -> ColourAlpha {
+        let scale_s = (replacement.width / tex_attrs.width()) as i32;
+        let scale_t = (replacement.height / tex_attrs.height()) as i32;
+        // Use the fractional part of the coordinates to find the replacement texel.
+        let base_tex_s = ((tex_coords.s.to_bits() as i32) * scale_s) >> 4;
+        let base_tex_t = ((tex_coords.t.to_bits() as i32) * scale_t) >> 4;
+        let s = Self::wrap_tex_coord(base_tex_s, replacement.width, tex_attrs.contains(TextureAttrs::REPEAT_S), tex_attrs.contains(TextureAttrs::FLIP_S));
+        let t = Self::wrap_tex_coord(base_tex_t, replacement.height, tex_attrs.contains(TextureAttrs::REPEAT_T), tex_attrs.contains(TextureAttrs::FLIP_T));
+        replacement.texel(s, t)
+    }
+
     /// Extract texture coordinates.
     fn get_tex_coords(tex_coords: TexCoords, tex_attrs: TextureAttrs) -> (u32, u32) {
-        let width = tex_attrs.width();
-        let base_tex_s = tex_coords.s.to_num::<i32>();
-
-        let tex_s = if tex_attrs.contains(TextureAttrs::REPEAT_S) {
-            let unsigned_tex_s = base_tex_s as u32;
-            let mask = width - 1;
-            if tex_attrs.contains(TextureAttrs::FLIP_S) && (unsigned_tex_s & width) != 0 {
-                // Flip
-                let s = unsigned_tex_s & mask;
-                mask - s
-            } else {
-                unsigned_tex_s & mask
-            }
-        } else {
-            // Clamp
-            let max = (width - 1) as i32;
-            std::cmp::min(max, std::cmp::max(base_tex_s, 0)) as u32
-        };
-        
-        let height = tex_attrs.height();
-        let base_tex_t = tex_coords.t.to_num::<i32>();
+        let tex_s = Self::wrap_tex_coord(
+            tex_coords.s.to_num::<i32>(), tex_attrs.width(),
+            tex_attrs.contains(TextureAttrs::REPEAT_S), tex_attrs.contains(TextureAttrs::FLIP_S)
+        );
+        let tex_t = Self::wrap_tex_coord(
+            tex_coords.t.to_num::<i32>(), tex_attrs.height(),
+            tex_attrs.contains(TextureAttrs::REPEAT_T), tex_attrs.contains(TextureAttrs::FLIP_T)
+        );
+        (tex_s, tex_t)
+    }
 
-        let tex_t = if tex_attrs.contains(TextureAttrs::REPEAT_T) {
-            let unsigned_tex_t = base_tex_t as u32;
-            let mask = height - 1;
-            if tex_attrs.contains(TextureAttrs::FLIP_T) && (unsigned_tex_t & height) != 0 {
+    /// Repeat, flip or clamp a texture coordinate to within `size`.
+    fn wrap_tex_coord(base_coord: i32, size: u32, repeat: bool, flip: bool) -> u32 {
+        if repeat {
+            let unsigned_coord = base_coord as u32;
+            let mask = size - 1;
+            if flip && (unsigned_coord & size) != 0 {
                 // Flip
-                let t = unsigned_tex_t & mask;
-                mask - t
+                let coord = unsigned_coord & mask;
+                mask - coord
             } else {
-                unsigned_tex_t & mask
+                unsigned_coord & mask
             }
         } else {
             // Clamp
-            let max = (height - 1) as i32;
-            std::cmp::min(max, std::cmp::max(base_tex_t, 0)) as u32
-        };
-
-        (tex_s, tex_t)
+            let max = (size - 1) as i32;
+            std::cmp::min(max, std::cmp::max(base_coord, 0)) as u32
+        }
     }
 
     /// Lookup 2bpp texel colour.
@@ -864,8 +1130,7 @@ impl Software3DRenderer {
         let shift = sub_block_s << 1;
         let block_data = (vram.get_tex_byte(block_addr + sub_block_t) >> shift) & 0b11;
 
-        let block_palette_upper = (block_addr >> 2) & 0x1_0000;
-        let block_palette_addr = 0x2_0000 + (block_palette_upper | (block_addr / 2));
+        let block_palette_addr = textures::compressed_palette_index_addr(block_addr);
         let block_palette_data = vram.get_tex_halfword(block_palette_addr);
 
         let base_palette_addr = (palette as u32) << 3;
@@ -1090,3 +1355,142 @@ impl Software3DRenderer {
         compare_pix(left) || compare_pix(right) || compare_pix(top) || compare_pix(bottom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Add a polygon with the given attributes and native (x, y, depth) points.
+    fn add_polygon(render_engine: &mut RenderingEngine, scale: i16, attrs: u32, points: &[(i16, i16, i32)]) {
+        let polygon_ram = &mut render_engine.polygon_ram;
+        let mut polygon = Polygon {
+            attrs:          PolygonAttrs::from_bits_truncate(attrs),
+            tex:            TextureAttrs::default(),
+            palette:        0,
+            num_vertices:   0,
+            vertex_indices: [0; 8],
+        };
+        for (n, &(x, y, depth)) in points.iter().enumerate() {
+            let index = polygon_ram.insert_vertex(Vertex {
+                screen_p:   Coords { x: I16F0::from_num(x * scale), y: I16F0::from_num(y * scale) },
+                depth:      Depth::from_num(depth),
+                colour:     Colour { r: (n as u8) * 60, g: 0xFF - (n as u8) * 40, b: 0x80 },
+                tex_coords: TexCoords::default(),
+            });
+            polygon.add_vertex_index(index);
+        }
+        let y_min = points.iter().map(|p| p.1).min().unwrap();
+        let y_max = points.iter().map(|p| p.1).max().unwrap();
+        polygon_ram.insert_polygon(polygon, I16F0::from_num(y_max), I16F0::from_num(y_min));
+    }
+
+    fn test_scene(scale: i16) -> RenderingEngine {
+        let mut render_engine = RenderingEngine::new();
+        render_engine.control = Display3DControl::EDGE_MARKING | Display3DControl::FOG_ENABLE |
+            Display3DControl::ANTI_ALIAS | Display3DControl::BLENDING_ENABLE;
+        render_engine.clear_depth = Depth::from_num(0x7FFF);
+        render_engine.fog_enabled = true;
+        render_engine.fog_offset = 0x800;
+        render_engine.fog_table = (0..32).map(|n| n * 4).collect();
+        render_engine.edge_colour = (0..8).map(|n| Colour { r: n * 30, g: 0, b: 0xFF }).collect();
+
+        let opaque = 31 << 16;
+        let translucent = 15 << 16;
+        let shadow = 3 << 4;
+        let fog = PolygonAttrs::FOG_BLEND_ENABLE.bits();
+        add_polygon(&mut render_engine, scale, opaque | (1 << 24) | fog, &[(10, 5, 0x1000), (200, 20, 0x1200), (180, 170, 0x1400), (30, 150, 0x1100)]);
+        add_polygon(&mut render_engine, scale, opaque | (2 << 24), &[(100, 0, 0x800), (250, 100, 0x900), (60, 191, 0xA00)]);
+        add_polygon(&mut render_engine, scale, translucent | (3 << 24) | fog, &[(0, 40, 0x400), (255, 40, 0x400), (255, 140, 0x500), (0, 140, 0x500)]);
+        // Shadow volume: the mask is behind the scene, the shadow in front.
+        add_polygon(&mut render_engine, scale, translucent | shadow, &[(50, 50, 0x2000), (150, 50, 0x2000), (150, 120, 0x2000), (50, 120, 0x2000)]);
+        add_polygon(&mut render_engine, scale, translucent | shadow | (5 << 24), &[(50, 50, 0x100), (150, 50, 0x100), (150, 120, 0x100), (50, 120, 0x100)]);
+        render_engine
+    }
+
+    #[test]
+    fn bands_match_single_thread() {
+        let vram = Engine3DVRAM::default();
+        let draw = |scale: usize, threads: usize| {
+            let render_engine = test_scene(scale as i16);
+            let mut renderer = Software3DRenderer::with_threads(scale, TextureConfig::default(), threads);
+            let mut target = vec![ColourAlpha::transparent(); 256 * 192 * scale * scale];
+            renderer.draw(&render_engine, &vram, &mut target);
+            target.iter().map(|p| (p.col.r, p.col.g, p.col.b, p.alpha)).collect::<Vec<_>>()
+        };
+
+        for scale in [1, 2] {
+            let single = draw(scale, 1);
+            for threads in [2, 4, 7] {
+                assert!(single == draw(scale, threads), "scale {} with {} threads differs from single thread", scale, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn run_parallel_all_items() {
+        let mut lines = vec![0_usize; 10];
+        run_parallel(lines.chunks_mut(3).enumerate(), |(n, chunk)| chunk.iter_mut().for_each(|line| *line = n + 1));
+        assert_eq!(lines, vec![1, 1, 1, 2, 2, 2, 3, 3, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn run_parallel_panic() {
+        run_parallel(0..4, |n| assert!(n != 2));
+    }
+
+    #[test]
+    fn edge_coverage() {
+        let edge = |top: (i16, i16), bottom: (i16, i16)| Edge {
+            top:    Coords { x: I16F0::from_num(top.0), y: I16F0::from_num(top.1) },
+            bottom: Coords { x: I16F0::from_num(bottom.0), y: I16F0::from_num(bottom.1) },
+        };
+
+        // Vertical edge along the left side of column 10.
+        let vertical = edge((10, 0), (10, 20));
+        assert_eq!(Rasteriser::edge_coverage(vertical, 10, 5, true), 0x1F);
+        assert_eq!(Rasteriser::edge_coverage(vertical, 9, 5, true), 0);
+        assert_eq!(Rasteriser::edge_coverage(vertical, 9, 5, false), 0x1F);
+
+        // Diagonal edge through the pixel centre covers half.
+        let diagonal = edge((0, 0), (20, 20));
+        assert_eq!(Rasteriser::edge_coverage(diagonal, 5, 5, true), 0xF);
+        assert_eq!(Rasteriser::edge_coverage(diagonal, 5, 5, false), 0xF);
+        assert!(Rasteriser::edge_coverage(diagonal, 6, 5, true) > 0xF);
+        assert!(Rasteriser::edge_coverage(diagonal, 6, 5, false) < 0xF);
+
+        // Shallow edge: coverage changes along y.
+        let shallow = edge((0, 10), (40, 12));
+        assert!(Rasteriser::edge_coverage(shallow, 20, 10, false) < Rasteriser::edge_coverage(shallow, 20, 11, false));
+
+        // Single point.
+        assert_eq!(Rasteriser::edge_coverage(edge((3, 3), (3, 3)), 3, 3, true), 0x1F);
+    }
+
+    #[test]
+    fn fog_after_anti_alias() {
+        let renderer = Software3DRenderer::with_threads(1, TextureConfig::default(), 1);
+        let mut render_engine = RenderingEngine::new();
+        render_engine.control = Display3DControl::FOG_ENABLE | Display3DControl::ANTI_ALIAS;
+        render_engine.fog_table = vec![127; 32];
+        render_engine.fog_colour = Colour { r: 0x10, g: 0x20, b: 0x30 };
+        render_engine.fog_alpha = 0x1F;
+
+        let attrs = Attributes { fog: true, coverage: 0x10, ..Default::default() };
+        let attr_buffer = vec![attrs; 256];
+        let depth_buffer = vec![Depth::from_num(0x7FFF); 256];
+        let below_buffer = vec![ColourAlpha { col: Colour { r: 0xFF, g: 0, b: 0 }, alpha: 0x1F }; 256];
+        let buffers = Buffers {
+            attr_buffer:    &attr_buffer,
+            depth_buffer:   &depth_buffer,
+            below_buffer:   &below_buffer,
+        };
+        let mut target = vec![ColourAlpha { col: Colour { r: 0, g: 0xFF, b: 0 }, alpha: 0x1F }; 256];
+        renderer.rasteriser.post_process(&render_engine, buffers, 0, &mut target);
+
+        // Fully fogged: the unfogged pixel behind doesn't show through the edge.
+        for pixel in target {
+            assert_eq!((pixel.col.r, pixel.col.g, pixel.col.b, pixel.alpha), (0x10, 0x20, 0x30, 0x1F));
+        }
+    }
+}
@@ -27,4 +27,11 @@ impl TexPaletteCache {
     pub fn get_tex_colour(&self, index: u32) -> Colour {
         self.tex_palette[index as usize].clone()
     }
+
+    /// Get `count` colours from `index`, stopping at the end of the palette.
+    pub fn get_tex_colours(&self, index: u32, count: u32) -> &[Colour] {
+        let start = std::cmp::min(index as usize, self.tex_palette.len());
+        let end = std::cmp::min(start + count as usize, self.tex_palette.len());
+        &self.tex_palette[start..end]
+    }
 }
@@ -0,0 +1,304 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    sync::Arc
+};
+
+use crate::{
+    ds::video::memory::Engine3DVRAM,
+    common::video::colour::*
+};
+use super::super::types::TextureAttrs;
+use super::palette::TexPaletteCache;
+
+/// Attributes which identify a texture in VRAM.
+const KEY_ATTRS: u32 = TextureAttrs::TRANSPARENT_0.bits() |
+    TextureAttrs::FORMAT.bits() |
+    TextureAttrs::SIZE_T.bits() |
+    TextureAttrs::SIZE_S.bits() |
+    TextureAttrs::ADDR.bits();
+
+/// Texture attributes and palette.
+type TextureKey = (u32, u16);
+
+/// Folders for texture dumping and replacement.
+#[derive(Clone, Default)]
+pub struct TextureConfig {
+    /// Decoded textures are written here as PNG, named by their hash.
+    pub dump_path:  Option<PathBuf>,
+    /// Replacement textures are loaded from here, named by the hash of the original.
+    pub pack_path:  Option<PathBuf>,
+}
+
+/// A user-provided texture.
+///
+/// Its size is an integer multiple of the texture it replaces.
+pub struct Replacement {
+    pub width:  u32,
+    pub height: u32,
+    texels:     Vec<ColourAlpha>,
+}
+
+impl Replacement {
+    /// Load a PNG, and check that it can replace a texture with the attributes provided.
+    fn load(path: &Path, tex_attrs: TextureAttrs) -> Result<Self> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let (width, height) = (info.width, info.height);
+        if width < tex_attrs.width() || width % tex_attrs.width() != 0 ||
+            height < tex_attrs.height() || height % tex_attrs.height() != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "size {}x{} is not a multiple of {}x{}", width, height, tex_attrs.width(), tex_attrs.height()
+            )));
+        }
+
+        let bytes = &buffer[..info.buffer_size()];
+        let texels = match info.color_type {
+            png::ColorType::Rgba => bytes.chunks_exact(4).map(|p| ColourAlpha {
+                col: Colour { r: p[0], g: p[1], b: p[2] },
+                alpha: p[3] >> 3
+            }).collect(),
+            png::ColorType::Rgb => bytes.chunks_exact(3).map(|p| ColourAlpha {
+                col: Colour { r: p[0], g: p[1], b: p[2] },
+                alpha: 0x1F
+            }).collect(),
+            other => return Err(Error::new(ErrorKind::InvalidData, format!("unsupported colour type {:?}", other))),
+        };
+
+        Ok(Self { width, height, texels })
+    }
+
+    #[inline]
+    pub fn texel(&self, s: u32, t: u32) -> ColourAlpha {
+        self.texels[(t * self.width + s) as usize]
+    }
+}
+
+/// Hashes the textures used by each frame.
+/// Dumps new textures, and finds replacements for them.
+pub struct TextureReplacer {
+    dump_path:      Option<PathBuf>,
+    pack_path:      Option<PathBuf>,
+
+    /// Hashes of textures that have been dumped.
+    dumped:         HashSet<u64>,
+    /// Replacements by texture hash. None if the pack has no replacement.
+    replacements:   HashMap<u64, Option<Arc<Replacement>>>,
+    /// Textures used in the current frame.
+    frame_textures: HashMap<TextureKey, Option<Arc<Replacement>>>,
+}
+
+impl TextureReplacer {
+    pub fn new(config: TextureConfig) -> Self {
+        if let Some(path) = &config.dump_path {
+            if let Err(e) = std::fs::create_dir_all(path) {
+                println!("Couldn't create texture dump folder {}: {}", path.display(), e);
+            }
+        }
+        Self {
+            dump_path:      config.dump_path,
+            pack_path:      config.pack_path,
+
+            dumped:         HashSet::new(),
+            replacements:   HashMap::new(),
+            frame_textures: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dump_path.is_some() || self.pack_path.is_some()
+    }
+
+    pub fn start_frame(&mut self) {
+        self.frame_textures.clear();
+    }
+
+    /// Returns true if the texture has been hashed this frame.
+    pub fn is_prepared(&self, tex_attrs: TextureAttrs, palette: u16) -> bool {
+        self.frame_textures.contains_key(&Self::key(tex_attrs, palette))
+    }
+
+    pub fn should_dump(&self, hash: u64) -> bool {
+        self.dump_path.is_some() && !self.dumped.contains(&hash)
+    }
+
+    /// Write the decoded texture as PNG, if it doesn't already exist.
+    pub fn dump(&mut self, hash: u64, tex_attrs: TextureAttrs, texels: &[ColourAlpha]) {
+        self.dumped.insert(hash);
+        let Some(dump_path) = &self.dump_path else {
+            return;
+        };
+        let path = dump_path.join(Self::file_name(hash));
+        if path.exists() {
+            return;
+        }
+        if let Err(e) = Self::write_png(&path, tex_attrs.width(), tex_attrs.height(), texels) {
+            println!("Couldn't dump texture {}: {}", path.display(), e);
+        }
+    }
+
+    /// Use the texture with the given hash for these attributes this frame.
+    /// Loads its replacement from the pack the first time it is seen.
+    pub fn set_texture(&mut self, tex_attrs: TextureAttrs, palette: u16, hash: u64) {
+        let replacement = if let Some(pack_path) = &self.pack_path {
+            self.replacements.entry(hash).or_insert_with(|| {
+                let path = pack_path.join(Self::file_name(hash));
+                if !path.exists() {
+                    return None;
+                }
+                match Replacement::load(&path, tex_attrs) {
+                    Ok(replacement) => Some(Arc::new(replacement)),
+                    Err(e) => {
+                        println!("Couldn't load replacement texture {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            }).clone()
+        } else {
+            None
+        };
+        self.frame_textures.insert(Self::key(tex_attrs, palette), replacement);
+    }
+
+    /// Get the replacement for a texture used this frame.
+    #[inline]
+    pub fn replacement(&self, tex_attrs: TextureAttrs, palette: u16) -> Option<&Replacement> {
+        if self.pack_path.is_none() {
+            return None;
+        }
+        self.frame_textures.get(&Self::key(tex_attrs, palette))
+            .and_then(|replacement| replacement.as_deref())
+    }
+}
+
+impl TextureReplacer {
+    fn key(tex_attrs: TextureAttrs, palette: u16) -> TextureKey {
+        (tex_attrs.bits() & KEY_ATTRS, palette)
+    }
+
+    fn file_name(hash: u64) -> String {
+        format!("{:016X}.png", hash)
+    }
+
+    fn write_png(path: &Path, width: u32, height: u32, texels: &[ColourAlpha]) -> Result<()> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data = texels.iter()
+            .flat_map(|t| [t.col.r, t.col.g, t.col.b, (t.alpha << 3) | (t.alpha >> 2)])
+            .collect::<Vec<_>>();
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+/// Get the address of the palette index for a 4x4 compressed texture block.
+pub fn compressed_palette_index_addr(block_addr: u32) -> u32 {
+    let block_palette_upper = (block_addr >> 2) & 0x1_0000;
+    0x2_0000 + (block_palette_upper | (block_addr / 2))
+}
+
+/// Hash the VRAM contents of a texture, along with its format and palette colours.
+///
+/// The address is not included, so the same texture loaded to a different place
+/// has the same hash.
+pub fn hash_texture(tex_attrs: TextureAttrs, palette: u16, vram: &Engine3DVRAM, palette_cache: &TexPaletteCache) -> u64 {
+    let mut hash = FnvHash::new();
+    hash.write(&(tex_attrs.bits() & KEY_ATTRS & !TextureAttrs::ADDR.bits()).to_le_bytes());
+
+    let texels = tex_attrs.width() * tex_attrs.height();
+    let addr = tex_attrs.addr();
+    let (texel_bytes, palette_addr, palette_size) = match tex_attrs.format() {
+        1 => (texels, (palette as u32) << 3, 32),
+        2 => (texels / 4, (palette as u32) << 2, 4),
+        3 => (texels / 2, (palette as u32) << 3, 16),
+        4 => (texels, (palette as u32) << 3, 256),
+        5 => (texels / 4, 0, 0),
+        6 => (texels, (palette as u32) << 3, 8),
+        7 => (texels * 2, 0, 0),
+        _ => (0, 0, 0),
+    };
+
+    for offset in 0..texel_bytes {
+        hash.write(&[vram.get_tex_byte(addr + offset)]);
+    }
+    for colour in palette_cache.get_tex_colours(palette_addr, palette_size) {
+        hash.write(&[colour.r, colour.g, colour.b]);
+    }
+
+    if tex_attrs.format() == 5 {
+        // Each 4x4 block has its own palette index and mode.
+        let base_palette_addr = (palette as u32) << 3;
+        for block_addr in (addr..(addr + texel_bytes)).step_by(4) {
+            let block_palette_data = vram.get_tex_halfword(compressed_palette_index_addr(block_addr));
+            hash.write(&block_palette_data.to_le_bytes());
+            let block_palette_offset = ((block_palette_data & 0x3FFF) as u32) << 1;
+            for colour in palette_cache.get_tex_colours(base_palette_addr + block_palette_offset, 4) {
+                hash.write(&[colour.r, colour.g, colour.b]);
+            }
+        }
+    }
+
+    hash.finish()
+}
+
+/// 64-bit FNV-1a. Hashes must be stable between runs, since they are used as file names.
+struct FnvHash(u64);
+
+impl FnvHash {
+    fn new() -> Self {
+        Self(0xCBF2_9CE4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    fn finish(self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::mem::ram::RAM;
+
+    #[test]
+    fn fnv_hash() {
+        assert_eq!(FnvHash::new().finish(), 0xCBF2_9CE4_8422_2325);
+        let mut hash = FnvHash::new();
+        hash.write(b"a");
+        assert_eq!(hash.finish(), 0xAF63_DC4C_8601_EC8C);
+    }
+
+    #[test]
+    fn texture_hash() {
+        // 8x8 4bpp texture at 0x80 and 0x100, with colour 0 transparent.
+        let mut tex_data = vec![0; 0x2_0000];
+        let texels = (0..0x20).collect::<Vec<u8>>();
+        tex_data[0x80..0xA0].copy_from_slice(&texels);
+        tex_data[0x100..0x120].copy_from_slice(&texels);
+        let vram = Engine3DVRAM {
+            tex_0:  Some(Box::new(RAM::from(tex_data))),
+            ..Default::default()
+        };
+        let tex_attrs = TextureAttrs::from_bits_truncate((1 << 29) | (3 << 26) | 0x10);
+        let hash = hash_texture(tex_attrs, 0, &vram, &TexPaletteCache::new());
+        assert_eq!(hash, 0xBED9_2BDF_F9D6_9681);
+
+        // The address is not part of the hash.
+        let moved_attrs = TextureAttrs::from_bits_truncate((1 << 29) | (3 << 26) | 0x20);
+        assert_eq!(hash_texture(moved_attrs, 0, &vram, &TexPaletteCache::new()), hash);
+    }
+}
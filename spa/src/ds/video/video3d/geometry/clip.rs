@@ -44,10 +44,13 @@ pub struct ClippingUnit {
     viewport_y:         N,
     viewport_width:     N,
     viewport_height:    N,
+
+    /// Screen coords are output at this multiple of the native resolution.
+    render_scale:       N,
 }
 
 impl ClippingUnit {
-    pub fn new() -> Self {
+    pub fn new(render_scale: usize) -> Self {
         Self {
             polygon_ram:    Box::new(PolygonRAM::new()),
 
@@ -59,6 +62,8 @@ impl ClippingUnit {
             viewport_y:         N::ZERO,
             viewport_width:     N::ZERO,
             viewport_height:    N::ZERO,
+
+            render_scale:       N::from_num(render_scale),
         }
     }
 
@@ -82,7 +87,10 @@ impl ClippingUnit {
     /// Add a polygon and vertices to the vertex list RAM.
     /// 
     /// Also make a note of its index in the current polygon.
-    pub fn add_polygon(&mut self, mut staged_polygon: Polygon, vertices: &mut [StagedVertex], capture: bool) {
+    /// 
+    /// Returns false if the polygon was dropped because the list RAM is full.
+    /// Culled polygons don't use any list RAM.
+    pub fn add_polygon(&mut self, mut staged_polygon: Polygon, vertices: &mut [StagedVertex], capture: bool) -> bool {
         let (mut min_y, mut max_y) = (I16F0::MAX, I16F0::ZERO);
 
         let mut one_dot_w = N::MAX;
@@ -94,9 +102,16 @@ impl ClippingUnit {
             let y = ((vertex.position.y().to_fixed::<I40F24>() + w) / (w * 2)).to_fixed::<N>();
             let z = ((vertex.position.z().to_fixed::<I40F24>() + w) / (w * 2)).to_fixed::<I19F13>();
 
-            let screen_p = self.get_screen_coords(x.to_fixed(), N::ONE - y.to_fixed::<N>());
-            max_y = std::cmp::max(max_y, screen_p.y);
-            min_y = std::cmp::min(min_y, screen_p.y);
+            let (screen_x, screen_y) = self.get_screen_coords(x.to_fixed(), N::ONE - y.to_fixed::<N>());
+            // Polygons are sorted and limited by native lines.
+            let native_y = screen_y.round().to_fixed::<I16F0>();
+            max_y = std::cmp::max(max_y, native_y);
+            min_y = std::cmp::min(min_y, native_y);
+            // Keep sub-pixel precision when rendering above native resolution.
+            let screen_p = Coords {
+                x: (screen_x * self.render_scale).round().to_fixed(),
+                y: (screen_y * self.render_scale).round().to_fixed(),
+            };
             one_dot_w = std::cmp::min(vertex.position.w(), one_dot_w);
 
             // TODO: re-use vertices...
@@ -115,31 +130,36 @@ impl ClippingUnit {
                     println!("Input: {:X}, {:X}, {:X}, {:X}", vertex.position.x(), vertex.position.y(), vertex.position.z(), vertex.position.w());
                     println!("Depth: {:X}", depth);
                 }
-                let out_vertex = Vertex {
+                Vertex {
                     screen_p,
                     depth: depth,
                     colour: vertex.colour,
                     tex_coords: vertex.tex_coords
-                };
-                let idx = self.polygon_ram.insert_vertex(out_vertex.clone());
-                //vertex.idx = Some(idx);
-                //idx
+                }
             //};
-            staged_polygon.add_vertex_index(idx);
-            out_vertex
         }).collect::<Vec<_>>();
 
-        if test_winding(&staged_polygon, &vertices_out)/* && self.test_one_dot_display(&staged_polygon.attrs, &vertices_out, one_dot_w.to_fixed())*/ {
-            self.polygon_ram.insert_polygon(staged_polygon, max_y, min_y);
+        if !test_winding(&staged_polygon, &vertices_out)/* || !self.test_one_dot_display(&staged_polygon.attrs, &vertices_out, one_dot_w.to_fixed())*/ {
+            return true;
+        }
+        if !self.polygon_ram.reserve(vertices_out.len()) {
+            return false;
         }
+        for out_vertex in vertices_out {
+            let idx = self.polygon_ram.insert_vertex(out_vertex);
+            staged_polygon.add_vertex_index(idx);
+        }
+        self.polygon_ram.insert_polygon(staged_polygon, max_y, min_y);
+        true
     }
     
-    pub fn get_screen_coords(&self, x: N, y: N) -> Coords {
+    /// Transform normalised coords into the viewport, in native screen space.
+    pub fn get_screen_coords(&self, x: N, y: N) -> (N, N) {
         let clamped_x = x.clamp(N::ZERO, N::ONE);
         let clamped_y = y.clamp(N::ZERO, N::ONE);
         let screen_x = self.viewport_x + (clamped_x * self.viewport_width);
         let screen_y = self.viewport_y + (clamped_y * self.viewport_height);
-        Coords { x: screen_x.round().to_fixed(), y: screen_y.round().to_fixed() }
+        (screen_x, screen_y)
     }
 
     /// Returns true if clip occurred.
@@ -219,12 +239,13 @@ impl ClippingUnit {
 
     fn _test_one_dot_display(&self, attrs: &PolygonAttrs, vertices: &[Vertex], dot_w: Depth) -> bool {
         if !attrs.contains(PolygonAttrs::RENDER_DOT) {
+            let scale = self.render_scale.to_num::<i16>();
             let v0 = &vertices[0];
-            let screen_x = v0.screen_p.x.to_num::<i16>();
-            let screen_y = v0.screen_p.y.to_num::<i16>();
+            let screen_x = v0.screen_p.x.to_num::<i16>() / scale;
+            let screen_y = v0.screen_p.y.to_num::<i16>() / scale;
             for vn in vertices.iter().skip(1) {
-                if vn.screen_p.x.to_num::<i16>() != screen_x ||
-                    vn.screen_p.y.to_num::<i16>() != screen_y {
+                if vn.screen_p.x.to_num::<i16>() / scale != screen_x ||
+                    vn.screen_p.y.to_num::<i16>() / scale != screen_y {
                     // Not a one-dot polygon.
                     return true;
                 }
@@ -281,4 +302,70 @@ fn test_winding(polygon: &Polygon, vertices: &[Vertex]) -> bool {
         // Always display line polygons.
         true
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> Vec<StagedVertex> {
+        [(-0.5, -0.5), (0.5, -0.5), (0.0, 0.5)].iter().map(|&(x, y)| StagedVertex {
+            position:   Vector::new([N::from_num(x), N::from_num(y), N::ZERO, N::ONE]),
+            ..Default::default()
+        }).collect()
+    }
+
+    fn polygon(attrs: PolygonAttrs) -> Polygon {
+        Polygon {
+            attrs:          attrs | PolygonAttrs::from_bits_truncate(31 << 16),
+            tex:            TextureAttrs::default(),
+            palette:        0,
+            num_vertices:   0,
+            vertex_indices: [0; 8],
+        }
+    }
+
+    #[test]
+    fn culled_polygons_use_no_ram() {
+        let mut clipping_unit = ClippingUnit::new(1);
+        clipping_unit.set_viewport(0xBFFF_0000);
+
+        assert!(clipping_unit.add_polygon(polygon(PolygonAttrs::RENDER_BACK), &mut triangle(), false));
+        assert_eq!(clipping_unit.polygon_ram.count(), 0);
+
+        assert!(clipping_unit.add_polygon(polygon(PolygonAttrs::RENDER_FRONT), &mut triangle(), false));
+        assert_eq!(clipping_unit.polygon_ram.count(), (3 << 16) | 1);
+        let vertex = &clipping_unit.polygon_ram.vertices[2];
+        assert_eq!((vertex.screen_p.x.to_num::<i32>(), vertex.screen_p.y.to_num::<i32>()), (128, 48));
+
+        // Fill the vertex RAM.
+        while clipping_unit.polygon_ram.vertices.len() < PolygonRAM::MAX_VERTICES - 2 {
+            clipping_unit.polygon_ram.insert_vertex(Vertex::default());
+        }
+        assert!(!clipping_unit.add_polygon(polygon(PolygonAttrs::RENDER_FRONT), &mut triangle(), false));
+        assert!(clipping_unit.add_polygon(polygon(PolygonAttrs::RENDER_BACK), &mut triangle(), false));
+        assert_eq!(clipping_unit.polygon_ram.count() & 0xFFFF, 1);
+    }
+
+    #[test]
+    fn render_scale() {
+        let vertices = || [(-0.5, -0.5), (0.3, -0.5), (0.0, 0.5)].iter().map(|&(x, y)| StagedVertex {
+            position:   Vector::new([N::from_num(x), N::from_num(y), N::ZERO, N::ONE]),
+            ..Default::default()
+        }).collect::<Vec<_>>();
+        let screen_p = |scale: usize| {
+            let mut clipping_unit = ClippingUnit::new(scale);
+            clipping_unit.set_viewport(0xBFFF_0000);
+            assert!(clipping_unit.add_polygon(polygon(PolygonAttrs::RENDER_FRONT), &mut vertices(), false));
+            let order = clipping_unit.polygon_ram.opaque_polygons.iter().next().unwrap();
+            assert_eq!((order.y_min.to_num::<i32>(), order.y_max.to_num::<i32>()), (48, 144));
+            clipping_unit.polygon_ram.vertices.iter()
+                .map(|v| (v.screen_p.x.to_num::<i32>(), v.screen_p.y.to_num::<i32>()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(screen_p(1), vec![(64, 144), (166, 144), (128, 48)]);
+        // x = 166.4 isn't snapped to the native grid before scaling.
+        assert_eq!(screen_p(2), vec![(128, 288), (333, 288), (256, 96)]);
+    }
+}
@@ -50,9 +50,13 @@ impl LightingUnit {
         self.vertex_colour
     }
 
+    /// Number of lights enabled for the current polygon.
+    pub fn enabled_lights(&self) -> usize {
+        self.lights.iter().filter(|light| light.enabled).count()
+    }
+
     /// Calculate colour.
-    pub fn set_normal(&mut self, normal: Vector<3>) -> isize {
-        let mut cycles = 8;
+    pub fn set_normal(&mut self, normal: Vector<3>) {
         self.vertex_colour = self.emission_colour;
 
         for light in &self.lights {
@@ -79,11 +83,7 @@ impl LightingUnit {
             self.vertex_colour.add(&diffuse_colour);
             self.vertex_colour.add(&ambient_colour);
             self.vertex_colour.add(&specular_colour);
-
-            cycles += 1;
         }
-        
-        cycles
     }
 
     pub fn set_vertex_colour(&mut self, colour: u32) {
@@ -27,6 +27,8 @@ pub struct MatrixUnit {
     pos_dir_pointer:    usize,
 
     current_texture:    Matrix,
+    texture_stack:      Matrix,
+    tex_pointer:        usize,
 
     pub capture: bool,
 }
@@ -51,7 +53,12 @@ impl MatrixUnit {
     }
 
     pub fn pos_dir_stack_level(&self) -> u32 {
-        self.pos_dir_pointer as u32
+        (self.pos_dir_pointer & 0x1F) as u32
+    }
+
+    /// Check if position and direction matrices are both being modified.
+    pub fn is_pos_dir_mode(&self) -> bool {
+        self.mode == POS_DIR_MODE
     }
 
     // Get current matrices
@@ -71,43 +78,58 @@ impl MatrixUnit {
 
 // Commands
 impl MatrixUnit {
-    pub fn set_matrix_mode(&mut self, mode: u32) -> isize {
+    pub fn set_matrix_mode(&mut self, mode: u32) {
         self.old_mode = self.mode;
         self.mode = mode & 0b11;
-        1
     }
     
-    pub fn push_matrix(&mut self) -> isize {
+    /// Push the current matrix.
+    /// 
+    /// The projection and texture stacks have 1 entry, the position stack has 31.
+    /// Pushing beyond this flags an error.
+    pub fn push_matrix(&mut self) {
         match self.mode {
             PROJ_MODE => {
+                if self.proj_pointer >= 1 {
+                    self.stack_error = true;
+                }
                 self.projection_stack = self.current_projection.clone();
-                self.proj_pointer = 1;
+                self.proj_pointer = (self.proj_pointer + 1) & 1;
                 if self.capture {
                     println!("pushed proj {}", self.proj_pointer);
                 }
             },
             POS_MODE | POS_DIR_MODE => {
-                self.position_stack[self.pos_dir_pointer] = self.current_position.clone();
-                self.direction_stack[self.pos_dir_pointer] = self.current_direction.clone();
-                if self.pos_dir_pointer >= 30 {
+                if self.pos_dir_pointer >= 31 {
                     self.stack_error = true;
                 }
-                self.pos_dir_pointer = (self.pos_dir_pointer + 1) % 31;
+                let index = self.pos_dir_pointer & 0x1F;
+                self.position_stack[index] = self.current_position.clone();
+                self.direction_stack[index] = self.current_direction.clone();
+                self.pos_dir_pointer = (self.pos_dir_pointer + 1) & 0x3F;
                 if self.capture {
                     println!("pushed posdir {}", self.pos_dir_pointer);
                 }
             },
-            TEX_MODE => println!("cannot push texture matrix"),
+            TEX_MODE => {
+                if self.tex_pointer >= 1 {
+                    self.stack_error = true;
+                }
+                self.texture_stack = self.current_texture.clone();
+                self.tex_pointer = (self.tex_pointer + 1) & 1;
+            },
             _ => unreachable!()
         }
-        17
     }
     
-    pub fn pop_matrix(&mut self, pops: u32) -> isize {
+    pub fn pop_matrix(&mut self, pops: u32) {
         let signed_pops = u32::sign_extend(pops, 6);
         match self.mode {
             PROJ_MODE => {
-                self.proj_pointer = 0;
+                if self.proj_pointer == 0 {
+                    self.stack_error = true;
+                }
+                self.proj_pointer = self.proj_pointer.wrapping_sub(1) & 1;
                 self.current_projection = self.projection_stack.clone();
                 self.current_clip = self.current_position.mul(&self.current_projection);
                 if self.capture {
@@ -116,24 +138,30 @@ impl MatrixUnit {
             },
             POS_MODE | POS_DIR_MODE => {
                 let new_pointer = (self.pos_dir_pointer as isize) - (signed_pops as isize);
-                if new_pointer < 0 {
+                self.pos_dir_pointer = (new_pointer as usize) & 0x3F;
+                if self.pos_dir_pointer >= 31 {
                     self.stack_error = true;
                 }
-                self.pos_dir_pointer = (new_pointer as usize) % 31; // TODO: unsure
-                self.current_position = self.position_stack[self.pos_dir_pointer].clone();
-                self.current_direction = self.direction_stack[self.pos_dir_pointer].clone();
+                let index = self.pos_dir_pointer & 0x1F;
+                self.current_position = self.position_stack[index].clone();
+                self.current_direction = self.direction_stack[index].clone();
                 self.current_clip = self.current_position.mul(&self.current_projection);
                 if self.capture {
                     println!("pop posdir {} => {}", pops, new_pointer);
                 }
             },
-            TEX_MODE => println!("cannot pop texture matrix"),
+            TEX_MODE => {
+                if self.tex_pointer == 0 {
+                    self.stack_error = true;
+                }
+                self.tex_pointer = self.tex_pointer.wrapping_sub(1) & 1;
+                self.current_texture = self.texture_stack.clone();
+            },
             _ => unreachable!()
         }
-        36
     }
     
-    pub fn store_matrix(&mut self, pos: u32) -> isize {
+    pub fn store_matrix(&mut self, pos: u32) {
         match self.mode {
             PROJ_MODE => {
                 if self.capture {
@@ -145,19 +173,19 @@ impl MatrixUnit {
                 if self.capture {
                     println!("store POS {}", pos);
                 }
-                if pos > 30 {
+                let index = (pos & 0x1F) as usize;
+                if index == 31 {
                     self.stack_error = true;
                 }
-                self.position_stack[pos as usize] = self.current_position.clone();
-                self.direction_stack[pos as usize] = self.current_direction.clone();
+                self.position_stack[index] = self.current_position.clone();
+                self.direction_stack[index] = self.current_direction.clone();
             },
-            TEX_MODE => (),
+            TEX_MODE => self.texture_stack = self.current_texture.clone(),
             _ => unreachable!()
         }
-        17
     }
     
-    pub fn restore_matrix(&mut self, pos: u32) -> isize {
+    pub fn restore_matrix(&mut self, pos: u32) {
         match self.mode {
             PROJ_MODE => {
                 if self.capture {
@@ -170,20 +198,20 @@ impl MatrixUnit {
                 if self.capture {
                     println!("restore POS {}", pos);
                 }
-                if pos > 30 {
+                let index = (pos & 0x1F) as usize;
+                if index == 31 {
                     self.stack_error = true;
                 }
-                self.current_position = self.position_stack[pos as usize].clone();
-                self.current_direction = self.direction_stack[pos as usize].clone();
+                self.current_position = self.position_stack[index].clone();
+                self.current_direction = self.direction_stack[index].clone();
                 self.current_clip = self.current_position.mul(&self.current_projection);
             },
-            TEX_MODE => (),
+            TEX_MODE => self.current_texture = self.texture_stack.clone(),
             _ => unreachable!()
         }
-        36
     }
 
-    pub fn set_identity(&mut self) -> isize {
+    pub fn set_identity(&mut self) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection = Matrix::identity();
@@ -203,10 +231,9 @@ impl MatrixUnit {
             },
             _ => unreachable!()
         }
-        19
     }
     
-    pub fn set_4x4(&mut self, value: &[N]) -> isize {
+    pub fn set_4x4(&mut self, value: &[N]) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection = Matrix::from_4x4(value);
@@ -226,10 +253,9 @@ impl MatrixUnit {
             },
             _ => unreachable!()
         }
-        34
     }
     
-    pub fn set_4x3(&mut self, value: &[N]) -> isize {
+    pub fn set_4x3(&mut self, value: &[N]) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection = Matrix::from_4x3(value);
@@ -249,88 +275,75 @@ impl MatrixUnit {
             },
             _ => unreachable!()
         }
-        30
     }
 
-    pub fn mul_4x4(&mut self, value: &[N]) -> isize {
+    pub fn mul_4x4(&mut self, value: &[N]) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection.mul_4x4(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                35
             },
             POS_MODE => {
                 self.current_position.mul_4x4(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                35
             },
             POS_DIR_MODE => {
                 self.current_position.mul_4x4(value);
                 self.current_direction.mul_4x4(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                65
             },
             TEX_MODE => {
                 self.current_texture.mul_4x4(value);
-                35
             },
             _ => unreachable!()
         }
     }
     
-    pub fn mul_4x3(&mut self, value: &[N]) -> isize {
+    pub fn mul_4x3(&mut self, value: &[N]) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection.mul_4x3(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                31
             },
             POS_MODE => {
                 self.current_position.mul_4x3(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                31
             },
             POS_DIR_MODE => {
                 self.current_position.mul_4x3(value);
                 self.current_direction.mul_4x3(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                61
             },
             TEX_MODE => {
                 self.current_texture.mul_4x3(value);
-                31
             },
             _ => unreachable!()
         }
     }
 
-    pub fn mul_3x3(&mut self, value: &[N]) -> isize {
+    pub fn mul_3x3(&mut self, value: &[N]) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection.mul_3x3(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                28
             },
             POS_MODE => {
                 self.current_position.mul_3x3(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                28
             },
             POS_DIR_MODE => {
                 self.current_position.mul_3x3(value);
                 self.current_direction.mul_3x3(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                58
             },
             TEX_MODE => {
                 self.current_texture.mul_3x3(value);
-                28
             },
             _ => unreachable!()
         }
     }
 
-    pub fn mul_scale(&mut self, value: &[N]) -> isize {
+    pub fn mul_scale(&mut self, value: &[N]) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection.mul_scale(value);
@@ -343,32 +356,166 @@ impl MatrixUnit {
             TEX_MODE => self.current_texture.mul_scale(value),
             _ => unreachable!()
         }
-        22
     }
 
-    pub fn mul_trans(&mut self, value: &[N]) -> isize {
+    pub fn mul_trans(&mut self, value: &[N]) {
         match self.mode {
             PROJ_MODE => {
                 self.current_projection.mul_trans(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                22
             },
             POS_MODE => {
                 self.current_position.mul_trans(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                22
             },
             POS_DIR_MODE => {
                 self.current_position.mul_trans(value);
                 self.current_direction.mul_trans(value);
                 self.current_clip = self.current_position.mul(&self.current_projection);
-                52
             },
             TEX_MODE => {
                 self.current_texture.mul_trans(value);
-                22
             },
             _ => unreachable!()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An identity matrix with the top-left element set to `n`.
+    fn matrix(n: i32) -> Vec<N> {
+        let mut m = Matrix::identity();
+        m.elements[0] = N::from_num(n);
+        m.elements.to_vec()
+    }
+
+    #[test]
+    fn projection_stack() {
+        let mut matrices = MatrixUnit::new();
+        matrices.set_matrix_mode(PROJ_MODE);
+        matrices.set_4x4(&matrix(1));
+        matrices.push_matrix();
+        assert_eq!(matrices.proj_stack_level(), 1);
+        assert!(!matrices.has_stack_error());
+
+        // Overflow: the pointer wraps and the single entry is overwritten.
+        matrices.set_4x4(&matrix(2));
+        matrices.push_matrix();
+        assert_eq!(matrices.proj_stack_level(), 0);
+        assert!(matrices.has_stack_error());
+        matrices.clear_stack_error();
+
+        // Underflow: still reads the single entry.
+        matrices.set_4x4(&matrix(3));
+        matrices.pop_matrix(1);
+        assert_eq!(matrices.proj_stack_level(), 1);
+        assert!(matrices.has_stack_error());
+        assert_eq!(matrices.current_projection.elements[0], N::from_num(2));
+        matrices.clear_stack_error();
+
+        matrices.pop_matrix(1);
+        assert_eq!(matrices.proj_stack_level(), 0);
+        assert!(!matrices.has_stack_error());
+
+        // Store and restore ignore the parameter.
+        matrices.set_4x4(&matrix(4));
+        matrices.store_matrix(5);
+        matrices.set_4x4(&matrix(5));
+        matrices.restore_matrix(0);
+        assert_eq!(matrices.current_projection.elements[0], N::from_num(4));
+        assert!(!matrices.has_stack_error());
+    }
+
+    #[test]
+    fn position_stack() {
+        let mut matrices = MatrixUnit::new();
+        matrices.set_matrix_mode(POS_MODE);
+        for n in 0..31 {
+            matrices.set_4x4(&matrix(n));
+            matrices.push_matrix();
+        }
+        assert_eq!(matrices.pos_dir_stack_level(), 31);
+        assert!(!matrices.has_stack_error());
+
+        // Push to entry 31.
+        matrices.set_4x4(&matrix(31));
+        matrices.push_matrix();
+        assert_eq!(matrices.pos_dir_stack_level(), 0);
+        assert!(matrices.has_stack_error());
+        matrices.clear_stack_error();
+
+        // Pop back down within range.
+        matrices.pop_matrix(2);
+        assert_eq!(matrices.pos_dir_stack_level(), 30);
+        assert!(!matrices.has_stack_error());
+        assert_eq!(matrices.current_position.elements[0], N::from_num(30));
+
+        // Pop with a negative offset (-2), back beyond the top.
+        matrices.pop_matrix(0x3E);
+        assert_eq!(matrices.pos_dir_stack_level(), 0);
+        assert!(matrices.has_stack_error());
+        matrices.clear_stack_error();
+
+        matrices.pop_matrix(30);
+        assert_eq!(matrices.pos_dir_stack_level(), 2);
+        assert!(!matrices.has_stack_error());
+        assert_eq!(matrices.current_position.elements[0], N::from_num(2));
+
+        // Pop beneath the bottom of the stack wraps around.
+        matrices.pop_matrix(3);
+        assert_eq!(matrices.pos_dir_stack_level(), 31);
+        assert!(matrices.has_stack_error());
+    }
+
+    #[test]
+    fn position_store_restore() {
+        let mut matrices = MatrixUnit::new();
+        matrices.set_matrix_mode(POS_DIR_MODE);
+        matrices.set_4x4(&matrix(7));
+        matrices.store_matrix(30);
+        assert!(!matrices.has_stack_error());
+        matrices.set_4x4(&matrix(8));
+        // The index wraps at 32.
+        matrices.restore_matrix(32 + 30);
+        assert!(!matrices.has_stack_error());
+        assert_eq!(matrices.current_position.elements[0], N::from_num(7));
+        assert_eq!(matrices.dir_matrix().elements[0], N::from_num(7));
+
+        matrices.store_matrix(31);
+        assert!(matrices.has_stack_error());
+        matrices.clear_stack_error();
+        matrices.restore_matrix(31);
+        assert!(matrices.has_stack_error());
+        assert_eq!(matrices.pos_dir_stack_level(), 0);
+    }
+
+    #[test]
+    fn texture_stack() {
+        let mut matrices = MatrixUnit::new();
+        matrices.set_matrix_mode(TEX_MODE);
+        matrices.set_4x4(&matrix(1));
+        matrices.push_matrix();
+        assert!(!matrices.has_stack_error());
+        matrices.push_matrix();
+        assert!(matrices.has_stack_error());
+        matrices.clear_stack_error();
+
+        matrices.set_4x4(&matrix(2));
+        matrices.pop_matrix(1);
+        assert!(matrices.has_stack_error());
+        assert_eq!(matrices.tex_matrix().elements[0], N::from_num(1));
+        matrices.clear_stack_error();
+        matrices.pop_matrix(1);
+        assert!(!matrices.has_stack_error());
+
+        matrices.set_4x4(&matrix(3));
+        matrices.store_matrix(0);
+        matrices.set_4x4(&matrix(4));
+        matrices.restore_matrix(0);
+        assert_eq!(matrices.tex_matrix().elements[0], N::from_num(3));
+        assert!(!matrices.has_stack_error());
+    }
+}
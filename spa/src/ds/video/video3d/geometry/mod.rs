@@ -48,6 +48,8 @@ pub struct GeometryEngine {
     pub pos_test_res:   [u32; 4],
     /// Result of the direction (vector) test.
     pub dir_test_res:   [u16; 3],
+    /// Set when a polygon is dropped because the list RAM is full.
+    pub list_ram_overflow:  bool,
 
     /// Current polygon attributes.
     polygon_attrs:  PolygonAttrs,
@@ -74,9 +76,10 @@ pub struct GeometryEngine {
 }
 
 impl GeometryEngine {
-    pub fn new() -> Self {
+    /// Screen coords are output at `render_scale` times the native resolution.
+    pub fn new(render_scale: usize) -> Self {
         Self {
-            clipping_unit:  ClippingUnit::new(),
+            clipping_unit:  ClippingUnit::new(render_scale),
 
             matrices:       Box::new(MatrixUnit::new()),
             lighting:       Box::new(LightingUnit::new()),
@@ -84,6 +87,7 @@ impl GeometryEngine {
             box_test_res:   false,
             pos_test_res:   [0; 4],
             dir_test_res:   [0; 3],
+            list_ram_overflow:  false,
 
             polygon_attrs:  PolygonAttrs::default(),
             texture_attrs:  TextureAttrs::default(),
@@ -105,13 +109,16 @@ impl GeometryEngine {
     pub fn set_dot_polygon_depth(&mut self, data: u16) {
         self.clipping_unit.set_dot_polygon_depth(data);
     }
+
+    pub fn enabled_lights(&self) -> usize {
+        self.lighting.enabled_lights()
+    }
 }
 
 // GPU commands
 impl GeometryEngine {
-    pub fn set_viewport(&mut self, data: u32) -> isize {
+    pub fn set_viewport(&mut self, data: u32) {
         self.clipping_unit.set_viewport(data);
-        1
     }
 
     /// Set values for next frame.
@@ -120,12 +127,11 @@ impl GeometryEngine {
         self.clipping_unit.set_w_buffer(u32::test_bit(data, 1));
     }
 
-    pub fn set_vertex_colour(&mut self, data: u32) -> isize {
+    pub fn set_vertex_colour(&mut self, data: u32) {
         self.lighting.set_vertex_colour(data);
-        1
     }
 
-    pub fn set_normal(&mut self, data: u32) -> isize {
+    pub fn set_normal(&mut self, data: u32) {
         let x_bits = (data & 0x3FF) as u16;
         let y_bits = ((data >> 10) & 0x3FF) as u16;
         let z_bits = ((data >> 20) & 0x3FF) as u16;
@@ -134,7 +140,7 @@ impl GeometryEngine {
             N::from_bits(bits::u16::sign_extend(y_bits << 3, 13).into()),
             N::from_bits(bits::u16::sign_extend(z_bits << 3, 13).into()),
         ]);
-        let tex_cycles = if self.texture_attrs.transform_mode() == 2 {
+        if self.texture_attrs.transform_mode() == 2 {
             let s = N::from_num(self.tex_coords.s.to_bits() as i32);
             let t = N::from_num(self.tex_coords.t.to_bits() as i32);
             let m = self.matrices.tex_matrix();
@@ -142,33 +148,27 @@ impl GeometryEngine {
             let t0 = normal.x() * m.elements[1] + normal.y() * m.elements[5] + normal.z() * m.elements[9] + t;
             self.trans_tex_coords.s = I12F4::from_bits(s0.to_num::<i16>());
             self.trans_tex_coords.t = I12F4::from_bits(t0.to_num::<i16>());
-            2
-        } else {
-            0
-        };
+        }
         let normal = self.matrices.dir_matrix().mul_vector_3(&normal);
         // Calculate colour.
-        self.lighting.set_normal(normal) + tex_cycles
+        self.lighting.set_normal(normal);
     }
 
-    pub fn set_dif_amb_colour(&mut self, data: u32) -> isize {
+    pub fn set_dif_amb_colour(&mut self, data: u32) {
         self.lighting.set_dif_amb_colour(data);
-        4
     }
     
-    pub fn set_spe_emi_colour(&mut self, data: u32) -> isize {
+    pub fn set_spe_emi_colour(&mut self, data: u32) {
         self.lighting.set_spe_emi_colour(data);
-        4
     }
     
-    pub fn set_specular_table(&mut self, data: impl Iterator<Item = u32>) -> isize {
+    pub fn set_specular_table(&mut self, data: impl Iterator<Item = u32>) {
         for d in data {
             self.lighting.set_specular_table(d);
         }
-        32
     }
     
-    pub fn set_light_direction(&mut self, data: u32) -> isize {
+    pub fn set_light_direction(&mut self, data: u32) {
         let x_bits = (data & 0x3FF) as u16;
         let y_bits = ((data >> 10) & 0x3FF) as u16;
         let z_bits = ((data >> 20) & 0x3FF) as u16;
@@ -180,32 +180,25 @@ impl GeometryEngine {
         let direction = self.matrices.dir_matrix().mul_vector_3(&v);
         let light = (data >> 30) as usize;
         self.lighting.set_light_direction(light, direction);
-        6
     }
 
-    pub fn set_light_colour(&mut self, data: u32) -> isize {
+    pub fn set_light_colour(&mut self, data: u32) {
         self.lighting.set_light_colour(data);
-        1
     }
 
-    pub fn set_tex_attrs(&mut self, data: u32) -> isize {
+    pub fn set_tex_attrs(&mut self, data: u32) {
         self.texture_attrs = TextureAttrs::from_bits_truncate(data);
-        1
     }
     
-    pub fn set_tex_palette(&mut self, data: u32) -> isize {
+    pub fn set_tex_palette(&mut self, data: u32) {
         self.tex_palette = (data & 0x1FFF) as u16;
-        1
     }
     
-    pub fn set_tex_coords(&mut self, data: u32) -> isize {
+    pub fn set_tex_coords(&mut self, data: u32) {
         self.tex_coords.s = I12F4::from_bits(bytes::u32::lo(data) as i16);
         self.tex_coords.t = I12F4::from_bits(bytes::u32::hi(data) as i16);
         match self.texture_attrs.transform_mode() {
-            0 => {
-                self.trans_tex_coords = self.tex_coords.clone();
-                1
-            },
+            0 => self.trans_tex_coords = self.tex_coords.clone(),
             1 => {
                 let s = N::from_num(self.tex_coords.s.to_bits() as i32);
                 let t = N::from_num(self.tex_coords.t.to_bits() as i32);
@@ -214,16 +207,15 @@ impl GeometryEngine {
                 let t0 = s * m.elements[1] + t * m.elements[5] + m.elements[9] + m.elements[13];
                 self.trans_tex_coords.s = I12F4::from_bits(s0.to_num::<i16>());
                 self.trans_tex_coords.t = I12F4::from_bits(t0.to_num::<i16>());
-                2
             },
-            _ => 1,    // Transformed later.
+            _ => (),    // Transformed later.
         }
     }
 
     /// Called before vertex data is input.
     /// 
     /// Also decides which primitive type to use.
-    pub fn begin_vertex_list(&mut self, data: u32) -> isize {
+    pub fn begin_vertex_list(&mut self, data: u32) {
         let primitive = match data & 0b11 {
             0b00 => {
                 self.stage_size = 3;
@@ -248,12 +240,10 @@ impl GeometryEngine {
             _ => unreachable!()
         };
         self.primitive = Some(primitive);
-        1
     }
 
-    pub fn end_vertex_list(&mut self) -> isize {
+    pub fn end_vertex_list(&mut self) {
         self.primitive = None;
-        1
     }
 
     /// Set vertex coordinates. Uses 2 parameter words. I4F12 format.
@@ -261,73 +251,72 @@ impl GeometryEngine {
     /// First param: X in lower half, Y in upper half.
     /// 
     /// Second param: Z in lower half.
-    pub fn set_vertex_coords_16(&mut self, lo: u32, hi: u32) -> isize {
+    pub fn set_vertex_coords_16(&mut self, lo: u32, hi: u32) {
         self.current_vertex[0] = I4F12::from_bits(bytes::u32::lo(lo) as i16);
         self.current_vertex[1] = I4F12::from_bits(bytes::u32::hi(lo) as i16);
         self.current_vertex[2] = I4F12::from_bits(bytes::u32::lo(hi) as i16);
-        self.process_vertex() + 1
+        self.process_vertex();
     }
     
     /// Set vertex coordinates. I4F6 format.
     /// 
     /// Param: X, Y, Z, each 10 bits.
-    pub fn set_vertex_coords_10(&mut self, data: u32) -> isize {
+    pub fn set_vertex_coords_10(&mut self, data: u32) {
         let x = (data & 0x3FF) << 6;
         let y = ((data >> 10) & 0x3FF) << 6;
         let z = ((data >> 20) & 0x3FF) << 6;
         self.current_vertex[0] = I4F12::from_bits(x as i16);
         self.current_vertex[1] = I4F12::from_bits(y as i16);
         self.current_vertex[2] = I4F12::from_bits(z as i16);
-        self.process_vertex()
+        self.process_vertex();
     }
     
     /// Set vertex coordinates X and Y. I4F12 format. Keep old Z.
     /// 
     /// Param: X in lower half, Y in upper half.
-    pub fn set_vertex_coords_xy(&mut self, data: u32) -> isize {
+    pub fn set_vertex_coords_xy(&mut self, data: u32) {
         self.current_vertex[0] = I4F12::from_bits(bytes::u32::lo(data) as i16);
         self.current_vertex[1] = I4F12::from_bits(bytes::u32::hi(data) as i16);
-        self.process_vertex()
+        self.process_vertex();
     }
     
     /// Set vertex coordinates X and Z. I4F12 format. Keep old Y.
     /// 
     /// Param: X in lower half, Z in upper half.
-    pub fn set_vertex_coords_xz(&mut self, data: u32) -> isize {
+    pub fn set_vertex_coords_xz(&mut self, data: u32) {
         self.current_vertex[0] = I4F12::from_bits(bytes::u32::lo(data) as i16);
         self.current_vertex[2] = I4F12::from_bits(bytes::u32::hi(data) as i16);
-        self.process_vertex()
+        self.process_vertex();
     }
     
     /// Set vertex coordinates Y and Z. I4F12 format. Keep old X.
     /// 
     /// Param: Y in lower half, Z in upper half.
-    pub fn set_vertex_coords_yz(&mut self, data: u32) -> isize {
+    pub fn set_vertex_coords_yz(&mut self, data: u32) {
         self.current_vertex[1] = I4F12::from_bits(bytes::u32::lo(data) as i16);
         self.current_vertex[2] = I4F12::from_bits(bytes::u32::hi(data) as i16);
-        self.process_vertex()
+        self.process_vertex();
     }
     
     /// Set vertex coordinates as a diff of current. F10 format.
     /// 
     /// Param: X, Y, Z, each 10 bits.
-    pub fn diff_vertex_coords(&mut self, data: u32) -> isize {
+    pub fn diff_vertex_coords(&mut self, data: u32) {
         let x_diff = data & 0x3FF;
         let y_diff = (data >> 10) & 0x3FF;
         let z_diff = (data >> 20) & 0x3FF;
         self.current_vertex[0] += I4F12::from_bits(bits::u16::sign_extend(x_diff as u16, 10));
         self.current_vertex[1] += I4F12::from_bits(bits::u16::sign_extend(y_diff as u16, 10));
         self.current_vertex[2] += I4F12::from_bits(bits::u16::sign_extend(z_diff as u16, 10));
-        self.process_vertex()
+        self.process_vertex();
     }
 
-    pub fn set_polygon_attrs(&mut self, data: u32) -> isize {
+    pub fn set_polygon_attrs(&mut self, data: u32) {
         self.polygon_attrs = PolygonAttrs::from_bits_truncate(data);
         self.lighting.set_enabled(self.polygon_attrs);
-        1
     }
 
-    pub fn box_test(&mut self, args: &[u32]) -> isize {
+    pub fn box_test(&mut self, args: &[u32]) {
         //println!("BOX TEST!");
         use crate::utils::bits::u8;
 
@@ -388,10 +377,9 @@ impl GeometryEngine {
         //self.box_test_res = true; // !fail_test;
         self.box_test_res = true;
 
-        103
     }
 
-    pub fn position_test(&mut self, args: &[u32]) -> isize {
+    pub fn position_test(&mut self, args: &[u32]) {
         self.current_vertex[0] = I4F12::from_bits(bytes::u32::lo(args[0]) as i16);
         self.current_vertex[1] = I4F12::from_bits(bytes::u32::hi(args[0]) as i16);
         self.current_vertex[2] = I4F12::from_bits(bytes::u32::lo(args[1]) as i16);
@@ -408,10 +396,9 @@ impl GeometryEngine {
         self.pos_test_res[2] = transformed_vertex.z().to_bits() as u32;
         self.pos_test_res[3] = transformed_vertex.w().to_bits() as u32;
 
-        9
     }
 
-    pub fn direction_test(&mut self, data: u32) -> isize {
+    pub fn direction_test(&mut self, data: u32) {
         let x_bits = (data & 0x3FF) as u16;
         let y_bits = ((data >> 10) & 0x3FF) as u16;
         let z_bits = ((data >> 20) & 0x3FF) as u16;
@@ -426,13 +413,12 @@ impl GeometryEngine {
         self.dir_test_res[1] = direction.y().to_bits() as u16;
         self.dir_test_res[2] = direction.z().to_bits() as u16;
 
-        5
     }
 }
 
 // Internal processing
 impl GeometryEngine {
-    fn process_vertex(&mut self) -> isize {
+    fn process_vertex(&mut self) {
         let vertex = Vector::new([
             self.current_vertex[0].to_fixed::<N>(),
             self.current_vertex[1].to_fixed::<N>(),
@@ -470,7 +456,6 @@ impl GeometryEngine {
         };
         self.output_vertex();
 
-        8
     }
 
     /// Advance the staging state machine and possibly output a polygon.
@@ -575,6 +560,8 @@ impl GeometryEngine {
                 println!("  ({:X}, {:X}, {:X}, {:X}) | ({:X}, {:X})", v.position.x(), v.position.y(), v.position.z(), v.position.w(), v.tex_coords.s, v.tex_coords.t);
             }
         }
-        self.clipping_unit.add_polygon(output_polygon, &mut out_vertices, self.capture);
+        if !self.clipping_unit.add_polygon(output_polygon, &mut out_vertices, self.capture) {
+            self.list_ram_overflow = true;
+        }
     }
 }
@@ -17,7 +17,7 @@ use crate::{utils::{
 use commandfifo::GeomCommandFifo;
 use geometry::{GeometryEngine, N};
 pub use render::RenderingEngine;
-pub use drawing::Software3DRenderer;
+pub use drawing::{Software3DRenderer, TextureConfig};
 
 bitflags! {
     #[derive(Default)]
@@ -40,10 +40,14 @@ bitflags! {
 pub struct Video3D {
     geom_command_fifo:      GeomCommandFifo,
     current_commands:       u32,
+    last_command:           u8,
     pending_swap:           Option<u32>,
 
     geometry_engine:        GeometryEngine,
+    /// Geometry engine cycles. Negative while a command is executing.
     cycle_count:            isize,
+    /// Leftover ARM9 bus cycles.
+    bus_cycles:             usize,
 
     pub rendering_engine:   Arc<Mutex<RenderingEngine>>,
 
@@ -51,14 +55,17 @@ pub struct Video3D {
 }
 
 impl Video3D {
-    pub fn new() -> Self {
+    /// Polygons are output for rendering at `render_scale` times the native resolution.
+    pub fn new(render_scale: usize) -> Self {
         Self {
             geom_command_fifo:      GeomCommandFifo::new(),
             current_commands:       0,
+            last_command:           0,
             pending_swap:           None,
 
-            geometry_engine:        GeometryEngine::new(),
+            geometry_engine:        GeometryEngine::new(render_scale),
             cycle_count:            0,
+            bus_cycles:             0,
 
             rendering_engine:   Arc::new(Mutex::new(RenderingEngine::new())),
 
@@ -79,7 +86,10 @@ impl Video3D {
             }
         }
         if self.cycle_count < 0 {   // TODO: always add when writing to command buffer (?)
-            self.cycle_count += cycles as isize;
+            // The geometry engine runs at half the speed of the ARM9 bus.
+            self.bus_cycles += cycles;
+            self.cycle_count += (self.bus_cycles >> 1) as isize;
+            self.bus_cycles &= 1;
         }
 
         let irq = if self.geom_command_fifo.interrupt() {
@@ -90,10 +100,16 @@ impl Video3D {
         (irq, self.geom_command_fifo.under_half_full())
     }
 
+    /// Check if the command FIFO and PIPE are full.
+    pub fn geom_fifo_full(&self) -> bool {
+        self.geom_command_fifo.is_full()
+    }
+
     pub fn on_vblank(&mut self) {
         if let Some(swap_data) = self.pending_swap {
             self.pending_swap = None;
-            self.cycle_count -= self.swap_buffers(swap_data);
+            self.swap_buffers(swap_data);
+            self.cycle_count -= self.command_cycles(0x50);
         }
     }
 }
@@ -103,12 +119,12 @@ impl MemInterface32 for Video3D {
         match addr {
             0x0400_0060 => self.rendering_engine.lock().control.bits().into(),
 
-            0x0400_0320 => 46,   // TODO: Rendered line count
+            0x0400_0320 => self.rendering_engine.lock().rendered_lines.into(),
 
             0x0400_04A4 => 0,   // ?? Read by Super mario 64
 
             0x0400_0600 => self.get_geom_engine_status().bits(),
-            0x0400_0604 => 100,   // TODO: POLY+VTX COUNT
+            0x0400_0604 => self.geometry_engine.clipping_unit.polygon_ram.count(),
             0x0400_0620..=0x0400_062F => self.geometry_engine.pos_test_res[((addr / 4) % 4) as usize],
             0x0400_0630 => bytes::u32::make(
                 self.geometry_engine.dir_test_res[1],
@@ -229,18 +245,21 @@ impl MemInterface32 for Video3D {
 }
 
 impl Video3D {
-    fn swap_buffers(&mut self, data: u32) -> isize {
+    fn swap_buffers(&mut self, data: u32) {
         self.debug_capture = crate::ds::DEBUG_TRIGGER.swap(false, std::sync::atomic::Ordering::Relaxed);
         self.geometry_engine.capture = self.debug_capture;
         self.geometry_engine.matrices.capture = self.debug_capture;
-        std::mem::swap(
-            &mut self.geometry_engine.clipping_unit.polygon_ram,
-            &mut self.rendering_engine.lock().polygon_ram
-        );
+        {
+            let mut rendering_engine = self.rendering_engine.lock();
+            std::mem::swap(
+                &mut self.geometry_engine.clipping_unit.polygon_ram,
+                &mut rendering_engine.polygon_ram
+            );
+            rendering_engine.check_render_limits();
+        }
         let manual_sort = u32::test_bit(data, 0);
         self.geometry_engine.clipping_unit.polygon_ram.clear(manual_sort);
         self.geometry_engine.swap_buffers(data);
-        392
     }
 
     /// Do a single command, returning the number of cycles used in the process.
@@ -275,8 +294,8 @@ impl Video3D {
             }
         }
 
-        let cycles = match command {
-            0x00 => Some(0),  // NOP
+        let executed = match command {
+            0x00 => Some(()),  // NOP
 
             0x10 => self.geom_command_fifo.pop().map(|d| self.geometry_engine.matrices.set_matrix_mode(d)),
             0x11 => Some(self.geometry_engine.matrices.push_matrix()),
@@ -330,36 +349,93 @@ impl Video3D {
             0x71 => self.geom_command_fifo.pop_n(2).map(|d| self.geometry_engine.position_test(&d.collect::<Vec<_>>())),
             0x72 => self.geom_command_fifo.pop().map(|d| self.geometry_engine.direction_test(d)),
 
-            _ => Some(0), // Undefined
+            _ => Some(()), // Undefined
         };
 
+        let cycles = executed.map(|_| self.command_cycles(command));
         if cycles.is_some() {
             self.current_commands >>= 8;
+            self.last_command = command;
+        }
+
+        if std::mem::take(&mut self.geometry_engine.list_ram_overflow) {
+            self.rendering_engine.lock().control.insert(types::Display3DControl::LIST_RAM_OVERFLOW);
         }
 
         cycles
     }
 
+    /// Number of geometry engine cycles taken by a command.
+    /// 
+    /// Matrix multiplies and translations take 30 more cycles in position & vector mode,
+    /// and NORMAL takes 1 more cycle for each enabled light after the first.
+    fn command_cycles(&self, command: u8) -> isize {
+        let pos_dir_cycles = if self.geometry_engine.matrices.is_pos_dir_mode() {30} else {0};
+        match command {
+            0x10 => 1,      // MTX_MODE
+            0x11 => 17,     // MTX_PUSH
+            0x12 => 36,     // MTX_POP
+            0x13 => 17,     // MTX_STORE
+            0x14 => 36,     // MTX_RESTORE
+            0x15 => 19,     // MTX_IDENTITY
+            0x16 => 34,     // MTX_LOAD_4x4
+            0x17 => 30,     // MTX_LOAD_4x3
+            0x18 => 35 + pos_dir_cycles,    // MTX_MULT_4x4
+            0x19 => 31 + pos_dir_cycles,    // MTX_MULT_4x3
+            0x1A => 28 + pos_dir_cycles,    // MTX_MULT_3x3
+            0x1B => 22,     // MTX_SCALE
+            0x1C => 22 + pos_dir_cycles,    // MTX_TRANS
+
+            0x20 => 1,      // COLOR
+            0x21 => 9 + (self.geometry_engine.enabled_lights().saturating_sub(1) as isize), // NORMAL
+            0x22 => 1,      // TEXCOORD
+            0x23 => 9,      // VTX_16
+            0x24..=0x28 => 8,   // VTX_10, VTX_XY, VTX_XZ, VTX_YZ, VTX_DIFF
+            0x29 => 1,      // POLYGON_ATTR
+            0x2A => 1,      // TEXIMAGE_PARAM
+            0x2B => 1,      // PLTT_BASE
+
+            0x30 => 4,      // DIF_AMB
+            0x31 => 4,      // SPE_EMI
+            0x32 => 6,      // LIGHT_VECTOR
+            0x33 => 1,      // LIGHT_COLOR
+            0x34 => 32,     // SHININESS
+
+            0x40 => 1,      // BEGIN_VTXS
+            0x41 => 1,      // END_VTXS
+
+            0x50 => 392,    // SWAP_BUFFERS
+            0x60 => 1,      // VIEWPORT
+
+            0x70 => 103,    // BOX_TEST
+            0x71 => 9,      // POS_TEST
+            0x72 => 5,      // VEC_TEST
+
+            _ => 0,         // NOP and undefined
+        }
+    }
+
     fn is_busy(&self) -> bool {
         !self.geom_command_fifo.is_empty() || self.current_commands != 0 || self.cycle_count < 0 || self.pending_swap.is_some()
     }
 
     fn get_geom_engine_status(&self) -> GeometryEngineStatus {
-        let cmd_buffer_count = self.geom_command_fifo.len();
+        let cmd_buffer_count = self.geom_command_fifo.fifo_len();
         let proj_stack_level = self.geometry_engine.matrices.proj_stack_level();
         let pos_dir_stack_level = self.geometry_engine.matrices.pos_dir_stack_level();
 
         let mut status = GeometryEngineStatus::from_bits_truncate((cmd_buffer_count << 16) | (proj_stack_level << 13) | (pos_dir_stack_level << 8));
         status.set(GeometryEngineStatus::GEOM_BUSY, self.is_busy());
-        status.set(GeometryEngineStatus::CMD_FIFO_EMPTY, self.geom_command_fifo.is_empty());
+        status.set(GeometryEngineStatus::CMD_FIFO_EMPTY, cmd_buffer_count == 0);
         status.set(GeometryEngineStatus::CMD_FIFO_UNDER_HALF, self.geom_command_fifo.under_half_full());
         status.set(GeometryEngineStatus::CMD_FIFO_FULL, self.geom_command_fifo.is_full());
 
-        // TODO: mat stack busy?
+        let executing = self.cycle_count < 0;
+        status.set(GeometryEngineStatus::MAT_STACK_BUSY, executing && (self.last_command == 0x11 || self.last_command == 0x12));
         status.set(GeometryEngineStatus::MAT_STACK_ERROR, self.geometry_engine.matrices.has_stack_error());
 
         status.set(GeometryEngineStatus::TEST_BOX_RESULT, self.geometry_engine.box_test_res);
-        // TODO: test busy?
+        status.set(GeometryEngineStatus::TEST_BUSY, executing && (0x70..=0x72).contains(&self.last_command));
 
         status | self.geom_command_fifo.get_interrupt_cond()
     }
@@ -387,4 +463,60 @@ impl Video3D {
             .elements[index as usize]
             .to_bits() as u32
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock until all commands have finished, returning the number of ARM9 bus cycles taken.
+    fn run_commands(video: &mut Video3D) -> usize {
+        let mut cycles = 0;
+        loop {
+            video.clock(1);
+            cycles += 1;
+            if !video.is_busy() {
+                return cycles;
+            }
+        }
+    }
+
+    #[test]
+    fn matrix_command_cycles() {
+        let mut video = Video3D::new(1);
+        video.write_word(0x0400_0444, 0);
+        video.clock(1);
+        assert!(video.get_geom_engine_status().contains(GeometryEngineStatus::MAT_STACK_BUSY));
+        assert_eq!(run_commands(&mut video), 34 - 1);
+        assert!(!video.get_geom_engine_status().contains(GeometryEngineStatus::MAT_STACK_BUSY));
+
+        for (mode, cycles) in [(1, 35), (2, 65), (3, 35)] {
+            video.write_word(0x0400_0440, mode);
+            for _ in 0..16 {
+                video.write_word(0x0400_0460, 0);
+            }
+            assert_eq!(run_commands(&mut video), 2 * (1 + cycles));
+        }
+    }
+
+    #[test]
+    fn normal_cycles() {
+        let mut video = Video3D::new(1);
+        for (lights, cycles) in [(0b0000, 9), (0b0001, 9), (0b0011, 10), (0b0111, 11), (0b1111, 12)] {
+            video.write_word(0x0400_04A4, lights);
+            video.write_word(0x0400_0484, 0);
+            assert_eq!(run_commands(&mut video), 2 * (1 + cycles));
+        }
+    }
+
+    #[test]
+    fn test_busy() {
+        let mut video = Video3D::new(1);
+        for _ in 0..3 {
+            video.write_word(0x0400_05C0, 0);
+        }
+        video.clock(1);
+        assert!(video.get_geom_engine_status().contains(GeometryEngineStatus::TEST_BUSY));
+        assert_eq!(run_commands(&mut video), 206 - 1);
+        assert!(!video.get_geom_engine_status().contains(GeometryEngineStatus::TEST_BUSY));
+    }
+}
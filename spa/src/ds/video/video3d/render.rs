@@ -7,10 +7,20 @@ use crate::utils::{
 };
 use super::types::*;
 
+/// Rendering engine cycles (33MHz) per scanline.
+const LINE_CYCLES: usize = 355 * 6;
+/// Number of lines that can be buffered ahead of display.
+const LINE_BUFFER_SIZE: usize = 48;
+/// Approximate cost of rasterising one polygon on a line.
+const POLYGON_LINE_CYCLES: usize = 64;
+/// Fixed cost of each line.
+const LINE_SETUP_CYCLES: usize = 256;
+
 pub struct RenderingEngine {
     pub polygon_ram:    Box<PolygonRAM>,
 
     pub control:        Display3DControl,
+    pub rendered_lines: u8,
 
     pub clear_colour:   Colour,
     pub clear_alpha:    u8,
@@ -38,7 +48,8 @@ impl RenderingEngine {
             polygon_ram:    Box::new(PolygonRAM::new()),
 
             control:        Display3DControl::default(),
-            
+            rendered_lines: 46,
+
             clear_colour:   Colour::default(),
             clear_alpha:    0,
             clear_poly_id:  0,
@@ -70,6 +81,51 @@ impl RenderingEngine {
         }
         self.control = (self.control & Display3DControl::ERROR_BITS) | (control_in & Display3DControl::WRITEABLE_BITS);
     }
+
+    /// Called after new polygons have been swapped in.
+    /// 
+    /// Estimates how far ahead of the display the line buffer stays
+    /// while rendering the new polygons.
+    pub fn check_render_limits(&mut self) {
+        let mut line_cost = vec![LINE_SETUP_CYCLES; 192];
+        for p in self.polygon_ram.polygon_orders() {
+            // Polygons are drawn on lines y_min..y_max.
+            let top = p.y_min.to_num::<i32>().clamp(0, 192) as usize;
+            let bottom = p.y_max.to_num::<i32>().clamp(0, 192) as usize;
+            for cost in &mut line_cost[top..bottom] {
+                *cost += POLYGON_LINE_CYCLES;
+            }
+        }
+
+        // Rendering starts LINE_BUFFER_SIZE lines before the first line is displayed.
+        // A line can only start once there is space in the buffer.
+        let mut finish_times = Vec::with_capacity(192);
+        let mut time = 0;
+        for (y, cost) in line_cost.iter().enumerate() {
+            time = std::cmp::max(time, y * LINE_CYCLES) + cost;
+            finish_times.push(time);
+        }
+
+        let mut min_buffered = LINE_BUFFER_SIZE;
+        for y in 0..192 {
+            let display_time = (LINE_BUFFER_SIZE + y) * LINE_CYCLES;
+            let ready = finish_times.iter().take_while(|t| **t <= display_time).count();
+            if ready <= y {
+                min_buffered = 0;
+                break;
+            }
+            if ready == 192 {
+                // The buffer drains at the end of the frame once every line is rendered.
+                break;
+            }
+            min_buffered = std::cmp::min(min_buffered, ready - y);
+        }
+
+        if min_buffered == 0 {
+            self.control.insert(Display3DControl::LINE_UNDERFLOW);
+        }
+        self.rendered_lines = min_buffered.saturating_sub(2) as u8;
+    }
 }
 
 // GPU Commands
@@ -124,3 +180,63 @@ impl RenderingEngine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixed::types::I16F0;
+
+    /// Add `count` polygons covering lines `y_min..y_max`.
+    fn add_polygons(render_engine: &mut RenderingEngine, count: usize, y_min: i16, y_max: i16) {
+        for _ in 0..count {
+            let polygon = Polygon {
+                attrs:          PolygonAttrs::default(),
+                tex:            TextureAttrs::default(),
+                palette:        0,
+                num_vertices:   0,
+                vertex_indices: [0; 8],
+            };
+            render_engine.polygon_ram.insert_polygon(polygon, I16F0::from_num(y_max), I16F0::from_num(y_min));
+        }
+    }
+
+    #[test]
+    fn rendered_lines() {
+        // Nothing to draw: the buffer stays full.
+        let mut render_engine = RenderingEngine::new();
+        render_engine.check_render_limits();
+        assert_eq!(render_engine.rendered_lines, 46);
+        assert!(!render_engine.control.contains(Display3DControl::LINE_UNDERFLOW));
+
+        // Slow lines drain the buffer.
+        let mut busy_lines = 0;
+        for count in [20, 30, 35] {
+            let mut render_engine = RenderingEngine::new();
+            add_polygons(&mut render_engine, count, 0, 192);
+            render_engine.check_render_limits();
+            assert!(!render_engine.control.contains(Display3DControl::LINE_UNDERFLOW));
+            if busy_lines != 0 {
+                assert!(render_engine.rendered_lines < busy_lines);
+            }
+            busy_lines = render_engine.rendered_lines;
+        }
+        assert!(busy_lines > 0 && busy_lines < 46);
+
+        // Too slow to keep up.
+        let mut render_engine = RenderingEngine::new();
+        add_polygons(&mut render_engine, 40, 0, 192);
+        render_engine.check_render_limits();
+        assert_eq!(render_engine.rendered_lines, 0);
+        assert!(render_engine.control.contains(Display3DControl::LINE_UNDERFLOW));
+    }
+
+    #[test]
+    fn rendered_lines_span() {
+        // Polygons ending on a line don't cover it.
+        let mut render_engine = RenderingEngine::new();
+        add_polygons(&mut render_engine, 100, 0, 0);
+        add_polygons(&mut render_engine, 100, 192, 192);
+        render_engine.check_render_limits();
+        assert_eq!(render_engine.rendered_lines, 46);
+    }
+}
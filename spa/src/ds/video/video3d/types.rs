@@ -256,6 +256,11 @@ pub struct PolygonRAM {
 }
 
 impl PolygonRAM {
+    /// Polygon list RAM capacity.
+    pub const MAX_POLYGONS: usize = 2048;
+    /// Vertex list RAM capacity.
+    pub const MAX_VERTICES: usize = 6144;
+
     pub fn new() -> Self {
         Self {
             opaque_polygons:        BTreeSet::new(),
@@ -279,6 +284,26 @@ impl PolygonRAM {
         self.vertices.clear();
     }
 
+    /// Check if a polygon with `num_vertices` vertices can fit.
+    pub fn reserve(&self, num_vertices: usize) -> bool {
+        self.polygons.len() < Self::MAX_POLYGONS && self.vertices.len() + num_vertices <= Self::MAX_VERTICES
+    }
+
+    /// Number of polygons and vertices stored, as read from RAM_COUNT.
+    pub fn count(&self) -> u32 {
+        ((self.vertices.len() as u32) << 16) | (self.polygons.len() as u32)
+    }
+
+    /// Iterate over the screen-space spans of all polygons.
+    pub fn polygon_orders(&self) -> impl Iterator<Item = &PolygonOrder> {
+        let trans: Box<dyn Iterator<Item = &PolygonOrder> + '_> = if self.use_manual_mode {
+            Box::new(self.trans_polygon_manual.iter())
+        } else {
+            Box::new(self.trans_polygon_auto.iter())
+        };
+        self.opaque_polygons.iter().chain(trans)
+    }
+
     /// Insert a vertex.
     /// 
     /// Returns the index to the vertex.
@@ -307,3 +332,41 @@ impl PolygonRAM {
         self.polygons.push(polygon);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon() -> Polygon {
+        Polygon {
+            attrs:          PolygonAttrs::default(),
+            tex:            TextureAttrs::default(),
+            palette:        0,
+            num_vertices:   0,
+            vertex_indices: [0; 8],
+        }
+    }
+
+    #[test]
+    fn polygon_ram_limits() {
+        let mut polygon_ram = PolygonRAM::new();
+        assert!(polygon_ram.reserve(PolygonRAM::MAX_VERTICES));
+        assert!(!polygon_ram.reserve(PolygonRAM::MAX_VERTICES + 1));
+
+        for _ in 0..(PolygonRAM::MAX_VERTICES - 2) {
+            polygon_ram.insert_vertex(Vertex::default());
+        }
+        polygon_ram.insert_polygon(polygon(), I16F0::from_num(10), I16F0::ZERO);
+        assert_eq!(polygon_ram.count(), (((PolygonRAM::MAX_VERTICES - 2) as u32) << 16) | 1);
+        assert!(polygon_ram.reserve(2));
+        assert!(!polygon_ram.reserve(3));
+
+        polygon_ram.clear(false);
+        assert_eq!(polygon_ram.count(), 0);
+        for _ in 0..PolygonRAM::MAX_POLYGONS {
+            polygon_ram.insert_polygon(polygon(), I16F0::from_num(10), I16F0::ZERO);
+        }
+        assert_eq!(polygon_ram.count(), PolygonRAM::MAX_POLYGONS as u32);
+        assert!(!polygon_ram.reserve(1));
+    }
+}
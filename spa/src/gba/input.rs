@@ -1,5 +1,31 @@
+// Dealing with user input.
+
 use crate::common::peripheral::joypad::Buttons;
 
+#[derive(Clone)]
+pub struct UserInput {
+    pub buttons:    Buttons,
+
+    /// Light level for solar sensor. 0.0 is dark, 1.0 is bright.
+    pub light_level:    f64,
+    /// Rotation rate for gyro sensor. Between -1.0 and 1.0.
+    pub rotation:       f64,
+    /// Tilt (x, y) for tilt sensor. Each axis is between -1.0 and 1.0.
+    pub tilt:           (f64, f64),
+}
+
+impl Default for UserInput {
+    fn default() -> Self {
+        Self {
+            buttons:    Buttons::from_bits_truncate(0xFFFF),
+
+            light_level:    0.0,
+            rotation:       0.0,
+            tilt:           (0.0, 0.0),
+        }
+    }
+}
+
 impl From<crate::Button> for Buttons {
     fn from(b: crate::Button) -> Buttons {
         use crate::Button::*;
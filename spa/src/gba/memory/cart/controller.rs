@@ -23,6 +23,37 @@ bitflags!{
     }
 }
 
+/// Size of the prefetch buffer in halfwords.
+const PREFETCH_SIZE: u32 = 8;
+
+/// The game pak prefetch buffer.
+/// 
+/// While the cart bus is idle, sequential halfwords are fetched
+/// after the last instruction fetched from ROM.
+#[derive(Default)]
+struct Prefetch {
+    /// Address of the next halfword to be fetched into the buffer.
+    next_addr:  u32,
+    /// Halfwords in the buffer.
+    count:      u32,
+    /// Cycles spent fetching the next halfword.
+    progress:   usize,
+}
+
+impl Prefetch {
+    /// Address of the oldest halfword in the buffer.
+    fn head(&self) -> u32 {
+        self.next_addr - (self.count * 2)
+    }
+
+    /// Empty the buffer, and start fetching from `addr`.
+    fn restart(&mut self, addr: u32) {
+        self.next_addr = addr;
+        self.count = 0;
+        self.progress = 0;
+    }
+}
+
 /// The controller for the game pak, which controls wait states for memory accesses,
 /// and the pre-fetch buffer.
 pub struct GamePakController {
@@ -36,7 +67,9 @@ pub struct GamePakController {
     wait_2_n:   usize,
     wait_2_s:   usize,
 
-    // TODO: prefetch buffer
+    prefetch:       Prefetch,
+    /// Cycles the cart bus has been in use since the last clock.
+    busy_cycles:    usize,
 }
 
 impl GamePakController {
@@ -51,31 +84,123 @@ impl GamePakController {
             wait_1_s:   5,
             wait_2_n:   5,
             wait_2_s:   9,
+
+            prefetch:       Prefetch::default(),
+            busy_cycles:    0,
         }
     }
 
-    pub fn sram_wait_cycles(&self) -> usize {
-        self.sram_wait
+    /// Cycles for an SRAM access.
+    /// Any data access interrupts the prefetch.
+    pub fn sram_wait_cycles(&mut self) -> usize {
+        self.data_access(self.sram_wait)
     }
 
-    pub fn wait_cycles_0(&self, cycle_type: MemCycleType) -> usize {
-        match cycle_type {
+    /// Cycles for a data access to wait state 0 region.
+    /// Any data access interrupts the prefetch.
+    pub fn wait_cycles_0(&mut self, cycle_type: MemCycleType) -> usize {
+        let cycles = match cycle_type {
             MemCycleType::N => self.wait_0_n,
             MemCycleType::S => self.wait_0_s,
-        }
+        };
+        self.data_access(cycles)
     }
 
-    pub fn wait_cycles_1(&self, cycle_type: MemCycleType) -> usize {
-        match cycle_type {
+    /// Cycles for a data access to wait state 1 region.
+    /// Any data access interrupts the prefetch.
+    pub fn wait_cycles_1(&mut self, cycle_type: MemCycleType) -> usize {
+        let cycles = match cycle_type {
             MemCycleType::N => self.wait_1_n,
             MemCycleType::S => self.wait_1_s,
-        }
+        };
+        self.data_access(cycles)
     }
 
-    pub fn wait_cycles_2(&self, cycle_type: MemCycleType) -> usize {
-        match cycle_type {
+    /// Cycles for a data access to wait state 2 region.
+    /// Any data access interrupts the prefetch.
+    pub fn wait_cycles_2(&mut self, cycle_type: MemCycleType) -> usize {
+        let cycles = match cycle_type {
             MemCycleType::N => self.wait_2_n,
             MemCycleType::S => self.wait_2_s,
+        };
+        self.data_access(cycles)
+    }
+
+    /// Cycles for an instruction fetch of `halfwords` halfwords (1 for THUMB, 2 for ARM) from ROM.
+    /// 
+    /// If the prefetch buffer is enabled and has the data, this will be fast.
+    pub fn fetch_cycles(&mut self, cycle_type: MemCycleType, addr: u32, halfwords: u32) -> usize {
+        if !self.control.contains(Control::PREFETCH) {
+            let cycles = self.region_cycles(cycle_type, addr) + ((halfwords - 1) as usize) * self.region_cycles(MemCycleType::S, addr);
+            self.busy_cycles += cycles;
+            return cycles;
+        }
+
+        if addr == self.prefetch.head() && self.prefetch.count >= halfwords {
+            // Already in the buffer.
+            self.prefetch.count -= halfwords;
+            1
+        } else if addr == self.prefetch.head() {
+            // Partially fetched: wait for the rest.
+            let s_cycles = self.region_cycles(MemCycleType::S, addr);
+            let remaining = halfwords - self.prefetch.count;
+            let cycles = s_cycles.saturating_sub(self.prefetch.progress) + ((remaining - 1) as usize) * s_cycles;
+            self.prefetch.restart(addr + halfwords * 2);
+            self.busy_cycles += cycles;
+            cycles
+        } else {
+            // Not in the buffer: fetch as normal, and start prefetching from here.
+            let cycles = self.region_cycles(cycle_type, addr) + ((halfwords - 1) as usize) * self.region_cycles(MemCycleType::S, addr);
+            self.prefetch.restart(addr + halfwords * 2);
+            self.busy_cycles += cycles;
+            cycles
+        }
+    }
+
+    /// Advance the prefetch buffer.
+    /// 
+    /// It fills during cycles where the cart bus was not in use.
+    pub fn clock(&mut self, cycles: usize) {
+        let idle_cycles = cycles.saturating_sub(self.busy_cycles);
+        self.busy_cycles = 0;
+
+        if !self.control.contains(Control::PREFETCH) || self.prefetch.count >= PREFETCH_SIZE {
+            return;
+        }
+        if self.prefetch.next_addr < 0x0800_0000 || self.prefetch.next_addr >= 0x0E00_0000 {
+            return;
+        }
+
+        let s_cycles = self.region_cycles(MemCycleType::S, self.prefetch.next_addr);
+        self.prefetch.progress += idle_cycles;
+        while self.prefetch.progress >= s_cycles && self.prefetch.count < PREFETCH_SIZE {
+            self.prefetch.progress -= s_cycles;
+            self.prefetch.count += 1;
+            self.prefetch.next_addr += 2;
+        }
+        if self.prefetch.count == PREFETCH_SIZE {
+            self.prefetch.progress = 0;
+        }
+    }
+}
+
+// Internal
+impl GamePakController {
+    /// Data access uses the bus, and interrupts the fetch in progress.
+    fn data_access(&mut self, cycles: usize) -> usize {
+        self.prefetch.progress = 0;
+        self.busy_cycles += cycles;
+        cycles
+    }
+
+    fn region_cycles(&self, cycle_type: MemCycleType, addr: u32) -> usize {
+        match (addr >> 24, cycle_type) {
+            (0x08..=0x09, MemCycleType::N) => self.wait_0_n,
+            (0x08..=0x09, MemCycleType::S) => self.wait_0_s,
+            (0x0A..=0x0B, MemCycleType::N) => self.wait_1_n,
+            (0x0A..=0x0B, MemCycleType::S) => self.wait_1_s,
+            (_, MemCycleType::N) => self.wait_2_n,
+            (_, MemCycleType::S) => self.wait_2_s,
         }
     }
 }
@@ -97,10 +222,13 @@ impl MemInterface16 for GamePakController {
                 self.wait_0_n = transfer_cycles((self.control & Control::WAIT_0_N).bits() >> 2);
                 self.wait_0_s = if self.control.contains(Control::WAIT_0_S) {2} else {3};
                 self.wait_1_n = transfer_cycles((self.control & Control::WAIT_1_N).bits() >> 5);
-                self.wait_1_s = if self.control.contains(Control::WAIT_0_S) {2} else {5};
+                self.wait_1_s = if self.control.contains(Control::WAIT_1_S) {2} else {5};
                 self.wait_2_n = transfer_cycles((self.control & Control::WAIT_2_N).bits() >> 8);
-                self.wait_2_s = if self.control.contains(Control::WAIT_0_S) {2} else {9};
-                // TODO: prefetch buffer
+                self.wait_2_s = if self.control.contains(Control::WAIT_2_S) {2} else {9};
+                if !self.control.contains(Control::PREFETCH) {
+                    let next_addr = self.prefetch.next_addr;
+                    self.prefetch.restart(next_addr);
+                }
             },
             0x0400_0206 => {},
             _ => unreachable!()
@@ -120,4 +248,69 @@ fn transfer_cycles(from: u16) -> usize {
         0b11 => 9,
         _ => unreachable!()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefetch_controller() -> GamePakController {
+        let mut controller = GamePakController::new();
+        controller.write_halfword(0x0400_0204, Control::PREFETCH.bits());
+        controller
+    }
+
+    #[test]
+    fn prefetch_fill() {
+        let mut controller = prefetch_controller();
+        assert_eq!(controller.fetch_cycles(MemCycleType::N, 0x0800_0000, 1), 5);
+        controller.clock(5);
+        assert_eq!(controller.prefetch.count, 0);
+
+        // 3 cycles per sequential halfword.
+        controller.clock(7);
+        assert_eq!(controller.prefetch.count, 2);
+        assert_eq!(controller.prefetch.progress, 1);
+
+        // Stops when full.
+        controller.clock(100);
+        assert_eq!(controller.prefetch.count, PREFETCH_SIZE);
+        assert_eq!(controller.prefetch.next_addr, 0x0800_0012);
+    }
+
+    #[test]
+    fn prefetch_sequential_hit() {
+        let mut controller = prefetch_controller();
+        controller.fetch_cycles(MemCycleType::N, 0x0800_0000, 1);
+        controller.clock(5 + 6);
+        assert_eq!(controller.prefetch.count, 2);
+
+        // ARM fetch of both halfwords in the buffer.
+        assert_eq!(controller.fetch_cycles(MemCycleType::S, 0x0800_0002, 2), 1);
+        assert_eq!(controller.prefetch.count, 0);
+        assert_eq!(controller.prefetch.head(), 0x0800_0006);
+
+        // Partially fetched halfword only waits for the remaining cycles.
+        controller.clock(1);
+        assert_eq!(controller.fetch_cycles(MemCycleType::S, 0x0800_0006, 1), 2);
+    }
+
+    #[test]
+    fn prefetch_invalidate() {
+        let mut controller = prefetch_controller();
+        controller.fetch_cycles(MemCycleType::N, 0x0800_0000, 1);
+        controller.clock(5 + 24);
+        assert_eq!(controller.prefetch.count, PREFETCH_SIZE);
+
+        // Non-sequential fetch empties the buffer.
+        assert_eq!(controller.fetch_cycles(MemCycleType::N, 0x0800_0100, 1), 5);
+        assert_eq!(controller.prefetch.count, 0);
+        assert_eq!(controller.prefetch.next_addr, 0x0800_0102);
+
+        // Data access interrupts the fetch in progress.
+        controller.clock(5 + 2);
+        controller.wait_cycles_0(MemCycleType::N);
+        assert_eq!(controller.prefetch.progress, 0);
+        controller.clock(5);
+        assert_eq!(controller.prefetch.count, 0);
+    }
+}
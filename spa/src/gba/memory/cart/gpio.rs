@@ -0,0 +1,305 @@
+/// General purpose IO port on cartridge.
+///
+/// Some carts have extra hardware (sensors, rumble) attached to 4 pins,
+/// which are accessed through registers in the ROM area.
+
+use crossbeam_channel::Sender;
+
+use crate::{
+    Event,
+    utils::{
+        bits::{u8, u16},
+        meminterface::MemInterface16
+    },
+    gba::input::UserInput
+};
+
+const PIN_MASK: u8 = 0xF;
+
+/// A device attached to the GPIO pins.
+pub trait GPIODevice {
+    /// Pins set by the GBA. Only pins with direction set to output are included.
+    fn write_pins(&mut self, pins: u8);
+    /// Pins set by the device.
+    fn read_pins(&self) -> u8;
+    /// Update with the latest user input.
+    fn set_input(&mut self, _input: &UserInput) {}
+}
+
+pub struct GPIO {
+    data:       u8,
+    /// Set bits are output (GBA -> device).
+    direction:  u8,
+    /// If false, the registers cannot be read (reads return ROM data).
+    readable:   bool,
+
+    device:     Box<dyn GPIODevice + Send>,
+}
+
+impl GPIO {
+    pub fn new(device: Box<dyn GPIODevice + Send>) -> Self {
+        Self {
+            data:       0,
+            direction:  0,
+            readable:   false,
+
+            device:     device,
+        }
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.readable
+    }
+
+    pub fn set_input(&mut self, input: &UserInput) {
+        self.device.set_input(input);
+    }
+}
+
+impl MemInterface16 for GPIO {
+    fn read_halfword(&mut self, addr: u32) -> u16 {
+        match addr {
+            0x0800_00C4 => ((self.data & self.direction) | (self.device.read_pins() & !self.direction)) as u16,
+            0x0800_00C6 => self.direction as u16,
+            0x0800_00C8 => if self.readable {1} else {0},
+            _ => 0
+        }
+    }
+
+    fn write_halfword(&mut self, addr: u32, data: u16) {
+        match addr {
+            0x0800_00C4 => {
+                self.data = (data as u8) & PIN_MASK;
+                self.device.write_pins(self.data & self.direction);
+            },
+            0x0800_00C6 => {
+                self.direction = (data as u8) & PIN_MASK;
+                self.device.write_pins(self.data & self.direction);
+            },
+            0x0800_00C8 => self.readable = u8::test_bit(data as u8, 0),
+            _ => {}
+        }
+    }
+}
+
+/// Solar sensor (Boktai).
+///
+/// The game resets a counter, then clocks it until the output flag is set.
+/// The brighter the light, the sooner the flag is set.
+pub struct SolarSensor {
+    clock:      bool,
+    counter:    u8,
+    sample:     u8,
+    light:      f64,
+}
+
+impl SolarSensor {
+    const CLK: u8 = u8::bit(0);
+    const RST: u8 = u8::bit(1);
+    const CS: u8 = u8::bit(2);
+    const FLAG: u8 = u8::bit(3);
+
+    const DARK: f64 = 0xE8 as f64;
+    const BRIGHT: f64 = 0x50 as f64;
+
+    pub fn new() -> Self {
+        Self {
+            clock:      false,
+            counter:    0,
+            sample:     Self::DARK as u8,
+            light:      0.0,
+        }
+    }
+}
+
+impl GPIODevice for SolarSensor {
+    fn write_pins(&mut self, pins: u8) {
+        if (pins & Self::CS) != 0 {
+            // Not selected.
+            return;
+        }
+        if (pins & Self::RST) != 0 {
+            self.counter = 0;
+            self.sample = (Self::DARK - (Self::DARK - Self::BRIGHT) * self.light) as u8;
+        }
+        let clock = (pins & Self::CLK) != 0;
+        if clock && !self.clock {
+            self.counter = self.counter.wrapping_add(1);
+        }
+        self.clock = clock;
+    }
+
+    fn read_pins(&self) -> u8 {
+        if self.counter >= self.sample {
+            Self::FLAG
+        } else {
+            0
+        }
+    }
+
+    fn set_input(&mut self, input: &UserInput) {
+        self.light = input.light_level;
+    }
+}
+
+/// Gyro sensor and rumble (WarioWare Twisted).
+///
+/// The rotation is sampled on START, and then shifted out serially
+/// (MSB first) on the falling edge of the clock.
+pub struct GyroSensor {
+    clock:      bool,
+    shift:      u16,
+    rotation:   f64,
+
+    rumble:     Rumble,
+}
+
+impl GyroSensor {
+    const START: u8 = u8::bit(0);
+    const CLK: u8 = u8::bit(1);
+    const DATA: u8 = u8::bit(2);
+
+    const CENTRE: f64 = 0x6C0 as f64;
+    const RANGE: f64 = 0x400 as f64;
+
+    pub fn new(events: Sender<Event>) -> Self {
+        Self {
+            clock:      false,
+            shift:      0,
+            rotation:   0.0,
+
+            rumble:     Rumble::new(events),
+        }
+    }
+}
+
+impl GPIODevice for GyroSensor {
+    fn write_pins(&mut self, pins: u8) {
+        if (pins & Self::START) != 0 {
+            self.shift = (Self::CENTRE + Self::RANGE * self.rotation) as u16;
+        }
+        let clock = (pins & Self::CLK) != 0;
+        if !clock && self.clock {
+            self.shift <<= 1;
+        }
+        self.clock = clock;
+        self.rumble.write_pins(pins);
+    }
+
+    fn read_pins(&self) -> u8 {
+        if u16::test_bit(self.shift, 15) {
+            Self::DATA
+        } else {
+            0
+        }
+    }
+
+    fn set_input(&mut self, input: &UserInput) {
+        self.rotation = input.rotation;
+    }
+}
+
+/// Rumble motor (Drill Dozer, WarioWare Twisted).
+pub struct Rumble {
+    active: bool,
+    events: Sender<Event>,
+}
+
+impl Rumble {
+    const MOTOR: u8 = u8::bit(3);
+
+    pub fn new(events: Sender<Event>) -> Self {
+        Self {
+            active: false,
+            events: events,
+        }
+    }
+}
+
+impl GPIODevice for Rumble {
+    fn write_pins(&mut self, pins: u8) {
+        let active = (pins & Self::MOTOR) != 0;
+        if active != self.active {
+            self.active = active;
+            // The frontend might not be listening.
+            let _ = self.events.send(Event::Rumble(active));
+        }
+    }
+
+    fn read_pins(&self) -> u8 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, atomic::{AtomicU8, Ordering}};
+
+    /// Records the pins written, and drives pins 1 and 3 high.
+    struct TestDevice {
+        written: Arc<AtomicU8>,
+    }
+
+    impl GPIODevice for TestDevice {
+        fn write_pins(&mut self, pins: u8) {
+            self.written.store(pins, Ordering::Relaxed);
+        }
+
+        fn read_pins(&self) -> u8 {
+            0b1010
+        }
+    }
+
+    #[test]
+    fn port_direction() {
+        let written = Arc::new(AtomicU8::new(0));
+        let mut gpio = GPIO::new(Box::new(TestDevice { written: written.clone() }));
+
+        // All pins start as input.
+        gpio.write_halfword(0x0800_00C4, 0xF);
+        assert_eq!(written.load(Ordering::Relaxed), 0);
+        assert_eq!(gpio.read_halfword(0x0800_00C4), 0b1010);
+
+        // Only output pins are sent to the device, and only input pins are read from it.
+        gpio.write_halfword(0x0800_00C6, 0xFF05);
+        assert_eq!(gpio.read_halfword(0x0800_00C6), 0b0101);
+        assert_eq!(written.load(Ordering::Relaxed), 0b0101);
+        assert_eq!(gpio.read_halfword(0x0800_00C4), 0b1111);
+
+        gpio.write_halfword(0x0800_00C4, 0xFFF0);
+        assert_eq!(written.load(Ordering::Relaxed), 0);
+        assert_eq!(gpio.read_halfword(0x0800_00C4), 0b1010);
+    }
+
+    #[test]
+    fn readable() {
+        let mut gpio = GPIO::new(Box::new(SolarSensor::new()));
+        assert!(!gpio.is_readable());
+        gpio.write_halfword(0x0800_00C8, 1);
+        assert!(gpio.is_readable());
+        assert_eq!(gpio.read_halfword(0x0800_00C8), 1);
+        gpio.write_halfword(0x0800_00C8, 2);
+        assert!(!gpio.is_readable());
+    }
+
+    /// Count clocks until the solar sensor sets its flag.
+    fn solar_clocks(light_level: f64) -> usize {
+        let mut solar = SolarSensor::new();
+        solar.set_input(&UserInput { light_level, ..Default::default() });
+        solar.write_pins(SolarSensor::RST);
+        solar.write_pins(0);
+        (1..=0x100).find(|_| {
+            solar.write_pins(SolarSensor::CLK);
+            solar.write_pins(0);
+            solar.read_pins() == SolarSensor::FLAG
+        }).unwrap()
+    }
+
+    #[test]
+    fn solar_sensor() {
+        assert_eq!(solar_clocks(0.0), SolarSensor::DARK as usize);
+        assert_eq!(solar_clocks(1.0), SolarSensor::BRIGHT as usize);
+        assert!(solar_clocks(0.5) < solar_clocks(0.25));
+    }
+}
@@ -2,6 +2,8 @@
 
 mod controller;
 mod ram;
+mod gpio;
+mod tilt;
 
 use std::{
     io::{
@@ -10,14 +12,26 @@ use std::{
     fs::File,
     path::Path
 };
+use crossbeam_channel::Sender;
 use crate::utils::{
     bytes::u16,
-    meminterface::MemInterface16
+    meminterface::{MemInterface8, MemInterface16}
 };
 use crate::common::mem::ram::RAM;
+use crate::{
+    Event,
+    gba::input::UserInput
+};
 
 pub use controller::GamePakController;
 use ram::*;
+use gpio::*;
+use tilt::TiltSensor;
+
+const SOLAR_CODES: &[&[u8; 4]] = &[b"U3IJ", b"U3IE", b"U3IP", b"U32J", b"U32E", b"U32P", b"U33J"];
+const GYRO_CODES: &[&[u8; 4]] = &[b"RZWJ", b"RZWE", b"RZWP"];
+const RUMBLE_CODES: &[&[u8; 4]] = &[b"V49J", b"V49E", b"V49P"];
+const TILT_CODES: &[&[u8; 4]] = &[b"KYGJ", b"KYGE", b"KYGP", b"KHPJ"];
 
 /// The ROM and RAM inside a game pak (cartridge).
 pub struct GamePak {
@@ -26,18 +40,52 @@ pub struct GamePak {
     /// ROM is larger than 16MB
     large:  bool,
     eeprom: bool,
+
+    /// Extra hardware on the cart.
+    gpio:   Option<GPIO>,
+    tilt:   Option<TiltSensor>,
 }
 
 impl GamePak {
-    pub fn new(rom_path: &Path, save_path: Option<&Path>) -> Result<Self> {
+    pub fn new(rom_path: &Path, save_path: Option<&Path>, events: Sender<Event>) -> Result<Self> {
         let mut rom_file = File::open(rom_path)?;
         let mut buffer = Vec::new();
         rom_file.read_to_end(&mut buffer)?;
+        Ok(Self::from_data(buffer, save_path, events))
+    }
 
+    /// No cart is inserted. Reads from ROM return the cart bus pattern:
+    /// each halfword holds the lower 16 bits of its address divided by 2.
+    pub fn new_empty(events: Sender<Event>) -> Self {
+        Self::from_data(Vec::new(), None, events)
+    }
+
+    fn from_data(mut buffer: Vec<u8>, save_path: Option<&Path>, events: Sender<Event>) -> Self {
         // Detect save file type.
         let (ram, eeprom) = make_save_ram(&buffer, save_path);
         let is_large = buffer.len() > 0x0100_0000;
 
+        // Detect extra hardware from game code.
+        let game_code = buffer.get(0xAC..0xB0).unwrap_or(&[]);
+        let gpio = if SOLAR_CODES.iter().any(|c| &c[..] == game_code) {
+            println!("Found solar sensor");
+            Some(GPIO::new(Box::new(SolarSensor::new())))
+        } else if GYRO_CODES.iter().any(|c| &c[..] == game_code) {
+            println!("Found gyro sensor");
+            Some(GPIO::new(Box::new(GyroSensor::new(events))))
+        } else if RUMBLE_CODES.iter().any(|c| &c[..] == game_code) {
+            println!("Found rumble");
+            Some(GPIO::new(Box::new(Rumble::new(events))))
+        } else {
+            None
+        };
+        let tilt = if TILT_CODES.iter().any(|c| &c[..] == game_code) {
+            println!("Found tilt sensor");
+            Some(TiltSensor::new())
+        } else {
+            None
+        };
+
         // Fill buffer with garbage.
         let start = buffer.len() / 2;
         for i in start..0x0100_0000 {
@@ -45,18 +93,35 @@ impl GamePak {
             buffer.push(u16::lo(data));
             buffer.push(u16::hi(data));
         }
-        Ok(Self {
+        Self {
             rom:    buffer.into(),
             ram:    ram,
             large:  is_large,
             eeprom: eeprom,
-        })
+
+            gpio:   gpio,
+            tilt:   tilt,
+        }
     }
 
     /// Write the save to the save file.
     pub fn flush_save(&mut self) {
         self.ram.flush();
     }
+
+    /// Pass user input to any sensors on the cart.
+    pub fn set_input(&mut self, input: &UserInput) {
+        if let Some(gpio) = &mut self.gpio {
+            gpio.set_input(input);
+        }
+        if let Some(tilt) = &mut self.tilt {
+            tilt.set_input(input);
+        }
+    }
+
+    fn gpio_readable(&self) -> bool {
+        self.gpio.as_ref().map(|g| g.is_readable()).unwrap_or(false)
+    }
 }
 
 impl MemInterface16 for GamePak {
@@ -69,7 +134,9 @@ impl MemInterface16 for GamePak {
             0x0B00_0000..=0x0BFF_FFFF if self.eeprom => self.ram.read_byte(addr),
             0x0D00_0000..=0x0DFF_FEFF if self.eeprom && self.large => self.rom.read_byte(rom_addr),
             0x0D00_0000..=0x0DFF_FFFF if self.eeprom => self.ram.read_byte(addr),
+            0x0800_00C4..=0x0800_00C9 if self.gpio_readable() => self.gpio.as_mut().unwrap().read_byte(addr),
             0x0800_0000..=0x0DFF_FFFF => self.rom.read_byte(rom_addr),
+            0x0E00_8000..=0x0E00_85FF if self.tilt.is_some() => self.tilt.as_mut().unwrap().read_byte(addr),
             0x0E00_0000..=0x0EFF_FFFF => self.ram.read_byte(addr & 0xFFFF),
             _ => unreachable!()
         }
@@ -80,7 +147,11 @@ impl MemInterface16 for GamePak {
             0x0900_0000..=0x09FF_FFFF if self.eeprom => self.ram.write_byte(addr, data),
             0x0B00_0000..=0x0BFF_FFFF if self.eeprom => self.ram.write_byte(addr, data),
             0x0D00_0000..=0x0DFF_FFFF if self.eeprom => self.ram.write_byte(addr, data),
+            0x0E00_8000..=0x0E00_85FF if self.tilt.is_some() => self.tilt.as_mut().unwrap().write_byte(addr, data),
             0x0E00_0000..=0x0EFF_FFFF => self.ram.write_byte(addr & 0xFFFF, data),
+            0x0800_00C4..=0x0800_00C9 => if let Some(gpio) = &mut self.gpio {
+                gpio.write_byte(addr, data);
+            },
             0x0800_0000..=0x0DFF_FFFF => panic!("Trying to write to ROM 0x{:X}", addr),
             _ => unreachable!()
         }
@@ -95,7 +166,9 @@ impl MemInterface16 for GamePak {
             0x0B00_0000..=0x0BFF_FFFF if self.eeprom => self.ram.read_halfword(addr),
             0x0D00_0000..=0x0DFF_FEFF if self.eeprom && self.large => self.rom.read_halfword(rom_addr),
             0x0D00_0000..=0x0DFF_FFFF if self.eeprom => self.ram.read_halfword(addr),
+            0x0800_00C4..=0x0800_00C9 if self.gpio_readable() => self.gpio.as_mut().unwrap().read_halfword(addr),
             0x0800_0000..=0x0DFF_FFFF => self.rom.read_halfword(rom_addr),
+            0x0E00_8000..=0x0E00_85FF if self.tilt.is_some() => self.tilt.as_mut().unwrap().read_halfword(addr),
             0x0E00_0000..=0x0EFF_FFFF => self.ram.read_halfword(addr & 0xFFFF),
             _ => unreachable!()
         }
@@ -106,7 +179,11 @@ impl MemInterface16 for GamePak {
             0x0900_0000..=0x09FF_FFFF if self.eeprom => self.ram.write_halfword(addr, data),
             0x0B00_0000..=0x0BFF_FFFF if self.eeprom => self.ram.write_halfword(addr, data),
             0x0D00_0000..=0x0DFF_FFFF if self.eeprom => self.ram.write_halfword(addr, data),
+            0x0E00_8000..=0x0E00_85FF if self.tilt.is_some() => self.tilt.as_mut().unwrap().write_halfword(addr, data),
             0x0E00_0000..=0x0EFF_FFFF => self.ram.write_halfword(addr & 0xFFFF, data),
+            0x0800_00C4..=0x0800_00C9 => if let Some(gpio) = &mut self.gpio {
+                gpio.write_halfword(addr, data);
+            },
             0x0800_0000..=0x0DFF_FFFF => panic!("Trying to write to ROM 0x{:X}", addr),
             _ => unreachable!()
         }
@@ -121,7 +198,9 @@ impl MemInterface16 for GamePak {
             0x0B00_0000..=0x0BFF_FFFF if self.eeprom => self.ram.read_word(addr),
             0x0D00_0000..=0x0DFF_FEFF if self.eeprom && self.large => self.rom.read_word(rom_addr),
             0x0D00_0000..=0x0DFF_FFFF if self.eeprom => self.ram.read_word(addr),
+            0x0800_00C4..=0x0800_00C9 if self.gpio_readable() => self.gpio.as_mut().unwrap().read_word(addr),
             0x0800_0000..=0x0DFF_FFFF => self.rom.read_word(rom_addr),
+            0x0E00_8000..=0x0E00_85FF if self.tilt.is_some() => self.tilt.as_mut().unwrap().read_word(addr),
             0x0E00_0000..=0x0EFF_FFFF => self.ram.read_word(addr & 0xFFFF),
             _ => unreachable!()
         }
@@ -0,0 +1,113 @@
+/// Tilt sensor (Yoshi Topsy-Turvy, Koro Koro Puzzle).
+///
+/// Mapped into the SRAM region at 0x0E00_8000.
+
+use crate::{
+    utils::{
+        bits::u8,
+        meminterface::MemInterface8
+    },
+    gba::input::UserInput
+};
+
+pub struct TiltSensor {
+    /// First write (0x55) received.
+    latched:    bool,
+    /// Sampled values are ready to be read.
+    ready:      bool,
+
+    x:          u16,
+    y:          u16,
+    tilt:       (f64, f64),
+}
+
+impl TiltSensor {
+    const CENTRE: f64 = 0x3A0 as f64;
+    const RANGE: f64 = 0xE0 as f64;
+
+    pub fn new() -> Self {
+        Self {
+            latched:    false,
+            ready:      false,
+
+            x:          Self::CENTRE as u16,
+            y:          Self::CENTRE as u16,
+            tilt:       (0.0, 0.0),
+        }
+    }
+
+    pub fn set_input(&mut self, input: &UserInput) {
+        self.tilt = input.tilt;
+    }
+
+    fn sample(&mut self) {
+        self.x = (Self::CENTRE + Self::RANGE * self.tilt.0) as u16;
+        self.y = (Self::CENTRE + Self::RANGE * self.tilt.1) as u16;
+        self.ready = true;
+    }
+}
+
+impl MemInterface8 for TiltSensor {
+    fn read_byte(&mut self, addr: u32) -> u8 {
+        match addr {
+            0x0E00_8200 => self.x as u8,
+            0x0E00_8300 => {
+                let ready = if self.ready {u8::bit(7)} else {0};
+                ((self.x >> 8) as u8 & 0xF) | ready
+            },
+            0x0E00_8400 => self.y as u8,
+            0x0E00_8500 => (self.y >> 8) as u8 & 0xF,
+            _ => 0
+        }
+    }
+
+    fn write_byte(&mut self, addr: u32, data: u8) {
+        match addr {
+            0x0E00_8000 => if data == 0x55 {
+                self.latched = true;
+                self.ready = false;
+            },
+            0x0E00_8100 => if data == 0xAA && self.latched {
+                self.latched = false;
+                self.sample();
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_xy(tilt: &mut TiltSensor) -> (u16, u16) {
+        let x = (tilt.read_byte(0x0E00_8200) as u16) | (((tilt.read_byte(0x0E00_8300) & 0xF) as u16) << 8);
+        let y = (tilt.read_byte(0x0E00_8400) as u16) | ((tilt.read_byte(0x0E00_8500) as u16) << 8);
+        (x, y)
+    }
+
+    #[test]
+    fn latch_sequence() {
+        let mut tilt = TiltSensor::new();
+        tilt.set_input(&UserInput { tilt: (1.0, -1.0), ..Default::default() });
+
+        // 0xAA alone doesn't sample.
+        tilt.write_byte(0x0E00_8100, 0xAA);
+        assert_eq!(tilt.read_byte(0x0E00_8300) & 0x80, 0);
+        assert_eq!(read_xy(&mut tilt), (0x3A0, 0x3A0));
+
+        // Other values don't latch.
+        tilt.write_byte(0x0E00_8000, 0x54);
+        tilt.write_byte(0x0E00_8100, 0xAA);
+        assert_eq!(tilt.read_byte(0x0E00_8300) & 0x80, 0);
+
+        tilt.write_byte(0x0E00_8000, 0x55);
+        tilt.write_byte(0x0E00_8100, 0xAA);
+        assert_eq!(tilt.read_byte(0x0E00_8300) & 0x80, 0x80);
+        assert_eq!(read_xy(&mut tilt), (0x480, 0x2C0));
+
+        // The next latch clears the ready flag.
+        tilt.write_byte(0x0E00_8000, 0x55);
+        assert_eq!(tilt.read_byte(0x0E00_8300) & 0x80, 0);
+    }
+}
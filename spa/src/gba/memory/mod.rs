@@ -3,9 +3,12 @@ mod cart;
 mod swi;
 
 use arm::{Mem32, MemCycleType};
-use crossbeam_channel::{Receiver, unbounded};
+use crossbeam_channel::{Receiver, Sender, unbounded};
 
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    ops::Range
+};
 
 use crate::{
     utils::{
@@ -16,34 +19,78 @@ use crate::{
         mem::{
             bios::BIOS,
             ram::RAM,
+            jit::{JITTracker, MirroredRAM},
         },
         peripheral::{
             dma::{DMA, DMAAddress},
             timers::Timers,
-            joypad::{Joypad, Buttons},
+            joypad::Joypad,
         },
         video::framecomms::FrameSender,
         resampler::SamplePacket
     },
+    Event,
     gba::{
+        input::UserInput,
         interrupt::{Interrupts, InterruptControl},
         video::*,
         audio::GBAAudio
     }
 };
-use cart::{GamePak, GamePakController};
-pub use swi::emulated_swi;
+use cart::GamePakController;
+pub use swi::{emulated_swi, post_boot_state};
+pub use cart::GamePak;
+use swi::EmulatedBIOSMem;
+
+/// Address ranges that the JIT compiles from by default: BIOS, WRAM, fast WRAM, and ROM.
+pub const DEFAULT_JIT_RANGES: &[Range<u32>] = &[0..0x4000, 0x0200_0000..0x0204_0000, 0x0300_0000..0x0300_8000, 0x0800_0000..0x0E00_0000];
+
+const WRAM: MirroredRAM = MirroredRAM { start: 0x0200_0000, end: 0x0300_0000, size: 0x4_0000 };
+const FAST_WRAM: MirroredRAM = MirroredRAM { start: 0x0300_0000, end: 0x0400_0000, size: 0x8000 };
 
 /// Locations for external files that are used by GBA.
 pub struct MemoryConfig {
     pub rom_path:   PathBuf,
     pub save_path:  Option<PathBuf>,
     pub bios_path:  Option<PathBuf>,
+
+    /// Use the JIT compiler.
+    pub jit:        bool,
+    /// Address ranges for the JIT to compile from.
+    /// If None, `DEFAULT_JIT_RANGES` are used.
+    pub jit_ranges: Option<Vec<Range<u32>>>,
+}
+
+impl MemoryConfig {
+    /// If the ROM is a multiboot image (.mb), it is loaded into WRAM
+    /// and run from there, with no cart inserted.
+    pub fn is_multiboot(&self) -> bool {
+        self.rom_path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("mb"))
+            .unwrap_or(false)
+    }
+
+    /// Get the ranges that the JIT should compile from.
+    /// Empty if JIT is disabled.
+    pub fn jit_ranges(&self) -> Vec<Range<u32>> {
+        if !self.jit {
+            Vec::new()
+        } else if let Some(ranges) = &self.jit_ranges {
+            ranges.clone()
+        } else {
+            DEFAULT_JIT_RANGES.to_vec()
+        }
+    }
 }
 
 /// Game Boy Advance memory bus
 pub struct MemoryBus<R: Renderer> {
     bios:       BIOS,
+    /// Last opcode fetched from BIOS.
+    bios_latch: u32,
+    /// Currently executing from BIOS.
+    in_bios:    bool,
     internal:   Internal,
 
     wram:       RAM,
@@ -62,22 +109,34 @@ pub struct MemoryBus<R: Renderer> {
     dma:                DMA,
     interrupt_control:  InterruptControl,
 
-    frame_sender:       FrameSender<Buttons>,
+    frame_sender:       FrameSender<UserInput>,
+
+    /// Set by the emulated BIOS.
+    reset_request:      Option<u32>,
+    jit:                JITTracker,
 }
 
 impl<R: Renderer> MemoryBus<R> {
-    pub fn new(config: &MemoryConfig, frame_sender: FrameSender<Buttons>) -> std::io::Result<Box<Self>> {
+    pub fn new(config: &MemoryConfig, frame_sender: FrameSender<UserInput>, events: Sender<Event>) -> std::io::Result<Box<Self>> {
         let bios = if let Some(path) = &config.bios_path {
             BIOS::new_from_file(&path)?
         } else {
             construct_bios()
         };
-        let game_pak = cart::GamePak::new(&config.rom_path, config.save_path.as_ref().map(|p| p.as_path()))?;
+        let mut wram = RAM::new(256 * 1024);
+        let game_pak = if config.is_multiboot() {
+            load_multiboot(&config.rom_path, &mut wram)?;
+            cart::GamePak::new_empty(events)
+        } else {
+            cart::GamePak::new(&config.rom_path, config.save_path.as_ref().map(|p| p.as_path()), events)?
+        };
         Ok(Box::new(Self {
             bios:       bios,
+            bios_latch: 0xE129_F000,    // Opcode fetched when the BIOS jumps to the game.
+            in_bios:    false,
             internal:   Internal::new(),
 
-            wram:       RAM::new(256 * 1024),
+            wram:       wram,
             fast_wram:  RAM::new(32 * 1024),
 
             game_pak:           game_pak,
@@ -94,9 +153,24 @@ impl<R: Renderer> MemoryBus<R> {
             interrupt_control:  InterruptControl::new(),
 
             frame_sender:       frame_sender,
+
+            reset_request:      None,
+            jit:                JITTracker::new(&config.jit_ranges()),
         }))
     }
 
+    /// Check if the emulated BIOS has requested a reset.
+    /// Returns the address to start executing from.
+    pub fn take_reset_request(&mut self) -> Option<u32> {
+        self.reset_request.take()
+    }
+
+    /// Get a range of JIT-compiled memory that has been written to since it was last taken.
+    /// Call until None is returned.
+    pub fn take_jit_dirty(&mut self) -> Option<Range<u32>> {
+        self.jit.take_dirty()
+    }
+
     pub fn enable_audio(&mut self) -> (Receiver<SamplePacket>, Receiver<f64>) {
         let (sample_tx, sample_rx) = unbounded();
         let (rate_tx, rate_rx) = unbounded();
@@ -223,8 +297,9 @@ impl<R: Renderer> MemoryBus<R> {
     fn frame_end(&mut self) {
         self.game_pak.flush_save();
 
-        if let Some(buttons) = self.frame_sender.sync_frame() {
-            self.joypad.set_all_buttons(buttons);
+        if let Some(input) = self.frame_sender.sync_frame() {
+            self.joypad.set_all_buttons(input.buttons);
+            self.game_pak.set_input(&input);
         }
     }
 }
@@ -233,6 +308,7 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
     type Addr = u32;
 
     fn clock(&mut self, cycles: usize) -> Option<arm::ExternalException> {
+        self.game_pak_control.clock(cycles);
         if self.do_clock(cycles) {
             self.frame_end();
         }
@@ -260,8 +336,34 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
         }
     }
 
+    // BIOS can only be read while executing inside it.
+    // Otherwise, the last fetched BIOS opcode is returned.
+    // Instruction fetches from ROM can use the prefetch buffer.
+    fn fetch_instr_halfword(&mut self, cycle: MemCycleType, addr: Self::Addr) -> (u16, usize) {
+        self.in_bios = addr < 0x4000;
+        if self.in_bios {
+            self.bios_latch = self.bios.read_word(addr & 0xFFFF_FFFC);
+        }
+        match addr {
+            0x0800_0000..=0x0DFF_FFFF => (self.game_pak.read_halfword(addr), self.game_pak_control.fetch_cycles(cycle, addr, 1)),
+            _ => self.load_halfword(cycle, addr)
+        }
+    }
+
+    fn fetch_instr_word(&mut self, cycle: MemCycleType, addr: Self::Addr) -> (u32, usize) {
+        self.in_bios = addr < 0x4000;
+        if self.in_bios {
+            self.bios_latch = self.bios.read_word(addr);
+        }
+        match addr {
+            0x0800_0000..=0x0DFF_FFFF => (self.game_pak.read_word(addr), self.game_pak_control.fetch_cycles(cycle, addr, 2)),
+            _ => self.load_word(cycle, addr)
+        }
+    }
+
     fn load_byte(&mut self, cycle: MemCycleType, addr: Self::Addr) -> (u8, usize) {
         match addr {
+            0x0000_0000..=0x0000_3FFF if !self.in_bios => ((self.bios_latch >> ((addr & 3) * 8)) as u8, 1),
             0x0000_0000..=0x0000_3FFF => (self.bios.read_byte(addr), 1),                // BIOS
             0x0200_0000..=0x02FF_FFFF => (self.wram.read_byte(addr & 0x3_FFFF), 3),     // WRAM
             0x0300_0000..=0x03FF_FFFF => (self.fast_wram.read_byte(addr & 0x7FFF), 1),  // FAST WRAM
@@ -284,10 +386,12 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
             0x0000_0000..=0x0000_3FFF => 1, // BIOS
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.wram.write_byte(addr & 0x3_FFFF, data);
+                self.jit.write(addr, 1, &WRAM);
                 3
             },
             0x0300_0000..=0x03FF_FFFF => {  // FAST WRAM
                 self.fast_wram.write_byte(addr & 0x7FFF, data);
+                self.jit.write(addr, 1, &FAST_WRAM);
                 1
             },
             0x0400_0000..=0x0400_03FE => {  // I/O
@@ -302,6 +406,10 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
             },
 
             // Cart
+            0x0800_00C4..=0x0800_00C9 => {
+                self.game_pak.write_byte(addr, data);
+                self.game_pak_control.wait_cycles_0(cycle)
+            },
             0x0800_0000..=0x09FF_FFFF => self.game_pak_control.wait_cycles_0(cycle),
             0x0A00_0000..=0x0BFF_FFFF => self.game_pak_control.wait_cycles_1(cycle),
             0x0C00_0000..=0x0CFF_FFFF => self.game_pak_control.wait_cycles_2(cycle),
@@ -316,6 +424,7 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
 
     fn load_halfword(&mut self, cycle: MemCycleType, addr: Self::Addr) -> (u16, usize) {
         match addr {
+            0x0000_0000..=0x0000_3FFF if !self.in_bios => ((self.bios_latch >> ((addr & 2) * 8)) as u16, 1),
             0x0000_0000..=0x0000_3FFF => (self.bios.read_halfword(addr), 1),                // BIOS
             0x0200_0000..=0x02FF_FFFF => (self.wram.read_halfword(addr & 0x3_FFFF), 3),     // WRAM
             0x0300_0000..=0x03FF_FFFF => (self.fast_wram.read_halfword(addr & 0x7FFF), 1),  // FAST WRAM
@@ -338,10 +447,12 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
             0x0000_0000..=0x0000_3FFF => 1, // BIOS
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.wram.write_halfword(addr & 0x3_FFFF, data);
+                self.jit.write(addr, 2, &WRAM);
                 3
             },
             0x0300_0000..=0x03FF_FFFF => {  // FAST WRAM
                 self.fast_wram.write_halfword(addr & 0x7FFF, data);
+                self.jit.write(addr, 2, &FAST_WRAM);
                 1
             },
             0x0400_0000..=0x0400_03FE => {  // I/O
@@ -356,6 +467,10 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
             },
 
             // Cart
+            0x0800_00C4..=0x0800_00C9 => {
+                self.game_pak.write_halfword(addr, data);
+                self.game_pak_control.wait_cycles_0(cycle)
+            },
             0x0800_0000..=0x09FF_FFFF => self.game_pak_control.wait_cycles_0(cycle),
             0x0A00_0000..=0x0BFF_FFFF => self.game_pak_control.wait_cycles_1(cycle),
             0x0C00_0000..=0x0CFF_FFFF => self.game_pak_control.wait_cycles_2(cycle),
@@ -370,6 +485,7 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
 
     fn load_word(&mut self, cycle: MemCycleType, addr: Self::Addr) -> (u32, usize) {
         match addr {
+            0x0000_0000..=0x0000_3FFF if !self.in_bios => (self.bios_latch, 1),
             0x0000_0000..=0x0000_3FFF => (self.bios.read_word(addr), 1),                // BIOS
             0x0200_0000..=0x02FF_FFFF => (self.wram.read_word(addr & 0x3_FFFF), 6),     // WRAM
             0x0300_0000..=0x03FF_FFFF => (self.fast_wram.read_word(addr & 0x7FFF), 1),  // FAST WRAM
@@ -393,10 +509,12 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
             0x0000_0000..=0x0000_3FFF => 1, // BIOS
             0x0200_0000..=0x02FF_FFFF => {  // WRAM
                 self.wram.write_word(addr & 0x3_FFFF, data);
+                self.jit.write(addr, 4, &WRAM);
                 6
             },
             0x0300_0000..=0x03FF_FFFF => {  // FAST WRAM
                 self.fast_wram.write_word(addr & 0x7FFF, data);
+                self.jit.write(addr, 4, &FAST_WRAM);
                 1
             },
             0x0400_0000..=0x0400_03FE => {  // I/O
@@ -416,6 +534,10 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
             },
 
             // Cart
+            0x0800_00C4..=0x0800_00C9 => {
+                self.game_pak.write_word(addr, data);
+                self.game_pak_control.wait_cycles_0(cycle) << 1
+            },
             0x0800_0000..=0x09FF_FFFF => self.game_pak_control.wait_cycles_0(cycle) << 1,
             0x0A00_0000..=0x0BFF_FFFF => self.game_pak_control.wait_cycles_1(cycle) << 1,
             0x0C00_0000..=0x0CFF_FFFF => self.game_pak_control.wait_cycles_2(cycle) << 1,
@@ -429,6 +551,12 @@ impl<R: Renderer> Mem32 for MemoryBus<R> {
     }
 }
 
+impl<R: Renderer> EmulatedBIOSMem for MemoryBus<R> {
+    fn request_reset(&mut self, entry: u32) {
+        self.reset_request = Some(entry);
+    }
+}
+
 impl<R: Renderer> MemoryBus<R> {
     MemoryBusIO!{
         (0x0400_0000, 0x0400_0057, video),
@@ -493,7 +621,7 @@ impl MemInterface8 for Internal {
 /// 
 /// This just deals with IRQ interrupt handling.
 /// 
-/// Should work for games that don't make use of SWI calls.
+/// SWI calls are handled by `emulated_swi`, and the boot state is set by `post_boot_state`.
 pub fn construct_bios() -> BIOS {
     let mut bios_mem = vec![0; 0x4000];
 
@@ -508,6 +636,22 @@ pub fn construct_bios() -> BIOS {
     BIOS::new_from_data(bios_mem)
 }
 
+/// Load a multiboot image into WRAM.
+/// 
+/// Also writes the values that the BIOS fills in the header after transfer.
+fn load_multiboot(path: &Path, wram: &mut RAM) -> std::io::Result<()> {
+    let image = std::fs::read(path)?;
+    if image.len() > wram.len() as usize {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "multiboot image too large"));
+    }
+    for (addr, data) in image.iter().enumerate() {
+        wram.write_byte(addr as u32, *data);
+    }
+    wram.write_byte(0xC4, 3);   // Boot mode: multiplay
+    wram.write_byte(0xC5, 1);   // Slave ID
+    Ok(())
+}
+
 fn write_word_to_mem(mem: &mut [u8], addr: usize, data: u32) {
     let bytes = data.to_le_bytes();
     for (dest, byte) in mem[addr..(addr + 4)].iter_mut().zip(&bytes) {
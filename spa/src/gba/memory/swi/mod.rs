@@ -8,22 +8,39 @@ use arm::{
     Mem32,
     MemCycleType
 };
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::{
-    gba::interrupt::Interrupts,
-    utils::{
-        bytes::{u16, u32},
-        bits
-    }
+    common::swi::*,
+    gba::interrupt::Interrupts
 };
 
+/// Value of BIOS checksum.
+const BIOS_CHECKSUM: u32 = 0xBAAE_187F;
+
+/// Set once a game has called into the unemulated sound driver.
+static SOUND_DRIVER_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Memory that the emulated BIOS can run on.
+/// 
+/// Some calls need to do things that can't be done through the memory bus.
+pub trait EmulatedBIOSMem: Mem32<Addr = u32> {
+    /// Request that the CPU resets, and starts executing from `entry`.
+    fn request_reset(&mut self, entry: u32);
+}
+
 /// Emulated software interrupt for GBA.
 /// 
 /// Implements the BIOS SWI calls, clocks internally.
 /// 
 /// Input args are regs 0-3. Output args are regs 0, 1, 3.
-pub fn emulated_swi(comment: u32, mem: &mut impl Mem32<Addr = u32>, regs: &[u32; 4]) -> [u32; 3] {
+pub fn emulated_swi(comment: u32, mem: &mut impl EmulatedBIOSMem, regs: &[u32; 4]) -> [u32; 3] {
     let function = (comment as u8) | ((comment >> 16) as u8);
     match function {
+        // Reset
+        0x00 => {
+            soft_reset(mem);
+            [0, 0, 0]
+        },
         0x01 => {
             register_ram_reset(mem, regs[0]);
             [regs[0], regs[1], regs[3]]
@@ -64,10 +81,11 @@ pub fn emulated_swi(comment: u32, mem: &mut impl Mem32<Addr = u32>, regs: &[u32;
             let res = arctan(regs[0]);
             [res, 0, 0]
         },
-        /*0x0A => {
-            let (cycles, res) = arctan2(regs[0], regs[1]);
-            (cycles, [res, 0, 0])
-        },*/
+        0x0A => {
+            mem.clock(100);
+            let res = arctan2(regs[0], regs[1]);
+            [res, 0, 0]
+        },
         // Memset
         0x0B => {
             cpu_set(mem, regs[0], regs[1], regs[2]);
@@ -86,6 +104,10 @@ pub fn emulated_swi(comment: u32, mem: &mut impl Mem32<Addr = u32>, regs: &[u32;
             obj_affine_set(mem, regs[0], regs[1], regs[2], regs[3]);
             [regs[0], regs[1], regs[3]]
         },
+        0x0D => {
+            mem.clock(20);
+            [BIOS_CHECKSUM, 1, 0x4000]
+        },
         // Decompression
         0x10 => {
             bit_unpack(mem, regs[0], regs[1], regs[2]);
@@ -96,9 +118,11 @@ pub fn emulated_swi(comment: u32, mem: &mut impl Mem32<Addr = u32>, regs: &[u32;
             [regs[0], regs[1], regs[3]]
         },
         0x12 => {
-            lz77_uncomp_byte(mem, regs[0], regs[1]);
-            // TODO: fix bugs here
-            //lz77_uncomp_halfword(mem, regs[0], regs[1]);
+            lz77_uncomp_halfword(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x13 => {
+            huff_uncomp(mem, regs[0], regs[1]);
             [regs[0], regs[1], regs[3]]
         },
         0x14 => {
@@ -109,13 +133,148 @@ pub fn emulated_swi(comment: u32, mem: &mut impl Mem32<Addr = u32>, regs: &[u32;
             rl_uncomp_halfword(mem, regs[0], regs[1]);
             [regs[0], regs[1], regs[3]]
         },
+        0x16 => {
+            diff_8_unfilter_byte(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x17 => {
+            diff_8_unfilter_halfword(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x18 => {
+            diff_16_unfilter(mem, regs[0], regs[1]);
+            [regs[0], regs[1], regs[3]]
+        },
+        // Sound
+        0x19 => {
+            sound_bias(mem, regs[0]);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x1F => {
+            mem.clock(100);
+            let res = midi_key_to_freq(mem, regs[0], regs[1], regs[2]);
+            [res, regs[1], regs[3]]
+        },
+        0x28 => {
+            sound_driver_vsync_off(mem);
+            [regs[0], regs[1], regs[3]]
+        },
+        0x29 => {
+            sound_driver_vsync_on(mem);
+            [regs[0], regs[1], regs[3]]
+        },
+        // Multiboot
+        0x25 => {
+            // Link cable is not supported, so there is nothing to send to.
+            println!("Multiboot transfer failed: link cable not supported");
+            [1, regs[1], regs[3]]
+        },
+        0x26 => {
+            hard_reset(mem);
+            [0, 0, 0]
+        },
+        0x27 => {
+            custom_halt(mem, regs[2]);
+            [regs[0], regs[1], regs[3]]
+        },
+        // The rest of the BIOS sound driver is not emulated.
+        // Games that use it will be silent.
+        0x1A..=0x1E | 0x20..=0x24 | 0x2A => {
+            if !SOUND_DRIVER_WARNED.swap(true, Ordering::Relaxed) {
+                println!("SWI 0x{:X}: BIOS sound driver is not emulated. Provide the BIOS for sound.", function);
+            }
+            [regs[0], regs[1], regs[3]]
+        },
         _ => panic!("unsupported SWI 0x{:X}. This ROM requires the BIOS", function),
     }
 }
 
 /*** RESET ***/
-fn register_ram_reset(_mem: &mut impl Mem32<Addr = u32>, to_reset: u32) {
-    println!("Register RAM reset: {:X}", to_reset);
+
+/// Set memory and I/O to the state the BIOS leaves it in after booting.
+pub fn post_boot_state(mem: &mut impl Mem32<Addr = u32>) {
+    // Stacks, IRQ vector and flags.
+    clear_mem(mem, 0x0300_7E00, 0x0300_8000);
+
+    mem.store_byte(MemCycleType::N, 0x0400_0300, 1);        // POSTFLG
+    mem.store_halfword(MemCycleType::N, 0x0400_0088, 0x200); // SOUNDBIAS
+    reset_affine_params(mem);
+}
+
+fn soft_reset(mem: &mut impl EmulatedBIOSMem) {
+    let (flag, cycles) = mem.load_byte(MemCycleType::N, 0x0300_7FFA);
+    mem.clock(cycles);
+    clear_mem(mem, 0x0300_7E00, 0x0300_8000);
+
+    let entry = if flag == 0 {
+        0x0800_0000
+    } else {
+        0x0200_0000
+    };
+    mem.request_reset(entry);
+}
+
+fn hard_reset(mem: &mut impl EmulatedBIOSMem) {
+    register_ram_reset(mem, 0xFF);
+    post_boot_state(mem);
+    mem.request_reset(0x0800_0000);
+}
+
+fn register_ram_reset(mem: &mut impl Mem32<Addr = u32>, to_reset: u32) {
+    use crate::utils::bits::u32;
+    if u32::test_bit(to_reset, 0) {
+        clear_mem(mem, 0x0200_0000, 0x0204_0000);   // WRAM
+    }
+    if u32::test_bit(to_reset, 1) {
+        clear_mem(mem, 0x0300_0000, 0x0300_7E00);   // Fast WRAM (excluding stack)
+    }
+    if u32::test_bit(to_reset, 2) {
+        clear_mem(mem, 0x0500_0000, 0x0500_0400);   // Palette
+    }
+    if u32::test_bit(to_reset, 3) {
+        clear_mem(mem, 0x0600_0000, 0x0601_8000);   // VRAM
+    }
+    if u32::test_bit(to_reset, 4) {
+        clear_mem(mem, 0x0700_0000, 0x0700_0400);   // OAM
+    }
+    if u32::test_bit(to_reset, 5) {
+        // Serial
+        clear_mem(mem, 0x0400_0120, 0x0400_0130);
+        mem.store_halfword(MemCycleType::N, 0x0400_0134, 0x8000);
+        clear_mem(mem, 0x0400_0140, 0x0400_015C);
+    }
+    if u32::test_bit(to_reset, 6) {
+        // Sound (excluding bias)
+        clear_mem(mem, 0x0400_0060, 0x0400_0088);
+        clear_mem(mem, 0x0400_0090, 0x0400_00A0);
+    }
+    if u32::test_bit(to_reset, 7) {
+        // Video, DMA, timers, interrupts, wait control.
+        clear_mem(mem, 0x0400_0000, 0x0400_0060);
+        mem.store_halfword(MemCycleType::N, 0x0400_0000, 0x80);
+        reset_affine_params(mem);
+        clear_mem(mem, 0x0400_00B0, 0x0400_00E0);
+        clear_mem(mem, 0x0400_0100, 0x0400_0110);
+        clear_mem(mem, 0x0400_0200, 0x0400_020C);
+    }
+}
+
+/// Set the affine BG matrices to identity.
+fn reset_affine_params(mem: &mut impl Mem32<Addr = u32>) {
+    for addr in [0x0400_0020, 0x0400_0026, 0x0400_0030, 0x0400_0036].iter() {
+        let cycles = mem.store_halfword(MemCycleType::N, *addr, 0x100);
+        mem.clock(cycles);
+    }
+}
+
+/// Clear memory in range [start, end).
+fn clear_mem(mem: &mut impl Mem32<Addr = u32>, start: u32, end: u32) {
+    let mut cycle_type = MemCycleType::N;
+    for addr in (start..end).step_by(4) {
+        let cycles = mem.store_word(cycle_type, addr, 0);
+        mem.clock(cycles);
+        cycle_type = MemCycleType::S;
+    }
 }
 
 /*** HALT ***/
@@ -171,156 +330,61 @@ fn vblank_intr_wait(mem: &mut impl Mem32<Addr = u32>) {
     intr_wait(mem, 1, 1);
 }
 
-/*** MATHS ***/
-fn divide(op1: u32, op2: u32) -> [u32; 3] {
-    let op1_signed = op1 as i32;
-    let op2_signed = op2 as i32;
-
-    let div_res = op1_signed / op2_signed;
-    let div_mod = op1_signed % op2_signed;
-    let abs_res = div_res.abs();
-    [div_res as u32, div_mod as u32, abs_res as u32]
+fn custom_halt(mem: &mut impl Mem32<Addr = u32>, halt_type: u32) {
+    let cycles = mem.store_byte(MemCycleType::N, 0x0400_0301, halt_type as u8);
+    mem.clock(cycles + 57);
 }
 
-fn sqrt(op: u32) -> u32 {
-    let sqrt = (op as f64).sqrt();
-    sqrt.floor() as u32
+/*** MATHS ***/
+/// Result is signed.
+fn arctan(op: u32) -> u32 {
+    arctan_poly(op as i32) as u32
 }
 
-fn arctan(op: u32) -> u32 {
-    // We interpret the input as a 16-bit signed value,
-    // With fixed pt (1.1.14)
-    let opf = ((op as u16) as i16) as f64;
-    // Divide by 0x4000 to normalise to 1.0
-    let norm_opf = opf / 16384.0;
-    // Convert from radians
-    let res = norm_opf.atan() / std::f64::consts::FRAC_PI_2;
-    // Convert back to 16-bit fixed point
-    ((res * 16384.0).floor() as i32) as u32
+/// Polynomial approximation of arctan used by the BIOS.
+/// 
+/// Input is 1.1.14 fixed point. Output is in the range -0x4000 to 0x4000,
+/// where 0x4000 is pi/2.
+fn arctan_poly(op: i32) -> i32 {
+    let a = -(op.wrapping_mul(op) >> 14);
+    let mut b = (0xA9_i32.wrapping_mul(a) >> 14) + 0x390;
+    b = (b.wrapping_mul(a) >> 14) + 0x91C;
+    b = (b.wrapping_mul(a) >> 14) + 0xFB6;
+    b = (b.wrapping_mul(a) >> 14) + 0x16AA;
+    b = (b.wrapping_mul(a) >> 14) + 0x2081;
+    b = (b.wrapping_mul(a) >> 14) + 0x3651;
+    b = (b.wrapping_mul(a) >> 14) + 0xA2F9;
+    op.wrapping_mul(b) >> 16
 }
 
-/*fn arctan2(op1: u32, op2: u32) -> u32 {
+fn arctan2(op1: u32, op2: u32) -> u32 {
     // We interpret the inputs as 16-bit signed values,
     // With fixed pt (1.1.14)
-    let x_i = (op1 as u16) as i16;
-    let y_i = (op2 as u16) as i16;
-    if x_i == 0 {
-        if y_i > 0 {
-            return 100, 0x4000;
-        } else if y_i < 0 {
-            return 100, 0x
-        }
-    }
-
-    // Divide by 0x4000 to normalise to 1.0
-    let norm_op1f = op1f / 16384.0;
-    let norm_op2f = op2f / 16384.0;
-
-
-
-    // Normalise relative to 2 Pi
-    let res = norm_op1f.atan2(norm_op2f);
-    println!("atan2({}, {}) = {}", norm_op1f, norm_op2f, res);
-    let abs_res = if res < 0.0 {
-        res + std::f64::consts::PI
-    } else {
-        res + std::f64::consts::PI
-    } / std::f64::consts::TAU;
-    println!("norm = {}", abs_res);
-    // Convert to 16-bit unsigned.
-    (abs_res * 65536.0).floor() as u32
-}*/
-
-/*** MEMSET ***/
-fn cpu_set(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32, len_mode: u32) {
-    use crate::utils::bits::u32;
-    let mut count = len_mode & 0x1F_FFFF;
-    let fixed_src = u32::test_bit(len_mode, 24);
-    let use_word = u32::test_bit(len_mode, 26);
-
-    mem.clock(96);
-
-    if use_word {
-        if fixed_src {
-            let (data, read_cycles) = mem.load_word(arm::MemCycleType::N, src_addr);
-            mem.clock(read_cycles);
-            while count != 0 {
-                let write_cycles = mem.store_word(arm::MemCycleType::N, dst_addr, data);
-                dst_addr += 4;
-                count -= 1;
-                mem.clock(write_cycles + 7);
-            }
+    let x = ((op1 as u16) as i16) as i32;
+    let y = ((op2 as u16) as i16) as i32;
+    // Output is 0x0000 - 0xFFFF for 0 - 2 pi
+    let res = if y == 0 {
+        if x >= 0 {0} else {0x8000}
+    } else if x == 0 {
+        if y >= 0 {0x4000} else {0xC000}
+    } else if y >= 0 {
+        if x >= 0 && x >= y {
+            arctan_poly((y << 14) / x)
+        } else if x < 0 && -x >= y {
+            arctan_poly((y << 14) / x) + 0x8000
         } else {
-            while count != 0 {
-                let (data, read_cycles) = mem.load_word(arm::MemCycleType::N, src_addr);
-                let write_cycles = mem.store_word(arm::MemCycleType::N, dst_addr, data);
-                src_addr += 4;
-                dst_addr += 4;
-                count -= 1;
-                mem.clock(read_cycles + write_cycles + 9);
-            }
+            0x4000 - arctan_poly((x << 14) / y)
         }
     } else {
-        mem.clock(2);
-        if fixed_src {
-            let (data, read_cycles) = mem.load_halfword(arm::MemCycleType::N, src_addr);
-            mem.clock(read_cycles);
-            while count != 0 {
-                let write_cycles = mem.store_halfword(arm::MemCycleType::N, dst_addr, data);
-                dst_addr += 2;
-                count -= 1;
-                mem.clock(write_cycles + 7);
-            }
+        if x <= 0 && -x > -y {
+            arctan_poly((y << 14) / x) + 0x8000
+        } else if x > 0 && x >= -y {
+            arctan_poly((y << 14) / x) + 0x10000
         } else {
-            while count != 0 {
-                let (data, read_cycles) = mem.load_halfword(arm::MemCycleType::N, src_addr);
-                let write_cycles = mem.store_halfword(arm::MemCycleType::N, dst_addr, data);
-                src_addr += 2;
-                dst_addr += 2;
-                count -= 1;
-                mem.clock(read_cycles + write_cycles + 9);
-            }
-        }
-    }
-}
-
-fn cpu_fast_set(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32, len_mode: u32) {
-    use crate::utils::bits::u32;
-    let mut count = len_mode & 0x1F_FFF8;
-    let fixed_src = u32::test_bit(len_mode, 24);
-
-    mem.clock(95);
-
-    if fixed_src {
-        let (data, read_cycles) = mem.load_word(arm::MemCycleType::N, src_addr);
-        mem.clock(read_cycles + 9);
-        while count != 0 {
-            // 8 words transferred at a time.
-            let mut cycle_type = arm::MemCycleType::N;
-            for _ in 0..8 {
-                let write_cycles = mem.store_word(cycle_type, dst_addr, data);
-                dst_addr += 4;
-                count -= 1;
-                mem.clock(write_cycles);
-                cycle_type = arm::MemCycleType::S;
-            }
-            mem.clock(6);
-        }
-    } else {
-        while count != 0 {
-            // 8 words transferred at a time.
-            let mut cycle_type = arm::MemCycleType::N;
-            for _ in 0..8 {
-                let (data, read_cycles) = mem.load_word(cycle_type, src_addr);
-                let write_cycles = mem.store_word(cycle_type, dst_addr, data);
-                src_addr += 4;
-                dst_addr += 4;
-                count -= 1;
-                mem.clock(read_cycles + write_cycles + 1);
-                cycle_type = arm::MemCycleType::S;
-            }
+            0xC000 - arctan_poly((x << 14) / y)
         }
-    }
+    };
+    (res as u32) & 0xFFFF
 }
 
 /*** AFFINE SET ***/
@@ -414,269 +478,42 @@ fn obj_affine_set(mem: &mut impl Mem32<Addr = u32>, src_addr: u32, mut dst_addr:
 }
 
 
-/*** DECOMPRESS ***/
-fn bit_unpack(mem: &mut impl Mem32<Addr = u32>, src_addr: u32, mut dst_addr: u32, info_ptr: u32) {
-    let (info_lo, cycles_lo) = mem.load_word(MemCycleType::N, info_ptr);
-    let (info_hi, cycles_hi) = mem.load_word(MemCycleType::S, info_ptr + 4);
-    mem.clock(82 + cycles_lo + cycles_hi);
-
-    // Unpack info
-    let len = u32::lo(info_lo) as u32;
-    let info_mid = u32::hi(info_lo);
-    let src_width = u16::lo(info_mid);
-    let dst_width = u16::hi(info_mid);
-    let data_offset = info_hi & 0x7FFF_FFFF;
-    let zero_data = bits::u32::test_bit(info_hi, 31);
-
-    let src_mask = (1 << src_width) - 1;
-
-    // Do unpack
-    let mut out = 0_u32;
-    let mut out_bit_idx = 0;
-    for i in 0..len {
-        let (data, cycles) = mem.load_byte(MemCycleType::N, src_addr + i);
-        mem.clock(196 + cycles);
-
-        for offset in (0..8).step_by(src_width.into()) {
-            let src_data = ((data >> offset) & src_mask) as u32;
-            let dst_data = if src_data != 0 || zero_data {
-                src_data + data_offset
-            } else {
-                src_data
-            };
-            
-            out = out | (dst_data << out_bit_idx);
-            out_bit_idx += dst_width;
-            if out_bit_idx >= 32 {
-                let cycles = mem.store_word(MemCycleType::N, dst_addr, out);
-                mem.clock(cycles);
-                dst_addr += 4;
-                out = 0;
-                out_bit_idx = 0;
-            }
-        }
-    }
-
-    if out_bit_idx > 0 {
-        let cycles = mem.store_word(MemCycleType::N, dst_addr, out);
-        mem.clock(cycles);
-    }
-}
-
-fn lz77_uncomp_byte(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
-    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+/*** SOUND ***/
+fn sound_bias(mem: &mut impl Mem32<Addr = u32>, level: u32) {
+    let (bias, cycles) = mem.load_halfword(MemCycleType::N, 0x0400_0088);
     mem.clock(cycles);
-    src_addr += 4;
-
-    let len = header >> 8;
-    let end = dst_addr + len;
-    'outer: loop {
-        // Process block.
-        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
-        mem.clock(cycles);
-        src_addr += 1;
-
-        for i in (0..8).rev() {
-            if bits::u8::test_bit(flags, i) {
-                // Compressed
-                let (disp_lo, load_cycles_lo) = mem.load_byte(MemCycleType::N, src_addr);
-                let (disp_hi, load_cycles_hi) = mem.load_byte(MemCycleType::S, src_addr + 1);
-                mem.clock(load_cycles_lo + load_cycles_hi);
-                src_addr += 2;
-
-                let displacement = u16::make(disp_lo & 0xF, disp_hi) + 1;
-                let mut copy_src_addr = dst_addr - (displacement as u32);
-                let copy_len = 3 + ((disp_lo >> 4) & 0xF);
-
-                for _ in 0..copy_len {
-                    let (data, load_cycles) = mem.load_byte(MemCycleType::N, copy_src_addr);
-                    copy_src_addr += 1;
-                    let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
-                    dst_addr += 1;
-                    mem.clock(load_cycles + store_cycles);
-                }
-            } else {
-                // Raw data
-                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
-                src_addr += 1;
-                let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
-                dst_addr += 1;
-                mem.clock(load_cycles + store_cycles);
-            }
-
-            if dst_addr >= end {
-                break 'outer;
-            }
-        }
-    }
+    let target = if level == 0 {0} else {0x200};
+    // The BIOS moves the level slowly towards the target.
+    // Here it is set in one go.
+    let cycles = mem.store_halfword(MemCycleType::N, 0x0400_0088, (bias & 0xFC00) | target);
+    mem.clock(cycles + 100);
 }
 
-fn lz77_uncomp_halfword(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
-    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
+fn midi_key_to_freq(mem: &mut impl Mem32<Addr = u32>, wave_data: u32, key: u32, fine_adjust: u32) -> u32 {
+    let (freq, cycles) = mem.load_word(MemCycleType::N, wave_data + 4);
     mem.clock(cycles);
-    src_addr += 4;
-
-    let len = header >> 8;
-    let end = dst_addr + len;
-    let mut to_write = None;
-    'outer: loop {
-        // Process block.
-        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
-        mem.clock(cycles);
-        src_addr += 1;
-
-        for i in (0..8).rev() {
-            if bits::u8::test_bit(flags, i) {
-                // Compressed
-                let (disp_lo, load_cycles_lo) = mem.load_byte(MemCycleType::N, src_addr);
-                let (disp_hi, load_cycles_hi) = mem.load_byte(MemCycleType::S, src_addr + 1);
-                mem.clock(load_cycles_lo + load_cycles_hi);
-                src_addr += 2;
-
-                let displacement = u16::make(disp_lo & 0xF, disp_hi) + 1;
-                let mut copy_src_addr = dst_addr - (displacement as u32);
-                let copy_len = 3 + ((disp_lo >> 4) & 0xF);
-
-                for _ in 0..copy_len {
-                    let (data, load_cycles) = mem.load_byte(MemCycleType::N, copy_src_addr);
-                    copy_src_addr += 1;
-
-                    let store_cycles = if let Some(lo_byte) = to_write.take() {
-                        let halfword = u16::make(data, lo_byte);
-                        let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
-                        dst_addr += 2;
-                        store_cycles
-                    } else {
-                        to_write = Some(data);
-                        0
-                    };
-
-                    mem.clock(load_cycles + store_cycles);
-                }
-            } else {
-                // Raw data
-                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
-                src_addr += 1;
-
-                let store_cycles = if let Some(lo_byte) = to_write.take() {
-                    let halfword = u16::make(data, lo_byte);
-                    let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
-                    dst_addr += 2;
-                    store_cycles
-                } else {
-                    to_write = Some(data);
-                    0
-                };
-                
-                mem.clock(load_cycles + store_cycles);
-            }
-
-            if dst_addr >= end {
-                break 'outer;
-            }
-        }
-    }
+    let exp = (180.0 - ((key as u8) as f64) - (((fine_adjust as u8) as f64) / 256.0)) / 12.0;
+    ((freq as f64) / exp.exp2()) as u32
 }
 
-fn rl_uncomp_byte(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
-    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
-    mem.clock(cycles);
-    src_addr += 4;
-
-    let len = header >> 8;
-    let end = dst_addr + len;
-    'outer: loop {
-        // Process block.
-        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
-        mem.clock(cycles);
-        src_addr += 1;
-
-        if bits::u8::test_bit(flags, 7) {
-            // Compressed
-            let data_len = (flags & 0x7F) + 3;
-            let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
-            src_addr += 1;
-            mem.clock(load_cycles);
-
-            for _ in 0..data_len {
-                let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
-                dst_addr += 1;
-                mem.clock(store_cycles);
-            }
-        } else {
-            // Uncompressed
-            let data_len = (flags & 0x7F) + 1;
-
-            for _ in 0..data_len {
-                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
-                src_addr += 1;
-                let store_cycles = mem.store_byte(MemCycleType::N, dst_addr, data);
-                dst_addr += 1;
-                mem.clock(load_cycles + store_cycles);
-            }
-        }
-
-        if dst_addr >= end {
-            break 'outer;
-        }
+fn sound_driver_vsync_off(mem: &mut impl Mem32<Addr = u32>) {
+    // Stop sound DMA.
+    for addr in [0x0400_00C6, 0x0400_00D2].iter() {
+        let (control, load_cycles) = mem.load_halfword(MemCycleType::N, *addr);
+        let store_cycles = mem.store_halfword(MemCycleType::N, *addr, control & 0x7FFF);
+        mem.clock(load_cycles + store_cycles);
     }
 }
 
-fn rl_uncomp_halfword(mem: &mut impl Mem32<Addr = u32>, mut src_addr: u32, mut dst_addr: u32) {
-    let (header, cycles) = mem.load_word(MemCycleType::N, src_addr);
-    mem.clock(cycles);
-    src_addr += 4;
-
-    let len = header >> 8;
-    let end = dst_addr + len;
-    let mut to_write = None;
-    'outer: loop {
-        // Process block.
-        let (flags, cycles) = mem.load_byte(MemCycleType::N, src_addr);
-        mem.clock(cycles);
-        src_addr += 1;
-
-        if bits::u8::test_bit(flags, 7) {
-            // Compressed
-            let data_len = (flags & 0x7F) + 3;
-            let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
-            src_addr += 1;
-            mem.clock(load_cycles);
-
-            for _ in 0..data_len {
-                if let Some(lo_byte) = to_write.take() {
-                    let halfword = u16::make(data, lo_byte);
-                    let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
-                    dst_addr += 2;
-                    mem.clock(store_cycles);
-                } else {
-                    to_write = Some(data);
-                }
-            }
+fn sound_driver_vsync_on(mem: &mut impl Mem32<Addr = u32>) {
+    // Restart sound DMA. Only channels set to sound FIFO timing are enabled.
+    for addr in [0x0400_00C6, 0x0400_00D2].iter() {
+        let (control, load_cycles) = mem.load_halfword(MemCycleType::N, *addr);
+        let store_cycles = if (control & 0x3000) == 0x3000 {
+            mem.store_halfword(MemCycleType::N, *addr, control | 0x8000)
         } else {
-            // Uncompressed
-            let data_len = (flags & 0x7F) + 1;
-
-            for _ in 0..data_len {
-                let (data, load_cycles) = mem.load_byte(MemCycleType::N, src_addr);
-                src_addr += 1;
-
-                let store_cycles = if let Some(lo_byte) = to_write.take() {
-                    let halfword = u16::make(data, lo_byte);
-                    let store_cycles = mem.store_halfword(MemCycleType::N, dst_addr, halfword);
-                    dst_addr += 2;
-                    store_cycles
-                } else {
-                    to_write = Some(data);
-                    0
-                };
-
-                mem.clock(load_cycles + store_cycles);
-            }
-        }
-
-        if dst_addr >= end {
-            break 'outer;
-        }
+            0
+        };
+        mem.clock(load_cycles + store_cycles);
     }
 }
@@ -9,7 +9,7 @@ use crate::utils::{
     meminterface::MemInterface8
 };
 use crate::common::mem::bios::BIOS;
-use super::emulated_swi;
+use super::{emulated_swi, EmulatedBIOSMem};
 
 const TEST_RAM_SIZE: u32 = 32 * 1024;
 
@@ -22,6 +22,7 @@ pub struct TestMem {
     real_i_flags: u16,  // 0x0400_0202
 
     cycle_count: usize,
+    reset_entry: Option<u32>,
 }
 
 impl TestMem {
@@ -40,6 +41,7 @@ impl TestMem {
             real_i_flags: 0,
 
             cycle_count: 0,
+            reset_entry: None,
         })
     }
 }
@@ -128,6 +130,12 @@ impl Mem32 for TestMem {
     }
 }
 
+impl EmulatedBIOSMem for TestMem {
+    fn request_reset(&mut self, entry: u32) {
+        self.reset_entry = Some(entry);
+    }
+}
+
 fn run_real_bios(regs: &[u32; 4], swi_call: u8) -> (usize, [u32; 3]) {
     // SETUP
     let mem = TestMem::new(Some(&PathBuf::from("../spa-bin/gba_bios.bin")));
@@ -579,3 +587,127 @@ fn test_obj_affine_set() {
         //assert_eq!(cycles, true);
     }
 }
+
+#[test]
+fn test_soft_reset() {
+    let mut mem = TestMem::new(None);
+    mem.write_byte(0x0300_7FFA, 1);
+    let _ = emulated_swi(0x00, mem.as_mut(), &[0, 0, 0, 0]);
+    assert_eq!(mem.reset_entry, Some(0x0200_0000));
+    assert_eq!(mem.read_byte(0x0300_7FFA), 0);
+}
+
+#[test]
+fn test_bios_checksum() {
+    let data = vec![
+        [0, 0, 0, 0]
+    ];
+
+    for regs in data.iter() {
+        let (reg_outs, _cycles) = compare(regs, 0x0D, 1);
+        assert_eq!(reg_outs, true);
+    }
+}
+
+#[test]
+fn test_lz77_halfword() {
+    let data = vec![
+        [0x0300_0100, 0x0300_0200, 0, 0],
+    ];
+
+    let mem = vec![
+        0x10, 0x20, 0, 0,   // Header: 0x20 bytes
+        0x40,               // Flags: raw, compressed, raw...
+        0xAA,               // Raw
+        0xF0, 0x00,         // Copy 18 bytes, disp 1
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+        0x00,               // Flags: raw
+        0x07, 0x08, 0x11, 0x12, 0x13, 0x14, 0x15,
+    ];
+
+    for regs in data.iter() {
+        let (mem_out, _cycles) = compare_mem(regs, 0x0300_0100, &mem, 0x0300_0200, 0x12);
+        assert_eq!(mem_out, true);
+    }
+}
+
+#[test]
+fn test_huff() {
+    let data = vec![
+        [0x0300_0100, 0x0300_0200, 0, 0],
+    ];
+
+    let mem = vec![
+        0x28, 0x04, 0, 0,   // Header: 8-bit data, 4 bytes
+        0x01,               // Tree size
+        0xC0,               // Root: both children are data
+        0x41, 0x42,         // Data: 'A', 'B'
+        0x00, 0x00, 0x00, 0x60, // Bits: 0110
+    ];
+
+    for regs in data.iter() {
+        let (mem_out, _cycles) = compare_mem(regs, 0x0300_0100, &mem, 0x0300_0200, 0x13);
+        assert_eq!(mem_out, true);
+    }
+}
+
+#[test]
+fn test_diff_8_wram() {
+    let data = vec![
+        [0x0300_0100, 0x0300_0200, 0, 0],
+    ];
+
+    let mut mem = vec![0x81, 0x10, 0, 0];
+    mem.extend((0..0x10).map(|i| i as u8));
+
+    for regs in data.iter() {
+        let (mem_out, _cycles) = compare_mem(regs, 0x0300_0100, &mem, 0x0300_0200, 0x16);
+        assert_eq!(mem_out, true);
+    }
+}
+
+#[test]
+fn test_diff_8_vram() {
+    let data = vec![
+        [0x0300_0100, 0x0300_0200, 0, 0],
+    ];
+
+    let mut mem = vec![0x81, 0x10, 0, 0];
+    mem.extend((0..0x10).map(|i| i as u8));
+
+    for regs in data.iter() {
+        let (mem_out, _cycles) = compare_mem(regs, 0x0300_0100, &mem, 0x0300_0200, 0x17);
+        assert_eq!(mem_out, true);
+    }
+}
+
+#[test]
+fn test_diff_16() {
+    let data = vec![
+        [0x0300_0100, 0x0300_0200, 0, 0],
+    ];
+
+    let mut mem = vec![0x82, 0x10, 0, 0];
+    mem.extend((0..0x10).map(|i| (i * 0x11) as u8));
+
+    for regs in data.iter() {
+        let (mem_out, _cycles) = compare_mem(regs, 0x0300_0100, &mem, 0x0300_0200, 0x18);
+        assert_eq!(mem_out, true);
+    }
+}
+
+#[test]
+fn test_midi_key_to_freq() {
+    let data = vec![
+        ([0x0300_0100, 180, 0, 0], 0x0800_0000),    // Base frequency
+        ([0x0300_0100, 168, 0, 0], 0x0400_0000),    // One octave down
+        ([0x0300_0100, 156, 0, 0], 0x0200_0000),    // Two octaves down
+    ];
+
+    for (regs, expected) in data.iter() {
+        let mut mem = TestMem::new(None);
+        mem.write_word(0x0300_0104, 0x0800_0000);
+        let [freq, _, _] = emulated_swi(0x1F, mem.as_mut(), regs);
+        assert_eq!(freq, *expected);
+    }
+}
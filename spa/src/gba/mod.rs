@@ -5,36 +5,41 @@ mod audio;
 mod input;
 
 use arm::{
-    ARM7TDMI, ARMDriver, ARMCore
+    ARM7TDMI, ARMDriver, ARMCore, Mem32
 };
 use crossbeam_channel::{Receiver, unbounded};
 
+use std::ops::Range;
+
 use crate::common::{
     video::framecomms::{new_frame_comms, FrameRequester},
-    peripheral::joypad::Buttons,
     resampler::{Resampler, SamplePacket}
 };
 #[cfg(feature = "debug")]
 use crate::common::debug::DebugInterface;
 use memory::{
     MemoryBus,
-    emulated_swi
+    emulated_swi,
+    post_boot_state
 };
 use video::Renderer;
 use audio::REAL_BASE_SAMPLE_RATE;
 use super::{
-    AudioHandler, Device, Button, Coords
+    AudioHandler, Device, Button, Coords, Event
 };
 
-pub use memory::MemoryConfig;
+pub use memory::{MemoryConfig, DEFAULT_JIT_RANGES};
+pub use input::UserInput;
+pub(crate) use memory::GamePak;
 
 type RendererType = video::ProceduralRenderer;
 
 pub struct GBA {
-    frame_receiver: FrameRequester<Buttons>,
+    frame_receiver: FrameRequester<UserInput>,
     audio_channels: Option<(Receiver<SamplePacket>, Receiver<f64>)>,
+    event_receiver: Receiver<Event>,
 
-    buttons_pressed: Buttons,
+    current_input: UserInput,
 }
 
 impl GBA {
@@ -45,29 +50,37 @@ impl GBA {
         // We have to extract the audio receivers from the CPU and get them in the main thread to use
         //   for the audio handler.
         let (channel_sender, channel_receiver) = unbounded();
+        let (event_sender, event_receiver) = unbounded();
         std::thread::Builder::new().name("CPU".to_string()).spawn(move || {
             let no_bios = config.bios_path.is_none();
-            let bus = MemoryBus::<RendererType>::new(&config, frame_sender).unwrap();
-            let mut cpu = new_cpu(bus, no_bios, false);
+            let bus = MemoryBus::<RendererType>::new(&config, frame_sender, event_sender).unwrap();
+            let mut cpu = new_cpu(bus, no_bios, config.is_multiboot(), config.jit_ranges());
             let audio_channels = cpu.mut_mem().enable_audio();
             channel_sender.send(audio_channels).unwrap();
             loop {
                 cpu.step();
+                while let Some(range) = cpu.mut_mem().take_jit_dirty() {
+                    cpu.invalidate_jit_range(range);
+                }
+                if let Some(entry) = cpu.mut_mem().take_reset_request() {
+                    reset_cpu(&mut cpu, entry);
+                }
             }
         }).unwrap();
         let audio_channels = channel_receiver.recv().unwrap();
         Self {
             frame_receiver: frame_receiver,
             audio_channels: Some(audio_channels),
+            event_receiver: event_receiver,
 
-            buttons_pressed: Buttons::from_bits_truncate(0xFFFF),
+            current_input: UserInput::default(),
         }
     }
 }
 
 impl Device for GBA {
     fn frame(&mut self, upper_frame: &mut [u8], _lower_frame: &mut [u8]) {
-        self.frame_receiver.get_frame(&mut [upper_frame], self.buttons_pressed);
+        self.frame_receiver.get_frame(&mut [upper_frame], self.current_input.clone());
     }
 
     fn render_size(&self) -> [Coords<usize>; 2] {
@@ -91,29 +104,48 @@ impl Device for GBA {
     }
 
     fn set_button(&mut self, button: Button, pressed: bool) {
-        self.buttons_pressed.set(button.into(), !pressed);
+        self.current_input.buttons.set(button.into(), !pressed);
     }
 
     fn touchscreen_pressed(&mut self, _coords: Option<Coords<f64>>) {
         // No effect on GBA.
     }
+
+    fn set_light_level(&mut self, level: f64) {
+        self.current_input.light_level = level.clamp(0.0, 1.0);
+    }
+
+    fn set_rotation(&mut self, rate: f64) {
+        self.current_input.rotation = rate.clamp(-1.0, 1.0);
+    }
+
+    fn set_tilt(&mut self, tilt: Coords<f64>) {
+        self.current_input.tilt = (tilt.x.clamp(-1.0, 1.0), tilt.y.clamp(-1.0, 1.0));
+    }
+
+    fn take_events(&mut self) -> Vec<Event> {
+        self.event_receiver.try_iter().collect()
+    }
 }
 
 // Debug
 #[cfg(feature = "debug")]
 impl GBA {
     /// Make a new debuggable GBA.
-    pub fn new_debug(config: MemoryConfig) -> DebugInterface<Buttons> {
+    pub fn new_debug(config: MemoryConfig) -> DebugInterface<UserInput> {
         use crate::common::video::framecomms::debug::new_debug_frame_comms;
 
         let (render_width, render_height) = RendererType::render_size();
         let (frame_sender, frame_receiver) = new_debug_frame_comms(render_width * render_height * 4, 1);
-        let (debug_interface, debug_wrapper) = DebugInterface::new(frame_receiver, Buttons::from_bits_truncate(0xFFFF));
+        let (debug_interface, debug_wrapper) = DebugInterface::new(frame_receiver, UserInput::default());
+        // Events are dropped in debug mode.
+        let (event_sender, _) = unbounded();
 
         std::thread::Builder::new().name("CPU".to_string()).spawn(move || {
             let no_bios = config.bios_path.is_none();
-            let bus = MemoryBus::<RendererType>::new(&config, frame_sender).unwrap();
-            let cpu = new_cpu(bus, no_bios, false);
+            let bus = MemoryBus::<RendererType>::new(&config, frame_sender, event_sender).unwrap();
+            // JIT is not used when debugging.
+            let cpu = new_cpu(bus, no_bios, config.is_multiboot(), Vec::new());
             debug_wrapper.run_debug(cpu);
         }).unwrap();
 
@@ -121,15 +153,29 @@ impl GBA {
     }
 }
 
-fn new_cpu(mem_bus: Box<MemoryBus<RendererType>>, no_bios: bool, use_jit: bool) -> ARM7TDMI<MemoryBus<RendererType>> {
+/// Create the CPU. If `jit_ranges` is empty, the JIT is disabled.
+/// 
+/// Multiboot images skip the BIOS boot, even if the BIOS is present.
+fn new_cpu(mem_bus: Box<MemoryBus<RendererType>>, no_bios: bool, multiboot: bool, jit_ranges: Vec<Range<u32>>) -> ARM7TDMI<MemoryBus<RendererType>> {
     let mut cpu_builder = ARM7TDMI::new(mem_bus);
-    if use_jit {
-        cpu_builder = cpu_builder.enable_jit_in_ranges(vec![0..0x4000, 0x0800_0000..0x0E00_0000]);
+    if !jit_ranges.is_empty() {
+        cpu_builder = cpu_builder.enable_jit_in_ranges(jit_ranges);
     }
     if no_bios {
+        cpu_builder = cpu_builder.set_swi_hook(emulated_swi);
+    }
+    if no_bios || multiboot {
+        let mut cpu = cpu_builder.build();
+        post_boot_state(cpu.mut_mem());
+        let entry = if multiboot {
+            // Tell the BIOS to soft reset into WRAM.
+            cpu.mut_mem().store_byte(arm::MemCycleType::N, 0x0300_7FFA, 1);
+            0x0200_0000
+        } else {
+            0x0800_0000
+        };
         // Setup stack pointers.
-        let mut cpu = cpu_builder.set_swi_hook(emulated_swi).build();
-        cpu.do_branch(0x0800_0000);
+        cpu.do_branch(entry);
         cpu.write_cpsr(arm::CPSR::SVC);
         cpu.write_reg(13, 0x0300_7FE0);
         cpu.write_cpsr(arm::CPSR::IRQ);
@@ -141,4 +187,20 @@ fn new_cpu(mem_bus: Box<MemoryBus<RendererType>>, no_bios: bool, use_jit: bool)
     } else {
         cpu_builder.build()
     }
+}
+
+/// Reset the CPU after an emulated BIOS reset call.
+fn reset_cpu(cpu: &mut ARM7TDMI<MemoryBus<RendererType>>, entry: u32) {
+    cpu.write_cpsr(arm::CPSR::SVC);
+    cpu.write_reg(13, 0x0300_7FE0);
+    cpu.write_reg(14, 0);
+    cpu.write_cpsr(arm::CPSR::IRQ);
+    cpu.write_reg(13, 0x0300_7FA0);
+    cpu.write_reg(14, 0);
+    cpu.write_cpsr(arm::CPSR::SYS);
+    cpu.write_reg(13, 0x0300_7F00);
+    for reg in 0..13 {
+        cpu.write_reg(reg, 0);
+    }
+    cpu.do_branch(entry);
 }
\ No newline at end of file
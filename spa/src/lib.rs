@@ -5,6 +5,8 @@ mod utils;
 pub mod gba;
 pub mod ds;
 
+use std::path::{Path, PathBuf};
+
 use crate::common::resampler::Resampler;
 
 pub enum Button {
@@ -22,12 +24,30 @@ pub enum Button {
     R
 }
 
+/// Frets on the Guitar Hero: On Tour Guitar Grip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GuitarFret {
+    Green,
+    Red,
+    Yellow,
+    Blue
+}
+
 #[derive(Clone, Copy)]
 pub struct Coords<T> {
     pub x: T,
     pub y: T
 }
 
+/// Events emitted by the device, for the frontend to act on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// A rumble motor was switched on (true) or off (false).
+    Rumble(bool),
+    /// The console switched itself off.
+    PowerOff,
+}
+
 /// Represents a GBA or NDS.
 /// 
 /// The public interface.
@@ -59,6 +79,63 @@ pub trait Device {
     /// It creates a AudioHandler that can be sent to the audio thread.
     fn enable_audio(&mut self, sample_rate: f64) -> Option<AudioHandler>;
 
+    /// Set the light level seen by a cartridge solar sensor.
+    /// Should be between 0.0 (dark) and 1.0 (bright sunlight).
+    /// 
+    /// Has no effect if the cartridge has no solar sensor.
+    fn set_light_level(&mut self, _level: f64) {}
+
+    /// Set the rate of rotation seen by a cartridge gyro sensor.
+    /// Should be between -1.0 and 1.0, where 0.0 is stationary.
+    /// 
+    /// Has no effect if the cartridge has no gyro sensor.
+    fn set_rotation(&mut self, _rate: f64) {}
+
+    /// Set the tilt seen by a cartridge tilt sensor.
+    /// Each axis should be between -1.0 and 1.0, where 0.0 is level.
+    /// 
+    /// Has no effect if the cartridge has no tilt sensor.
+    fn set_tilt(&mut self, _tilt: Coords<f64>) {}
+
+    /// Set the date and time of the real-time clock.
+    /// 
+    /// Has no effect on GBA.
+    fn set_time(&mut self, _time: chrono::NaiveDateTime) {}
+
+    /// Provide mono PCM samples from the microphone.
+    /// Call before each frame with the samples recorded since the last frame.
+    /// They can be at any sample rate.
+    /// 
+    /// Has no effect on GBA.
+    fn microphone_samples(&mut self, _samples: &[i16]) {}
+
+    /// Press or release a fret on the Guitar Grip.
+    /// 
+    /// Has no effect unless a Guitar Grip is in the DS GBA slot.
+    fn set_guitar_fret(&mut self, _fret: GuitarFret, _pressed: bool) {}
+
+    /// Open (false) or close (true) the lid.
+    /// 
+    /// Has no effect on GBA.
+    fn set_lid_closed(&mut self, _closed: bool) {}
+
+    /// Insert a cartridge, replacing the current one.
+    /// 
+    /// Has no effect on GBA.
+    fn insert_cart(&mut self, _rom_path: &Path, _save_path: Option<PathBuf>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Remove the cartridge.
+    /// 
+    /// Has no effect on GBA.
+    fn eject_cart(&mut self) {}
+
+    /// Get all events that have occurred since this was last called.
+    fn take_events(&mut self) -> Vec<Event> {
+        Vec::new()
+    }
+
     fn trigger_debug(&mut self) {}
 }
 
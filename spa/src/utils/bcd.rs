@@ -32,6 +32,11 @@ impl Bcd8 {
     pub fn binary(&self) -> u8 {
         self.0
     }
+
+    /// Get the value in decimal.
+    pub fn decimal(&self) -> u8 {
+        ((self.0 >> 4) * 10) + (self.0 & 0xF)
+    }
 }
 
 mod tests {
@@ -48,6 +53,7 @@ mod tests {
             let bcd = Bcd8::from_binary(*decimal);
             let bcd_bits = bcd.binary();
             assert_eq!(bcd_bits, *bcd_hex);
+            assert_eq!(bcd.decimal(), *decimal);
         }
     }
 }
\ No newline at end of file